@@ -0,0 +1,207 @@
+//! Client-side aggregates over a table's records.
+
+use serde_json::{Map, Number, Value};
+
+/// What to compute for each group (or the whole table).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    Count,
+    Sum(String),
+    Avg(String),
+    Min(String),
+    Max(String),
+}
+
+impl Op {
+    /// The field the aggregate reads, if any.
+    pub fn field(&self) -> Option<&str> {
+        match self {
+            Op::Count => None,
+            Op::Sum(f) | Op::Avg(f) | Op::Min(f) | Op::Max(f) => Some(f),
+        }
+    }
+}
+
+/// An aggregate result, and how many records were left out of it because
+/// the aggregated (or group-by) field was missing or not a number.
+#[derive(Debug, PartialEq)]
+pub struct Outcome {
+    pub value: Value,
+    pub skipped: usize,
+}
+
+/// Aggregate `records`, or under `group_by` each set of records sharing a
+/// value of that field, giving `{group: aggregate}` in order of first
+/// appearance. String group values are used as-is, others as JSON text.
+pub fn aggregate(records: &[Value], op: &Op, group_by: Option<&str>) -> Outcome {
+    let mut skipped = 0;
+    let mut groups: Vec<(String, Vec<&Number>, usize)> = Vec::new();
+
+    for record in records {
+        let group = match group_by {
+            Some(field) => match record.get(field) {
+                Some(Value::String(s)) => s.clone(),
+                Some(Value::Null) | None => {
+                    skipped += 1;
+                    continue;
+                }
+                Some(other) => other.to_string(),
+            },
+            None => String::new(),
+        };
+        let number = match op.field() {
+            Some(field) => match record.get(field) {
+                Some(Value::Number(n)) => Some(n),
+                _ => {
+                    skipped += 1;
+                    continue;
+                }
+            },
+            None => None,
+        };
+
+        let index = match groups.iter().position(|(g, _, _)| *g == group) {
+            Some(index) => index,
+            None => {
+                groups.push((group, Vec::new(), 0));
+                groups.len() - 1
+            }
+        };
+        let (_, numbers, count) = &mut groups[index];
+        numbers.extend(number);
+        *count += 1;
+    }
+
+    let value = match group_by {
+        Some(_) => Value::Object(
+            groups
+                .into_iter()
+                .map(|(group, numbers, count)| (group, compute(op, &numbers, count)))
+                .collect::<Map<_, _>>(),
+        ),
+        None => match groups.pop() {
+            Some((_, numbers, count)) => compute(op, &numbers, count),
+            None => compute(op, &[], 0),
+        },
+    };
+    Outcome { value, skipped }
+}
+
+fn compute(op: &Op, numbers: &[&Number], count: usize) -> Value {
+    let as_f64 = |n: &&Number| n.as_f64().unwrap_or(0.0);
+    match op {
+        Op::Count => Value::from(count),
+        Op::Sum(_) => {
+            // Stay integral while every value is, so 2 + 3 prints as 5
+            let ints: Option<i64> = numbers
+                .iter()
+                .try_fold(0i64, |sum, n| n.as_i64().and_then(|n| sum.checked_add(n)));
+            match ints {
+                Some(sum) => Value::from(sum),
+                None => float(numbers.iter().map(as_f64).sum()),
+            }
+        }
+        Op::Avg(_) if numbers.is_empty() => Value::Null,
+        Op::Avg(_) => float(numbers.iter().map(as_f64).sum::<f64>() / numbers.len() as f64),
+        Op::Min(_) => extreme(numbers, |a, b| a < b),
+        Op::Max(_) => extreme(numbers, |a, b| a > b),
+    }
+}
+
+/// The number `better` prefers over all others, as given.
+fn extreme(numbers: &[&Number], better: impl Fn(f64, f64) -> bool) -> Value {
+    numbers
+        .iter()
+        .copied()
+        .reduce(|best, n| {
+            if better(n.as_f64().unwrap_or(0.0), best.as_f64().unwrap_or(0.0)) {
+                n
+            } else {
+                best
+            }
+        })
+        .map_or(Value::Null, |n| Value::Number(n.clone()))
+}
+
+fn float(value: f64) -> Value {
+    Number::from_f64(value).map_or(Value::Null, Value::Number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn orders() -> Vec<Value> {
+        vec![
+            json!({"id": "o1", "region": "east", "total": 10}),
+            json!({"id": "o2", "region": "west", "total": 4.5}),
+            json!({"id": "o3", "region": "east", "total": 5}),
+            json!({"id": "o4", "region": "east", "total": "n/a"}),
+            json!({"id": "o5", "total": 1}),
+        ]
+    }
+
+    #[test]
+    fn sum_and_avg_skip_non_numeric() {
+        let sum = aggregate(&orders(), &Op::Sum("total".into()), None);
+        assert_eq!(
+            sum,
+            Outcome {
+                value: json!(20.5),
+                skipped: 1
+            }
+        );
+
+        let avg = aggregate(&orders(), &Op::Avg("total".into()), None);
+        assert_eq!(avg.value, json!(5.125));
+        assert_eq!(avg.skipped, 1);
+    }
+
+    #[test]
+    fn integer_sums_stay_integral() {
+        let records = [json!({"n": 2}), json!({"n": 3})];
+        assert_eq!(
+            aggregate(&records, &Op::Sum("n".into()), None).value,
+            json!(5)
+        );
+    }
+
+    #[test]
+    fn min_and_max_keep_original_numbers() {
+        assert_eq!(
+            aggregate(&orders(), &Op::Min("total".into()), None).value,
+            json!(1)
+        );
+        assert_eq!(
+            aggregate(&orders(), &Op::Max("total".into()), None).value,
+            json!(10)
+        );
+    }
+
+    #[test]
+    fn grouped_count() {
+        let outcome = aggregate(&orders(), &Op::Count, Some("region"));
+        assert_eq!(outcome.value, json!({"east": 3, "west": 1}));
+        // o5 has no region
+        assert_eq!(outcome.skipped, 1);
+    }
+
+    #[test]
+    fn grouped_sum() {
+        let outcome = aggregate(&orders(), &Op::Sum("total".into()), Some("region"));
+        assert_eq!(outcome.value, json!({"east": 15, "west": 4.5}));
+        assert_eq!(outcome.skipped, 2);
+    }
+
+    #[test]
+    fn empty_input() {
+        assert_eq!(aggregate(&[], &Op::Count, None).value, json!(0));
+        assert_eq!(aggregate(&[], &Op::Sum("n".into()), None).value, json!(0));
+        assert_eq!(
+            aggregate(&[], &Op::Avg("n".into()), None).value,
+            Value::Null
+        );
+        assert_eq!(aggregate(&[], &Op::Count, Some("g")).value, json!({}));
+    }
+}
@@ -0,0 +1,465 @@
+use crate::connection::{self, Connection};
+use crate::error::Error;
+use crate::progress::Progress;
+use crate::{json_to_msgpack, msgpack_to_json, unix_now};
+use rmpv::Value;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+/// Version of the backup document layout, stored as `cortex_backup`.
+pub const FORMAT_VERSION: u64 = 1;
+
+/// Puts sent before waiting for their responses during a restore.
+const PUT_BATCH: usize = 100;
+
+/// Most connections a parallel restore or import may write over.
+pub const MAX_PARALLEL: u8 = 16;
+
+/// What a backup wrote.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub tables: usize,
+    pub records: usize,
+}
+
+/// Write every table the caller owns to `out` as one self-describing JSON
+/// document: `{"cortex_backup": 1, "tables": [{table, key, attributes, records}]}`,
+/// with the grants on a table under `acls` if it has any.
+///
+/// Tables are fetched and written one at a time, so only a single table's
+/// records are held in memory. `progress` advances as each table is written.
+pub fn backup(
+    conn: &mut Connection,
+    out: &mut impl Write,
+    progress: &Progress,
+) -> Result<Summary, Error> {
+    let tables = conn.call("tables", vec![])?;
+    let names: Vec<&str> = match &tables {
+        Some(Value::Array(names)) => names.iter().filter_map(Value::as_str).collect(),
+        _ => return Err(Error::Protocol("expected a list of tables".to_string())),
+    };
+    let grants = list_grants(conn)?;
+
+    let mut summary = Summary::default();
+    write_out(
+        out,
+        format_args!("{{\"cortex_backup\":{},\"tables\":[", FORMAT_VERSION),
+    )?;
+
+    for name in names {
+        let mut dump = dump_table(conn, name, false)?;
+        dump.acls = grants
+            .iter()
+            .filter(|(table, _)| names_table(table, name))
+            .map(|(_, grant)| grant.clone())
+            .collect();
+        let entry = serde_json::to_string(&dump)
+            .map_err(|e| Error::Output(format!("cannot encode '{}': {}", name, e)))?;
+
+        let separator = if summary.tables == 0 { "" } else { "," };
+        write_out(out, format_args!("{}\n{}", separator, entry))?;
+
+        summary.tables += 1;
+        summary.records += dump.records.len();
+        progress.add(dump.records.len());
+    }
+
+    write_out(out, format_args!("\n]}}\n"))?;
+    out.flush()
+        .map_err(|e| Error::Output(format!("write error: {}", e)))?;
+    Ok(summary)
+}
+
+/// Every grant on the caller's tables, with the (owner-qualified) name of
+/// the table it is on.
+fn list_grants(conn: &mut Connection) -> Result<Vec<(String, Grant)>, Error> {
+    let malformed = || Error::Protocol("expected a list of grants".to_string());
+    let Some(Value::Array(acls)) = conn.call("acl_list", vec![])? else {
+        return Err(malformed());
+    };
+    acls.iter()
+        .map(|acl| {
+            let acl = msgpack_to_json(acl);
+            let table = acl["table"].as_str().ok_or_else(malformed)?.to_string();
+            let grant = serde_json::from_value(acl).map_err(|_| malformed())?;
+            Ok((table, grant))
+        })
+        .collect()
+}
+
+/// Whether `table`, as `acl_list` names it (`1000:users`), is `name`.
+fn names_table(table: &str, name: &str) -> bool {
+    table == name
+        || table
+            .rsplit_once(':')
+            .is_some_and(|(_, short)| short == name)
+}
+
+/// Fetch one table's schema and, unless `schema_only`, all of its records.
+pub fn dump_table(
+    conn: &mut Connection,
+    name: &str,
+    schema_only: bool,
+) -> Result<TableDump, Error> {
+    let table = Value::String(name.into());
+    let schema = conn
+        .describe(name)?
+        .map(|s| msgpack_to_json(&s))
+        .ok_or_else(|| Error::Protocol(format!("no schema for table '{}'", name)))?;
+    let (key, attributes) = schema_order(name, &schema)?;
+
+    let records = if schema_only {
+        Vec::new()
+    } else {
+        match conn.call("all", vec![table])? {
+            Some(Value::Array(records)) => records.iter().map(msgpack_to_json).collect(),
+            _ => return Err(Error::Protocol(format!("expected records for '{}'", name))),
+        }
+    };
+
+    Ok(TableDump {
+        table: name.to_string(),
+        key,
+        attributes,
+        records,
+        acls: Vec::new(),
+    })
+}
+
+/// The primary key and attributes of a `describe` result, with the
+/// attributes in the order the table was created with.
+///
+/// Anything but an array of names is refused rather than read in some other
+/// order, as is a key that isn't one of the attributes.
+pub fn schema_order(
+    name: &str,
+    schema: &serde_json::Value,
+) -> Result<(String, Vec<String>), Error> {
+    let malformed = || Error::Protocol(format!("malformed schema for '{}'", name));
+    let key = schema["key"].as_str().ok_or_else(malformed)?;
+    let attributes = schema["attributes"]
+        .as_array()
+        .ok_or_else(malformed)?
+        .iter()
+        .map(|a| a.as_str().map(str::to_string).ok_or_else(malformed))
+        .collect::<Result<Vec<_>, _>>()?;
+    if !attributes.iter().any(|a| a == key) {
+        return Err(malformed());
+    }
+    Ok((key.to_string(), attributes))
+}
+
+/// Params for a `create_table` call: the daemon takes the first attribute
+/// as the primary key, so `key` goes first and the rest keep their order.
+pub fn create_table_params(table: &str, key: &str, attributes: &[String]) -> Vec<Value> {
+    let attributes = std::iter::once(key)
+        .chain(attributes.iter().map(String::as_str).filter(|a| *a != key))
+        .map(Value::from)
+        .collect();
+    vec![Value::from(table), Value::Array(attributes)]
+}
+
+fn write_out(out: &mut impl Write, args: std::fmt::Arguments) -> Result<(), Error> {
+    out.write_fmt(args)
+        .map_err(|e| Error::Output(format!("write error: {}", e)))
+}
+
+/// A backup document, as written by [`backup`].
+#[derive(Debug, Deserialize)]
+pub struct Document {
+    pub cortex_backup: u64,
+    pub tables: Vec<TableDump>,
+}
+
+/// One table's schema and records.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TableDump {
+    pub table: String,
+    pub key: String,
+    pub attributes: Vec<String>,
+    pub records: Vec<serde_json::Value>,
+    /// Absent from backups of tables without grants, and from backups
+    /// made before grants were included
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub acls: Vec<Grant>,
+}
+
+/// Permissions granted on a table, as `acl_list` gives them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Grant {
+    pub identity: String,
+    pub permissions: Vec<String>,
+    /// Unix time the grant lapses at, if it does
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+impl TableDump {
+    /// Params for the `create_table` call that recreates this table.
+    fn create_params(&self) -> Vec<Value> {
+        create_table_params(&self.table, &self.key, &self.attributes)
+    }
+
+    /// Params for the `acl_grant` calls that restore this table's grants,
+    /// leaving out any that have lapsed by `now`.
+    fn grant_params(&self, now: u64) -> Vec<Vec<Value>> {
+        self.acls
+            .iter()
+            .filter(|grant| grant.expires_at.is_none_or(|at| at > now))
+            .map(|grant| {
+                let mut params = vec![
+                    Value::from(grant.identity.as_str()),
+                    Value::from(self.table.as_str()),
+                    Value::from(grant.permissions.join(",")),
+                ];
+                params.extend(grant.expires_at.map(Value::from));
+                params
+            })
+            .collect()
+    }
+}
+
+impl Document {
+    pub fn read(input: impl Read) -> Result<Self, Error> {
+        let doc: Document = serde_json::from_reader(input)
+            .map_err(|e| Error::Input(format!("invalid backup file: {}", e)))?;
+        if doc.cortex_backup != FORMAT_VERSION {
+            return Err(Error::Input(format!(
+                "unsupported backup version {} (expected {})",
+                doc.cortex_backup, FORMAT_VERSION
+            )));
+        }
+        for dump in &doc.tables {
+            if !dump.attributes.contains(&dump.key) {
+                return Err(Error::Input(format!(
+                    "invalid backup file: key '{}' of table '{}' is not one of its attributes",
+                    dump.key, dump.table
+                )));
+            }
+        }
+        Ok(doc)
+    }
+}
+
+/// What to do when a table in the backup already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Existing {
+    /// Refuse to restore anything; the error suggests the given flags
+    Fail(&'static str),
+    /// Leave the existing table alone
+    Skip,
+    /// Drop it and restore the backed-up copy
+    Drop,
+}
+
+/// Recreate each table in `doc` and re-insert its records.
+///
+/// Conflicts with existing tables are checked before anything is changed.
+/// Returns one `{table, action, records}` summary per table.
+pub fn restore(
+    conn: &mut Connection,
+    doc: &Document,
+    existing: Existing,
+) -> Result<Vec<serde_json::Value>, Error> {
+    restore_with(conn, &mut [], doc, existing, &Progress::hidden())
+}
+
+/// Like [`restore`], but each table's records are split between `conn` and
+/// the extra `workers` connections and written concurrently, and `progress`
+/// advances as records are acknowledged (or skipped).
+pub fn restore_with(
+    conn: &mut Connection,
+    workers: &mut [Connection],
+    doc: &Document,
+    existing: Existing,
+    progress: &Progress,
+) -> Result<Vec<serde_json::Value>, Error> {
+    let current: Vec<String> = match conn.call("tables", vec![])? {
+        Some(Value::Array(names)) => names
+            .iter()
+            .filter_map(|n| n.as_str().map(str::to_string))
+            .collect(),
+        _ => return Err(Error::Protocol("expected a list of tables".to_string())),
+    };
+    let exists = |name: &str| current.iter().any(|t| t == name);
+
+    if let Existing::Fail(hint) = existing {
+        if let Some(dump) = doc.tables.iter().find(|d| exists(&d.table)) {
+            return Err(Error::Input(format!(
+                "table '{}' already exists (use {})",
+                dump.table, hint
+            )));
+        }
+    }
+
+    let mut summary = Vec::new();
+    for dump in &doc.tables {
+        let table = Value::String(dump.table.clone().into());
+        let action = match (exists(&dump.table), existing) {
+            (true, Existing::Skip) => {
+                summary.push(serde_json::json!({
+                    "table": dump.table, "action": "skipped", "records": 0
+                }));
+                progress.add(dump.records.len());
+                continue;
+            }
+            (true, _) => {
+                conn.call("drop_table", vec![table.clone()])?;
+                "recreated"
+            }
+            (false, _) => "created",
+        };
+
+        conn.call("create_table", dump.create_params())?;
+
+        let mut conns: Vec<&mut Connection> = std::iter::once(&mut *conn)
+            .chain(workers.iter_mut())
+            .collect();
+        let records = put_parallel(&mut conns, &dump.table, &table, &dump.records, progress)?;
+        let mut entry = serde_json::json!({
+            "table": dump.table, "action": action, "records": records
+        });
+        if !dump.acls.is_empty() {
+            let grants = dump.grant_params(unix_now());
+            entry["acls"] = serde_json::json!(grants.len());
+            for params in grants {
+                conn.call("acl_grant", params)?;
+            }
+        }
+        summary.push(entry);
+    }
+    Ok(summary)
+}
+
+/// The requests a restore into an empty namespace would send, in order.
+pub fn restore_plan(doc: &Document) -> Vec<(&'static str, Vec<Value>)> {
+    let mut plan = Vec::new();
+    for dump in &doc.tables {
+        plan.push(("create_table", dump.create_params()));
+        let table = Value::String(dump.table.clone().into());
+        for record in &dump.records {
+            plan.push(("put", vec![table.clone(), json_to_msgpack(record)]));
+        }
+        for params in dump.grant_params(unix_now()) {
+            plan.push(("acl_grant", params));
+        }
+    }
+    plan
+}
+
+/// Write `records` over `conns` with [`in_parallel`], each worker
+/// pipelining its share with [`put_all`].
+///
+/// Every worker stops at its first error; see [`worker_error`] for what is
+/// returned if any failed.
+fn put_parallel(
+    conns: &mut [&mut Connection],
+    name: &str,
+    table: &Value,
+    records: &[serde_json::Value],
+    progress: &Progress,
+) -> Result<usize, Error> {
+    let outcomes = in_parallel(conns, records, |conn, share| {
+        let mut written = 0;
+        let result = put_all(conn, table, share, &mut written, progress);
+        (written, result)
+    });
+    let written = outcomes.iter().map(|(n, _)| n).sum();
+    let errors = outcomes.into_iter().filter_map(|(_, r)| r.err());
+    match worker_error(errors, conns.len(), written, records.len(), name) {
+        Some(e) => Err(e),
+        None => Ok(written),
+    }
+}
+
+/// Give each connection a contiguous share of `items` and run `work` on the
+/// shares concurrently, one thread per connection, giving each share's
+/// result in order. A single connection does its work on this thread.
+pub fn in_parallel<T: Sync, R: Send>(
+    conns: &mut [&mut Connection],
+    items: &[T],
+    work: impl Fn(&mut Connection, &[T]) -> R + Sync,
+) -> Vec<R> {
+    if let [conn] = conns {
+        return vec![work(conn, items)];
+    }
+
+    let share = items.len().div_ceil(conns.len()).max(1);
+    let work = &work;
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = conns
+            .iter_mut()
+            .zip(items.chunks(share))
+            .map(|(conn, chunk)| scope.spawn(move || work(conn, chunk)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().expect("write worker panicked"))
+            .collect()
+    })
+}
+
+/// The first of the errors `workers` parallel writers stopped at, if any.
+/// With more than one worker it notes how many failed, any other distinct
+/// errors, and how many of the `records` were written to table `name`.
+pub fn worker_error(
+    errors: impl IntoIterator<Item = Error>,
+    workers: usize,
+    written: usize,
+    records: usize,
+    name: &str,
+) -> Option<Error> {
+    let mut errors = errors.into_iter();
+    let first = errors.next()?;
+    if workers == 1 {
+        return Some(first);
+    }
+
+    let mut others: Vec<String> = Vec::new();
+    let mut failed = 1;
+    for e in errors {
+        failed += 1;
+        let message = e.to_string();
+        if message != first.to_string() && !others.contains(&message) {
+            others.push(message);
+        }
+    }
+    let mut note = format!(
+        "{} of {} workers failed; {} of {} records written to '{}'",
+        failed, workers, written, records, name
+    );
+    if !others.is_empty() {
+        note += &format!("; also: {}", others.join(", "));
+    }
+    Some(first.annotate(&note))
+}
+
+/// Pipeline `put`s in batches: send a batch, then collect its responses.
+/// `written` counts the puts the daemon acknowledged, even on failure.
+fn put_all(
+    conn: &mut Connection,
+    table: &Value,
+    records: &[serde_json::Value],
+    written: &mut usize,
+    progress: &Progress,
+) -> Result<(), Error> {
+    for batch in records.chunks(PUT_BATCH) {
+        let mut ids = Vec::with_capacity(batch.len());
+        for record in batch {
+            ids.push(conn.send("put", vec![table.clone(), json_to_msgpack(record)])?);
+        }
+        for id in ids {
+            let response = conn.recv()?;
+            if response[1].as_u64() != Some(u64::from(id)) {
+                return Err(Error::Protocol(format!(
+                    "response out of order: expected msgid {}",
+                    id
+                )));
+            }
+            connection::decode_response(response)?;
+            *written += 1;
+            progress.add(1);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,110 @@
+//! `acl`: granting, revoking, listing, and checking table permissions.
+
+use crate::error::Error;
+use crate::{call, msgpack_to_json, parse_duration, unix_now, AclCommands, Cli};
+use rmpv::Value;
+
+pub fn run(cli: &Cli, command: &AclCommands) -> Result<Option<Value>, Error> {
+    match command {
+        AclCommands::Grant {
+            identity,
+            table,
+            perms,
+            expires,
+        } => {
+            validate_identity(identity)?;
+            let mut params = vec![
+                Value::String(identity.clone().into()),
+                Value::String(table.clone().into()),
+                Value::String(perms.clone().into()),
+            ];
+            if let Some(expires) = expires {
+                let expires_at = unix_now() + parse_duration(expires)?;
+                params.push(Value::Integer(expires_at.into()));
+            }
+            call(cli, "acl_grant", params)
+        }
+        AclCommands::Revoke {
+            identity,
+            table,
+            perms,
+        } => {
+            validate_identity(identity)?;
+            call(
+                cli,
+                "acl_revoke",
+                vec![
+                    Value::String(identity.clone().into()),
+                    Value::String(table.clone().into()),
+                    Value::String(perms.clone().into()),
+                ],
+            )
+        }
+        AclCommands::List { table, identity } => {
+            let mut filters = Vec::new();
+            if let Some(table) = table {
+                filters.push((Value::from("table"), Value::from(table.as_str())));
+            }
+            if let Some(identity) = identity {
+                validate_identity(identity)?;
+                filters.push((Value::from("identity"), Value::from(identity.as_str())));
+            }
+            if filters.is_empty() {
+                return call(cli, "acl_list", vec![]);
+            }
+
+            // Daemons that ignore the filters return every grant
+            let acls = call(cli, "acl_list", vec![Value::Map(filters)])?;
+            Ok(acls.map(|acls| filter_acls(acls, table.as_deref(), identity.as_deref())))
+        }
+        AclCommands::Check { identity, table } => {
+            validate_identity(identity)?;
+            call(
+                cli,
+                "acl_check",
+                vec![
+                    Value::String(identity.clone().into()),
+                    Value::String(table.clone().into()),
+                ],
+            )
+        }
+    }
+}
+
+/// Check an ACL identity is `uid:NUMBER`, `gid:NUMBER`, or `*` before sending it.
+pub fn validate_identity(identity: &str) -> Result<(), Error> {
+    if identity == "*" {
+        return Ok(());
+    }
+
+    let id = identity
+        .strip_prefix("uid:")
+        .or_else(|| identity.strip_prefix("gid:"));
+
+    match id {
+        Some(n) if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => Ok(()),
+        _ => Err(Error::Input(format!(
+            "invalid identity '{}': expected uid:NUMBER, gid:NUMBER, or *",
+            identity
+        ))),
+    }
+}
+
+/// Keep the `acl_list` entries on `table` (owned by anyone, so `users`
+/// also matches `1000:users`) and granted to `identity`.
+pub fn filter_acls(acls: Value, table: Option<&str>, identity: Option<&str>) -> Value {
+    let Value::Array(acls) = acls else {
+        return acls;
+    };
+    let listed = |acl: &Value| {
+        let json = msgpack_to_json(acl);
+        let table_listed = table.is_none_or(|name| {
+            json["table"].as_str().is_some_and(|t| {
+                t == name || t.rsplit_once(':').is_some_and(|(_, short)| short == name)
+            })
+        });
+        let identity_listed = identity.is_none_or(|id| json["identity"] == id);
+        table_listed && identity_listed
+    };
+    Value::Array(acls.into_iter().filter(listed).collect())
+}
@@ -0,0 +1,160 @@
+//! `raw` and `batch`: sending requests as written, and running a script of
+//! commands over one connection.
+
+use crate::connection::Connection;
+use crate::error::Error;
+use crate::{call, input_to_msgpack, open, read_input_file, Cli, Commands, OutputFormat};
+use clap::Parser;
+use rmpv::Value;
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+
+pub fn run(cli: &Cli, command: &Commands) -> Result<Option<Value>, Error> {
+    match command {
+        Commands::Raw {
+            method,
+            params,
+            params_file,
+        } => {
+            let params = match params_file {
+                Some(path) => read_input_file(path, &mut io::stdin().lock())?,
+                None => params.clone().unwrap_or_else(|| "[]".to_string()),
+            };
+            call(cli, method, parse_raw_params(&params)?)
+        }
+        Commands::Batch { input, fail_fast } => {
+            let script = read_input_file(input, &mut io::stdin().lock())?;
+            let shared = Rc::new(RefCell::new(open(cli)?));
+            Ok(Some(run_batch(cli, &shared, &script, *fail_fast)))
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Run each line of `script` (blank lines and `#` comments aside) as a
+/// cortex command over `shared`, giving `{"ok": result}` or
+/// `{"error": message, "code": category}` for each in order. With
+/// `fail_fast` nothing runs after the first failure.
+pub fn run_batch(
+    cli: &Cli,
+    shared: &Rc<RefCell<Connection>>,
+    script: &str,
+    fail_fast: bool,
+) -> Value {
+    let mut results = Vec::new();
+    let lines = script
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'));
+    for line in lines {
+        let result = batch_command(cli, shared, line).and_then(|inner| crate::run(&inner));
+        let failed = result.is_err();
+        results.push(Value::Map(match result {
+            Ok(value) => vec![(Value::from("ok"), value.unwrap_or(Value::Nil))],
+            Err(e) => vec![
+                (Value::from("error"), Value::from(e.to_string())),
+                (Value::from("code"), Value::from(e.code())),
+            ],
+        }));
+        if failed && fail_fast {
+            break;
+        }
+    }
+    Value::Array(results)
+}
+
+/// Parse one batch line into a command that shares the batch's connection
+/// and connection settings.
+fn batch_command(outer: &Cli, shared: &Rc<RefCell<Connection>>, line: &str) -> Result<Cli, Error> {
+    let args = split_command_line(line)?;
+    let mut cli =
+        Cli::try_parse_from(std::iter::once("cortex".to_string()).chain(args)).map_err(|e| {
+            let message = e.to_string();
+            let first = message.lines().next().unwrap_or_default();
+            Error::Input(first.trim_start_matches("error: ").to_string())
+        })?;
+
+    // Anything that prints as it goes would land in the middle of the
+    // result document
+    if matches!(
+        cli.command,
+        None | Some(Commands::Batch { .. })
+            | Some(Commands::Watch { .. })
+            | Some(Commands::Completions { .. })
+            | Some(Commands::Backup { file: None })
+            | Some(Commands::All {
+                page_size: Some(_),
+                ..
+            })
+    ) || matches!(&cli.command, Some(Commands::Export { file, .. }) if file.as_os_str() == "-")
+        || cli.output == Some(OutputFormat::Ndjson)
+    {
+        return Err(Error::Input(format!("'{}' can't run in a batch", line)));
+    }
+
+    cli.socket = cli.socket.or_else(|| outer.socket.clone());
+    cli.timeout = cli.timeout.or_else(|| outer.timeout.clone());
+    cli.retry = cli.retry.or(outer.retry);
+    cli.retry_on_busy = cli.retry_on_busy.or(outer.retry_on_busy);
+    if cli.busy_pattern.is_empty() {
+        cli.busy_pattern = outer.busy_pattern.clone();
+    }
+    cli.no_handshake |= outer.no_handshake;
+    cli.verbose |= outer.verbose;
+    cli.dry_run |= outer.dry_run;
+    cli.shared = Some(Rc::clone(shared));
+    Ok(cli)
+}
+
+/// Split a batch line into arguments as a shell would: on whitespace,
+/// keeping 'single-quoted' text as-is and honoring backslash escapes
+/// outside quotes and before `"` or `\` inside "double quotes".
+pub fn split_command_line(line: &str) -> Result<Vec<String>, Error> {
+    let unterminated = || Error::Input(format!("unterminated quote in '{}'", line));
+    let mut args = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            args.extend(word.take());
+            continue;
+        }
+        let current = word.get_or_insert_with(String::new);
+        match c {
+            '\'' => loop {
+                match chars.next().ok_or_else(unterminated)? {
+                    '\'' => break,
+                    c => current.push(c),
+                }
+            },
+            '"' => loop {
+                match chars.next().ok_or_else(unterminated)? {
+                    '"' => break,
+                    '\\' => match chars.next().ok_or_else(unterminated)? {
+                        c @ ('"' | '\\') => current.push(c),
+                        c => {
+                            current.push('\\');
+                            current.push(c);
+                        }
+                    },
+                    c => current.push(c),
+                }
+            },
+            '\\' => current.extend(chars.next()),
+            c => current.push(c),
+        }
+    }
+    args.extend(word);
+    Ok(args)
+}
+
+/// Parse the JSON array given to `raw` into RPC params.
+pub fn parse_raw_params(params: &str) -> Result<Vec<Value>, Error> {
+    match serde_json::from_str(params) {
+        Ok(serde_json::Value::Array(items)) => items.iter().map(input_to_msgpack).collect(),
+        Ok(_) => Err(Error::Input("params must be a JSON array".to_string())),
+        Err(e) => Err(Error::Input(format!("invalid JSON params: {}", e))),
+    }
+}
@@ -0,0 +1,177 @@
+//! The command handlers behind [`crate::run`], one module per group of
+//! commands, and the helpers several groups share.
+
+pub mod acl;
+pub mod batch;
+pub mod queries;
+pub mod records;
+pub mod server;
+pub mod tables;
+pub mod transfer;
+pub mod watch;
+
+use crate::connection::{self, Connection};
+use crate::error::Error;
+use crate::{call, completions, help, project, Cli, Commands};
+use rmpv::Value;
+
+/// Largest record `put` sends without `--max-value-size`. The daemon drops
+/// connections whose request outgrows 1 MiB, so this leaves room for the
+/// rest of the request.
+const DEFAULT_MAX_VALUE_SIZE: u64 = 1_000_000;
+
+/// Requests sent before reading their responses when pipelining, as for
+/// `--keys-file`.
+const KEYS_BATCH: usize = 100;
+
+/// Run `command`, handing it to the module for its group.
+pub fn run(cli: &Cli, command: &Commands) -> Result<Option<Value>, Error> {
+    match command {
+        Commands::Ping { .. }
+        | Commands::WaitReady
+        | Commands::Status
+        | Commands::Health
+        | Commands::Version
+        | Commands::Whoami => server::run(cli, command),
+        Commands::Tables { .. }
+        | Commands::CreateTable { .. }
+        | Commands::DropTable { .. }
+        | Commands::CreateIndex { .. }
+        | Commands::DropIndex { .. }
+        | Commands::Truncate { .. }
+        | Commands::Describe { .. }
+        | Commands::CopyTable { .. }
+        | Commands::Migrate { .. } => tables::run(cli, command),
+        Commands::Get { .. }
+        | Commands::Exists { .. }
+        | Commands::Put { .. }
+        | Commands::PutMany { .. }
+        | Commands::Txn { .. }
+        | Commands::Validate { .. }
+        | Commands::Expire { .. }
+        | Commands::Append { .. }
+        | Commands::Incr { .. }
+        | Commands::Decr { .. }
+        | Commands::Delete { .. } => records::run(cli, command),
+        Commands::Query { .. }
+        | Commands::All { .. }
+        | Commands::Diff { .. }
+        | Commands::Aggregate { .. }
+        | Commands::Count { .. }
+        | Commands::Range { .. }
+        | Commands::Keys { .. } => queries::run(cli, command),
+        Commands::Watch { .. } => watch::run(cli, command),
+        Commands::Backup { .. }
+        | Commands::Export { .. }
+        | Commands::Import { .. }
+        | Commands::Restore { .. } => transfer::run(cli, command),
+        Commands::Acl { command } => acl::run(cli, command),
+        Commands::Raw { .. } | Commands::Batch { .. } => batch::run(cli, command),
+        Commands::Completions { shell } => {
+            completions::write_script(*shell, &mut std::io::stdout().lock())
+                .map_err(|e| Error::Output(format!("write error: {}", e)))?;
+            Ok(None)
+        }
+        Commands::HelpTopic { topic } => {
+            help::print_topic_help(topic.as_deref());
+            Ok(None)
+        }
+    }
+}
+
+/// Records given as one JSON array, or as JSON values one after another
+/// (such as one object per line).
+pub fn parse_records(text: &str) -> Result<Vec<serde_json::Value>, serde_json::Error> {
+    let mut records = Vec::new();
+    for value in serde_json::Deserializer::from_str(text).into_iter() {
+        match value? {
+            serde_json::Value::Array(items) => records.extend(items),
+            record => records.push(record),
+        }
+    }
+    Ok(records)
+}
+
+/// Refuse a record whose MessagePack encoding is over `--max-value-size`,
+/// rather than have the daemon drop the connection partway through it.
+/// Gives the encoded size otherwise.
+fn check_value_size(cli: &Cli, record: &Value) -> Result<u64, Error> {
+    let limit = cli.max_value_size.unwrap_or(DEFAULT_MAX_VALUE_SIZE);
+    let mut encoded = Vec::new();
+    rmpv::encode::write_value(&mut encoded, record)
+        .map_err(|e| Error::Input(format!("cannot encode record: {}", e)))?;
+    if encoded.len() as u64 > limit {
+        return Err(Error::Input(format!(
+            "record is {} bytes encoded, over the {} byte limit (raise it with --max-value-size)",
+            encoded.len(),
+            limit
+        )));
+    }
+    Ok(encoded.len() as u64)
+}
+
+/// Send one `method` request per entry of `params` over `conn`, in batches
+/// of [`KEYS_BATCH`] without waiting for each response, giving each
+/// request's result in order.
+fn pipeline(
+    conn: &mut Connection,
+    method: &str,
+    params: impl IntoIterator<Item = Vec<Value>>,
+) -> Result<Vec<Result<Option<Value>, Error>>, Error> {
+    let mut results = Vec::new();
+    let mut params = params.into_iter().peekable();
+    while params.peek().is_some() {
+        let ids = params
+            .by_ref()
+            .take(KEYS_BATCH)
+            .map(|params| conn.send(method, params))
+            .collect::<Result<Vec<_>, Error>>()?;
+        for id in ids {
+            let response = conn.recv()?;
+            if response[1].as_u64() != Some(u64::from(id)) {
+                return Err(Error::Protocol(format!(
+                    "response out of order: expected msgid {}",
+                    id
+                )));
+            }
+            results.push(connection::decode_response(response));
+        }
+    }
+    Ok(results)
+}
+
+/// Call a read method, asking the daemon to project records down to
+/// `fields` and re-applying the projection locally to fix the field order.
+fn call_projected(
+    cli: &Cli,
+    method: &str,
+    mut params: Vec<Value>,
+    fields: Option<&str>,
+) -> Result<Option<Value>, Error> {
+    let Some(fields) = fields else {
+        return call(cli, method, params);
+    };
+
+    let fields = parse_fields(fields);
+    params.push(fields_param(&fields));
+
+    let result = call(cli, method, params)?;
+    Ok(result.map(|value| project(value, &fields)))
+}
+
+pub fn parse_fields(fields: &str) -> Vec<String> {
+    fields
+        .split(',')
+        .map(|f| f.trim().to_string())
+        .filter(|f| !f.is_empty())
+        .collect()
+}
+
+pub fn fields_param(fields: &[String]) -> Value {
+    Value::Array(
+        fields
+            .iter()
+            .map(|f| Value::String(f.clone().into()))
+            .collect(),
+    )
+}
@@ -0,0 +1,473 @@
+//! Reading records in bulk: `query`, `all`, `count`, `keys`, `range`,
+//! `aggregate`, and `diff`.
+
+use super::{call_projected, fields_param, parse_fields};
+use crate::connection::Connection;
+use crate::error::Error;
+use crate::{
+    aggregate, backup, call, connect, diff, export, json_to_msgpack, msgpack_to_json, print_text,
+    project, query, read_json_arg, write_json_line, Cli, Commands, ExtractArgs, OutputFormat,
+    SortArgs,
+};
+use rmpv::Value;
+use std::io::{self, Write};
+
+pub fn run(cli: &Cli, command: &Commands) -> Result<Option<Value>, Error> {
+    match command {
+        Commands::Query {
+            table,
+            pattern,
+            file,
+            fields,
+            sort,
+            time,
+            extract,
+            cursor,
+        } => {
+            let pat = read_json_arg(pattern.as_deref(), file.as_deref(), "JSON pattern")?;
+            let mut pattern = query::Pattern::new(json_to_msgpack(&pat))?;
+            if let (Some(cursor), Some(limit)) = (cursor, sort.limit) {
+                if pattern.is_nested() || pattern.conditions_param().is_some() {
+                    return Err(Error::Input(
+                        "--cursor works with top-level field patterns only, \
+                         not nested objects or $ operators"
+                            .to_string(),
+                    ));
+                }
+                let params = vec![Value::from(table.as_str()), pattern.server];
+                return cursor_page(cli, "match", params, None, cursor, limit, fields.as_deref());
+            }
+            time.apply(&mut pattern)?;
+            match_records(cli, table, pattern, fields.as_deref(), sort, Some(extract))
+        }
+        Commands::All {
+            table,
+            fields,
+            sort: SortArgs {
+                limit: Some(limit), ..
+            },
+            cursor: Some(cursor),
+            ..
+        } => {
+            let params = vec![Value::from(table.as_str())];
+            cursor_page(cli, "all", params, None, cursor, *limit, fields.as_deref())
+        }
+        Commands::All {
+            table,
+            fields,
+            sort,
+            time,
+            page_size: None,
+            ..
+        } if time.is_set() || sort.server_conditions(true).is_some() => {
+            let mut pattern = query::Pattern::new(Value::Map(Vec::new()))?;
+            time.apply(&mut pattern)?;
+            match_records(cli, table, pattern, fields.as_deref(), sort, None)
+        }
+        Commands::All {
+            table,
+            fields,
+            sort,
+            page_size: None,
+            ..
+        } => list_records(
+            cli,
+            "all",
+            vec![Value::String(table.clone().into())],
+            fields.as_deref(),
+            sort,
+            None,
+        ),
+        Commands::All {
+            table,
+            fields,
+            page_size: Some(page_size),
+            ..
+        } => {
+            let conn = &mut connect(cli)?;
+            let (fields, color) = (fields.as_deref(), cli.stdout_color());
+            if cli.quiet {
+                page_records(conn, table, *page_size, fields, color, &mut io::sink())?;
+            } else {
+                let out = &mut io::stdout().lock();
+                page_records(conn, table, *page_size, fields, color, out)?;
+            }
+            Ok(None)
+        }
+        Commands::Diff { left, right } => {
+            let conn = &mut connect(cli)?;
+            let left = backup::dump_table(conn, left, false)?;
+            let right = backup::dump_table(conn, right, false)?;
+            if left.key != right.key {
+                return Err(Error::Input(format!(
+                    "tables have different primary keys ('{}' and '{}')",
+                    left.key, right.key
+                )));
+            }
+
+            let result = diff::diff(&left.key, &left.records, &right.records);
+            if cli.output == Some(OutputFormat::Json) {
+                return Ok(Some(json_to_msgpack(&result)));
+            }
+            print_text(cli, &diff::render(&result));
+            Ok(None)
+        }
+        Commands::Aggregate {
+            table,
+            op,
+            group_by,
+        } => {
+            let op = op.op();
+            // Only fetch the fields the aggregate reads
+            let fields: Vec<String> = op
+                .field()
+                .into_iter()
+                .chain(group_by.as_deref())
+                .map(str::to_string)
+                .collect();
+            let mut params = vec![Value::String(table.clone().into())];
+            if !fields.is_empty() {
+                params.push(fields_param(&fields));
+            }
+
+            let records = match call(cli, "all", params)? {
+                Some(Value::Array(records)) => records,
+                _ => return Err(Error::Protocol("expected a list of records".to_string())),
+            };
+            let records: Vec<_> = records.iter().map(msgpack_to_json).collect();
+            let outcome = aggregate::aggregate(&records, &op, group_by.as_deref());
+            if outcome.skipped > 0 && !cli.quiet {
+                eprintln!(
+                    "skipped {} records with a missing or non-numeric field",
+                    outcome.skipped
+                );
+            }
+            Ok(Some(json_to_msgpack(&outcome.value)))
+        }
+        Commands::Count { table, pattern } => {
+            let pattern = match pattern {
+                Some(pattern) => read_json_arg(Some(pattern), None, "JSON pattern")?,
+                None => serde_json::json!({}),
+            };
+            count_records(cli, table, query::Pattern::new(json_to_msgpack(&pattern))?)
+        }
+        Commands::Range {
+            table,
+            from,
+            to,
+            limit,
+        } => {
+            let bounds = [
+                ("from", from.as_deref().map(Value::from)),
+                ("to", to.as_deref().map(Value::from)),
+                ("limit", limit.map(Value::from)),
+            ]
+            .into_iter()
+            .filter_map(|(name, value)| Some((Value::from(name), value?)))
+            .collect();
+            let params = vec![Value::from(table.as_str()), Value::Map(bounds)];
+            call(cli, "range", params)
+        }
+        Commands::Keys {
+            table,
+            prefix,
+            limit: Some(limit),
+            cursor: Some(cursor),
+        } => {
+            let limit = u32::try_from(*limit).unwrap_or(u32::MAX);
+            let params = vec![Value::from(table.as_str())];
+            let prefix = prefix
+                .as_deref()
+                .map(|prefix| (Value::from("prefix"), Value::from(prefix)));
+            cursor_page(cli, "keys", params, prefix, cursor, limit, None)
+        }
+        Commands::Keys {
+            table,
+            prefix,
+            limit,
+            ..
+        } => {
+            let table = Value::String(table.clone().into());
+            let keys = match prefix {
+                Some(prefix) => {
+                    let options = Value::Map(vec![(
+                        Value::String("prefix".into()),
+                        Value::String(prefix.clone().into()),
+                    )]);
+                    let conn = &mut connect(cli)?;
+                    let keys = match conn.call("keys", vec![table.clone(), options]) {
+                        // Daemons without prefix support: filter the full list here
+                        Err(e) if e.is_unknown_method() => conn.call("keys", vec![table])?,
+                        result => result?,
+                    };
+                    keys.map(|keys| filter_prefix(keys, prefix))
+                }
+                None => call(cli, "keys", vec![table])?,
+            };
+            Ok(match (keys, limit) {
+                (Some(Value::Array(mut keys)), Some(limit)) => {
+                    keys.truncate(*limit);
+                    Some(Value::Array(keys))
+                }
+                (keys, _) => keys,
+            })
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Keep the string keys in a `keys` result that start with `prefix`.
+fn filter_prefix(keys: Value, prefix: &str) -> Value {
+    match keys {
+        Value::Array(keys) => Value::Array(
+            keys.into_iter()
+                .filter(|k| k.as_str().is_some_and(|k| k.starts_with(prefix)))
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+/// One page of `method` results after `cursor` (from the start if empty),
+/// as `{"records": [...], "cursor": next}` (`"keys"` for `keys`), with
+/// records projected onto `fields`. `next` is null on the last page.
+/// `option` goes to the daemon alongside the cursor and limit.
+fn cursor_page(
+    cli: &Cli,
+    method: &str,
+    mut params: Vec<Value>,
+    option: Option<(Value, Value)>,
+    cursor: &str,
+    limit: u32,
+    fields: Option<&str>,
+) -> Result<Option<Value>, Error> {
+    let cursor = match cursor {
+        "" => Value::Nil,
+        cursor => Value::from(cursor),
+    };
+    let mut paging = vec![
+        (Value::from("cursor"), cursor),
+        (Value::from("limit"), Value::from(limit)),
+    ];
+    paging.extend(option);
+    params.push(Value::Map(paging));
+    let page = call(cli, method, params)?;
+    let Some(fields) = fields.map(parse_fields) else {
+        return Ok(page);
+    };
+    Ok(page.map(|page| match page {
+        Value::Map(entries) => Value::Map(
+            entries
+                .into_iter()
+                .map(|(k, v)| match k.as_str() {
+                    Some("records") => (k, project(v, &fields)),
+                    _ => (k, v),
+                })
+                .collect(),
+        ),
+        other => other,
+    }))
+}
+
+/// Fetch the records of `table` matching `pattern` for `query` (or `all`
+/// with time bounds), filtering here whatever the daemon can't.
+fn match_records(
+    cli: &Cli,
+    table: &str,
+    mut pattern: query::Pattern,
+    fields: Option<&str>,
+    sort: &SortArgs,
+    extract: Option<&ExtractArgs>,
+) -> Result<Option<Value>, Error> {
+    let table = Value::String(table.into());
+    let mut conditions = pattern.conditions_param();
+    // The daemon can't apply --limit when part of the pattern is checked
+    // here: it would cut the list before the records that match
+    if let Some(order) = sort.server_conditions(!pattern.is_nested()) {
+        match conditions.get_or_insert_with(|| Value::Map(Vec::new())) {
+            Value::Map(entries) => entries.extend(order),
+            _ => unreachable!("conditions are a map"),
+        }
+    }
+    if !pattern.is_nested() && conditions.is_none() {
+        let params = vec![table, pattern.server];
+        return list_records(cli, "match", params, fields, sort, extract);
+    }
+
+    // Fetch whole records: a projection could drop the nested fields
+    // that still need checking
+    sort.check_fields(fields)?;
+    let records = match conditions {
+        Some(conditions) => {
+            let conn = &mut connect(cli)?;
+            let params = vec![table.clone(), pattern.server.clone(), conditions];
+            match conn.call("match", params) {
+                Err(e) if e.is_unknown_method() && sort.server_sort => {
+                    return Err(e.annotate("this daemon can't sort; drop --server-sort"));
+                }
+                // Daemons without regex, range, or sort support: match the
+                // rest of the pattern there and the conditions here
+                Err(e) if e.is_unknown_method() => {
+                    pattern.evaluate_conditions_locally();
+                    conn.call("match", vec![table, pattern.server.clone()])?
+                }
+                result => result?,
+            }
+        }
+        None => call(cli, "match", vec![table, pattern.server.clone()])?,
+    };
+    let records = records.map(|records| pattern.filter(records));
+    let records = match fields {
+        Some(fields) => records.map(|r| project(r, &parse_fields(fields))),
+        None => records,
+    };
+    records
+        .map(|r| sort.apply(r).and_then(|r| extract_each(extract, r)))
+        .transpose()
+}
+
+/// How many records in `table` match `pattern`, counted on the daemon.
+/// Nested fields can only be checked here, so for those patterns (and for
+/// daemons without `count`) the matches are fetched and counted instead.
+fn count_records(cli: &Cli, table: &str, pattern: query::Pattern) -> Result<Option<Value>, Error> {
+    if !pattern.has_nested_fields() {
+        let mut params = vec![Value::from(table), pattern.server.clone()];
+        params.extend(pattern.conditions_param());
+        match call(cli, "count", params) {
+            Err(e) if e.is_unknown_method() => {}
+            result => return result,
+        }
+    }
+    // A pattern with conditions always takes the path through match_records
+    // that collects the records rather than streaming them
+    let records = if pattern.is_nested() || pattern.conditions_param().is_some() {
+        match_records(cli, table, pattern, None, &SortArgs::default(), None)?
+    } else {
+        call(cli, "match", vec![Value::from(table), pattern.server])?
+    };
+    match records {
+        Some(Value::Array(records)) => Ok(Some(Value::from(records.len()))),
+        _ => Err(Error::Protocol("expected a list of records".to_string())),
+    }
+}
+
+/// Fetch records for `all` and `query`. Under `--output ndjson` records are
+/// printed as they are decoded instead of being collected first, unless a
+/// sort needs the whole list.
+fn list_records(
+    cli: &Cli,
+    method: &str,
+    params: Vec<Value>,
+    fields: Option<&str>,
+    sort: &SortArgs,
+    extract: Option<&ExtractArgs>,
+) -> Result<Option<Value>, Error> {
+    sort.check_fields(fields)?;
+
+    let unordered = sort.sort_by.is_none() && sort.limit.is_none();
+    if cli.output == Some(OutputFormat::Ndjson) && unordered && !cli.quiet {
+        let out = &mut io::stdout().lock();
+        stream_records(
+            &mut *connect(cli)?,
+            method,
+            params,
+            fields,
+            extract,
+            cli.stdout_color(),
+            out,
+        )?;
+        return Ok(None);
+    }
+
+    let records = call_projected(cli, method, params, fields)?;
+    records
+        .map(|r| sort.apply(r).and_then(|r| extract_each(extract, r)))
+        .transpose()
+}
+
+/// Apply `--extract` to every record of a list result, dropping those it
+/// may skip.
+fn extract_each(extract: Option<&ExtractArgs>, records: Value) -> Result<Value, Error> {
+    match (extract, records) {
+        (Some(extract), Value::Array(records)) => {
+            let mut values = Vec::with_capacity(records.len());
+            for (i, record) in records.into_iter().enumerate() {
+                values.extend(extract.one(record, &format!("result {}", i + 1))?);
+            }
+            Ok(Value::Array(values))
+        }
+        (_, records) => Ok(records),
+    }
+}
+
+/// Print each record of a list result as its own JSON line, flushing as
+/// it goes, so memory use doesn't grow with the size of the table.
+pub fn stream_records(
+    conn: &mut Connection,
+    method: &str,
+    mut params: Vec<Value>,
+    fields: Option<&str>,
+    extract: Option<&ExtractArgs>,
+    color: bool,
+    out: &mut impl Write,
+) -> Result<usize, Error> {
+    let fields = fields.map(parse_fields);
+    if let Some(fields) = &fields {
+        params.push(fields_param(fields));
+    }
+
+    let mut count = 0;
+    conn.call_each(method, params, |record| {
+        let record = match &fields {
+            Some(fields) => project(record, fields),
+            None => record,
+        };
+        count += 1;
+        let record = match extract {
+            Some(extract) => match extract.one(record, &format!("result {}", count))? {
+                Some(value) => value,
+                None => return Ok(()),
+            },
+            None => record,
+        };
+        write_json_line(out, &record, color, "\n")
+    })?;
+    Ok(count)
+}
+
+/// Page through `all` over one connection, `page_size` records per request,
+/// printing each record as a JSON line. Each page starts after the last key
+/// of the one before, until the daemon gives no cursor for the next.
+pub fn page_records(
+    conn: &mut Connection,
+    table: &str,
+    page_size: u32,
+    fields: Option<&str>,
+    color: bool,
+    out: &mut impl Write,
+) -> Result<usize, Error> {
+    let fields = fields.map(parse_fields);
+    let mut cursor = Value::Nil;
+    let mut count = 0;
+    loop {
+        let paging = Value::Map(vec![
+            (Value::from("cursor"), cursor),
+            (Value::from("limit"), Value::from(page_size)),
+        ]);
+        let mut params = vec![Value::from(table), paging];
+        params.extend(fields.as_deref().map(fields_param));
+        let (records, next) = export::split_page(table, conn.call("all", params)?)?;
+        count += records.len();
+        for record in records {
+            let record = match &fields {
+                Some(fields) => project(record, fields),
+                None => record,
+            };
+            write_json_line(out, &record, color, "\n")?;
+        }
+        match next {
+            Value::Nil => return Ok(count),
+            next => cursor = next,
+        }
+    }
+}
@@ -0,0 +1,872 @@
+//! Reading and writing single records, or a few by key: `get`, `exists`,
+//! `put`, `put-many`, `txn`, `validate`, `append`, `incr`, `decr`, `expire`,
+//! and `delete`.
+
+use super::{
+    call_projected, check_value_size, fields_param, parse_fields, parse_records, pipeline,
+    DEFAULT_MAX_VALUE_SIZE,
+};
+use crate::connection::{self, Connection};
+use crate::error::Error;
+use crate::{
+    backup, call, confirm, connect, diff, dry_run_request, finish, input_to_msgpack,
+    json_to_msgpack, msgpack_to_json, open, parse_duration, parse_key, project, read_input_file,
+    read_json_arg, read_json_values, schema, validate_name, Cli, Commands, KeyType,
+    MUTATING_METHODS,
+};
+use cortex_client::Op;
+use rmpv::Value;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::Path;
+
+pub fn run(cli: &Cli, command: &Commands) -> Result<Option<Value>, Error> {
+    match command {
+        Commands::Get {
+            table,
+            keys_file: Some(path),
+            key_type,
+            fields,
+            ..
+        } => {
+            let keys = read_keys_file(path)?;
+            call_keys(cli, "get", table, &keys, *key_type, fields.as_deref()).map(Some)
+        }
+        Commands::Get {
+            table,
+            key,
+            key_type,
+            fields,
+            default,
+            assert_eq,
+            assert_field,
+            extract,
+            raw_string,
+            with_ttl,
+            ..
+        } => {
+            let key = key
+                .as_deref()
+                .ok_or_else(|| Error::Input("missing key".to_string()))?;
+            let key_param = parse_key(key, *key_type)?;
+            let output = |value| {
+                if *raw_string {
+                    print_raw_string(cli, value, &mut io::stdout().lock(), &mut io::stderr())
+                } else if *with_ttl {
+                    with_remaining_ttl(cli, table, key_param.clone(), value)
+                } else {
+                    Ok(value)
+                }
+            };
+            let default = match default {
+                Some(json) => Some(
+                    serde_json::from_str::<serde_json::Value>(json)
+                        .map_err(|e| Error::Input(format!("invalid --default JSON: {}", e)))?,
+                ),
+                None => None,
+            };
+            let expected = match assert_eq {
+                Some(json) => Some(
+                    serde_json::from_str::<serde_json::Value>(json)
+                        .map_err(|e| Error::Input(format!("invalid --assert-eq JSON: {}", e)))?,
+                ),
+                None => None,
+            };
+            let field_checks = assert_field
+                .iter()
+                .map(|check| parse_field_assertion(check))
+                .collect::<Result<Vec<_>, Error>>()?;
+            let result = call_projected(
+                cli,
+                "get",
+                vec![Value::String(table.clone().into()), key_param.clone()],
+                fields.as_deref(),
+            );
+            let result = match (result, default) {
+                (Err(e), Some(default)) if e.reason() == Some("not_found") => {
+                    Ok(Some(json_to_msgpack(&default)))
+                }
+                (Ok(None | Some(Value::Nil)), Some(default)) => Ok(Some(json_to_msgpack(&default))),
+                (result, _) => result,
+            };
+            if expected.is_none() && field_checks.is_empty() {
+                let value = match result? {
+                    Some(record) if !record.is_nil() => {
+                        let which = format!("record '{}'", key);
+                        Some(extract.one(record, &which)?.unwrap_or(Value::Nil))
+                    }
+                    value => value,
+                };
+                return output(value);
+            }
+
+            let record = match result {
+                Ok(None | Some(Value::Nil)) => None,
+                Err(e) if e.reason() == Some("not_found") => None,
+                Ok(Some(record)) => Some(record),
+                Err(e) => return Err(e),
+            };
+            let Some(record) = record else {
+                return Err(Error::Conflict(format!(
+                    "assertion failed: no record '{}' in '{}'",
+                    key, table
+                )));
+            };
+            check_assertions(&msgpack_to_json(&record), expected.as_ref(), &field_checks).map_err(
+                |failures| {
+                    Error::Conflict(format!(
+                        "assertion failed for record '{}':\n{}",
+                        key,
+                        failures.join("\n")
+                    ))
+                },
+            )?;
+            let which = format!("record '{}'", key);
+            output(Some(extract.one(record, &which)?.unwrap_or(Value::Nil)))
+        }
+        Commands::Exists {
+            table,
+            key,
+            key_type,
+            ..
+        } => {
+            // Project onto no fields, so the record itself is never sent
+            let params = vec![
+                Value::from(table.as_str()),
+                parse_key(key, *key_type)?,
+                Value::Array(Vec::new()),
+            ];
+            match call(cli, "get", params) {
+                Ok(None | Some(Value::Nil)) => Ok(Some(Value::Boolean(false))),
+                Err(e) if e.reason() == Some("not_found") => Ok(Some(Value::Boolean(false))),
+                Ok(Some(_)) => Ok(Some(Value::Boolean(true))),
+                Err(e) => Err(e),
+            }
+        }
+        Commands::Put {
+            table,
+            json,
+            file,
+            if_absent,
+            if_match,
+            ttl,
+            schema,
+        } => {
+            let file = match json.as_deref() {
+                Some("-") => Some(Path::new("-")),
+                _ => file.as_deref(),
+            };
+            let mut records = match file {
+                Some(path) => read_json_values(path)?,
+                None => vec![read_json_arg(json.as_deref(), None, "JSON")?],
+            };
+            if records.len() > 1 {
+                if *if_absent || if_match.is_some() || ttl.is_some() {
+                    return Err(Error::Input(
+                        "--if-absent, --if-match, and --ttl take one record, not several"
+                            .to_string(),
+                    ));
+                }
+                if let Some(path) = schema {
+                    check_schema(path, &records)?;
+                }
+                let out = &mut io::stdout().lock();
+                return write_records(cli, table, &records, cli.stdout_color(), out);
+            }
+            if let Some(path) = schema {
+                check_schema(path, &records)?;
+            }
+            let record = records.remove(0);
+            let ttl = ttl.as_deref().map(ttl_param).transpose()?;
+            let record_msgpack = input_to_msgpack(&record)?;
+            check_value_size(cli, &record_msgpack)?;
+            let mut params = vec![Value::String(table.clone().into()), record_msgpack];
+
+            let expected = match (if_absent, if_match) {
+                (true, _) => Value::Nil,
+                (false, Some(expected)) => {
+                    let expected: serde_json::Value = serde_json::from_str(expected)
+                        .map_err(|e| Error::Input(format!("invalid --if-match JSON: {}", e)))?;
+                    if !expected.is_object() {
+                        return Err(Error::Input("--if-match must be a JSON object".to_string()));
+                    }
+                    input_to_msgpack(&expected)?
+                }
+                (false, None) => {
+                    params.extend(ttl);
+                    return call(cli, "put", params);
+                }
+            };
+
+            params.push(expected);
+            params.extend(ttl);
+            call(cli, "cas_put", params).map_err(|e| match e {
+                e if e.reason() == Some("condition_failed") => Error::Conflict(if *if_absent {
+                    "condition failed: a record with this key already exists".to_string()
+                } else {
+                    "condition failed: stored record does not match --if-match".to_string()
+                }),
+                other => other,
+            })
+        }
+        Commands::PutMany { table, file } => {
+            let text = read_input_file(file, &mut io::stdin().lock())?;
+            let records = parse_records(&text)
+                .map_err(|e| Error::Input(format!("invalid JSON in {}: {}", file.display(), e)))?;
+            if records.is_empty() {
+                return Err(Error::Input(format!("no records in {}", file.display())));
+            }
+            let out = &mut io::stdout().lock();
+            write_records(cli, table, &records, cli.stdout_color(), out)
+        }
+        Commands::Txn { file } => {
+            let text = read_input_file(file, &mut io::stdin().lock())?;
+            let ops = match serde_json::from_str(&text) {
+                Ok(serde_json::Value::Array(ops)) if !ops.is_empty() => ops,
+                Ok(_) => {
+                    return Err(Error::Input(format!(
+                        "{} must hold a non-empty JSON array of ops",
+                        file.display()
+                    )))
+                }
+                Err(e) => {
+                    return Err(Error::Input(format!(
+                        "invalid JSON in {}: {}",
+                        file.display(),
+                        e
+                    )))
+                }
+            };
+            let ops = ops
+                .iter()
+                .enumerate()
+                .map(|(i, op)| {
+                    let op = Op::from_json(op).map_err(|e| e.annotate(&format!("op {}", i + 1)))?;
+                    validate_name("table", op.table())?;
+                    Ok(op)
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            call(
+                cli,
+                "transaction",
+                cortex_client::txn::transaction_params(&ops)?,
+            )
+        }
+        Commands::Validate {
+            table,
+            schema,
+            file,
+        } => {
+            let schema = schema::Schema::load(schema)?;
+            let text = read_input_file(file, &mut io::stdin().lock())?;
+            let records = parse_records(&text)
+                .map_err(|e| Error::Input(format!("invalid JSON in {}: {}", file.display(), e)))?;
+            let report = validation_report(table, &schema, &records);
+            let failed = report["failed"].as_u64().unwrap_or(0);
+            let report = json_to_msgpack(&report);
+            if failed == 0 {
+                return Ok(Some(report));
+            }
+            // As with health, the report is wanted whether or not it passes
+            let out = &mut io::stdout().lock();
+            finish(
+                cli,
+                Ok(Some(report)),
+                cli.stdout_color(),
+                out,
+                &mut io::stderr(),
+            );
+            Err(Error::Conflict(format!(
+                "{} of {} records do not match the schema",
+                failed,
+                records.len()
+            )))
+        }
+        Commands::Expire {
+            table,
+            key,
+            ttl,
+            key_type,
+            ..
+        } => {
+            let ttl = ttl.as_deref().map(parse_ttl).transpose()?;
+            let params = vec![
+                Value::from(table.as_str()),
+                parse_key(key, *key_type)?,
+                ttl.map_or(Value::Nil, Value::from),
+            ];
+            call(cli, "expire", params)
+        }
+        Commands::Append {
+            table,
+            key,
+            field,
+            value,
+            key_type,
+        } => {
+            let value: serde_json::Value = serde_json::from_str(value)
+                .map_err(|e| Error::Input(format!("invalid JSON value: {}", e)))?;
+            let params = vec![
+                Value::String(table.clone().into()),
+                parse_key(key, *key_type)?,
+                Value::String(field.clone().into()),
+                input_to_msgpack(&value)?,
+            ];
+            call(cli, "append", params).map_err(|e| match e {
+                e if e.reason() == Some("not_an_array") => Error::Conflict(format!(
+                    "field '{}' of record '{}' is not an array",
+                    field, key
+                )),
+                other => other,
+            })
+        }
+        command @ (Commands::Incr {
+            table,
+            key,
+            field,
+            amount,
+            key_type,
+        }
+        | Commands::Decr {
+            table,
+            key,
+            field,
+            amount,
+            key_type,
+        }) => {
+            let decr = matches!(command, Commands::Decr { .. });
+            let params = vec![
+                Value::String(table.clone().into()),
+                parse_key(key, *key_type)?,
+                Value::String(field.clone().into()),
+                counter_amount(amount, decr)?,
+            ];
+            call(cli, "incr", params).map_err(|e| match e.reason() {
+                Some("not_a_number") => Error::Conflict(format!(
+                    "field '{}' of record '{}' is not a number",
+                    field, key
+                )),
+                Some("key_field") => {
+                    Error::Input(format!("'{}' is the key field and can't change", field))
+                }
+                _ => e,
+            })
+        }
+        Commands::Delete {
+            table,
+            keys_file: Some(path),
+            key_type,
+            yes,
+            ..
+        } => {
+            let keys = read_keys_file(path)?;
+            let stdin = io::stdin();
+            confirm(
+                &format!("Delete {} records from '{}'?", keys.len(), table),
+                *yes || cli.dry_run,
+                stdin.is_terminal(),
+                &mut stdin.lock(),
+            )?;
+            call_keys(cli, "delete", table, &keys, *key_type, None).map(Some)
+        }
+        Commands::Delete {
+            table,
+            key,
+            key_type,
+            pattern,
+            yes,
+            ..
+        } => {
+            let stdin = io::stdin();
+            let (method, params) = delete_request(
+                table,
+                key.as_deref(),
+                *key_type,
+                pattern.as_deref(),
+                *yes || cli.dry_run,
+                stdin.is_terminal(),
+                &mut stdin.lock(),
+            )?;
+            call(cli, method, params)
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Most records sent in one `put_many` request, however small they are.
+const PUT_MANY_BATCH: usize = 1000;
+
+/// Write a string `value` to `out` as-is for `get --raw-string`. Anything
+/// else is handed back to be printed as JSON, with a note on `notes`.
+pub fn print_raw_string(
+    cli: &Cli,
+    value: Option<Value>,
+    out: &mut impl Write,
+    notes: &mut dyn Write,
+) -> Result<Option<Value>, Error> {
+    let Some(text) = value.as_ref().and_then(Value::as_str) else {
+        if !cli.quiet {
+            let _ = writeln!(
+                notes,
+                "note: --raw-string: result is not a string; printing JSON"
+            );
+        }
+        return Ok(value);
+    };
+    if !cli.quiet {
+        let written = if cli.no_newline {
+            write!(out, "{}", text)
+        } else {
+            writeln!(out, "{}", text)
+        };
+        written.map_err(|e| Error::Output(format!("write error: {}", e)))?;
+    }
+    Ok(None)
+}
+
+/// Check `records` against the schema at `path`, failing on the first that
+/// doesn't match. Its position (counting from 1) is given when there are
+/// several.
+pub fn check_schema(path: &Path, records: &[serde_json::Value]) -> Result<(), Error> {
+    let schema = schema::Schema::load(path)?;
+    for (i, record) in records.iter().enumerate() {
+        let violations = schema.violations(record);
+        if violations.is_empty() {
+            continue;
+        }
+        let which = if records.len() > 1 {
+            format!("record {}", i + 1)
+        } else {
+            "record".to_string()
+        };
+        return Err(Error::Input(format!(
+            "{} does not match {}:\n{}",
+            which,
+            path.display(),
+            render_violations(&violations)
+        )));
+    }
+    Ok(())
+}
+
+/// Violations one per line, indented under the message introducing them.
+fn render_violations(violations: &[schema::Violation]) -> String {
+    violations
+        .iter()
+        .map(|v| format!("  {}", v))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Pass and fail counts for `records` against `schema`, with each failing
+/// record's position (counting from 1) and violations.
+pub fn validation_report(
+    table: &str,
+    schema: &schema::Schema,
+    records: &[serde_json::Value],
+) -> serde_json::Value {
+    let failures: Vec<serde_json::Value> = records
+        .iter()
+        .enumerate()
+        .filter_map(|(i, record)| {
+            let violations = schema.violations(record);
+            if violations.is_empty() {
+                return None;
+            }
+            let errors: Vec<String> = violations.iter().map(|v| v.to_string()).collect();
+            Some(serde_json::json!({ "record": i + 1, "errors": errors }))
+        })
+        .collect();
+    serde_json::json!({
+        "table": table,
+        "passed": records.len() - failures.len(),
+        "failed": failures.len(),
+        "failures": failures,
+    })
+}
+
+/// Split a `--assert-field FIELD=VALUE`, reading VALUE as JSON if it is
+/// valid JSON and as a plain string otherwise.
+fn parse_field_assertion(check: &str) -> Result<(String, serde_json::Value), Error> {
+    let (field, value) = check
+        .split_once('=')
+        .filter(|(field, _)| !field.is_empty())
+        .ok_or_else(|| {
+            Error::Input(format!(
+                "invalid --assert-field '{}': expected FIELD=VALUE",
+                check
+            ))
+        })?;
+    let value = serde_json::from_str(value)
+        .unwrap_or_else(|_| serde_json::Value::String(value.to_string()));
+    Ok((field.to_string(), value))
+}
+
+/// Compare a fetched record against `get`'s assertions, giving one line
+/// per mismatch.
+fn check_assertions(
+    record: &serde_json::Value,
+    expected: Option<&serde_json::Value>,
+    fields: &[(String, serde_json::Value)],
+) -> Result<(), Vec<String>> {
+    let mut failures = Vec::new();
+    match expected {
+        Some(expected) if expected.is_object() && record.is_object() => {
+            for (name, change) in diff::field_changes(expected, record) {
+                failures.push(format!(
+                    "  {}: expected {}, got {}",
+                    name, change["left"], change["right"]
+                ));
+            }
+        }
+        Some(expected) if expected != record => {
+            failures.push(format!("  expected {}, got {}", expected, record));
+        }
+        _ => {}
+    }
+    for (name, value) in fields {
+        let actual = record.get(name).unwrap_or(&serde_json::Value::Null);
+        if actual != value {
+            failures.push(format!("  {}: expected {}, got {}", name, value, actual));
+        }
+    }
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(failures)
+    }
+}
+
+/// The keys listed one per line in `path` (or stdin for `-`), skipping
+/// blank lines.
+fn read_keys_file(path: &Path) -> Result<Vec<String>, Error> {
+    let text = read_input_file(path, &mut io::stdin().lock())?;
+    let keys: Vec<String> = text
+        .lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect();
+    if keys.is_empty() {
+        return Err(Error::Input(format!("no keys in {}", path.display())));
+    }
+    Ok(keys)
+}
+
+/// Run `method` (`get` or `delete`) on each of `keys` over one connection,
+/// pipelined in batches, giving `{"key": key, "ok": result}` or
+/// `{"key": key, "error": message, "code": category}` for each, in the
+/// order given. Only connection and protocol failures stop the run.
+fn call_keys(
+    cli: &Cli,
+    method: &str,
+    table: &str,
+    keys: &[String],
+    key_type: KeyType,
+    fields: Option<&str>,
+) -> Result<Value, Error> {
+    let table = Value::String(table.into());
+    let fields = fields.map(parse_fields);
+    let keys = keys
+        .iter()
+        .map(|key| parse_key(key, key_type))
+        .collect::<Result<Vec<_>, Error>>()?;
+    let params = keys.iter().map(|key| {
+        let mut params = vec![table.clone(), key.clone()];
+        params.extend(fields.as_deref().map(fields_param));
+        params
+    });
+
+    let results = if cli.dry_run && MUTATING_METHODS.contains(&method) {
+        params
+            .map(|params| Ok(Some(dry_run_request(method, params))))
+            .collect()
+    } else {
+        pipeline(&mut *connect(cli)?, method, params)?
+    };
+
+    let summary = keys.into_iter().zip(results).map(|(key, result)| {
+        let key = (Value::from("key"), key);
+        Value::Map(match result {
+            Ok(value) => {
+                let value = value.unwrap_or(Value::Nil);
+                let value = match &fields {
+                    Some(fields) => project(value, fields),
+                    None => value,
+                };
+                vec![key, (Value::from("ok"), value)]
+            }
+            Err(e) => vec![
+                key,
+                (Value::from("error"), Value::from(e.to_string())),
+                (Value::from("code"), Value::from(e.code())),
+            ],
+        })
+    });
+    Ok(Value::Array(summary.collect()))
+}
+
+/// Write `records` with [`put_many`], giving its report, or printing it
+/// to `out` and failing if any record wasn't written.
+pub fn write_records(
+    cli: &Cli,
+    table: &str,
+    records: &[serde_json::Value],
+    color: bool,
+    out: &mut impl Write,
+) -> Result<Option<Value>, Error> {
+    let report = put_many(cli, table, records, 1)?;
+    check_report(cli, report, records.len(), color, out)
+}
+
+/// Give a write `report` as the result if every record was written, or
+/// else print it to `out` and fail.
+pub fn check_report(
+    cli: &Cli,
+    report: Value,
+    records: usize,
+    color: bool,
+    out: &mut impl Write,
+) -> Result<Option<Value>, Error> {
+    let failed = report["failed"].as_u64().unwrap_or(0);
+    if failed == 0 {
+        return Ok(Some(report));
+    }
+    // Like validate, show which records failed as well as failing
+    finish(cli, Ok(Some(report)), color, out, &mut io::stderr());
+    Err(Error::Daemon(format!(
+        "{} of {} records were not written",
+        failed, records
+    )))
+}
+
+/// How many records were written and, for each failure, the record's
+/// position (counting from 1) and error. Records with no outcome were
+/// skipped, so count as neither.
+pub fn write_report(table: &str, outcomes: Vec<Option<Result<(), Error>>>) -> serde_json::Value {
+    let written = outcomes
+        .iter()
+        .filter(|o| matches!(o, Some(Ok(()))))
+        .count();
+    let failures: Vec<serde_json::Value> = outcomes
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, outcome)| match outcome {
+            Some(Err(e)) => Some(serde_json::json!({
+                "record": i + 1,
+                "error": e.to_string(),
+                "code": e.code(),
+            })),
+            _ => None,
+        })
+        .collect();
+    serde_json::json!({
+        "table": table,
+        "written": written,
+        "failed": failures.len(),
+        "failures": failures,
+    })
+}
+
+/// The outcome of writing a record, with the record's position.
+type Written = (usize, Result<(), Error>);
+
+/// Share `items` out over `parallel` connections with
+/// [`backup::in_parallel`]. Each worker pushes the outcome of every record
+/// it sends, by position, and stops at its first error otherwise; the
+/// outcomes are given unless a worker stopped early.
+pub fn write_in_parallel<T: Sync>(
+    cli: &Cli,
+    parallel: u8,
+    table: &str,
+    items: &[T],
+    work: impl Fn(&mut Connection, &[T], &mut Vec<Written>) -> Result<(), Error> + Sync,
+) -> Result<Vec<Written>, Error> {
+    let mut conn = connect(cli)?;
+    let mut workers = (1..parallel)
+        .map(|_| open(cli))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut conns: Vec<&mut Connection> = std::iter::once(&mut *conn)
+        .chain(workers.iter_mut())
+        .collect();
+    let shares = backup::in_parallel(&mut conns, items, |conn, share| {
+        let mut results = Vec::new();
+        let finished = work(conn, share, &mut results);
+        (results, finished)
+    });
+
+    let mut results = Vec::new();
+    let mut errors = Vec::new();
+    for (share, finished) in shares {
+        results.extend(share);
+        errors.extend(finished.err());
+    }
+    let written = results.iter().filter(|(_, r)| r.is_ok()).count();
+    match backup::worker_error(errors, conns.len(), written, items.len(), table) {
+        Some(e) => Err(e),
+        None => Ok(results),
+    }
+}
+
+/// Write `records` to `table` in as few `put_many` requests as
+/// `--max-value-size` allows, falling back to pipelined `put`s for a daemon
+/// without `put_many`. Gives a report of how many were written and, for
+/// each failure, the record's position (counting from 1) and error.
+/// Records too large or malformed to send count as failures too. The
+/// batches are shared out over `parallel` connections.
+pub fn put_many(
+    cli: &Cli,
+    table: &str,
+    records: &[serde_json::Value],
+    parallel: u8,
+) -> Result<Value, Error> {
+    let mut outcomes: Vec<Option<Result<(), Error>>> = vec![None; records.len()];
+    let mut batches: Vec<Vec<(usize, Value)>> = Vec::new();
+    let limit = cli.max_value_size.unwrap_or(DEFAULT_MAX_VALUE_SIZE);
+    let mut batch_size = 0;
+    for (i, record) in records.iter().enumerate() {
+        let encoded = input_to_msgpack(record)
+            .and_then(|value| check_value_size(cli, &value).map(|size| (value, size)));
+        let (value, size) = match encoded {
+            Ok(encoded) => encoded,
+            Err(e) => {
+                outcomes[i] = Some(Err(e));
+                continue;
+            }
+        };
+        match batches.last_mut() {
+            Some(batch) if batch.len() < PUT_MANY_BATCH && batch_size + size <= limit => {
+                batch_size += size;
+                batch.push((i, value));
+            }
+            _ => {
+                batch_size = size;
+                batches.push(vec![(i, value)]);
+            }
+        }
+    }
+
+    let table_value = Value::from(table);
+    let params = |batch: &[(usize, Value)]| {
+        let records = batch.iter().map(|(_, record)| record.clone()).collect();
+        vec![table_value.clone(), Value::Array(records)]
+    };
+    if cli.dry_run {
+        let requests = batches
+            .iter()
+            .map(|batch| dry_run_request("put_many", params(batch)));
+        return Ok(Value::Array(requests.collect()));
+    }
+
+    let results = write_in_parallel(cli, parallel, table, &batches, |conn, batches, results| {
+        let mut single_puts = false;
+        for batch in batches {
+            let sent = if single_puts {
+                None
+            } else {
+                match conn.call("put_many", params(batch)) {
+                    Ok(result) => Some(connection::decode_put_many(result, batch.len())?),
+                    Err(e) if e.is_unknown_method() => None,
+                    Err(e) => return Err(e),
+                }
+            };
+            let sent = match sent {
+                Some(sent) => sent,
+                None => {
+                    single_puts = true;
+                    let puts = batch
+                        .iter()
+                        .map(|(_, record)| vec![table_value.clone(), record.clone()]);
+                    let sent = pipeline(conn, "put", puts)?;
+                    sent.into_iter().map(|r| r.map(|_| ())).collect()
+                }
+            };
+            results.extend(batch.iter().map(|(i, _)| *i).zip(sent));
+        }
+        Ok(())
+    })?;
+    for (i, result) in results {
+        outcomes[i] = Some(result);
+    }
+
+    Ok(json_to_msgpack(&write_report(table, outcomes)))
+}
+
+/// Build the RPC for `delete`: a single key, or a confirmed `delete_match`
+/// when a pattern is given.
+pub fn delete_request(
+    table: &str,
+    key: Option<&str>,
+    key_type: KeyType,
+    pattern: Option<&str>,
+    yes: bool,
+    interactive: bool,
+    input: &mut impl BufRead,
+) -> Result<(&'static str, Vec<Value>), Error> {
+    let table_param = Value::String(table.into());
+
+    let Some(pattern) = pattern else {
+        let key = key.ok_or_else(|| Error::Input("missing key".to_string()))?;
+        return Ok(("delete", vec![table_param, parse_key(key, key_type)?]));
+    };
+
+    let pat: serde_json::Value = serde_json::from_str(pattern)
+        .map_err(|e| Error::Input(format!("invalid JSON pattern: {}", e)))?;
+    if !pat.is_object() {
+        return Err(Error::Input("pattern must be a JSON object".to_string()));
+    }
+    confirm(
+        &format!("Delete every record in '{}' matching {}?", table, pattern),
+        yes,
+        interactive,
+        input,
+    )?;
+    Ok(("delete_match", vec![table_param, json_to_msgpack(&pat)]))
+}
+
+/// The amount `incr` adds: `amount` as a number, negated for `decr`.
+fn counter_amount(amount: &str, negate: bool) -> Result<Value, Error> {
+    let invalid = || Error::Input(format!("amount must be a number, got '{}'", amount));
+    let number: serde_json::Number = amount.trim().parse().map_err(|_| invalid())?;
+    match (number.as_i64(), number.as_f64()) {
+        (Some(n), _) if negate => n.checked_neg().map(Value::from).ok_or_else(invalid),
+        (Some(n), _) => Ok(Value::from(n)),
+        (None, Some(f)) if f.is_finite() => Ok(Value::from(if negate { -f } else { f })),
+        _ => Err(invalid()),
+    }
+}
+
+/// The `{"ttl": seconds}` options a write takes to make its record expire.
+fn ttl_param(duration: &str) -> Result<Value, Error> {
+    Ok(Value::Map(vec![(
+        Value::String("ttl".into()),
+        Value::from(parse_ttl(duration)?),
+    )]))
+}
+
+/// A TTL in seconds: a bare number of seconds, or a duration like `30m`.
+fn parse_ttl(ttl: &str) -> Result<u64, Error> {
+    match ttl.trim().parse::<u64>() {
+        Ok(0) => Err(Error::Input("TTL must be at least 1 second".to_string())),
+        Ok(secs) => Ok(secs),
+        Err(_) => parse_duration(ttl),
+    }
+}
+
+/// `record` as `{"record": record, "ttl": seconds}`, the seconds left
+/// before it expires, or null if it never does or there is no record.
+fn with_remaining_ttl(
+    cli: &Cli,
+    table: &str,
+    key: Value,
+    record: Option<Value>,
+) -> Result<Option<Value>, Error> {
+    let ttl = match call(cli, "ttl", vec![Value::from(table), key]) {
+        Ok(ttl) => ttl.unwrap_or(Value::Nil),
+        Err(e) if e.reason() == Some("not_found") => Value::Nil,
+        Err(e) => return Err(e),
+    };
+    Ok(Some(Value::Map(vec![
+        (Value::from("record"), record.unwrap_or(Value::Nil)),
+        (Value::from("ttl"), ttl),
+    ])))
+}
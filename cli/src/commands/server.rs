@@ -0,0 +1,393 @@
+//! Asking about the daemon itself: `ping`, `wait-ready`, `status`,
+//! `health`, `version`, and `whoami`.
+
+use crate::connection::Connection;
+use crate::error::Error;
+use crate::{
+    call, connect, finish, json_to_msgpack, msgpack_to_json, parse_seconds, print_text, Cli,
+    Commands, OutputFormat, DEFAULT_SOCKET, VERSION,
+};
+use rmpv::Value;
+use std::io;
+use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant};
+
+pub fn run(cli: &Cli, command: &Commands) -> Result<Option<Value>, Error> {
+    match command {
+        Commands::Ping {
+            connect_only: true, ..
+        } => {
+            probe(cli.socket.as_deref().unwrap_or(DEFAULT_SOCKET))?;
+            Ok(Some(Value::from("connected")))
+        }
+        Commands::Ping { latency: false, .. } => call(cli, "ping", vec![]),
+        Commands::Ping {
+            latency: true,
+            count,
+            ..
+        } => {
+            let times = ping_latency(&mut *connect(cli)?, *count)?;
+            if cli.output == Some(OutputFormat::Json) {
+                return Ok(Some(latency_summary(&times)));
+            }
+            print_text(cli, &format!("{}\n", render_latency(&times)));
+            Ok(None)
+        }
+        Commands::WaitReady => {
+            let limit = match &cli.timeout {
+                Some(t) => Duration::from_secs(parse_seconds(t)?),
+                None => WAIT_READY_TIMEOUT,
+            };
+            let socket = cli.socket.as_deref().unwrap_or(DEFAULT_SOCKET);
+            wait_ready(socket, limit)?;
+            Ok(None)
+        }
+        Commands::Status => {
+            let status = call(cli, "status", vec![])?;
+            match (cli.output, status) {
+                (Some(OutputFormat::Json), status) => Ok(status),
+                (_, Some(status)) => {
+                    print_text(cli, &render_status(&msgpack_to_json(&status)));
+                    Ok(None)
+                }
+                (_, None) => Ok(None),
+            }
+        }
+        Commands::Health => {
+            let report = match connect(cli) {
+                Ok(mut conn) => health_report(Ok(&mut conn)),
+                Err(e) => health_report(Err(e)),
+            };
+            let failure = health_failure(&report);
+            let report = json_to_msgpack(&report);
+            let Some(failure) = failure else {
+                return Ok(Some(report));
+            };
+            // The report is the point of the command, failing or not
+            let out = &mut io::stdout().lock();
+            finish(
+                cli,
+                Ok(Some(report)),
+                cli.stdout_color(),
+                out,
+                &mut io::stderr(),
+            );
+            Err(failure)
+        }
+        Commands::Version => {
+            let daemon = match call(cli, "status", vec![]) {
+                Ok(status) => status.and_then(|status| {
+                    msgpack_to_json(&status)["version"]
+                        .as_str()
+                        .map(str::to_string)
+                }),
+                // Still worth reporting the CLI version
+                Err(Error::Connection(_) | Error::Timeout(_)) => None,
+                Err(e) => return Err(e),
+            };
+            let report = version_report(VERSION, daemon.as_deref());
+            if cli.output == Some(OutputFormat::Json) {
+                return Ok(Some(json_to_msgpack(&report)));
+            }
+            print_text(cli, &render_version(&report));
+            Ok(None)
+        }
+        Commands::Whoami => {
+            let whoami = call(cli, "whoami", vec![])?;
+            if cli.output == Some(OutputFormat::Json) {
+                return Ok(whoami);
+            }
+            // Just the number, for $(cortex whoami)
+            Ok(whoami.map(|whoami| json_to_msgpack(&msgpack_to_json(&whoami)["uid"])))
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// How long `wait-ready` waits without `--timeout`.
+const WAIT_READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Pause between `wait-ready` attempts.
+const WAIT_READY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// `{cli, daemon, compatible}`; daemon and compatible are null if it's unreachable.
+fn version_report(cli: &str, daemon: Option<&str>) -> serde_json::Value {
+    serde_json::json!({
+        "cli": cli,
+        "daemon": daemon,
+        "compatible": daemon.map(|daemon| versions_compatible(cli, daemon)),
+    })
+}
+
+pub fn render_version(report: &serde_json::Value) -> String {
+    let daemon = report["daemon"].as_str().unwrap_or("unreachable");
+    let compatible = match report["compatible"].as_bool() {
+        Some(true) => "yes",
+        Some(false) => "no",
+        None => "unknown",
+    };
+    format!(
+        "cli:        {}\ndaemon:     {}\ncompatible: {}\n",
+        report["cli"].as_str().unwrap_or_default(),
+        daemon,
+        compatible
+    )
+}
+
+/// Semver compatibility: same major version, and for 0.x the same minor.
+/// Pre-release and build suffixes are ignored.
+pub fn versions_compatible(a: &str, b: &str) -> bool {
+    fn major_minor(version: &str) -> Option<(u64, u64)> {
+        let core = version.split(['-', '+']).next()?;
+        let mut parts = core.split('.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next()?.parse().ok()?;
+        Some((major, minor))
+    }
+    match (major_minor(a), major_minor(b)) {
+        (Some((0, a_minor)), Some((0, b_minor))) => a_minor == b_minor,
+        (Some((a_major, _)), Some((b_major, _))) => a_major == b_major,
+        _ => false,
+    }
+}
+
+/// `{"status": "healthy|degraded|down", "checks": [...]}` from pinging the
+/// daemon over `conn` and reading its status. Each check has a `name`, a
+/// `status` of ok, fail, or unknown (not reported by this daemon), and for
+/// anything but ok a `detail`.
+pub fn health_report(conn: Result<&mut Connection, Error>) -> serde_json::Value {
+    use serde_json::json;
+
+    let check = |name: &str, result: Result<(), String>| match result {
+        Ok(()) => json!({"name": name, "status": "ok"}),
+        Err(detail) => json!({"name": name, "status": "fail", "detail": detail}),
+    };
+
+    let pinged = conn.and_then(|conn| {
+        conn.call("ping", vec![])?;
+        Ok(conn)
+    });
+    let conn = match pinged {
+        Ok(conn) => conn,
+        Err(e) => {
+            return json!({
+                "status": "down",
+                "checks": [check("connectivity", Err(e.to_string()))],
+            })
+        }
+    };
+    let mut checks = vec![check("connectivity", Ok(()))];
+
+    let status = conn
+        .call("status", vec![])
+        .map(|status| status.map(|s| msgpack_to_json(&s)).unwrap_or_default());
+    let (mnesia, disk) = match &status {
+        Ok(status) => (
+            match status["mnesia"].as_str() {
+                Some("yes") => Ok(()),
+                Some(state) => Err(format!("mnesia is not running ({})", state)),
+                None => Err("status does not report mnesia".to_string()),
+            },
+            status["data_dir_writable"].as_bool().map(|writable| {
+                writable
+                    .then_some(())
+                    .ok_or_else(|| "data directory is not writable".to_string())
+            }),
+        ),
+        Err(e) => (Err(format!("status failed: {}", e)), None),
+    };
+    checks.push(check("mnesia", mnesia));
+    checks.push(match disk {
+        Some(result) => check("disk", result),
+        None => json!({"name": "disk", "status": "unknown",
+                       "detail": "daemon does not report disk state"}),
+    });
+
+    let healthy = checks.iter().all(|c| c["status"] != "fail");
+    json!({
+        "status": if healthy { "healthy" } else { "degraded" },
+        "checks": checks,
+    })
+}
+
+/// The error `health` exits with for a report that isn't healthy.
+pub fn health_failure(report: &serde_json::Value) -> Option<Error> {
+    let failed: Vec<String> = report["checks"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|c| c["status"] == "fail")
+        .map(|c| {
+            format!(
+                "{}: {}",
+                c["name"].as_str().unwrap_or("?"),
+                c["detail"].as_str().unwrap_or("?")
+            )
+        })
+        .collect();
+    match report["status"].as_str() {
+        Some("down") => Some(Error::Connection(format!(
+            "daemon is down ({})",
+            failed.join("; ")
+        ))),
+        Some("degraded") => Some(Error::Daemon(format!(
+            "daemon is degraded ({})",
+            failed.join("; ")
+        ))),
+        _ => None,
+    }
+}
+
+/// Render a `status` result as aligned `key: value` lines, flagging a
+/// database that isn't running.
+pub fn render_status(status: &serde_json::Value) -> String {
+    const ORDER: [&str; 7] = [
+        "status",
+        "version",
+        "uid",
+        "uptime_seconds",
+        "mnesia",
+        "node",
+        "tables",
+    ];
+
+    let Some(map) = status.as_object() else {
+        return format!("{}\n", status);
+    };
+
+    let mut keys: Vec<&str> = ORDER
+        .iter()
+        .copied()
+        .filter(|k| map.contains_key(*k))
+        .collect();
+    keys.extend(
+        map.keys()
+            .map(String::as_str)
+            .filter(|k| !ORDER.contains(k)),
+    );
+
+    fn label(key: &str) -> &str {
+        match key {
+            "uptime_seconds" => "uptime",
+            other => other,
+        }
+    }
+    let width = keys.iter().map(|k| label(k).len()).max().unwrap_or(0);
+
+    let mut out = String::new();
+    for key in keys {
+        let value = &map[key];
+        let text = match (key, value) {
+            ("uptime_seconds", serde_json::Value::Number(n)) if n.is_u64() => {
+                format_uptime(n.as_u64().unwrap_or(0))
+            }
+            ("mnesia", serde_json::Value::String(s)) if s != "yes" => {
+                format!("{} (UNHEALTHY)", s)
+            }
+            (_, serde_json::Value::String(s)) => s.clone(),
+            (_, v) => v.to_string(),
+        };
+        out.push_str(&format!(
+            "{:width$} {}\n",
+            format!("{}:", label(key)),
+            text,
+            width = width + 1
+        ));
+    }
+    out
+}
+
+/// Format seconds as `3d 4h 5m`, dropping leading zero units.
+pub fn format_uptime(secs: u64) -> String {
+    let (days, hours, minutes) = (secs / 86_400, secs % 86_400 / 3_600, secs % 3_600 / 60);
+    match (days, hours) {
+        (0, 0) if minutes == 0 => format!("{}s", secs),
+        (0, 0) => format!("{}m", minutes),
+        (0, _) => format!("{}h {}m", hours, minutes),
+        _ => format!("{}d {}h {}m", days, hours, minutes),
+    }
+}
+
+/// Connect and ping until the daemon answers, pausing between attempts.
+/// Fails with a timeout once `limit` has passed without an answer.
+pub fn wait_ready(socket: &str, limit: Duration) -> Result<(), Error> {
+    let deadline = Instant::now() + limit;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let attempt = Connection::new(socket).and_then(|mut conn| {
+            conn.set_timeout(Some(remaining.max(Duration::from_millis(1))))?;
+            conn.call("ping", vec![])
+        });
+        let last = match attempt {
+            Ok(_) => return Ok(()),
+            Err(e) => e,
+        };
+        if Instant::now() + WAIT_READY_INTERVAL >= deadline {
+            return Err(Error::Timeout(format!(
+                "daemon at {} not ready after {}s: {}",
+                socket,
+                limit.as_secs(),
+                last
+            )));
+        }
+        std::thread::sleep(WAIT_READY_INTERVAL);
+    }
+}
+
+/// Check that `socket` accepts connections, closing the connection at once
+/// without sending anything.
+fn probe(socket: &str) -> Result<(), Error> {
+    match UnixStream::connect(socket) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            Err(Error::Connection(format!("no socket at {}", socket)))
+        }
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => Err(Error::Connection(format!(
+            "{} exists but is not accepting connections",
+            socket
+        ))),
+        Err(e) => Err(Error::io(&format!("cannot connect to {}", socket), e)),
+    }
+}
+
+/// Send `count` pings over one connection, timing each round trip.
+pub fn ping_latency(conn: &mut Connection, count: u32) -> Result<Vec<Duration>, Error> {
+    (0..count)
+        .map(|_| {
+            let start = Instant::now();
+            conn.call("ping", vec![])?;
+            Ok(start.elapsed())
+        })
+        .collect()
+}
+
+fn latency_stats(times: &[Duration]) -> (f64, f64, f64) {
+    let ms: Vec<f64> = times.iter().map(|t| t.as_secs_f64() * 1000.0).collect();
+    let min = ms.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = ms.iter().copied().fold(0.0, f64::max);
+    let avg = ms.iter().sum::<f64>() / ms.len().max(1) as f64;
+    (min, avg, max)
+}
+
+/// Round-trip summary for `ping --latency --output json`.
+fn latency_summary(times: &[Duration]) -> Value {
+    let (min, avg, max) = latency_stats(times);
+    Value::Map(vec![
+        (Value::String("count".into()), Value::from(times.len())),
+        (Value::String("min_ms".into()), Value::F64(min)),
+        (Value::String("avg_ms".into()), Value::F64(avg)),
+        (Value::String("max_ms".into()), Value::F64(max)),
+    ])
+}
+
+/// Render round-trip times the way `ping(8)` summarizes them.
+pub fn render_latency(times: &[Duration]) -> String {
+    let (min, avg, max) = latency_stats(times);
+    format!(
+        "{} pings: min/avg/max = {:.3}/{:.3}/{:.3} ms",
+        times.len(),
+        min,
+        avg,
+        max
+    )
+}
@@ -0,0 +1,256 @@
+//! Managing tables: `tables`, `create-table`, `drop-table`, `create-index`,
+//! `drop-index`, `truncate`, `describe`, `copy-table`, and `migrate`.
+
+use crate::error::Error;
+use crate::{
+    backup, call, confirm, confirm_typed, connect, dry_run_request, glob, input_to_msgpack,
+    json_to_msgpack, msgpack_to_json, print_text, validate_name, Cli, Commands, MigrateCommands,
+    OutputFormat,
+};
+use rmpv::Value;
+use std::io::{self, IsTerminal};
+
+pub fn run(cli: &Cli, command: &Commands) -> Result<Option<Value>, Error> {
+    match command {
+        Commands::Tables { pattern } => {
+            let tables = call(cli, "tables", vec![])?;
+            Ok(match (pattern, tables) {
+                (Some(pattern), Some(Value::Array(names))) => Some(Value::Array(
+                    names
+                        .into_iter()
+                        .filter(|name| name.as_str().is_some_and(|n| glob::matches(pattern, n)))
+                        .collect(),
+                )),
+                (_, tables) => tables,
+            })
+        }
+        Commands::CreateTable {
+            name,
+            attrs,
+            if_not_exists,
+        } => {
+            validate_name("table", name)?;
+            let attributes = attrs
+                .split(',')
+                .map(|s| {
+                    let attr = s.trim();
+                    validate_name("attribute", attr)?;
+                    Ok(Value::String(attr.into()))
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            let params = vec![
+                Value::String(name.clone().into()),
+                Value::Array(attributes.clone()),
+            ];
+            if !*if_not_exists || cli.dry_run {
+                return call(cli, "create_table", params);
+            }
+
+            let mut conn = connect(cli)?;
+            match conn.call("create_table", params) {
+                Err(e) if e.reason() == Some("already_exists") => {
+                    let schema = conn.describe(name)?.map(|s| msgpack_to_json(&s));
+                    let wanted: Vec<&str> = attributes.iter().filter_map(Value::as_str).collect();
+                    check_existing_schema(name, &wanted, schema.as_ref())?;
+                    if !cli.quiet {
+                        eprintln!("table '{}' already exists", name);
+                    }
+                    Ok(None)
+                }
+                result => result,
+            }
+        }
+        Commands::DropTable { name, yes } => {
+            let stdin = io::stdin();
+            confirm_typed(
+                name,
+                *yes || cli.dry_run,
+                stdin.is_terminal(),
+                &mut stdin.lock(),
+            )?;
+            call(cli, "drop_table", vec![Value::String(name.clone().into())])
+        }
+        Commands::CreateIndex { table, attribute } => {
+            validate_name("attribute", attribute)?;
+            let params = vec![Value::from(table.as_str()), Value::from(attribute.as_str())];
+            call(cli, "create_index", params).map_err(|e| match e.reason() {
+                Some("no_attribute") => Error::Input(format!(
+                    "table '{}' has no attribute '{}' (add it with migrate add-attribute)",
+                    table, attribute
+                )),
+                Some("key_field") => Error::Input(format!(
+                    "'{}' is the primary key, which needs no index",
+                    attribute
+                )),
+                Some("index_exists") => {
+                    Error::Conflict(format!("'{}' is already indexed", attribute))
+                }
+                _ => e,
+            })
+        }
+        Commands::DropIndex { table, attribute } => {
+            let params = vec![Value::from(table.as_str()), Value::from(attribute.as_str())];
+            call(cli, "drop_index", params).map_err(|e| match e.reason() {
+                Some("no_index") => {
+                    Error::Input(format!("'{}' of '{}' is not indexed", attribute, table))
+                }
+                _ => e,
+            })
+        }
+        Commands::Truncate { table, yes } => {
+            let stdin = io::stdin();
+            confirm(
+                &format!("Delete all records in '{}'?", table),
+                *yes || cli.dry_run,
+                stdin.is_terminal(),
+                &mut stdin.lock(),
+            )?;
+            call(cli, "truncate", vec![Value::String(table.clone().into())])
+        }
+        Commands::Describe { table } => {
+            let schema = call(cli, "describe", vec![Value::String(table.clone().into())])?;
+            match (cli.output, schema) {
+                (Some(OutputFormat::Json), schema) => Ok(schema),
+                (_, Some(schema)) => {
+                    print_text(cli, &render_describe(&msgpack_to_json(&schema)));
+                    Ok(None)
+                }
+                (_, None) => Ok(None),
+            }
+        }
+        Commands::CopyTable {
+            src,
+            dst,
+            schema_only,
+            overwrite,
+            yes,
+        } => {
+            if src == dst {
+                return Err(Error::Input(
+                    "source and destination are the same table".to_string(),
+                ));
+            }
+            let mut conn = connect(cli)?;
+            let dump = backup::dump_table(&mut conn, src, *schema_only)?;
+            let doc = backup::Document {
+                cortex_backup: backup::FORMAT_VERSION,
+                tables: vec![backup::TableDump {
+                    table: dst.clone(),
+                    ..dump
+                }],
+            };
+
+            if cli.dry_run {
+                let plan = backup::restore_plan(&doc)
+                    .into_iter()
+                    .map(|(method, params)| dry_run_request(method, params))
+                    .collect();
+                return Ok(Some(Value::Array(plan)));
+            }
+
+            let existing = if *overwrite {
+                let stdin = io::stdin();
+                confirm(
+                    &format!("Drop and replace table '{}' if it exists?", dst),
+                    *yes,
+                    stdin.is_terminal(),
+                    &mut stdin.lock(),
+                )?;
+                backup::Existing::Drop
+            } else {
+                backup::Existing::Fail("--overwrite")
+            };
+
+            let mut summary = backup::restore(&mut conn, &doc, existing)?;
+            Ok(Some(json_to_msgpack(&summary.remove(0))))
+        }
+        Commands::Migrate {
+            command:
+                MigrateCommands::AddAttribute {
+                    table,
+                    name,
+                    default,
+                },
+        } => {
+            validate_name("attribute", name)?;
+            let default = match default {
+                Some(json) => input_to_msgpack(
+                    &serde_json::from_str(json)
+                        .map_err(|e| Error::Input(format!("invalid --default JSON: {}", e)))?,
+                )?,
+                None => Value::Nil,
+            };
+            let change = Value::Map(vec![
+                (Value::from("add_attribute"), Value::from(name.as_str())),
+                (Value::from("default"), default),
+            ]);
+            call(
+                cli,
+                "alter_table",
+                vec![Value::String(table.clone().into()), change],
+            )
+            .map_err(|e| match e {
+                e if e.reason() == Some("attribute_exists") => Error::Input(format!(
+                    "table '{}' already has an attribute '{}'",
+                    table, name
+                )),
+                other => other,
+            })
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Render a `describe` result as a readable summary.
+pub fn render_describe(schema: &serde_json::Value) -> String {
+    let name = schema["table"].as_str().unwrap_or("?");
+    let key = schema["key"].as_str().unwrap_or("?");
+
+    let mut out = format!("table: {}\nkey:   {}\nattributes:\n", name, key);
+    for attr in schema["attributes"].as_array().into_iter().flatten() {
+        let attr = attr.as_str().unwrap_or("?");
+        let indexed = schema["indexes"]
+            .as_array()
+            .is_some_and(|indexes| indexes.iter().any(|i| i.as_str() == Some(attr)));
+        if attr == key {
+            out.push_str(&format!("  {} (primary key)\n", attr));
+        } else if indexed {
+            out.push_str(&format!("  {} (indexed)\n", attr));
+        } else {
+            out.push_str(&format!("  {}\n", attr));
+        }
+    }
+    out
+}
+
+/// Check that an existing table's `describe` result has the key and
+/// attributes `create-table` asked for (attribute order aside from the key
+/// doesn't matter).
+fn check_existing_schema(
+    name: &str,
+    wanted: &[&str],
+    schema: Option<&serde_json::Value>,
+) -> Result<(), Error> {
+    let key = schema.and_then(|s| s["key"].as_str());
+    let existing: Vec<&str> = schema
+        .and_then(|s| s["attributes"].as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(serde_json::Value::as_str)
+        .collect();
+
+    let mut sorted_existing = existing.clone();
+    let mut sorted_wanted = wanted.to_vec();
+    sorted_existing.sort_unstable();
+    sorted_wanted.sort_unstable();
+    if key == wanted.first().copied() && sorted_existing == sorted_wanted {
+        return Ok(());
+    }
+    Err(Error::Conflict(format!(
+        "table '{}' already exists with attributes {} (key {}), not {}",
+        name,
+        existing.join(","),
+        key.unwrap_or("?"),
+        wanted.join(",")
+    )))
+}
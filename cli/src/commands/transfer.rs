@@ -0,0 +1,300 @@
+//! Moving records in and out of the daemon: `backup`, `restore`, `export`,
+//! and `import`.
+
+use super::records::{check_report, put_many, write_in_parallel, write_report};
+use super::{check_value_size, pipeline};
+use crate::connection::Connection;
+use crate::error::Error;
+use crate::{
+    backup, call, confirm, connect, dry_run_request, export, import, input_to_msgpack,
+    json_to_msgpack, open, progress, validate_name, Cli, Commands, OutputFormat,
+};
+use rmpv::Value;
+use std::fs::File;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+
+pub fn run(cli: &Cli, command: &Commands) -> Result<Option<Value>, Error> {
+    match command {
+        Commands::Backup { file } => {
+            let mut conn = connect(cli)?;
+            let progress = cli.progress("backup", None);
+            let summary = match file {
+                Some(path) => backup_to_file(&mut conn, path, &progress),
+                None => backup::backup(&mut conn, &mut io::stdout().lock(), &progress),
+            };
+            progress.finish();
+            let summary = summary?;
+            if !cli.quiet {
+                eprintln!(
+                    "backed up {} tables ({} records)",
+                    summary.tables, summary.records
+                );
+            }
+            Ok(None)
+        }
+        Commands::Export { table, file } => {
+            let format = file_format(cli);
+            let mut conn = connect(cli)?;
+            let progress = cli.progress("export", None);
+            let count = if file.as_os_str() == "-" {
+                export::export(
+                    &mut conn,
+                    table,
+                    format,
+                    &mut io::stdout().lock(),
+                    &progress,
+                )
+            } else {
+                write_via_partial(file, |out| {
+                    export::export(&mut conn, table, format, out, &progress)
+                })
+            };
+            progress.finish();
+            let count = count?;
+            if !cli.quiet {
+                eprintln!("exported {} records from '{}'", count, table);
+            }
+            Ok(None)
+        }
+        Commands::Import {
+            table,
+            file,
+            create,
+            on_conflict,
+            parallel,
+        } => {
+            let records = import::read_records(file, file_format(cli))?;
+            let Some(first) = records.first() else {
+                return Err(Error::Input(format!("no records in {}", file.display())));
+            };
+            let created = if *create {
+                create_table_like(cli, table, first)?
+            } else {
+                None
+            };
+            let result = match on_conflict {
+                import::OnConflict::Overwrite => put_many(cli, table, &records, *parallel)?,
+                import::OnConflict::Skip => {
+                    put_new_records(cli, table, &records, false, *parallel)?
+                }
+                import::OnConflict::Fail => put_new_records(cli, table, &records, true, *parallel)?,
+            };
+            match (created, result) {
+                // Under --dry-run, the requests that would be sent
+                (Some(create), Value::Array(mut requests)) => {
+                    requests.insert(0, create);
+                    Ok(Some(Value::Array(requests)))
+                }
+                (_, report) => {
+                    let out = &mut io::stdout().lock();
+                    check_report(cli, report, records.len(), cli.stdout_color(), out)
+                }
+            }
+        }
+        Commands::Restore {
+            file,
+            skip_existing,
+            drop_first,
+            yes,
+            parallel,
+        } => {
+            let doc = if file.as_os_str() == "-" {
+                backup::Document::read(io::stdin().lock())?
+            } else {
+                let input = File::open(file)
+                    .map_err(|e| Error::Input(format!("cannot open {}: {}", file.display(), e)))?;
+                backup::Document::read(io::BufReader::new(input))?
+            };
+
+            if cli.dry_run {
+                // Without connecting we can't tell which tables exist, so show
+                // the full restore into an empty namespace
+                let plan = backup::restore_plan(&doc)
+                    .into_iter()
+                    .map(|(method, params)| dry_run_request(method, params))
+                    .collect();
+                return Ok(Some(Value::Array(plan)));
+            }
+
+            let existing = match (skip_existing, drop_first) {
+                (true, _) => backup::Existing::Skip,
+                (_, true) => {
+                    let stdin = io::stdin();
+                    confirm(
+                        "Drop and replace tables that already exist?",
+                        *yes,
+                        stdin.is_terminal(),
+                        &mut stdin.lock(),
+                    )?;
+                    backup::Existing::Drop
+                }
+                _ => backup::Existing::Fail("--skip-existing or --drop-first"),
+            };
+
+            let mut conn = connect(cli)?;
+            let mut workers = (1..*parallel)
+                .map(|_| open(cli))
+                .collect::<Result<Vec<_>, _>>()?;
+            let total = doc.tables.iter().map(|t| t.records.len()).sum();
+            let progress = cli.progress("restore", Some(total));
+            let summary = backup::restore_with(&mut conn, &mut workers, &doc, existing, &progress);
+            progress.finish();
+            let summary = summary?;
+            Ok(Some(json_to_msgpack(&serde_json::Value::Array(summary))))
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Back up into `path` via a temporary file, so a failed backup never
+/// replaces a good one.
+pub fn backup_to_file(
+    conn: &mut Connection,
+    path: &Path,
+    progress: &progress::Progress,
+) -> Result<backup::Summary, Error> {
+    write_via_partial(path, |out| backup::backup(conn, out, progress))
+}
+
+/// The export or import file format `--format` picks: csv or msgpack, or
+/// JSON lines for anything else.
+fn file_format(cli: &Cli) -> export::Format {
+    match cli.output {
+        Some(OutputFormat::Csv) => export::Format::Csv,
+        Some(OutputFormat::Msgpack) => export::Format::Msgpack,
+        _ => export::Format::Jsonl,
+    }
+}
+
+/// Have `write` fill `path` through a `.partial` file alongside it, which
+/// only replaces `path` once `write` succeeds.
+fn write_via_partial<T>(
+    path: &Path,
+    write: impl FnOnce(&mut io::BufWriter<File>) -> Result<T, Error>,
+) -> Result<T, Error> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".partial");
+    let tmp = PathBuf::from(tmp);
+
+    let file = File::create(&tmp)
+        .map_err(|e| Error::Output(format!("cannot create {}: {}", tmp.display(), e)))?;
+    let result = write(&mut io::BufWriter::new(file)).inspect_err(|_| {
+        let _ = std::fs::remove_file(&tmp);
+    })?;
+
+    std::fs::rename(&tmp, path)
+        .map_err(|e| Error::Output(format!("cannot write {}: {}", path.display(), e)))?;
+    Ok(result)
+}
+
+/// Create `table` with the fields of `record` as its attributes, the first
+/// as the primary key, unless it exists already. Under `--dry-run`, gives
+/// the request that would be sent instead.
+fn create_table_like(
+    cli: &Cli,
+    table: &str,
+    record: &serde_json::Value,
+) -> Result<Option<Value>, Error> {
+    let fields = record
+        .as_object()
+        .filter(|fields| !fields.is_empty())
+        .ok_or_else(|| Error::Input("the first record must be a non-empty object".to_string()))?;
+    validate_name("table", table)?;
+    let attributes = fields
+        .keys()
+        .map(|field| validate_name("attribute", field).map(|_| Value::from(field.as_str())))
+        .collect::<Result<Vec<_>, Error>>()?;
+    let params = vec![Value::from(table), Value::Array(attributes)];
+    match call(cli, "create_table", params) {
+        Ok(created) if cli.dry_run => Ok(created),
+        Ok(_) => Ok(None),
+        Err(e) if e.reason() == Some("already_exists") => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Insert the `records` whose keys aren't in `table` yet, with pipelined
+/// `cas_put`s that leave existing records alone. With `stop_at_existing`
+/// they are sent one at a time instead, and the first record whose key is
+/// taken ends the import as a conflict. Reports like [`put_many`], plus how
+/// many records were skipped.
+fn put_new_records(
+    cli: &Cli,
+    table: &str,
+    records: &[serde_json::Value],
+    stop_at_existing: bool,
+    parallel: u8,
+) -> Result<Value, Error> {
+    let mut outcomes: Vec<Option<Result<(), Error>>> = vec![None; records.len()];
+    let mut requests = Vec::new();
+    for (i, record) in records.iter().enumerate() {
+        let encoded =
+            input_to_msgpack(record).and_then(|value| check_value_size(cli, &value).map(|_| value));
+        match encoded {
+            Ok(value) => requests.push((i, vec![Value::from(table), value, Value::Nil])),
+            Err(e) => outcomes[i] = Some(Err(e)),
+        }
+    }
+    if cli.dry_run {
+        let requests = requests
+            .into_iter()
+            .map(|(_, params)| dry_run_request("cas_put", params));
+        return Ok(Value::Array(requests.collect()));
+    }
+
+    // Once one worker finds a key taken, the others stop too
+    let stopped = AtomicBool::new(false);
+    let results = write_in_parallel(cli, parallel, table, &requests, |conn, share, results| {
+        if !stop_at_existing {
+            let params = share.iter().map(|(_, params)| params.clone());
+            let sent = pipeline(conn, "cas_put", params)?;
+            let sent = sent.into_iter().map(|r| r.map(|_| ()));
+            results.extend(share.iter().map(|(i, _)| *i).zip(sent));
+            return Ok(());
+        }
+        for (i, params) in share {
+            if stopped.load(std::sync::atomic::Ordering::SeqCst) {
+                break;
+            }
+            let result = conn.call("cas_put", params.clone()).map(|_| ());
+            let taken = matches!(&result, Err(e) if e.reason() == Some("condition_failed"));
+            results.push((*i, result));
+            if taken {
+                stopped.store(true, std::sync::atomic::Ordering::SeqCst);
+                break;
+            }
+        }
+        Ok(())
+    })?;
+
+    let mut skipped = 0;
+    let mut taken = None;
+    for (i, result) in results {
+        match result {
+            Err(e) if e.reason() == Some("condition_failed") => {
+                skipped += 1;
+                // Shares come back in order, so this is the first in the file
+                taken.get_or_insert(i);
+            }
+            result => outcomes[i] = Some(result),
+        }
+    }
+    if let (true, Some(i)) = (stop_at_existing, taken) {
+        let written = outcomes
+            .iter()
+            .filter(|o| matches!(o, Some(Ok(()))))
+            .count();
+        return Err(Error::Conflict(format!(
+            "record {} has a key already in '{}' ({} records were written before the \
+             import stopped)",
+            i + 1,
+            table,
+            written
+        )));
+    }
+    let mut report = write_report(table, outcomes);
+    report["skipped"] = serde_json::json!(skipped);
+    Ok(json_to_msgpack(&report))
+}
@@ -0,0 +1,186 @@
+//! `watch`: following a table's changes as JSON lines.
+
+use crate::connection::{self, Connection};
+use crate::error::Error;
+use crate::{connect, msgpack_to_json, open, parse_seconds, Cli, Commands, RETRY_DELAY};
+use rmpv::Value;
+use std::io::{self, Write};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub fn run(cli: &Cli, command: &Commands) -> Result<Option<Value>, Error> {
+    match command {
+        Commands::Watch {
+            table,
+            duration,
+            reconnect,
+        } => {
+            let deadline = duration
+                .as_deref()
+                .map(|d| parse_seconds(d).map(|secs| Instant::now() + Duration::from_secs(secs)))
+                .transpose()?;
+            let stop = Arc::new(AtomicBool::new(false));
+            let flag = Arc::clone(&stop);
+            // Without the handler Ctrl-C still ends the process, and the
+            // daemon drops the subscription along with the connection
+            let _ =
+                ctrlc::set_handler(move || flag.store(true, std::sync::atomic::Ordering::SeqCst));
+            let out = &mut io::stdout().lock();
+            if *reconnect {
+                let notices: &mut dyn Write = if cli.quiet {
+                    &mut io::sink()
+                } else {
+                    &mut io::stderr()
+                };
+                watch_reconnecting(|| open(cli), table, &stop, deadline, out, notices)?;
+            } else {
+                watch(&mut *connect(cli)?, table, &stop, deadline, out)?;
+            }
+            Ok(None)
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// How often `watch` wakes between changes to check for Ctrl-C or the end
+/// of `--duration`.
+const WATCH_POLL: Duration = Duration::from_millis(200);
+
+/// Longest pause between `watch --reconnect` attempts.
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(10);
+
+/// Subscribe to a table and print each change notification as a JSON line
+/// until the daemon closes the stream, `stop` is set, or `deadline` passes.
+/// The last two unsubscribe before returning.
+pub fn watch(
+    conn: &mut Connection,
+    table: &str,
+    stop: &AtomicBool,
+    deadline: Option<Instant>,
+    out: &mut impl Write,
+) -> Result<(), Error> {
+    conn.call("subscribe", vec![Value::String(table.into())])?;
+    follow_changes(conn, table, stop, deadline, out)
+}
+
+/// Print the changes arriving on a subscribed connection until stopped,
+/// past the deadline, or the daemon closes the connection.
+fn follow_changes(
+    conn: &mut Connection,
+    table: &str,
+    stop: &AtomicBool,
+    deadline: Option<Instant>,
+    out: &mut impl Write,
+) -> Result<(), Error> {
+    loop {
+        if stop.load(std::sync::atomic::Ordering::SeqCst)
+            || deadline.is_some_and(|at| Instant::now() >= at)
+        {
+            return unsubscribe(conn, table);
+        }
+        // Changes can be hours apart; --timeout only covers the subscribe
+        // itself, so wake up just often enough to notice Ctrl-C
+        conn.set_timeout(Some(WATCH_POLL))?;
+        match conn.at_eof() {
+            Ok(true) => return Ok(()),
+            Ok(false) => {}
+            Err(Error::Timeout(_)) => continue,
+            Err(e) => return Err(e),
+        }
+        // A change has started arriving; never give up halfway through it
+        conn.set_timeout(None)?;
+        let message = conn.recv()?;
+        if let Some(("change", events)) = connection::decode_notification(&message) {
+            for event in events {
+                write_watch_line(out, &msgpack_to_json(event))?;
+            }
+        }
+    }
+}
+
+fn write_watch_line(out: &mut impl Write, event: &serde_json::Value) -> Result<(), Error> {
+    writeln!(out, "{}", event)
+        .and_then(|_| out.flush())
+        .map_err(|e| Error::Output(format!("write error: {}", e)))
+}
+
+/// [`watch`] that outlives the daemon: when the connection closes or
+/// breaks, reconnect through `open` with backoff and subscribe again, until
+/// stopped or past the deadline. Changes made while disconnected are lost,
+/// so each reconnection prints a `{"__reconnected__": true}` line among
+/// the changes, and a notice to `notices`.
+pub fn watch_reconnecting(
+    mut open: impl FnMut() -> Result<Connection, Error>,
+    table: &str,
+    stop: &AtomicBool,
+    deadline: Option<Instant>,
+    out: &mut impl Write,
+    notices: &mut dyn Write,
+) -> Result<(), Error> {
+    let done = || {
+        stop.load(std::sync::atomic::Ordering::SeqCst)
+            || deadline.is_some_and(|at| Instant::now() >= at)
+    };
+    let mut subscribe = || -> Result<Connection, Error> {
+        let mut conn = open()?;
+        conn.call("subscribe", vec![Value::String(table.into())])?;
+        Ok(conn)
+    };
+
+    let mut conn = subscribe()?;
+    loop {
+        let lost = match follow_changes(&mut conn, table, stop, deadline, out) {
+            Ok(()) if done() => return Ok(()),
+            Ok(()) => "the daemon closed the connection".to_string(),
+            Err(e @ (Error::Connection(_) | Error::Timeout(_))) => e.to_string(),
+            Err(e) => return Err(e),
+        };
+        let _ = writeln!(notices, "watch: {}; reconnecting", lost);
+
+        let mut delay = RETRY_DELAY;
+        conn = loop {
+            let wake = Instant::now() + delay;
+            while !done() && Instant::now() < wake {
+                std::thread::sleep(WATCH_POLL.min(wake.saturating_duration_since(Instant::now())));
+            }
+            if done() {
+                return Ok(());
+            }
+            match subscribe() {
+                Ok(conn) => break conn,
+                Err(Error::Connection(_) | Error::Timeout(_)) => {
+                    delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+                }
+                Err(e) => return Err(e),
+            }
+        };
+        let _ = writeln!(
+            notices,
+            "watch: reconnected to '{}'; changes made while disconnected were missed",
+            table
+        );
+        write_watch_line(out, &serde_json::json!({ "__reconnected__": true }))?;
+    }
+}
+
+/// End a `watch` subscription, skipping change notifications still in
+/// flight ahead of the reply. Daemons without `unsubscribe` drop the
+/// subscription when the connection closes, which is just as good.
+fn unsubscribe(conn: &mut Connection, table: &str) -> Result<(), Error> {
+    conn.set_timeout(Some(RETRY_DELAY * 5))?;
+    let msgid = conn.send("unsubscribe", vec![Value::String(table.into())])?;
+    loop {
+        let message = conn.recv()?;
+        if connection::decode_notification(&message).is_some() {
+            continue;
+        }
+        if message.as_array().and_then(|parts| parts.get(1)?.as_u64()) != Some(msgid.into()) {
+            return Err(Error::Protocol("invalid response format".to_string()));
+        }
+        return match connection::decode_response(message) {
+            Err(e) if e.is_unknown_method() => Ok(()),
+            result => result.map(|_| ()),
+        };
+    }
+}
@@ -0,0 +1,100 @@
+//! Shell completion. `cortex completions SHELL` prints a script that has the
+//! shell call back into cortex for each completion, so table arguments can
+//! be completed from the daemon's own list of tables.
+
+use crate::config::Config;
+use crate::{Cli, DEFAULT_SOCKET};
+use clap::{Command, CommandFactory, ValueEnum};
+use clap_complete::engine::{ArgValueCompleter, CompletionCandidate};
+use clap_complete::env::{Bash, EnvCompleter, Fish, Zsh};
+use clap_complete::CompleteEnv;
+use cortex_client::Connection;
+use rmpv::Value;
+use std::ffi::OsStr;
+use std::io::{self, Write};
+use std::time::Duration;
+
+/// Set to the shell's name when the shell asks cortex for completions.
+const VAR: &str = "CORTEX_COMPLETE";
+
+/// How long to wait for the daemon's table list before offering nothing,
+/// so a hung daemon doesn't hang the shell.
+const TABLES_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Arguments that name an existing table, in any subcommand.
+const TABLE_ARGS: [&str; 4] = ["table", "src", "left", "right"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// If the shell is asking for completions, answer and exit; otherwise
+/// return so the command runs as usual.
+pub fn complete_if_asked() {
+    CompleteEnv::with_factory(command).var(VAR).complete();
+}
+
+/// Write the script that registers cortex's completions with `shell`.
+pub fn write_script(shell: Shell, out: &mut dyn Write) -> io::Result<()> {
+    let completer: &dyn EnvCompleter = match shell {
+        Shell::Bash => &Bash,
+        Shell::Zsh => &Zsh,
+        Shell::Fish => &Fish,
+    };
+    completer.write_registration(VAR, "cortex", "cortex", "cortex", out)
+}
+
+/// The CLI's command tree, with table arguments completing table names.
+pub fn command() -> Command {
+    complete_tables(Cli::command())
+        .mut_subcommand("drop-table", |c| c.mut_arg("name", table_completer))
+}
+
+fn complete_tables(command: Command) -> Command {
+    command
+        .mut_args(|arg| {
+            if TABLE_ARGS.contains(&arg.get_id().as_str()) {
+                table_completer(arg)
+            } else {
+                arg
+            }
+        })
+        .mut_subcommands(complete_tables)
+}
+
+fn table_completer(arg: clap::Arg) -> clap::Arg {
+    arg.add(ArgValueCompleter::new(|current: &OsStr| {
+        let socket = Config::load(None)
+            .ok()
+            .and_then(|config| config.socket)
+            .unwrap_or_else(|| DEFAULT_SOCKET.to_string());
+        current
+            .to_str()
+            .map(|prefix| table_names(&socket, prefix))
+            .unwrap_or_default()
+            .into_iter()
+            .map(CompletionCandidate::new)
+            .collect()
+    }))
+}
+
+/// The tables at `socket` whose names start with `prefix`. Any failure
+/// just means there is nothing to suggest.
+pub fn table_names(socket: &str, prefix: &str) -> Vec<String> {
+    let tables = Connection::new(socket).and_then(|mut conn| {
+        conn.set_timeout(Some(TABLES_TIMEOUT))?;
+        conn.call("tables", Vec::new())
+    });
+    match tables {
+        Ok(Some(Value::Array(names))) => names
+            .iter()
+            .filter_map(Value::as_str)
+            .filter(|name| name.starts_with(prefix))
+            .map(str::to_string)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
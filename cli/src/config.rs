@@ -0,0 +1,180 @@
+use crate::error::Error;
+use crate::OutputFormat;
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// Defaults read from `~/.config/cortex/config.toml`.
+///
+/// Every field is optional; command-line flags win over anything set here,
+/// and anything set here wins over the built-in defaults. `CORTEX_OUTPUT`
+/// and `CORTEX_PRETTY` sit between the flags and the file.
+#[derive(Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub socket: Option<String>,
+    pub output: Option<OutputFormat>,
+    /// Duration like `10s` or `2m`, same syntax as `--timeout`
+    pub timeout: Option<String>,
+    pub pretty: Option<bool>,
+    pub retry: Option<u32>,
+    pub retry_on_busy: Option<u32>,
+    /// Largest record `put` sends, in encoded bytes
+    pub max_value_size: Option<u64>,
+    /// Largest response read before giving up, in bytes (0 for no limit)
+    pub max_response_size: Option<u64>,
+    /// Daemon error substrings `--retry-on-busy` treats as transient
+    pub busy_patterns: Option<Vec<String>>,
+}
+
+impl Config {
+    /// Load the config at `path`, or the default location if `path` is None.
+    ///
+    /// A missing default file is not an error; a missing explicit one is.
+    pub fn load(path: Option<&Path>) -> Result<Self, Error> {
+        let (path, explicit) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+
+        match std::fs::read_to_string(&path) {
+            Ok(text) => {
+                Self::parse(&text).map_err(|e| Error::Input(format!("{}: {}", path.display(), e)))
+            }
+            Err(e) if !explicit && e.kind() == std::io::ErrorKind::NotFound => {
+                Ok(Config::default())
+            }
+            Err(e) => Err(Error::Input(format!(
+                "cannot read config {}: {}",
+                path.display(),
+                e
+            ))),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        toml::from_str(text).map_err(|e| Error::Input(format!("invalid config: {}", e.message())))
+    }
+
+    /// Override settings with the `CORTEX_OUTPUT` and `CORTEX_PRETTY`
+    /// environment variables, looked up through `var`. Unset or empty
+    /// variables leave the file's settings alone.
+    pub fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), Error> {
+        let var = |name| var(name).filter(|value| !value.is_empty());
+        if let Some(value) = var("CORTEX_OUTPUT") {
+            let output = OutputFormat::from_str(&value, true).map_err(|_| {
+                let names: Vec<_> = OutputFormat::value_variants()
+                    .iter()
+                    .filter_map(|format| format.to_possible_value())
+                    .map(|format| format.get_name().to_string())
+                    .collect();
+                Error::Input(format!(
+                    "invalid CORTEX_OUTPUT '{}': expected one of {}",
+                    value,
+                    names.join(", ")
+                ))
+            })?;
+            self.output = Some(output);
+        }
+        if let Some(value) = var("CORTEX_PRETTY") {
+            self.pretty = Some(match value.to_ascii_lowercase().as_str() {
+                "1" | "true" | "yes" | "on" => true,
+                "0" | "false" | "no" | "off" => false,
+                _ => {
+                    return Err(Error::Input(format!(
+                        "invalid CORTEX_PRETTY '{}': expected true or false",
+                        value
+                    )))
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+/// `$XDG_CONFIG_HOME/cortex/config.toml`, falling back to `~/.config`.
+fn default_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(base.join("cortex").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_setting() {
+        let config = Config::parse(
+            r#"
+            socket = "/tmp/cortex.sock"
+            output = "json"
+            timeout = "10s"
+            pretty = true
+            retry = 3
+            retry_on_busy = 2
+            max_value_size = 4000000
+            max_response_size = 0
+            busy_patterns = ["busy", "locked"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config,
+            Config {
+                socket: Some("/tmp/cortex.sock".to_string()),
+                output: Some(OutputFormat::Json),
+                timeout: Some("10s".to_string()),
+                pretty: Some(true),
+                retry: Some(3),
+                retry_on_busy: Some(2),
+                max_value_size: Some(4_000_000),
+                max_response_size: Some(0),
+                busy_patterns: Some(vec!["busy".to_string(), "locked".to_string()]),
+            }
+        );
+    }
+
+    #[test]
+    fn environment_overrides_the_file() {
+        let env = |name: &str| match name {
+            "CORTEX_OUTPUT" => Some("NDJSON".to_string()),
+            "CORTEX_PRETTY" => Some("0".to_string()),
+            _ => None,
+        };
+        let mut config = Config::parse("output = \"json\"\npretty = true").unwrap();
+        config.apply_env(env).unwrap();
+        assert_eq!(config.output, Some(OutputFormat::Ndjson));
+        assert_eq!(config.pretty, Some(false));
+
+        let mut config = Config::parse("output = \"json\"").unwrap();
+        config.apply_env(|_| Some(String::new())).unwrap();
+        assert_eq!(config.output, Some(OutputFormat::Json));
+
+        let err = Config::default()
+            .apply_env(|name| (name == "CORTEX_OUTPUT").then(|| "xml".to_string()))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid CORTEX_OUTPUT 'xml': expected one of text, json, ndjson, table, csv, yaml, raw, msgpack"
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        let err = Config::parse("sokcet = \"/tmp/x\"").unwrap_err();
+        assert_eq!(err.code(), "input");
+    }
+
+    #[test]
+    fn missing_explicit_file_is_an_error() {
+        let missing = std::env::temp_dir().join("cortex-no-such-config.toml");
+        assert!(Config::load(Some(&missing)).is_err());
+    }
+}
@@ -0,0 +1,198 @@
+//! Record-level comparison of two tables.
+
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+
+/// Compare two tables' records, aligned on the primary key field `key`.
+///
+/// Returns `{added, removed, changed, unchanged}`: keys only in `right`,
+/// keys only in `left`, `{key, fields: {name: {left, right}}}` for records
+/// that differ (a missing field shows as null), and how many were equal.
+pub fn diff(key: &str, left: &[Value], right: &[Value]) -> Value {
+    let left = by_key(key, left);
+    let right = by_key(key, right);
+
+    let added: Vec<&Value> = right
+        .iter()
+        .filter(|(k, _)| !left.contains_key(*k))
+        .map(|(_, (key, _))| *key)
+        .collect();
+    let removed: Vec<&Value> = left
+        .iter()
+        .filter(|(k, _)| !right.contains_key(*k))
+        .map(|(_, (key, _))| *key)
+        .collect();
+
+    let mut changed = Vec::new();
+    let mut unchanged = 0;
+    for (k, (key, before)) in &left {
+        let Some((_, after)) = right.get(k) else {
+            continue;
+        };
+        let fields = field_changes(before, after);
+        if fields.is_empty() {
+            unchanged += 1;
+        } else {
+            changed.push(json!({"key": key, "fields": fields}));
+        }
+    }
+
+    json!({
+        "added": added,
+        "removed": removed,
+        "changed": changed,
+        "unchanged": unchanged,
+    })
+}
+
+/// Records indexed by their key, as text so keys of any type sort stably.
+fn by_key<'a>(key: &str, records: &'a [Value]) -> BTreeMap<String, (&'a Value, &'a Value)> {
+    records
+        .iter()
+        .filter_map(|record| {
+            let value = record.get(key)?;
+            let text = match value {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            Some((text, (value, record)))
+        })
+        .collect()
+}
+
+/// `{name: {left, right}}` for each field that differs between two records,
+/// in `before`'s field order and then any fields only `after` has.
+pub fn field_changes(before: &Value, after: &Value) -> Map<String, Value> {
+    let empty = Map::new();
+    let before = before.as_object().unwrap_or(&empty);
+    let after = after.as_object().unwrap_or(&empty);
+
+    let mut names: Vec<&String> = before.keys().collect();
+    names.extend(after.keys().filter(|k| !before.contains_key(*k)));
+
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let (l, r) = (before.get(name), after.get(name));
+            (l != r).then(|| {
+                let change = json!({
+                    "left": l.cloned().unwrap_or(Value::Null),
+                    "right": r.cloned().unwrap_or(Value::Null),
+                });
+                (name.clone(), change)
+            })
+        })
+        .collect()
+}
+
+/// `+ key` for added, `- key` for removed, and `~ key` followed by one
+/// `field: left -> right` line per change, then a one-line summary.
+pub fn render(diff: &Value) -> String {
+    let list = |name: &str| diff[name].as_array().cloned().unwrap_or_default();
+    let label = |key: &Value| match key {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+    let (added, removed, changed) = (list("added"), list("removed"), list("changed"));
+
+    let mut out = String::new();
+    for key in &added {
+        out += &format!("+ {}\n", label(key));
+    }
+    for key in &removed {
+        out += &format!("- {}\n", label(key));
+    }
+    for change in &changed {
+        out += &format!("~ {}\n", label(&change["key"]));
+        if let Some(fields) = change["fields"].as_object() {
+            for (name, values) in fields {
+                out += &format!("    {}: {} -> {}\n", name, values["left"], values["right"]);
+            }
+        }
+    }
+
+    let unchanged = diff["unchanged"].as_u64().unwrap_or(0);
+    match added.len() + removed.len() + changed.len() {
+        0 => out += &format!("no differences ({} records)\n", unchanged),
+        n => {
+            out += &format!(
+                "{} difference{} ({} added, {} removed, {} changed); {} unchanged\n",
+                n,
+                if n == 1 { "" } else { "s" },
+                added.len(),
+                removed.len(),
+                changed.len(),
+                unchanged
+            )
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn users() -> Vec<Value> {
+        vec![
+            json!({"id": "u1", "name": "alice"}),
+            json!({"id": "u2", "name": "bob"}),
+        ]
+    }
+
+    #[test]
+    fn added_only() {
+        let mut right = users();
+        right.push(json!({"id": "u3", "name": "carol"}));
+
+        let result = diff("id", &users(), &right);
+        assert_eq!(
+            result,
+            json!({"added": ["u3"], "removed": [], "changed": [], "unchanged": 2})
+        );
+    }
+
+    #[test]
+    fn removed_only() {
+        let result = diff("id", &users(), &users()[1..]);
+        assert_eq!(
+            result,
+            json!({"added": [], "removed": ["u1"], "changed": [], "unchanged": 1})
+        );
+    }
+
+    #[test]
+    fn field_level_changes() {
+        let right = vec![
+            json!({"id": "u1", "name": "alicia", "email": "a@b.com"}),
+            json!({"id": "u2", "name": "bob"}),
+        ];
+
+        let result = diff("id", &users(), &right);
+        assert_eq!(
+            result["changed"],
+            json!([{"key": "u1", "fields": {
+                "name": {"left": "alice", "right": "alicia"},
+                "email": {"left": null, "right": "a@b.com"}
+            }}])
+        );
+        assert_eq!(result["unchanged"], 1);
+        assert_eq!(
+            render(&result),
+            concat!(
+                "~ u1\n",
+                "    name: \"alice\" -> \"alicia\"\n",
+                "    email: null -> \"a@b.com\"\n",
+                "1 difference (0 added, 0 removed, 1 changed); 1 unchanged\n",
+            )
+        );
+    }
+
+    #[test]
+    fn identical_tables() {
+        assert_eq!(
+            render(&diff("id", &users(), &users())),
+            "no differences (2 records)\n"
+        );
+    }
+}
@@ -0,0 +1,120 @@
+//! Writing one table's records out as JSON lines, MessagePack, or CSV.
+
+use crate::backup::schema_order;
+use crate::connection::Connection;
+use crate::error::Error;
+use crate::msgpack_to_json;
+use crate::progress::Progress;
+use crate::render::{csv_cell, csv_row};
+use rmpv::Value;
+use std::io::Write;
+
+/// Records fetched per `all` request.
+pub const PAGE_SIZE: u32 = 1000;
+
+/// The layout of an export (or import) file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// One JSON object per line
+    #[default]
+    Jsonl,
+    /// One MessagePack map after another
+    Msgpack,
+    /// A header row of field names, then one row per record
+    Csv,
+}
+
+/// Write every record in `table` to `out` as `format`, returning how many
+/// were written.
+///
+/// Records are fetched a page at a time by cursor, so neither the daemon
+/// nor the client ever holds more than one page, and records written during
+/// the export don't shift later pages. `progress` advances with each page.
+pub fn export(
+    conn: &mut Connection,
+    table: &str,
+    format: Format,
+    out: &mut impl Write,
+    progress: &Progress,
+) -> Result<usize, Error> {
+    let columns = match format {
+        Format::Csv => {
+            let schema = conn
+                .describe(table)?
+                .map(|s| msgpack_to_json(&s))
+                .ok_or_else(|| Error::Protocol(format!("no schema for table '{}'", table)))?;
+            let (_, attributes) = schema_order(table, &schema)?;
+            write_csv_row(out, attributes.iter().map(String::as_str))?;
+            attributes
+        }
+        Format::Jsonl | Format::Msgpack => Vec::new(),
+    };
+
+    let mut cursor = Value::Nil;
+    let mut count = 0;
+    loop {
+        let paging = Value::Map(vec![
+            (Value::from("cursor"), cursor),
+            (Value::from("limit"), Value::from(PAGE_SIZE)),
+        ]);
+        let page = conn.call("all", vec![Value::from(table), paging])?;
+        let (records, next) = split_page(table, page)?;
+        for record in &records {
+            write_record(out, format, &columns, record)?;
+        }
+        count += records.len();
+        progress.add(records.len());
+        match next {
+            Value::Nil => break,
+            next => cursor = next,
+        }
+    }
+
+    out.flush()
+        .map_err(|e| Error::Output(format!("write error: {}", e)))?;
+    Ok(count)
+}
+
+/// The records and next cursor of an `all` page.
+pub fn split_page(table: &str, page: Option<Value>) -> Result<(Vec<Value>, Value), Error> {
+    let malformed = || Error::Protocol(format!("expected a page of records for '{}'", table));
+    let Some(Value::Map(entries)) = page else {
+        return Err(malformed());
+    };
+    let (mut records, mut cursor) = (None, Value::Nil);
+    for (k, v) in entries {
+        match (k.as_str(), v) {
+            (Some("records"), Value::Array(page)) => records = Some(page),
+            (Some("cursor"), next) => cursor = next,
+            _ => {}
+        }
+    }
+    Ok((records.ok_or_else(malformed)?, cursor))
+}
+
+fn write_record(
+    out: &mut impl Write,
+    format: Format,
+    columns: &[String],
+    record: &Value,
+) -> Result<(), Error> {
+    let written = match format {
+        Format::Jsonl => writeln!(out, "{}", msgpack_to_json(record)),
+        Format::Msgpack => rmpv::encode::write_value(out, record).map_err(Into::into),
+        Format::Csv => {
+            let record = msgpack_to_json(record);
+            let cells: Vec<String> = columns.iter().map(|c| csv_cell(&record[c])).collect();
+            return write_csv_row(out, cells.iter().map(String::as_str));
+        }
+    };
+    written.map_err(|e| Error::Output(format!("write error: {}", e)))
+}
+
+/// Write one CSV row of `cells`.
+fn write_csv_row<'a>(
+    out: &mut impl Write,
+    cells: impl Iterator<Item = &'a str>,
+) -> Result<(), Error> {
+    out.write_all(csv_row(cells).as_bytes())
+        .map_err(|e| Error::Output(format!("write error: {}", e)))
+}
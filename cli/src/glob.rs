@@ -0,0 +1,65 @@
+//! Shell-style wildcard matching for names.
+
+/// Whether all of `text` matches `pattern`, where `*` stands for any run of
+/// characters (including none) and `?` for exactly one. Everything else
+/// matches itself.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // Where the last `*` was, and the text position it has consumed up to
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                // Let the last `*` swallow one more character and retry
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn star_matches_prefix_and_suffix() {
+        assert!(matches("sm_*", "sm_instances"));
+        assert!(matches("sm_*", "sm_"));
+        assert!(!matches("sm_*", "users"));
+
+        assert!(matches("*_memories", "private_memories"));
+        assert!(!matches("*_memories", "memories"));
+        assert!(matches("*", ""));
+    }
+
+    #[test]
+    fn question_mark_matches_one_character() {
+        assert!(matches("log_?", "log_1"));
+        assert!(!matches("log_?", "log_"));
+        assert!(!matches("log_?", "log_12"));
+        assert!(matches("?_*_?", "a_mid_z"));
+    }
+
+    #[test]
+    fn no_wildcards_is_exact() {
+        assert!(matches("users", "users"));
+        assert!(!matches("users", "users2"));
+    }
+}
@@ -0,0 +1,1429 @@
+//! The help text: the command summary, and a page for each topic.
+
+pub fn print_help() {
+    println!(
+        r#"cortex - Local storage daemon CLI
+
+USAGE:
+  cortex <command> [args] [--pretty]
+
+COMMANDS:
+  ping                          Health check
+  wait-ready                    Block until the daemon answers (--timeout)
+  status                        Daemon status
+  health                        Healthy/degraded/down check for monitors
+  version                       CLI and daemon versions (compatibility check)
+  whoami                        UID the daemon sees for this connection
+  tables [--pattern GLOB]       List your tables
+
+  create-table NAME ATTRS       Create table (ATTRS: comma-separated, first is key)
+                                [--if-not-exists]
+  drop-table NAME [--yes]       Drop a table
+  create-index TABLE ATTR       Index an attribute for faster queries
+  drop-index TABLE ATTR         Remove an attribute's index
+  truncate TABLE [--yes]        Delete all records, keep table and ACLs
+  describe TABLE                Show attributes and primary key
+  copy-table SRC DST            Copy schema and records (--schema-only)
+  diff LEFT RIGHT               Show records added, removed, or changed
+  get TABLE KEY                 Get record by key (--fields a,b to project,
+                                --assert-eq/--assert-field to check it,
+                                --keys-file PATH for many keys)
+  exists TABLE KEY              Exit 0 if the record exists, 1 if not
+  put TABLE JSON                Insert/update record (- or --file PATH to read
+                                it, --schema PATH to check it first)
+  put-many TABLE FILE           Insert/update records from JSON lines (- for
+                                stdin)
+  txn                           Apply a JSON array of puts and deletes (--file
+                                PATH or stdin) all or nothing
+  validate TABLE --schema PATH  Check records (--file PATH or stdin) against a
+                                JSON Schema
+  append TABLE KEY FIELD JSON   Append a value to an array field
+  incr TABLE KEY FIELD [N]      Atomically add N (default 1) to a field
+  decr TABLE KEY FIELD [N]      Atomically subtract N (default 1) from a field
+  expire TABLE KEY TTL          Expire a record after TTL (--persist to keep it)
+  delete TABLE KEY              Delete record (--keys-file PATH for many)
+  query TABLE PATTERN           Query by pattern (JSON, or --file PATH)
+  all TABLE                     List all records (--since/--until TIME to filter)
+  aggregate TABLE --sum FIELD   Sum/avg/min/max/count records (--group-by FIELD)
+  count TABLE [PATTERN]         Count records (all, or those matching PATTERN)
+  keys TABLE                    List all keys in a table
+  range TABLE --from K --to K   List records with keys from K to K, in order
+                                (--limit N)
+  watch TABLE [--duration D]    Stream table changes as JSON lines (--reconnect
+                                to survive daemon restarts)
+  backup [FILE]                 Dump all your tables (schemas, records, ACLs)
+  export TABLE FILE             Write one table's records to FILE (--format
+                                jsonl, msgpack, or csv)
+  import TABLE FILE             Write records from FILE to a table (--create,
+                                --on-conflict skip|overwrite|fail)
+  restore FILE                  Recreate tables from a backup (--skip-existing)
+  raw METHOD [PARAMS]           Call any RPC method (PARAMS: JSON array)
+  batch FILE|- [--fail-fast]    Run commands (one per line) over one connection
+
+  migrate add-attribute TABLE NAME  Add an attribute (--default JSON backfills)
+
+  acl grant IDENTITY TABLE PERMS    Grant permissions (--expires 2h for temporary)
+  acl revoke IDENTITY TABLE PERMS   Revoke permissions
+  acl list [--table T]              List ACLs for your tables (--identity ID)
+  acl check IDENTITY TABLE          Show effective permissions
+
+  completions bash|zsh|fish     Print a tab completion script for the shell
+
+OPTIONS:
+  --pretty                      Pretty-print JSON output
+  --compact-under N             Pretty-print, but keep nested objects and arrays
+                                shorter than N bytes on one line
+  --compact-binary [N]          Show binary values over N bytes (default 64) as
+                                {{"__bin__": "<SIZE bytes, sha256=HASH>"}};
+                                --output json and ndjson still print them whole
+  --format, --output FORMAT     Output format: text, json, ndjson, table,
+                                csv, yaml, raw, or msgpack
+  --stats                       Print the result count and time to stderr
+  --no-newline                  Don't end the output with a newline
+  -0, --null-delimited          Print list results one per entry, each ended by
+                                NUL, strings unquoted (for xargs -0)
+  --errors FORMAT               Error format on stderr: text or json
+                                (json: {{"error":"...","code":"..."}}; default
+                                follows --output json)
+  --socket PATH                 Socket path (default: /run/cortex/cortex.sock)
+  --timeout DURATION            Give up on an unresponsive daemon (e.g. 10 or 10s)
+  --retry N                     Retry connecting N times if the daemon is down
+  --max-value-size BYTES        Refuse to put records over BYTES once encoded
+                                (default 1000000; the daemon drops requests
+                                over 1 MiB)
+  --max-response-size BYTES     Abort if a response grows past BYTES (default
+                                268435456, i.e. 256 MiB; 0 for no limit)
+  --retry-on-busy N             Retry a request N times if the daemon reports a
+                                transient error (one containing "busy" or
+                                "timeout", or a --busy-pattern TEXT)
+  --config PATH                 Config file (default: ~/.config/cortex/config.toml)
+  --no-handshake                Don't check the daemon's protocol version
+  --color WHEN                  Colorize JSON: auto (default), always, or never
+  --no-color                    Same as --color never (NO_COLOR is also honored)
+  --dry-run                     Show what put, delete, create-table, drop-table,
+                                truncate, restore, and acl grant/revoke would
+                                send, without connecting
+  -q, --quiet                   Print nothing on success (errors still go to stderr)
+  -v, --verbose                 Dump wire traffic to stderr for debugging
+  --version                     Show version
+  --help                        Show this help
+
+EXIT CODES:
+  0   Success
+  1   No record, for exists
+  2   Cannot connect to the daemon
+  3   Timed out waiting for the daemon
+  4   Protocol error (malformed response)
+  5   Daemon reported an error (e.g. access_denied, not_found)
+  6   Invalid input (bad arguments or JSON, refused confirmation)
+  7   Conditional put's precondition failed (--if-absent, --if-match)
+  8   Writing output failed (e.g. a closed stdout pipe)
+
+  Daemons that report errors as {{code, message}} maps print the message,
+  and with --output json the code as "reason". A conflict or
+  condition_failed code exits 7, invalid_params exits 6, and timeout 3.
+
+CONFIG:
+  socket, output, timeout, pretty, retry, retry_on_busy, max_value_size,
+  and max_response_size can be set in the config file using the same
+  values as the flags, as can the list of busy_patterns, e.g.:
+    socket = "/run/user/1000/cortex.sock"
+    timeout = "10s"
+    busy_patterns = ["busy", "locked"]
+  Command-line flags override the config file.
+
+ENVIRONMENT:
+  CORTEX_OUTPUT                 Default --format (text, json, ndjson, table,
+                                csv, yaml, raw, or msgpack)
+  CORTEX_PRETTY                 Pretty-print JSON when 1 or true (0 or false
+                                turns off pretty = true from the config file)
+  NO_COLOR                      Disable color unless --color always is given
+  These override the config file; command-line flags override them.
+
+EXAMPLES:
+  cortex create-table users id,name,email
+  cortex put users '{{"id":"u1","name":"alice","email":"a@b.com"}}'
+  cortex get users u1
+  cortex query users '{{"name":"alice"}}'
+  cortex acl grant 'uid:1001' users read
+  cortex acl grant '*' users read"#
+    );
+}
+
+pub fn print_topic_help(topic: Option<&str>) {
+    match topic {
+        None | Some("") => print_help(),
+        Some("ping") => println!(
+            r#"cortex ping - Health check
+
+USAGE:
+  cortex ping [--latency [--count N]]
+  cortex ping --connect-only
+
+DESCRIPTION:
+  Tests connectivity to the Cortex daemon. Returns "pong" if the daemon
+  is running and responsive.
+
+  --connect-only only opens a connection to the socket and closes it
+  again, without sending a request, and returns "connected". It works
+  before the daemon can answer requests, and fails with exit code 2 if
+  nothing is at the socket path or nothing is listening there.
+
+OPTIONS:
+  --latency         Report round-trip time in milliseconds instead
+  -c, --count N     Send N pings over one connection (with --latency)
+  --connect-only    Only check the socket accepts connections
+
+EXAMPLES:
+  cortex ping
+  # Output: "pong"
+  cortex ping --latency --count 5
+  # Output: 5 pings: min/avg/max = 0.081/0.112/0.204 ms
+  cortex ping --connect-only --quiet && echo "socket is up""#
+        ),
+        Some("wait-ready") => println!(
+            r#"cortex wait-ready - Wait for the daemon to start
+
+USAGE:
+  cortex wait-ready [--timeout DURATION]
+
+DESCRIPTION:
+  Connects and pings the daemon, retrying every 100ms, until it answers.
+  Exits 0 as soon as it does, or with code 3 once --timeout (default 30s)
+  has passed without an answer. Prints nothing on success.
+
+  Unlike --retry, which only rides out connection failures for a single
+  command, this is meant as a readiness gate for service managers and
+  container entrypoints.
+
+EXAMPLES:
+  cortex wait-ready --timeout 10s && cortex create-table users id,name
+  cortex wait-ready --socket /tmp/cortex.sock"#
+        ),
+        Some("health") => println!(
+            r#"cortex health - Check the daemon is working
+
+USAGE:
+  cortex health [--pretty]
+
+DESCRIPTION:
+  Connects, pings, and reads the daemon's status, then prints one report:
+
+    {{"status": "healthy", "checks": [
+      {{"name": "connectivity", "status": "ok"}},
+      {{"name": "mnesia", "status": "ok"}},
+      {{"name": "disk", "status": "ok"}}]}}
+
+  A check that fails has "status": "fail" and a "detail" saying why; one
+  an older daemon doesn't report is "unknown" and doesn't count against
+  it. The report is printed even when the daemon can't be reached.
+
+EXIT CODES:
+  0   healthy
+  2   down: the daemon could not be reached or didn't answer a ping
+  5   degraded: reachable, but Mnesia isn't running or can't write to disk
+
+EXAMPLES:
+  cortex health
+  cortex health --quiet || systemctl restart cortex"#
+        ),
+        Some("status") => println!(
+            r#"cortex status - Daemon status
+
+USAGE:
+  cortex status [--output json] [--pretty]
+
+DESCRIPTION:
+  Returns detailed status information about the Cortex daemon including
+  version, uptime, and Mnesia database state. Shown as an aligned summary
+  by default; a database that isn't running is marked UNHEALTHY.
+
+OPTIONS:
+  --output json   Print the raw status map as JSON
+  --pretty        Pretty-print the JSON output
+
+EXAMPLES:
+  cortex status
+  cortex status --output json --pretty"#
+        ),
+        Some("whoami") => println!(
+            r#"cortex whoami - Show who the daemon thinks you are
+
+USAGE:
+  cortex whoami [--output json]
+
+DESCRIPTION:
+  Prints the Unix UID the daemon read from this connection's socket
+  credentials. Tables are namespaced by this UID, so if your tables seem
+  to be missing, check you are running as the user that created them.
+
+OPTIONS:
+  --output json   Print {{"uid": ..., "identity": "uid:..."}}
+
+EXAMPLES:
+  cortex whoami
+  sudo -u agent-coder cortex whoami"#
+        ),
+        Some("version") => println!(
+            r#"cortex version - CLI and daemon versions
+
+USAGE:
+  cortex version [--output json]
+
+DESCRIPTION:
+  Prints the CLI version and, if the daemon can be reached, the daemon
+  version from 'cortex status' and whether the two are compatible (same
+  major version, or same minor version before 1.0). An unreachable daemon
+  is reported, not treated as an error.
+
+OPTIONS:
+  --output json   Print {{"cli": ..., "daemon": ..., "compatible": ...}},
+                  with null daemon and compatible if unreachable
+
+EXAMPLES:
+  cortex version
+  cortex version --output json"#
+        ),
+        Some("tables") => println!(
+            r#"cortex tables - List your tables
+
+USAGE:
+  cortex tables [--pattern GLOB] [--pretty]
+
+OPTIONS:
+  --pattern GLOB   Only list tables whose names match GLOB, where * matches
+                   any run of characters and ? exactly one
+
+DESCRIPTION:
+  Lists all tables owned by the current user (based on UID). Tables are
+  automatically namespaced by your UID internally.
+
+EXAMPLES:
+  cortex tables
+  cortex tables --pretty
+  cortex tables --pattern 'sm_*'"#
+        ),
+        Some("create-table") => println!(
+            r#"cortex create-table - Create a new table
+
+USAGE:
+  cortex create-table NAME ATTRS [--if-not-exists]
+
+ARGUMENTS:
+  NAME    Table name (will be namespaced to your UID automatically)
+  ATTRS   Comma-separated attribute names; first attribute is the primary key
+
+OPTIONS:
+  --if-not-exists   Succeed without changes if NAME already exists with the
+                    same key and attributes (in any order after the key).
+                    A table with a different schema is still an error (exit 7).
+
+DESCRIPTION:
+  Creates a new Mnesia table owned by you. The first attribute becomes
+  the primary key for get/delete operations.
+
+EXAMPLES:
+  cortex create-table users id,name,email
+  cortex create-table sessions session_id,user_id,expires
+  cortex create-table users id,name,email --if-not-exists"#
+        ),
+        Some("drop-table") => println!(
+            r#"cortex drop-table - Drop a table
+
+USAGE:
+  cortex drop-table NAME [--yes]
+
+DESCRIPTION:
+  Permanently deletes a table and all its data. When run from a terminal
+  you must type the table name to confirm; scripts must pass --yes.
+  WARNING: This operation cannot be undone.
+
+OPTIONS:
+  -y, --yes   Skip the confirmation prompt
+
+EXAMPLES:
+  cortex drop-table old_sessions
+  cortex drop-table old_sessions --yes"#
+        ),
+        Some("truncate") => println!(
+            r#"cortex truncate - Delete all records in a table
+
+USAGE:
+  cortex truncate TABLE [--yes]
+
+DESCRIPTION:
+  Removes every record from a table while keeping the table itself,
+  its schema, and its ACLs. Asks for confirmation when run from a
+  terminal; scripts must pass --yes.
+  WARNING: This operation cannot be undone.
+
+OPTIONS:
+  -y, --yes   Skip the confirmation prompt
+
+EXAMPLES:
+  cortex truncate sessions
+  cortex truncate sessions --yes"#
+        ),
+        Some("describe") => println!(
+            r#"cortex describe - Show a table's schema
+
+USAGE:
+  cortex describe TABLE [--output json]
+
+DESCRIPTION:
+  Shows the attributes a table was created with, in order, which one
+  is the primary key, and which are indexed. Prints a readable summary
+  by default; use --output json for machine-readable output.
+
+EXAMPLES:
+  cortex describe users
+  # table: users
+  # key:   id
+  # attributes:
+  #   id (primary key)
+  #   name
+  #   email (indexed)
+  cortex describe users --output json"#
+        ),
+        Some("create-index") | Some("drop-index") => println!(
+            r#"cortex create-index / drop-index - Manage attribute indexes
+
+USAGE:
+  cortex create-index TABLE ATTRIBUTE
+  cortex drop-index TABLE ATTRIBUTE
+
+DESCRIPTION:
+  A query normally checks every record in the table. Once an attribute
+  is indexed, a query whose pattern gives a value for it looks up just
+  the records holding that value (or an array containing it) and checks
+  the rest of the pattern against those. Results are the same either
+  way; only the work differs.
+
+  create-index indexes the records already in the table and prints how
+  many there were; the daemon keeps the index up to date from then on.
+  ATTRIBUTE must be one of the table's attributes and not the primary
+  key. Indexes need the admin permission to create or drop, and aren't
+  included in backups. describe shows which attributes are indexed.
+
+EXAMPLES:
+  cortex create-index memories type
+  # {{"index":"type","indexed":5210}}
+  cortex query memories '{{"type":"fact"}}'   # no longer a full scan
+  cortex drop-index memories type"#
+        ),
+        Some("get") => println!(
+            r#"cortex get - Get a record by key
+
+USAGE:
+  cortex get TABLE KEY [--key-type TYPE] [--fields FIELDS] [--default JSON]
+                       [--assert-eq JSON] [--assert-field FIELD=VALUE]...
+                       [--extract POINTER [--extract-optional]] [-r]
+                       [--with-ttl]
+  cortex get TABLE --keys-file PATH [--key-type TYPE] [--fields FIELDS]
+
+DESCRIPTION:
+  Retrieves a single record by its primary key. A missing record is a
+  not_found error (exit code 5) unless --default is given, in which case
+  that value is printed instead and the command succeeds.
+
+  With --assert-eq or --assert-field, get becomes a check for health and
+  readiness probes: it exits 0 and prints the record only if every
+  assertion holds, and otherwise exits 7 listing each mismatch on stderr.
+  A missing record fails the assertion too.
+
+  --extract prints just the value at an RFC 6901 JSON pointer such as
+  /address/city (array elements by index, e.g. /tags/0) instead of the
+  whole record. A pointer that doesn't resolve is an error (exit code 6)
+  unless --extract-optional is given, which prints null instead.
+
+  -r/--raw-string prints a string result (the record, or what --extract
+  picked out) without JSON quotes or escapes, for use in shell command
+  substitution. Any other result is printed as JSON, with a note on
+  stderr.
+
+  --with-ttl prints {{"record": RECORD, "ttl": SECONDS}}, where SECONDS is
+  how long the record has before the daemon deletes it, or null if it
+  never expires.
+
+  --keys-file reads keys one per line (- for stdin) and fetches them all
+  over one connection. The result lists {{"key": KEY, "ok": RECORD}} or
+  {{"key": KEY, "error": MESSAGE, "code": CATEGORY}} for each key, in the
+  order given; a missing key doesn't stop the rest.
+
+OPTIONS:
+  --key-type TYPE             Key type: string (default), int, float, or bool
+  --fields FIELDS             Only return these comma-separated fields, in order
+  --default JSON              Value to print when the record does not exist
+  --assert-eq JSON            Require the record to equal JSON
+  --assert-field FIELD=VALUE  Require FIELD to equal VALUE, read as JSON if
+                              valid and as a string otherwise; repeatable
+  --extract POINTER           Print only the value at this JSON pointer
+  --extract-optional          Print null where --extract doesn't resolve
+  -r, --raw-string            Print a string result unquoted
+  --with-ttl                  Also print the seconds until the record expires
+  --keys-file PATH            Get every key listed in PATH (- for stdin)
+
+EXAMPLES:
+  cortex get users u1
+  cortex get users u1 --fields name,email
+  cortex get orders 42 --key-type int
+  cortex get config database_url --pretty
+  cortex get config log_level --default '{{"key":"log_level","value":"info"}}'
+  cortex get config maintenance --assert-field enabled=false --quiet
+  cortex get users u1 --extract /address/city
+  url=$(cortex get config service --extract /url -r)
+  cortex get users --keys-file ids.txt --fields id,email"#
+        ),
+        Some("exists") => println!(
+            r#"cortex exists - Check whether a record exists
+
+USAGE:
+  cortex exists TABLE KEY [--key-type TYPE] [--print]
+
+DESCRIPTION:
+  Exits 0 if TABLE has a record under KEY and 1 if it doesn't, printing
+  nothing, so shell scripts can branch on it directly. No other failure
+  exits 1: each keeps its usual exit code (2 when the daemon can't be
+  reached, 5 when the table doesn't exist or can't be read, 8 when
+  --print can't write its answer, and so on). Only the answer is sent
+  back, not the record.
+
+OPTIONS:
+  --key-type TYPE   Key type: string (default), int, float, or bool
+  --print           Also print true or false
+
+EXAMPLES:
+  cortex exists users u1 && echo "u1 is registered"
+  if ! cortex exists sessions "$SESSION"; then login; fi
+  cortex exists counters 42 --key-type int --print"#
+        ),
+        Some("put") => println!(
+            r#"cortex put - Insert or update a record
+
+USAGE:
+  cortex put TABLE (JSON | - | --file PATH) [--if-absent | --if-match JSON]
+             [--ttl TTL] [--schema PATH]
+
+DESCRIPTION:
+  Inserts a new record or updates an existing one. The JSON must contain
+  the primary key field defined when the table was created.
+
+  With - in place of the JSON, or --file, the record is read from stdin
+  or the file instead, which avoids shell quoting and argument length
+  limits. If it holds several records (e.g. one per line), they are all
+  written as with put-many; the conditional forms and --ttl take a
+  single record only.
+
+  Maps whose keys aren't all strings are written (and shown by get) as
+  {{"__map__": [[key, value], ...]}}, since JSON keys must be strings.
+
+  A value written as {{"__bin__hex__": "deadbeef"}} is stored as MessagePack
+  binary (the bytes the hex digits spell), e.g. for hashes or raw keys.
+
+  The conditional forms check and write in one transaction, so concurrent
+  writers can't lose each other's updates. If the condition doesn't hold,
+  nothing is written and cortex exits with code 7.
+
+  With --ttl the daemon deletes the record once the TTL has passed
+  (checked every few seconds). Without it the record never expires, even
+  if an earlier write gave it a TTL. See expire to change a record's TTL
+  without rewriting it, and get --with-ttl to see the time left.
+
+  With --schema the record is checked against a JSON Schema (draft 7)
+  first; if it doesn't match, nothing is sent and each violation is
+  listed with the path to the offending value (exit code 6).
+
+OPTIONS:
+  --file PATH       Read the record from a JSON file (- for stdin) instead
+                    of the JSON argument
+  --if-absent       Only write if no record with this key exists yet
+  --if-match JSON   Only write if the stored record equals JSON exactly
+  --ttl TTL         Expire the record after TTL: a number of seconds, or a
+                    number followed by s, m, h, or d (e.g. 3600, 30m, 2h)
+  --schema PATH     Refuse records that don't match this JSON Schema
+
+EXAMPLES:
+  cortex put users '{{"id":"u1","name":"alice","email":"a@b.com"}}'
+  cortex put config '{{"key":"theme","value":"dark"}}'
+  cortex put locks '{{"id":"deploy","owner":"uid:1001"}}' --if-absent
+  cortex put sessions '{{"session_id":"s1","user_id":"u1"}}' --ttl 2h
+  cortex put users --file record.json
+  jq -c '.[]' users.json | cortex put users -
+  cortex put users --file record.json --schema users.schema.json
+  cortex put tasks '{{"id":"t1","state":"running"}}' \
+    --if-match '{{"id":"t1","state":"pending"}}'"#
+        ),
+        Some("put-many") => println!(
+            r#"cortex put-many - Insert or update many records
+
+USAGE:
+  cortex put-many TABLE FILE
+
+DESCRIPTION:
+  Writes every record in FILE (- for stdin), given as JSON objects one
+  per line or as a JSON array, then prints how many were written and
+  which failed. Records go to the daemon in as few requests as
+  --max-value-size allows rather than one request each.
+
+  Each record is written on its own: one that fails (say, for lacking
+  the primary key) doesn't stop the rest, and the records written
+  before and after it stay written. Failures are listed by position
+  (counting from 1) with the daemon's error, and cortex exits 5.
+
+  A record over --max-value-size is listed as a failure without being
+  sent. Against a daemon too old for put_many, the records are sent as
+  individual puts over one connection instead.
+
+EXAMPLES:
+  cortex put-many users users.jsonl
+  cortex all users | cortex put-many users_copy -
+  # {{"table":"users","written":41,"failed":1,
+  #  "failures":[{{"record":7,"error":"missing_key","code":"daemon"}}]}}"#
+        ),
+        Some("txn") => println!(
+            r#"cortex txn - Apply several writes all or nothing
+
+USAGE:
+  cortex txn [--file PATH]
+
+DESCRIPTION:
+  Reads a JSON array of ops from --file, or stdin without it, and has
+  the daemon apply them in one transaction: either every op takes
+  effect or, if any fails, none does. Ops can span tables, and each
+  must be one of
+
+    {{"op": "put", "table": "T", "record": {{...}}}}
+    {{"op": "delete", "table": "T", "key": K}}
+
+  Every op's table is checked against your permissions before anything
+  is written. Prints the number of ops applied.
+
+OPTIONS:
+  --file PATH   Read ops from PATH (default: - for stdin)
+
+EXAMPLES:
+  cortex txn --file move-order.json
+  echo '[{{"op":"put","table":"orders","record":{{"id":"o1","state":"paid"}}}},
+         {{"op":"delete","table":"carts","key":"c1"}}]' | cortex txn"#
+        ),
+        Some("validate") => println!(
+            r#"cortex validate - Check records against a JSON Schema
+
+USAGE:
+  cortex validate TABLE --schema PATH [--file PATH]
+
+DESCRIPTION:
+  Checks each record against a JSON Schema (draft 7) without connecting
+  to the daemon, and prints how many passed and failed. Failing records
+  are listed by position (counting from 1) with each violation and the
+  path to the offending value. Exits 0 if every record passes and 7
+  otherwise; a schema that isn't valid JSON Schema exits 6.
+
+  Records are read from --file, or stdin without it, as a JSON array or
+  as JSON objects one after another (e.g. one per line).
+
+OPTIONS:
+  --schema PATH   JSON Schema file to check against
+  --file PATH     Read records from PATH (default: - for stdin)
+
+EXAMPLES:
+  cortex validate users --schema users.schema.json --file users.json
+  cortex all users | cortex validate users --schema users.schema.json
+  # {{"table":"users","passed":41,"failed":1,
+  #  "failures":[{{"record":7,"errors":["/age: \"x\" is not of type \"integer\""]}}]}}"#
+        ),
+        Some("append") => println!(
+            r#"cortex append - Append a value to an array field
+
+USAGE:
+  cortex append TABLE KEY FIELD JSON [--key-type TYPE]
+
+DESCRIPTION:
+  Pushes JSON onto the end of the array in FIELD of the record with the
+  given primary key, in one transaction, so concurrent appends are never
+  lost. If the record has no FIELD yet, it becomes a one-element array.
+
+  The record must exist. If FIELD holds something other than an array,
+  nothing is written and cortex exits with code 7. Any TTL the record
+  has is kept.
+
+  Prints the array's new length.
+
+EXAMPLES:
+  cortex append memories m1 tags '"urgent"'
+  cortex append events e1 history '{{"at":"2025-01-01","state":"done"}}'
+  cortex append counters 42 samples 3.5 --key-type int"#
+        ),
+        Some("incr") | Some("decr") => println!(
+            r#"cortex incr / decr - Atomically add to or subtract from a numeric field
+
+USAGE:
+  cortex incr TABLE KEY FIELD [AMOUNT] [--key-type TYPE]
+  cortex decr TABLE KEY FIELD [AMOUNT] [--key-type TYPE]
+
+DESCRIPTION:
+  Adds AMOUNT (default 1) to the number in FIELD of the record with the
+  given primary key, or subtracts it for decr, in one transaction, so
+  concurrent updates are never lost the way a get followed by a put can
+  lose them. AMOUNT may be negative or fractional.
+
+  A missing FIELD counts as 0, and a missing record is created holding
+  just the key and FIELD. If FIELD holds something other than a number,
+  nothing is written and cortex exits with code 7. Any TTL the record
+  has is kept.
+
+  Prints the field's new value.
+
+EXAMPLES:
+  cortex incr jobs j1 attempts
+  cortex incr stats daily page_views 25
+  cortex decr quotas u1 remaining
+  cortex incr counters 42 total -1.5 --key-type int"#
+        ),
+        Some("expire") => println!(
+            r#"cortex expire - Set or clear a record's TTL
+
+USAGE:
+  cortex expire TABLE KEY TTL [--key-type TYPE]
+  cortex expire TABLE KEY --persist [--key-type TYPE]
+
+DESCRIPTION:
+  Has the daemon delete the record with the given primary key once TTL
+  has passed, counting from now, without rewriting the record. TTL is a
+  number of seconds or a number followed by s, m, h, or d. Setting a TTL
+  replaces any the record already had.
+
+  With --persist the record's TTL is cleared, so it never expires.
+
+  The record must exist; if it doesn't, cortex exits with code 5. Use
+  get --with-ttl to see how long a record has left.
+
+OPTIONS:
+  --persist         Clear the TTL instead of setting one
+  --key-type TYPE   Key type: string (default), int, float, or bool
+
+EXAMPLES:
+  cortex expire sessions s1 3600
+  cortex expire sessions s1 30m
+  cortex expire sessions s1 --persist
+  cortex get sessions s1 --with-ttl
+  # {{"record":{{"session_id":"s1","user_id":"u1"}},"ttl":1794}}"#
+        ),
+        Some("delete") => println!(
+            r#"cortex delete - Delete a record
+
+USAGE:
+  cortex delete TABLE KEY [--key-type TYPE]
+  cortex delete TABLE --pattern JSON [--yes]
+  cortex delete TABLE --keys-file PATH [--key-type TYPE] [--yes]
+
+DESCRIPTION:
+  Permanently deletes a single record by its primary key. With --pattern,
+  deletes every matching record in one server-side transaction and prints
+  the number deleted.
+
+  With --keys-file, deletes each key listed one per line (- for stdin)
+  over one connection and prints a result per key in the order given, as
+  for get --keys-file.
+
+OPTIONS:
+  --key-type TYPE   Key type: string (default), int, float, or bool
+  --pattern JSON    Delete all records matching this pattern (as in query)
+  --keys-file PATH  Delete every key listed in PATH (- for stdin)
+  -y, --yes         Skip the confirmation prompt for --pattern or --keys-file
+
+EXAMPLES:
+  cortex delete users u1
+  cortex delete sessions expired_session_123
+  cortex delete sessions --pattern '{{"status":"expired"}}' --yes
+  cortex delete sessions --keys-file stale.txt --yes"#
+        ),
+        Some("query") => println!(
+            r#"cortex query - Query records by pattern
+
+USAGE:
+  cortex query TABLE (PATTERN | --file PATH) [--fields FIELDS]
+               [--sort-by FIELD [--reverse] [--server-sort | --client-sort]]
+               [--limit N] [--since TIME] [--until TIME] [--time-field FIELD]
+               [--extract POINTER [--extract-optional]]
+  cortex query TABLE (PATTERN | --file PATH) [--fields FIELDS] --limit N
+               --cursor [CURSOR]
+
+DESCRIPTION:
+  Finds all records matching the given pattern. The pattern is a JSON
+  object where each field must match exactly; a scalar also matches an
+  array that contains it.
+
+  Object values match nested fields: {{"address":{{"city":"NYC"}}}} finds
+  records whose address has city NYC, whatever else it holds. Nesting
+  may go any number of levels deep, and an object pattern matches an
+  array if any element matches. Array patterns must match exactly.
+
+  {{"field":{{"$regex":"EXPR"}}}} matches records whose top-level field is
+  a string (or an array holding one) that EXPR finds a match in. EXPR is
+  checked before anything is sent; older daemons that can't evaluate it
+  leave the check to cortex.
+
+  Other operators compare a top-level field the same way, and several on
+  one field must all hold, as in {{"age":{{"$gte":18,"$lt":65}}}}:
+    $gt, $gte, $lt, $lte  Greater or less than a number, or a string
+                          in lexical order (so RFC 3339 times compare
+                          in time order)
+    $in                   Equal to one of an array of values
+    $contains             A string holding the given substring, or an
+                          array holding the given element
+    $prefix               A string starting with the given string
+  Apart from $contains, an array field matches if any element does.
+
+  --sort-by asks the daemon to sort, falling back to sorting here as
+  for `all`.
+
+  --cursor pages through the matches in primary key order, as for `all`.
+  It takes top-level field patterns only, without operators.
+
+OPTIONS:
+  --file PATH       Read the pattern from a JSON file (- for stdin) instead
+                    of the PATTERN argument
+  --fields FIELDS   Only return these comma-separated fields, in order
+  --sort-by FIELD   Sort by FIELD (numbers numerically, strings lexically,
+                    records without FIELD last); also --sort, and FIELD:desc
+                    or FIELD:asc picks the direction
+  --reverse         Sort in descending order
+  --limit N         Return at most N records (with --sort-by, the top N)
+  --server-sort     Require the daemon to sort (fails if it can't)
+  --client-sort     Sort here instead, after fetching every record
+  --since TIME      Only records whose time field is at or after TIME
+  --until TIME      Only records whose time field is at or before TIME
+  --time-field F    Field --since/--until compare (default: timestamp)
+  --extract POINTER Print only the value at this JSON pointer from each
+                    record, e.g. /address/city; a record it doesn't
+                    resolve in is an error (exit code 6)
+  --extract-optional
+                    Skip records --extract doesn't resolve in instead
+  --cursor [CURSOR] Fetch the --limit matches after CURSOR, or the first
+                    --limit without one, along with the next cursor
+
+  TIME is RFC 3339 (2024-01-15T10:30:00Z, 2024-01-15) or epoch seconds.
+  The time field may hold either; records where it holds neither are left
+  out. Like regexes, older daemons leave the check to cortex.
+
+EXAMPLES:
+  cortex query users '{{"name":"alice"}}' --pretty
+  cortex query sessions '{{"user_id":"u1"}}'
+  cortex query users '{{"address":{{"city":"NYC"}}}}'
+  cortex query memories '{{"content":{{"$regex":"(?i)deploy.*failed"}}}}'
+  cortex query users '{{"age":{{"$gt":30}},"role":{{"$in":["admin","ops"]}}}}'
+  cortex query files '{{"path":{{"$prefix":"/var/log/"}}}}'
+  cortex query sessions '{{"user_id":"u1"}}' --sort-by created_at --reverse
+  cortex query memories --file pattern.json
+  cortex query private_memories '{{"tags":"deploy"}}' --since 1705276800
+  cortex query users '{{"role":"admin"}}' --extract /email
+  cortex query events '{{"kind":"login"}}' --limit 500 --cursor"#
+        ),
+        Some("all") => println!(
+            r#"cortex all - List all records in a table
+
+USAGE:
+  cortex all TABLE [--fields FIELDS] [--sort-by FIELD [--reverse]]
+                   [--server-sort | --client-sort] [--limit N]
+                   [--since TIME] [--until TIME] [--time-field FIELD]
+  cortex all TABLE [--fields FIELDS] --page-size [N]
+  cortex all TABLE [--fields FIELDS] --limit N --cursor [CURSOR]
+
+DESCRIPTION:
+  Returns all records in a table as a JSON array. With --output ndjson,
+  records are printed one per line as they arrive, so memory use stays
+  flat however large the table is.
+
+  With --page-size, records are fetched N at a time (5000 if N is
+  omitted) in primary key order over one connection and printed one per
+  line, so neither the daemon nor the CLI builds the whole result at
+  once. Each page starts after the last key of the one before, so
+  records written in between don't shift later pages.
+
+  With --cursor, one page of --limit records is fetched per run, in
+  primary key order, as {{"records": [...], "cursor": NEXT}}. Pass NEXT
+  to --cursor for the page after; it is null on the last page. Leave
+  out the value of --cursor for the first page. Each page starts after
+  the last key of the one before, so records written in between don't
+  shift it.
+
+  --sort-by asks the daemon to sort, so with --limit only the top N
+  records are sent. A daemon too old to sort returns them unsorted and
+  they are sorted here instead.
+
+OPTIONS:
+  --fields FIELDS   Only return these comma-separated fields, in order
+  --sort-by FIELD   Sort by FIELD (numbers numerically, strings lexically,
+                    records without FIELD last); also --sort, and FIELD:desc
+                    or FIELD:asc picks the direction
+  --reverse         Sort in descending order
+  --limit N         Return at most N records (with --sort-by, the top N)
+  --server-sort     Require the daemon to sort (fails if it can't)
+  --client-sort     Sort here instead, after fetching every record
+  --since TIME      Only records whose time field is at or after TIME
+  --until TIME      Only records whose time field is at or before TIME
+  --time-field F    Field --since/--until compare (default: timestamp)
+  --page-size [N]   Fetch N records per request (cannot be combined with
+                    --sort-by, --limit, --since, or --until)
+  --cursor [CURSOR] Fetch the --limit records after CURSOR, or the first
+                    --limit without one, along with the next cursor
+
+  TIME is RFC 3339 (2024-01-15T10:30:00Z, 2024-01-15) or epoch seconds.
+  The time field may hold either; records where it holds neither are left
+  out.
+
+EXAMPLES:
+  cortex all users --pretty
+  cortex all users --fields id,name
+  cortex all users --sort-by name
+  cortex all scores --sort-by points --reverse --limit 10   # top 10
+  cortex all memories --sort timestamp:desc --limit 10      # 10 most recent
+  cortex all big_table --output ndjson | wc -l   # streamed, one record per line
+  cortex all big_table --page-size 1000 > big_table.ndjson
+  cortex all big_table --limit 1000 --cursor        # first page
+  cortex all big_table --limit 1000 --cursor u0999  # the page after u0999
+  cortex all sm_instances --since 2024-01-15T00:00:00Z --time-field updated
+  cortex all config"#
+        ),
+        Some("aggregate") => println!(
+            r#"cortex aggregate - Summarize a table's records
+
+USAGE:
+  cortex aggregate TABLE (--sum | --avg | --min | --max) FIELD [--group-by FIELD]
+  cortex aggregate TABLE --count [--group-by FIELD]
+
+DESCRIPTION:
+  Computes one aggregate over every record in TABLE and prints it as a
+  single number. With --group-by, records are grouped by the value of a
+  field and the result is an object mapping each group to its aggregate.
+
+  Records whose aggregated field is missing or not a number, or whose
+  group-by field is missing or null, are left out; how many were skipped
+  is reported on stderr. --avg, --min, and --max of no values are null.
+
+OPTIONS:
+  --sum FIELD        Sum of FIELD
+  --avg FIELD        Mean of FIELD
+  --min FIELD        Smallest value of FIELD
+  --max FIELD        Largest value of FIELD
+  --count            Number of records
+  --group-by FIELD   One result per distinct value of FIELD
+
+EXAMPLES:
+  cortex aggregate orders --sum total
+  cortex aggregate orders --avg total --group-by region
+  cortex aggregate sessions --count --group-by user_id"#
+        ),
+        Some("count") => println!(
+            r#"cortex count - Count the records in a table
+
+USAGE:
+  cortex count TABLE [PATTERN]
+
+DESCRIPTION:
+  Prints how many records TABLE holds, or with PATTERN how many match
+  it. PATTERN takes the same form as for query, operators included.
+
+  The daemon does the counting, so no records are sent. Patterns with
+  nested objects, and daemons too old to count, fall back to fetching
+  the matches and counting them here.
+
+  Use aggregate --count --group-by FIELD for a count per group.
+
+EXAMPLES:
+  cortex count users
+  cortex count sessions '{{"status":"active"}}'
+  cortex count events '{{"timestamp":{{"$gte":"2024-01-01"}}}}'"#
+        ),
+        Some("keys") => println!(
+            r#"cortex keys - List all keys in a table
+
+USAGE:
+  cortex keys TABLE [--prefix PREFIX] [--limit N [--cursor [CURSOR]]]
+                    [--pretty]
+
+DESCRIPTION:
+  Returns all primary keys in a table as a JSON array. Useful for
+  debugging or iterating over records without fetching full data.
+
+OPTIONS:
+  --prefix PREFIX   Only list keys starting with PREFIX (filtered by the
+                    daemon, so other keys are never sent)
+  --limit N         List at most N keys (after --prefix filtering)
+  --cursor [CURSOR] List the N keys after CURSOR in key order (the first N
+                    without one), as {{"keys": [...], "cursor": NEXT}};
+                    pass NEXT for the following page, until it is null
+
+EXAMPLES:
+  cortex keys users
+  cortex keys sessions --pretty
+  cortex keys cache --prefix session: --limit 100
+  cortex keys cache --limit 100 --cursor session:0042"#
+        ),
+        Some("range") => println!(
+            r#"cortex range - List the records in a range of keys
+
+USAGE:
+  cortex range TABLE [--from KEY] [--to KEY] [--limit N]
+
+DESCRIPTION:
+  Returns the records whose primary keys fall between --from and --to,
+  both included, as a JSON array in key order. Leaving out either bound
+  leaves that end open. The daemon picks the records out, so the rest of
+  the table is never sent.
+
+  Keys compare as strings, character by character, since that is how
+  the daemon stores them: "10" sorts before "9", so pad numeric keys
+  (e.g. "0009") for ranges to follow numeric order.
+
+OPTIONS:
+  --from KEY   Lowest key to include
+  --to KEY     Highest key to include
+  --limit N    Return at most N records, from the low end
+
+EXAMPLES:
+  cortex range events --from 2024-03-01 --to 2024-03-31
+  cortex range logs --from 2024-06 --limit 100
+  cortex range users --to m"#
+        ),
+        Some("watch") => println!(
+            r#"cortex watch - Stream changes to a table
+
+USAGE:
+  cortex watch TABLE [--duration D] [--reconnect]
+
+OPTIONS:
+  --duration D   Stop watching after D (e.g. 30, 10s, 5m)
+  --reconnect    Survive daemon restarts (see below)
+
+DESCRIPTION:
+  Subscribes to a table and prints one JSON line per change as it
+  happens, until interrupted with Ctrl-C, --duration runs out, or the
+  daemon closes the connection. Each event has an "op" ("write" or
+  "delete"), the "table", the "key", and for writes the new "record".
+  On Ctrl-C or at the end of --duration the subscription is cancelled
+  before exiting, and the exit status is 0.
+
+  With --reconnect, a closed or broken connection doesn't end the watch:
+  cortex reconnects with backoff (up to 10s between attempts) and
+  subscribes again, noting each reconnection on stderr. Changes made
+  while it was disconnected are lost, so a {{"__reconnected__":true}} line
+  marks the gap in the output.
+
+EXAMPLES:
+  cortex watch sm_instances
+  cortex watch sm_instances --reconnect
+  cortex watch sessions --duration 10s
+  # {{"op":"write","table":"sm_instances","key":"order-123","record":{{...}}}}
+  cortex watch sessions | grep '"op":"delete"'"#
+        ),
+        Some("backup") => println!(
+            r#"cortex backup - Back up all your tables
+
+USAGE:
+  cortex backup [FILE]
+
+DESCRIPTION:
+  Writes every table you own, with its schema, all records, and the
+  permissions granted on it, to FILE (or stdout) as a single JSON
+  document:
+
+    {{"cortex_backup":1,"tables":[
+    {{"table":"users","key":"id","attributes":["id","name"],"records":[...],
+     "acls":[{{"identity":"uid:1001","permissions":["read"]}}]}}
+    ]}}
+
+  Tables nobody has been granted anything on have no "acls".
+
+  Tables are fetched one at a time to bound memory. When writing to a
+  file, the backup only replaces FILE once it has completed.
+
+  If stderr is a terminal, a running record count is shown there
+  (never with --quiet), so stdout stays pipeable.
+
+EXAMPLES:
+  cortex backup cortex-$(date +%F).json
+  cortex backup | gzip > cortex.json.gz"#
+        ),
+        Some("export") => println!(
+            r#"cortex export - Write one table's records to a file
+
+USAGE:
+  cortex export TABLE FILE [--format jsonl|msgpack|csv]
+
+DESCRIPTION:
+  Writes every record in TABLE to FILE (or stdout, with -). Records are
+  fetched 1000 at a time, so tables of any size export without hitting
+  the daemon's response size limit, and the file only replaces FILE once
+  the export has completed.
+
+  Formats:
+    jsonl    One JSON object per line (the default)
+    msgpack  One MessagePack map after another, exactly as stored
+    csv      A header row of the table's attributes, then one row per
+             record; strings are written as-is, other values as JSON,
+             and null or missing fields as empty cells. Fields outside
+             the table's attributes are left out.
+
+  --format is the global output flag, so any other format (or none)
+  writes JSON lines.
+
+  If stderr is a terminal, a running record count is shown there
+  (never with --quiet). Use backup to save every table with its schema.
+
+EXAMPLES:
+  cortex export users users.jsonl
+  cortex export events events.csv --format csv
+  cortex export sessions - --format msgpack | gzip > sessions.msgpack.gz
+  cortex export users - | cortex put users-copy -"#
+        ),
+        Some("import") => println!(
+            r#"cortex import - Write records from a file to a table
+
+USAGE:
+  cortex import TABLE FILE [--format jsonl|msgpack|csv] [--create]
+                           [--on-conflict fail|skip|overwrite] [--parallel N]
+
+DESCRIPTION:
+  Reads records from FILE (or stdin, with -) in any format export
+  writes, and writes them to TABLE. In CSV files the header row names
+  the fields, empty cells are left out, and cells holding a JSON number,
+  boolean, array, or object are read as that value; anything else is a
+  string. FILE is read as JSON lines unless --format is msgpack or csv.
+
+  --create makes TABLE first if it doesn't exist, with the first
+  record's fields as its attributes and the first field as the primary
+  key.
+
+  --on-conflict decides what happens to a record whose key is already
+  in TABLE:
+    fail       Stop there with exit code 7 (the default); the records
+               before it stay written
+    skip       Leave the stored record alone and carry on
+    overwrite  Replace the stored record, as put-many does
+
+  With --parallel N (1-16, default 1), the records are split across N
+  connections and written concurrently. Under --on-conflict fail every
+  connection stops once any finds a key taken. A connection whose
+  request fails stops there, and the error says how many records were
+  written in all.
+
+  Prints how many records were written (and skipped). If any record
+  couldn't be written, each is listed with its position in the file and
+  cortex exits non-zero.
+
+EXAMPLES:
+  cortex export users users.jsonl   # on the old machine
+  cortex import users users.jsonl --create
+  cortex import events events.csv --format csv --on-conflict skip
+  gunzip -c sessions.msgpack.gz | cortex import sessions - --format msgpack"#
+        ),
+        Some("restore") => println!(
+            r#"cortex restore - Restore tables from a backup
+
+USAGE:
+  cortex restore FILE [--skip-existing | --drop-first [--yes]] [--parallel N]
+
+DESCRIPTION:
+  Reads a document written by 'cortex backup' (FILE, or - for stdin),
+  recreates each table with its original primary key and attributes,
+  re-inserts its records, and grants its permissions again (except
+  grants that have expired since). Prints one summary per table with
+  the action taken (created, recreated, or skipped), records restored,
+  and, for tables with grants, how many were restored.
+
+  With --dry-run nothing is sent; the requests a restore into an empty
+  namespace would make are printed instead.
+
+  By default nothing is restored if any backed-up table already exists.
+
+  With --parallel, each table's records are split across N connections
+  and written concurrently. If a write fails, each connection stops at
+  its first error and the error says how many records were written.
+
+  If stderr is a terminal, progress (records restored out of the total,
+  and the rate) is shown there, except with --quiet.
+
+OPTIONS:
+  --skip-existing   Leave tables that already exist untouched
+  --drop-first      Drop existing tables and restore the backed-up copy
+  -y, --yes         Skip the confirmation prompt for --drop-first
+  --parallel N      Write over N connections at once (1-16, default 1)
+
+EXAMPLES:
+  cortex restore cortex-2024-06-01.json
+  cortex --dry-run restore cortex-2024-06-01.json
+  gunzip -c cortex.json.gz | cortex restore - --skip-existing"#
+        ),
+        Some("copy-table") => println!(
+            r#"cortex copy-table - Copy a table
+
+USAGE:
+  cortex copy-table SRC DST [--schema-only] [--overwrite [--yes]]
+
+DESCRIPTION:
+  Creates DST with the same primary key and attributes as SRC, then
+  copies every record of SRC into it over a single connection. Prints
+  the action taken (created or recreated) and the number of records
+  copied.
+
+  Fails without changing anything if DST already exists, unless
+  --overwrite is given.
+
+OPTIONS:
+  --schema-only   Create DST but copy no records
+  --overwrite     Drop DST first if it already exists
+  -y, --yes       Skip the confirmation prompt for --overwrite
+
+EXAMPLES:
+  cortex copy-table users users_backup
+  cortex copy-table users users_staging --schema-only"#
+        ),
+        Some("diff") => println!(
+            r#"cortex diff - Compare two tables
+
+USAGE:
+  cortex diff LEFT RIGHT [--output json]
+
+DESCRIPTION:
+  Fetches both tables, lines their records up by primary key, and lists
+  keys only in RIGHT (+), keys only in LEFT (-), and keys whose records
+  differ (~), with each changed field's old and new value. Both tables
+  must have the same primary key.
+
+OPTIONS:
+  --output json   Print {{"added": [...], "removed": [...], "changed":
+                  [{{"key": ..., "fields": {{NAME: {{"left": ..., "right": ...}}}}}}],
+                  "unchanged": N}}; a missing field is shown as null
+
+EXAMPLES:
+  cortex diff users users_staging
+  cortex restore backup.json && cortex diff users users_restored"#
+        ),
+        Some("raw") => println!(
+            r#"cortex raw - Call any RPC method
+
+USAGE:
+  cortex raw METHOD [PARAMS | --params-file PATH]
+
+DESCRIPTION:
+  Sends METHOD with PARAMS (a JSON array, default []) straight to the
+  daemon and prints the result. Useful for trying out daemon methods
+  the CLI doesn't have a command for yet.
+
+  --params-file reads the array from PATH instead, or from stdin if PATH
+  is -, for payloads too large or awkward to pass as an argument.
+
+EXAMPLES:
+  cortex raw ping
+  cortex raw get '["users", "u1"]'
+  cortex raw match '["users", {{"name": "alice"}}]' --pretty
+  generate-params | cortex raw put --params-file -"#
+        ),
+        Some("batch") => println!(
+            r#"cortex batch - Run several commands over one connection
+
+USAGE:
+  cortex batch FILE [--fail-fast]
+  cortex batch - [--fail-fast]
+
+DESCRIPTION:
+  Reads commands from FILE, or stdin if FILE is -, one per line and
+  written as they would be after "cortex" on the command line (quotes
+  work as in a shell). Blank lines and lines starting with # are skipped.
+
+  The commands run in order over a single connection, and the output is
+  one JSON array with an entry per command: {{"ok": result}} if it
+  succeeded, or {{"error": "...", "code": "..."}} if it failed. The
+  batch itself exits 0 whatever its commands do; check the entries.
+
+  Commands that stream their output (watch, backup to stdout,
+  all --page-size, --output ndjson) can't run in a batch. Global flags
+  given to batch, such as --socket or --dry-run, apply to every command.
+
+OPTIONS:
+  --fail-fast   Stop after the first command that fails
+
+EXAMPLES:
+  printf '%s\n' 'get users u1' 'get users u2' | cortex batch -
+  cortex batch setup.txt --fail-fast --pretty"#
+        ),
+        Some("migrate") => println!(
+            r#"cortex migrate - Change a table's schema
+
+USAGE:
+  cortex migrate add-attribute TABLE NAME [--default JSON]
+
+DESCRIPTION:
+  add-attribute appends NAME to the table's attributes and, in the same
+  transaction, sets it on every existing record that doesn't have it: to
+  the --default value, or null without one. Records that already have a
+  NAME field keep their value. It is an error if the table already has
+  the attribute. Requires admin permission on the table.
+
+  Prints the attribute added and how many records were backfilled.
+
+EXAMPLES:
+  cortex migrate add-attribute users created_at
+  cortex migrate add-attribute users role --default '"member"'
+  cortex migrate add-attribute memories tags --default '[]'"#
+        ),
+        Some("completions") => println!(
+            r#"cortex completions - Set up tab completion
+
+USAGE:
+  cortex completions bash|zsh|fish
+
+DESCRIPTION:
+  Prints a script that registers tab completion for cortex with the
+  shell. Commands, flags, and their values complete as you'd expect, and
+  table arguments complete with the names of the tables you can see,
+  asked of the daemon at the configured socket (or the default one) as
+  you press tab. If the daemon can't be reached within half a second,
+  table names just aren't offered.
+
+  The script calls back into cortex for every completion, so load it
+  from your shell's startup file, as below, rather than saving its
+  output; that way it always matches the installed cortex.
+
+EXAMPLES:
+  echo 'source <(cortex completions bash)' >> ~/.bashrc
+  echo 'source <(cortex completions zsh)' >> ~/.zshrc
+  echo 'cortex completions fish | source' >> ~/.config/fish/config.fish"#
+        ),
+        Some("acl") => println!(
+            r#"cortex acl - Access control commands
+
+USAGE:
+  cortex acl <subcommand> [args]
+
+SUBCOMMANDS:
+  grant IDENTITY TABLE PERMS    Grant permissions (--expires DURATION)
+  revoke IDENTITY TABLE PERMS   Revoke permissions
+  list [--table T] [--identity ID]
+                                List ACLs for your tables, optionally only
+                                those on one table or for one identity
+  check IDENTITY TABLE          Show effective permissions for an identity
+
+IDENTITIES:
+  uid:1001    Specific user by UID
+  gid:1001    Members of a Unix group (primary or supplementary)
+  *           World (any authenticated user)
+
+EXPIRY:
+  --expires DURATION grants temporary access (s, m, h, or d units,
+  e.g. 30m, 2h, 1d). The daemon revokes the grant once it lapses, and
+  'acl list' shows each grant's expires_at (unix seconds, or null).
+
+PERMISSIONS:
+  read        Can get, query, all
+  write       Can put, delete
+  admin       Can grant/revoke ACLs, drop table
+
+EXAMPLES:
+  cortex acl grant 'uid:1001' users read
+  cortex acl grant '*' public_data read
+  cortex acl grant 'gid:1001' shared_notes read,write
+  cortex acl grant 'uid:1002' users read --expires 1h
+  cortex acl revoke 'uid:1001' users write
+  cortex acl list --pretty
+  cortex acl list --table users --identity 'uid:1001'
+  cortex acl check 'uid:1001' users
+  # Output: ["read","write"] (its own grants plus any '*' grants)"#
+        ),
+        Some("patterns") => println!(
+            r#"Cortex Usage Patterns
+
+Cortex is a generic storage layer - it has no opinions about how you
+structure your data. Here are common patterns that work well:
+
+AVAILABLE PATTERNS:
+  cortex help memories       Public/private agent memories
+  cortex help statemachine   Workflow state machines
+  cortex help identities     Agent identity via Unix users
+
+Run 'cortex help <pattern>' for detailed documentation."#
+        ),
+        Some("memories") => println!(
+            r#"Pattern: Public/Private Agent Memories
+
+OVERVIEW:
+  AI agents often need both private working memory and shared knowledge.
+  Use separate tables with different ACLs to implement this pattern.
+
+SETUP:
+  # Create private memory (only you can access)
+  cortex create-table private_memories id,content,timestamp,tags
+
+  # Create public memory (world-readable)
+  cortex create-table public_memories id,content,timestamp,tags
+  cortex acl grant '*' public_memories read
+
+MULTI-AGENT SETUP:
+  Each agent runs as a separate Unix user with its own UID:
+
+  sudo useradd -r -s /usr/sbin/nologin agent-researcher
+  sudo useradd -r -s /usr/sbin/nologin agent-coder
+
+  Each agent's tables are isolated. They can only read each other's
+  public_memories tables (if world-readable ACL is set)."#
+        ),
+        Some("statemachine") => println!(
+            r#"Pattern: Workflow State Machines
+
+OVERVIEW:
+  Track multi-step workflows with explicit states and transitions.
+
+SETUP:
+  cortex create-table sm_definitions id,name,states,transitions
+  cortex create-table sm_instances id,definition,state,data,created,updated
+
+DEFINE A WORKFLOW:
+  cortex put sm_definitions '{{
+    "id": "task-workflow",
+    "name": "Task Workflow",
+    "states": ["todo", "in_progress", "review", "done"]
+  }}'
+
+QUERY BY STATE:
+  cortex query sm_instances '{{"state":"review"}}' --pretty"#
+        ),
+        Some("identities") => println!(
+            r#"Pattern: Agent Identities
+
+OVERVIEW:
+  Cortex identifies users by their Unix UID, extracted from the socket
+  connection via SO_PEERCRED. This provides kernel-enforced identity.
+
+HOW IT WORKS:
+  1. Client connects to Unix socket
+  2. Cortex extracts UID via getpeereid/SO_PEERCRED (kernel-enforced)
+  3. All operations are scoped to that UID
+  4. Tables are namespaced: "users" becomes "1000:users" internally
+
+CREATING AGENT USERS:
+  sudo useradd -r -s /usr/sbin/nologin agent-coder
+  sudo -u agent-coder cortex put memories '{{...}}'
+
+FINDING YOUR UID:
+  id -u                    # Your current UID
+  id -u agent-coder        # Another user's UID
+  cortex whoami            # The UID the daemon sees for you"#
+        ),
+        Some(other) => {
+            eprintln!("Unknown help topic: {}", other);
+            eprintln!();
+            eprintln!("Available commands:");
+            eprintln!("  ping, wait-ready, status, health, version, whoami, tables,");
+            eprintln!("  create-table, drop-table, create-index, drop-index, truncate, describe,");
+            eprintln!("  copy-table, diff, get, exists, put, put-many, txn, validate, append,");
+            eprintln!("  incr, decr, expire, delete, query, all, aggregate, count, keys, range,");
+            eprintln!("  watch, backup, export, import, restore, raw, batch, migrate, acl,");
+            eprintln!("  completions");
+            eprintln!();
+            eprintln!("Available patterns:");
+            eprintln!("  patterns, memories, statemachine, identities");
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//! Reading records back from a file written by `cortex export`, or any file
+//! of JSON lines, MessagePack maps, or CSV.
+
+use crate::error::Error;
+use crate::export::Format;
+use crate::msgpack_to_json;
+use clap::ValueEnum;
+use serde_json::{Map, Value};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// What to do with a record whose key is already in the table.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OnConflict {
+    /// Stop the import at that record
+    #[default]
+    Fail,
+    /// Leave the stored record alone and carry on
+    Skip,
+    /// Replace the stored record
+    Overwrite,
+}
+
+/// The records in the file at `path` (stdin for `-`), read as `format`.
+pub fn read_records(path: &Path, format: Format) -> Result<Vec<Value>, Error> {
+    let mut data = Vec::new();
+    let read = if path.as_os_str() == "-" {
+        io::stdin().lock().read_to_end(&mut data)
+    } else {
+        File::open(path).and_then(|mut file| file.read_to_end(&mut data))
+    };
+    read.map_err(|e| Error::Input(format!("cannot read {}: {}", path.display(), e)))?;
+    parse_records(&data, format)
+        .map_err(|reason| Error::Input(format!("{}: {}", path.display(), reason)))
+}
+
+/// The records in `data`, or why it isn't valid `format`.
+fn parse_records(data: &[u8], format: Format) -> Result<Vec<Value>, String> {
+    match format {
+        Format::Jsonl => serde_json::Deserializer::from_slice(data)
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("invalid JSON: {}", e)),
+        Format::Msgpack => {
+            let mut rest = data;
+            let mut records = Vec::new();
+            while !rest.is_empty() {
+                let record = rmpv::decode::read_value(&mut rest)
+                    .map_err(|e| format!("invalid MessagePack: {}", e))?;
+                records.push(msgpack_to_json(&record));
+            }
+            Ok(records)
+        }
+        Format::Csv => {
+            let text = std::str::from_utf8(data).map_err(|e| format!("invalid UTF-8: {}", e))?;
+            csv_records(text)
+        }
+    }
+}
+
+/// One record per CSV row, with fields named by the header row. Empty cells
+/// are left out of the record; see [`csv_value`] for the rest.
+fn csv_records(text: &str) -> Result<Vec<Value>, String> {
+    let mut rows = csv_rows(text)?.into_iter();
+    let Some(header) = rows.next() else {
+        return Ok(Vec::new());
+    };
+    rows.enumerate()
+        .map(|(i, row)| {
+            if row.len() != header.len() {
+                return Err(format!(
+                    "row {} has {} fields but the header has {}",
+                    i + 1,
+                    row.len(),
+                    header.len()
+                ));
+            }
+            let record: Map<String, Value> = header
+                .iter()
+                .zip(row)
+                .filter(|(_, cell)| !cell.is_empty())
+                .map(|(name, cell)| (name.clone(), csv_value(cell)))
+                .collect();
+            Ok(Value::Object(record))
+        })
+        .collect()
+}
+
+/// A CSV cell as a field value: numbers, booleans, arrays, and objects
+/// written as JSON are read back as such (as export writes them), and
+/// anything else is a string.
+fn csv_value(cell: String) -> Value {
+    match serde_json::from_str(&cell) {
+        Ok(Value::String(_)) | Err(_) => Value::String(cell),
+        Ok(value) => value,
+    }
+}
+
+/// The rows of CSV `text` as RFC 4180 has them: quoted cells may hold
+/// commas, line breaks, and doubled quotes. Blank lines are skipped.
+fn csv_rows(text: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                cell.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if cell.is_empty() => quoted = true,
+            ',' if !quoted => row.push(std::mem::take(&mut cell)),
+            '\r' if !quoted && chars.peek() == Some(&'\n') => {}
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut cell));
+                rows.push(std::mem::take(&mut row));
+            }
+            c => cell.push(c),
+        }
+    }
+    if quoted {
+        return Err("a quoted CSV cell is never closed".to_string());
+    }
+    if !cell.is_empty() || !row.is_empty() {
+        row.push(cell);
+        rows.push(row);
+    }
+    rows.retain(|row| row.len() > 1 || !row[0].is_empty());
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn csv_reads_back_what_export_writes() {
+        let text = "id,name,age,tags\r\nu1,\"Ada, Countess\",36,\"[\"\"math\"\"]\"\n\nu2,,,\n";
+        assert_eq!(
+            csv_records(text).unwrap(),
+            [
+                json!({"id": "u1", "name": "Ada, Countess", "age": 36, "tags": ["math"]}),
+                json!({"id": "u2"}),
+            ]
+        );
+    }
+
+    #[test]
+    fn csv_cells_that_are_json_strings_stay_as_written() {
+        assert_eq!(csv_value("\"quoted\"".to_string()), json!("\"quoted\""));
+        assert_eq!(csv_value("true".to_string()), json!(true));
+        assert_eq!(csv_value("007x".to_string()), json!("007x"));
+    }
+
+    #[test]
+    fn malformed_csv_is_refused() {
+        assert_eq!(
+            csv_records("id,name\nu1\n").unwrap_err(),
+            "row 1 has 1 fields but the header has 2"
+        );
+        assert!(csv_rows("id\n\"open").is_err());
+    }
+
+    #[test]
+    fn msgpack_and_json_lines_read_one_record_after_another() {
+        let mut data = Vec::new();
+        for id in ["a", "b"] {
+            let record = rmpv::Value::Map(vec![("id".into(), id.into())]);
+            rmpv::encode::write_value(&mut data, &record).unwrap();
+        }
+        assert_eq!(
+            parse_records(&data, Format::Msgpack).unwrap(),
+            [json!({"id": "a"}), json!({"id": "b"})]
+        );
+        assert!(parse_records(&data[..data.len() - 1], Format::Msgpack).is_err());
+
+        assert_eq!(
+            parse_records(b"{\"id\":1}\n{\"id\":2}\n", Format::Jsonl).unwrap(),
+            [json!({"id": 1}), json!({"id": 2})]
+        );
+    }
+}
@@ -1,14 +1,43 @@
-use clap::{Parser, Subcommand};
+mod aggregate;
+mod backup;
+mod commands;
+mod completions;
+mod config;
+mod diff;
+mod export;
+mod glob;
+mod help;
+mod import;
+mod progress;
+mod query;
+mod render;
+mod schema;
+mod timestamp;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use config::Config;
+use cortex_client::json::{input_to_msgpack, json_to_msgpack, msgpack_to_json};
+use cortex_client::{connection, error, Connection, Error};
 use rmpv::Value;
-use std::io::{Read, Write};
-use std::os::unix::net::UnixStream;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::cell::{RefCell, RefMut};
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const DEFAULT_SOCKET: &str = "/run/cortex/cortex.sock";
-
-static MSG_ID: AtomicU32 = AtomicU32::new(1);
+/// Base delay between connection retries; grows linearly per attempt.
+const RETRY_DELAY: Duration = Duration::from_millis(200);
+/// Largest response read without `--max-response-size`, so a misbehaving
+/// daemon can't make the CLI buffer until it runs out of memory.
+const DEFAULT_MAX_RESPONSE_SIZE: u64 = 256 * 1024 * 1024;
 
 #[derive(Parser)]
 #[command(name = "cortex")]
@@ -21,24 +50,186 @@ struct Cli {
     #[arg(long, global = true)]
     pretty: bool,
 
-    /// Socket path
-    #[arg(long, global = true, default_value = DEFAULT_SOCKET)]
-    socket: String,
+    /// Pretty-print, but keep nested values shorter than N bytes on one line
+    #[arg(long, global = true, value_name = "N")]
+    compact_under: Option<usize>,
+
+    /// Show binary values over N bytes as their size and hash [default: 64]
+    #[arg(long, global = true, value_name = "N", num_args = 0..=1,
+          default_missing_value = "64")]
+    compact_binary: Option<usize>,
+
+    /// Socket path [default: /run/cortex/cortex.sock]
+    #[arg(long, global = true)]
+    socket: Option<String>,
+
+    /// Config file [default: ~/.config/cortex/config.toml]
+    #[arg(long, global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Give up on an unresponsive daemon after this long (e.g. 10 or 10s)
+    #[arg(long, global = true, value_name = "DURATION")]
+    timeout: Option<String>,
+
+    /// Retry connecting this many times if the daemon is unreachable
+    #[arg(long, global = true, value_name = "N")]
+    retry: Option<u32>,
+
+    /// Refuse to put a record larger than this many bytes once encoded
+    /// [default: 1000000]
+    #[arg(long, global = true, value_name = "BYTES")]
+    max_value_size: Option<u64>,
+
+    /// Abort if a response grows past this many bytes; 0 for no limit
+    /// [default: 268435456]
+    #[arg(long, global = true, value_name = "BYTES")]
+    max_response_size: Option<u64>,
+
+    /// Retry a request this many times if the daemon reports it busy
+    #[arg(long, global = true, value_name = "N")]
+    retry_on_busy: Option<u32>,
+
+    /// Treat daemon errors containing TEXT as transient under --retry-on-busy
+    /// (repeatable) [default: busy, timeout]
+    #[arg(long, global = true, value_name = "TEXT")]
+    busy_pattern: Vec<String>,
+
+    /// Colorize JSON output
+    #[arg(long, global = true, value_enum, value_name = "WHEN")]
+    color: Option<ColorChoice>,
+
+    /// Never colorize output (same as --color never)
+    #[arg(long, global = true, conflicts_with = "color")]
+    no_color: bool,
+
+    /// Show the requests mutating commands would send, without connecting
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// Print nothing on success; rely on the exit code
+    #[arg(long, short = 'q', global = true)]
+    quiet: bool,
+
+    /// Dump requests and responses (decoded and hex) to stderr
+    #[arg(long, short = 'v', global = true)]
+    verbose: bool,
+
+    /// Skip the protocol version check made on connecting
+    #[arg(long, global = true)]
+    no_handshake: bool,
+
+    /// Output format (defaults to JSON, or a readable summary where one exists)
+    #[arg(long, global = true, value_enum, visible_alias = "format")]
+    output: Option<OutputFormat>,
+
+    /// After the output, print the result count and time taken to stderr
+    #[arg(long, global = true)]
+    stats: bool,
+
+    /// Don't end the output with a newline
+    #[arg(long, global = true)]
+    no_newline: bool,
+
+    /// Print list results one element per entry, each ended by NUL (for xargs -0)
+    #[arg(long, short = '0', global = true)]
+    null_delimited: bool,
+
+    /// Error format on stderr (defaults to json under --output json, else text)
+    #[arg(long, global = true, value_enum)]
+    errors: Option<ErrorFormat>,
 
     #[command(subcommand)]
     command: Option<Commands>,
+
+    /// The connection a `batch` runs its commands over
+    #[arg(skip)]
+    shared: Option<Rc<RefCell<Connection>>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum OutputFormat {
+    /// Human-readable summary
+    Text,
+    /// JSON
+    Json,
+    /// One compact JSON value per line; lists print one element per line
+    Ndjson,
+    /// Aligned ASCII table, one row per record
+    Table,
+    /// CSV with a header row, one row per record
+    Csv,
+    /// YAML
+    Yaml,
+    /// A single value as-is: strings unquoted, for shell scripts
+    Raw,
+    /// MessagePack, exactly as the daemon sent it
+    Msgpack,
+}
+
+/// How to interpret a primary key given on the command line.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+enum KeyType {
+    #[default]
+    String,
+    Int,
+    Float,
+    Bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ColorChoice {
+    /// Color when stdout is a terminal and NO_COLOR is unset
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ErrorFormat {
+    /// error: <message>
+    Text,
+    /// {"error": "<message>", "code": "<category>"}
+    Json,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Health check
-    Ping,
+    Ping {
+        /// Report round-trip time instead of the reply
+        #[arg(long)]
+        latency: bool,
+        /// Number of pings to send over one connection
+        #[arg(long, short = 'c', default_value_t = 1, requires = "latency",
+              value_parser = clap::value_parser!(u32).range(1..))]
+        count: u32,
+        /// Only check the socket accepts connections; send nothing
+        #[arg(long, conflicts_with = "latency")]
+        connect_only: bool,
+    },
+
+    /// Wait until the daemon answers a ping (up to --timeout, default 30s)
+    WaitReady,
 
     /// Daemon status
     Status,
 
+    /// Check the daemon is reachable and working; exits non-zero if not
+    Health,
+
+    /// Show CLI and daemon versions and whether they are compatible
+    Version,
+
+    /// Show the UID the daemon identifies this connection as
+    Whoami,
+
     /// List your tables
-    Tables,
+    Tables {
+        /// Only list tables whose names match this glob (`*` and `?`)
+        #[arg(long)]
+        pattern: Option<String>,
+    },
 
     /// Create a new table
     #[command(visible_alias = "create_table")]
@@ -47,6 +238,10 @@ enum Commands {
         name: String,
         /// Comma-separated attributes (first is primary key)
         attrs: String,
+        /// Succeed without changes if the table already exists with the same
+        /// attributes; fail only if its schema differs
+        #[arg(long)]
+        if_not_exists: bool,
     },
 
     /// Drop a table
@@ -54,30 +249,230 @@ enum Commands {
     DropTable {
         /// Table name
         name: String,
+        /// Skip the confirmation prompt
+        #[arg(long, short = 'y')]
+        yes: bool,
+    },
+
+    /// Index an attribute so queries on it skip the full table scan
+    CreateIndex {
+        /// Table name
+        table: String,
+        /// Attribute to index (not the primary key)
+        attribute: String,
+    },
+
+    /// Remove an attribute's index
+    DropIndex {
+        /// Table name
+        table: String,
+        /// Indexed attribute
+        attribute: String,
+    },
+
+    /// Delete all records, keeping the table and its ACLs
+    Truncate {
+        /// Table name
+        table: String,
+        /// Skip the confirmation prompt
+        #[arg(long, short = 'y')]
+        yes: bool,
+    },
+
+    /// Show a table's attributes and primary key
+    Describe {
+        /// Table name
+        table: String,
     },
 
     /// Get a record by key
     Get {
+        /// Table name
+        table: String,
+        /// Primary key
+        #[arg(required_unless_present = "keys_file")]
+        key: Option<String>,
+        /// Get every key in this file (one per line), or - for stdin
+        #[arg(long, value_name = "PATH",
+              conflicts_with_all = ["key", "default", "assert_eq", "assert_field", "extract",
+                                    "raw_string"])]
+        keys_file: Option<PathBuf>,
+        /// Type of the primary key
+        #[arg(long, value_enum, default_value_t = KeyType::String)]
+        key_type: KeyType,
+        /// Only return these fields (comma-separated, in this order)
+        #[arg(long)]
+        fields: Option<String>,
+        /// Print this JSON value instead of failing if the record is missing
+        #[arg(long, value_name = "JSON")]
+        default: Option<String>,
+        /// Fail (exit 7) unless the record equals this JSON
+        #[arg(long, value_name = "JSON")]
+        assert_eq: Option<String>,
+        /// Fail (exit 7) unless the field has this value (JSON, or else a
+        /// string); repeatable
+        #[arg(long, value_name = "FIELD=VALUE")]
+        assert_field: Vec<String>,
+        #[command(flatten)]
+        extract: ExtractArgs,
+        /// Print a string result as-is, without JSON quotes or escapes
+        #[arg(long, short = 'r')]
+        raw_string: bool,
+        /// Print {"record": ..., "ttl": seconds} with the time left before
+        /// the record expires (null if it never does)
+        #[arg(long, conflicts_with_all = ["keys_file", "raw_string"])]
+        with_ttl: bool,
+    },
+
+    /// Check whether a record exists: exit 0 if it does, 1 if not
+    Exists {
         /// Table name
         table: String,
         /// Primary key
         key: String,
+        /// Type of the primary key
+        #[arg(long, value_enum, default_value_t = KeyType::String)]
+        key_type: KeyType,
+        /// Also print true or false
+        #[arg(long)]
+        print: bool,
     },
 
     /// Insert or update a record
     Put {
         /// Table name
         table: String,
-        /// Record as JSON
-        json: String,
+        /// Record as JSON, or - to read it (or one record per line) from stdin
+        #[arg(required_unless_present = "file")]
+        json: Option<String>,
+        /// Read the record (or one record per line) from this file, or - for stdin
+        #[arg(long, value_name = "PATH", conflicts_with = "json")]
+        file: Option<PathBuf>,
+        /// Only write if no record with this key exists yet
+        #[arg(long)]
+        if_absent: bool,
+        /// Only write if the stored record equals this JSON object
+        #[arg(long, value_name = "JSON", conflicts_with = "if_absent")]
+        if_match: Option<String>,
+        /// Have the daemon delete the record after this long: seconds, or a
+        /// duration like 30m, 2h, 1d
+        #[arg(long, value_name = "TTL")]
+        ttl: Option<String>,
+        /// Refuse to write a record that doesn't match this JSON Schema (draft 7)
+        #[arg(long, value_name = "PATH")]
+        schema: Option<PathBuf>,
     },
 
-    /// Delete a record
-    Delete {
+    /// Insert or update many records in one go
+    PutMany {
+        /// Table name
+        table: String,
+        /// Records as one JSON object per line (or a JSON array); - for stdin
+        file: PathBuf,
+    },
+
+    /// Apply several puts and deletes, across tables, all or nothing
+    Txn {
+        /// Ops as a JSON array; - for stdin
+        #[arg(long, value_name = "PATH", default_value = "-")]
+        file: PathBuf,
+    },
+
+    /// Check records against a JSON Schema without writing them
+    Validate {
+        /// Table the records are meant for
+        table: String,
+        /// JSON Schema (draft 7) file
+        #[arg(long, value_name = "PATH")]
+        schema: PathBuf,
+        /// Records as a JSON array or one JSON object per line; - for stdin
+        #[arg(long, value_name = "PATH", default_value = "-")]
+        file: PathBuf,
+    },
+
+    /// Make a record expire after a while, or never with --persist
+    Expire {
+        /// Table name
+        table: String,
+        /// Primary key
+        key: String,
+        /// Seconds until the record is deleted, or a duration like 30m, 2h, 1d
+        #[arg(required_unless_present = "persist")]
+        ttl: Option<String>,
+        /// Remove the record's expiry instead, so it is kept
+        #[arg(long, conflicts_with = "ttl")]
+        persist: bool,
+        /// Type of the primary key
+        #[arg(long, value_enum, default_value_t = KeyType::String)]
+        key_type: KeyType,
+    },
+
+    /// Append a value to an array field of a record
+    Append {
         /// Table name
         table: String,
         /// Primary key
         key: String,
+        /// Array field to append to (created if absent)
+        field: String,
+        /// Value to append, as JSON
+        value: String,
+        /// Type of the primary key
+        #[arg(long, value_enum, default_value_t = KeyType::String)]
+        key_type: KeyType,
+    },
+
+    /// Atomically add to a numeric field of a record
+    Incr {
+        /// Table name
+        table: String,
+        /// Primary key (the record is created if absent)
+        key: String,
+        /// Numeric field to add to (0 if absent)
+        field: String,
+        /// Amount to add
+        #[arg(default_value = "1", allow_negative_numbers = true)]
+        amount: String,
+        /// Type of the primary key
+        #[arg(long, value_enum, default_value_t = KeyType::String)]
+        key_type: KeyType,
+    },
+
+    /// Atomically subtract from a numeric field of a record
+    Decr {
+        /// Table name
+        table: String,
+        /// Primary key (the record is created if absent)
+        key: String,
+        /// Numeric field to subtract from (0 if absent)
+        field: String,
+        /// Amount to subtract
+        #[arg(default_value = "1", allow_negative_numbers = true)]
+        amount: String,
+        /// Type of the primary key
+        #[arg(long, value_enum, default_value_t = KeyType::String)]
+        key_type: KeyType,
+    },
+
+    /// Delete a record, or every record matching a pattern
+    Delete {
+        /// Table name
+        table: String,
+        /// Primary key
+        #[arg(required_unless_present_any = ["pattern", "keys_file"])]
+        key: Option<String>,
+        /// Type of the primary key
+        #[arg(long, value_enum, default_value_t = KeyType::String)]
+        key_type: KeyType,
+        /// Delete all records matching this JSON pattern instead of one key
+        #[arg(long, group = "bulk", conflicts_with_all = ["key", "key_type"])]
+        pattern: Option<String>,
+        /// Delete every key in this file (one per line), or - for stdin
+        #[arg(long, value_name = "PATH", group = "bulk", conflicts_with = "key")]
+        keys_file: Option<PathBuf>,
+        /// Skip the confirmation prompt for --pattern or --keys-file
+        #[arg(long, short = 'y', requires = "bulk")]
+        yes: bool,
     },
 
     /// Query records by pattern
@@ -85,19 +480,223 @@ enum Commands {
         /// Table name
         table: String,
         /// Pattern as JSON
-        pattern: String,
+        #[arg(required_unless_present = "file")]
+        pattern: Option<String>,
+        /// Read the pattern from this JSON file, or - for stdin
+        #[arg(long, value_name = "PATH", conflicts_with = "pattern")]
+        file: Option<PathBuf>,
+        /// Only return these fields (comma-separated, in this order)
+        #[arg(long)]
+        fields: Option<String>,
+        #[command(flatten)]
+        sort: SortArgs,
+        #[command(flatten)]
+        time: TimeArgs,
+        #[command(flatten)]
+        extract: ExtractArgs,
+        /// Fetch the --limit records after CURSOR (or the first, with no
+        /// CURSOR), and print the cursor for the next page
+        #[arg(long, value_name = "CURSOR", num_args = 0..=1, default_missing_value = "",
+              requires = "limit", conflicts_with_all = ["sort_by", "since", "until", "extract"])]
+        cursor: Option<String>,
     },
 
     /// List all records in a table
     All {
         /// Table name
         table: String,
+        /// Only return these fields (comma-separated, in this order)
+        #[arg(long)]
+        fields: Option<String>,
+        #[command(flatten)]
+        sort: SortArgs,
+        #[command(flatten)]
+        time: TimeArgs,
+        /// Fetch N records per request, printing each as a JSON line [default: 5000]
+        #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "5000",
+              value_parser = clap::value_parser!(u32).range(1..),
+              conflicts_with_all = ["sort_by", "limit", "since", "until"])]
+        page_size: Option<u32>,
+        /// Fetch the --limit records after CURSOR (or the first, with no
+        /// CURSOR), and print the cursor for the next page
+        #[arg(long, value_name = "CURSOR", num_args = 0..=1, default_missing_value = "",
+              requires = "limit", conflicts_with_all = ["sort_by", "since", "until"])]
+        cursor: Option<String>,
+    },
+
+    /// Compare two tables' records by primary key
+    Diff {
+        /// Table to compare from
+        left: String,
+        /// Table to compare to
+        right: String,
+    },
+
+    /// Sum, average, min, max, or count records, optionally per group
+    Aggregate {
+        /// Table name
+        table: String,
+        #[command(flatten)]
+        op: AggregateArgs,
+        /// Aggregate each distinct value of this field separately
+        #[arg(long, value_name = "FIELD")]
+        group_by: Option<String>,
+    },
+
+    /// Count the records in a table, or those matching a pattern
+    Count {
+        /// Table name
+        table: String,
+        /// Pattern as JSON, as for query
+        pattern: Option<String>,
     },
 
     /// List all keys in a table
     Keys {
         /// Table name
         table: String,
+        /// Only list keys starting with this string
+        #[arg(long)]
+        prefix: Option<String>,
+        /// List at most this many keys
+        #[arg(long, value_name = "N")]
+        limit: Option<usize>,
+        /// List the --limit keys after CURSOR (or the first, with no CURSOR),
+        /// and print the cursor for the next page
+        #[arg(long, value_name = "CURSOR", num_args = 0..=1, default_missing_value = "",
+              requires = "limit")]
+        cursor: Option<String>,
+    },
+
+    /// List the records whose keys fall in a range, in key order
+    Range {
+        /// Table name
+        table: String,
+        /// Lowest key to include [default: the first]
+        #[arg(long, value_name = "KEY")]
+        from: Option<String>,
+        /// Highest key to include [default: the last]
+        #[arg(long, value_name = "KEY")]
+        to: Option<String>,
+        /// List at most this many records
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+        limit: Option<u64>,
+    },
+
+    /// Stream changes to a table as JSON lines
+    Watch {
+        /// Table name
+        table: String,
+        /// Stop after this long (e.g. 30, 10s, 5m)
+        #[arg(long)]
+        duration: Option<String>,
+        /// If the daemon goes away, reconnect and subscribe again
+        #[arg(long)]
+        reconnect: bool,
+    },
+
+    /// Write every table you own (schemas, records, and grants) to one JSON document
+    Backup {
+        /// Output file [default: stdout]
+        file: Option<PathBuf>,
+    },
+
+    /// Write one table's records to a file as JSON lines, MessagePack, or CSV
+    Export {
+        /// Table name
+        table: String,
+        /// Output file, or - for stdout
+        file: PathBuf,
+    },
+
+    /// Write records from a JSON lines, MessagePack, or CSV file to a table
+    Import {
+        /// Table name
+        table: String,
+        /// Input file, or - for stdin
+        file: PathBuf,
+        /// Create the table if it doesn't exist, with the first record's
+        /// fields as attributes and the first field as the primary key
+        #[arg(long)]
+        create: bool,
+        /// What to do with a record whose key is already in the table
+        #[arg(long, value_enum, default_value_t = import::OnConflict::Fail)]
+        on_conflict: import::OnConflict,
+        /// Write records over this many connections at once (1-16)
+        #[arg(
+            long,
+            value_name = "N",
+            default_value_t = 1,
+            value_parser = clap::value_parser!(u8).range(1..=i64::from(backup::MAX_PARALLEL))
+        )]
+        parallel: u8,
+    },
+
+    /// Recreate tables and records from a backup file
+    Restore {
+        /// Backup file, or - for stdin
+        file: PathBuf,
+        /// Leave tables that already exist untouched
+        #[arg(long, conflicts_with = "drop_first")]
+        skip_existing: bool,
+        /// Drop tables that already exist and restore the backed-up copy
+        #[arg(long)]
+        drop_first: bool,
+        /// Skip the confirmation prompt for --drop-first
+        #[arg(long, short = 'y', requires = "drop_first")]
+        yes: bool,
+        /// Write records over this many connections at once (1-16)
+        #[arg(
+            long,
+            value_name = "N",
+            default_value_t = 1,
+            value_parser = clap::value_parser!(u8).range(1..=i64::from(backup::MAX_PARALLEL))
+        )]
+        parallel: u8,
+    },
+
+    /// Copy a table's schema and records into a new table
+    CopyTable {
+        /// Source table
+        src: String,
+        /// Destination table
+        dst: String,
+        /// Create the destination without copying any records
+        #[arg(long)]
+        schema_only: bool,
+        /// Drop the destination first if it already exists
+        #[arg(long)]
+        overwrite: bool,
+        /// Skip the confirmation prompt for --overwrite
+        #[arg(long, short = 'y', requires = "overwrite")]
+        yes: bool,
+    },
+
+    /// Call any RPC method with raw JSON params
+    Raw {
+        /// Method name
+        method: String,
+        /// Params as a JSON array [default: []]
+        params: Option<String>,
+        /// Read the params array from this file, or - for stdin
+        #[arg(long, value_name = "PATH", conflicts_with = "params")]
+        params_file: Option<PathBuf>,
+    },
+
+    /// Run commands read one per line over a single connection, printing
+    /// a JSON array of their results
+    Batch {
+        /// File of commands, or - for stdin
+        input: PathBuf,
+        /// Stop at the first command that fails
+        #[arg(long)]
+        fail_fast: bool,
+    },
+
+    /// Change a table's schema
+    Migrate {
+        #[command(subcommand)]
+        command: MigrateCommands,
     },
 
     /// Access control commands
@@ -106,6 +705,13 @@ enum Commands {
         command: AclCommands,
     },
 
+    /// Print a shell script that sets up tab completion for cortex
+    Completions {
+        /// Shell to set up
+        #[arg(value_enum)]
+        shell: completions::Shell,
+    },
+
     /// Show help for a topic (e.g., cortex help memories)
     #[command(name = "help")]
     HelpTopic {
@@ -114,16 +720,152 @@ enum Commands {
     },
 }
 
+/// Ordering for commands that return a list of records: on the daemon when
+/// it can, and here otherwise.
+#[derive(Args, Default)]
+struct SortArgs {
+    /// Sort records by this field, descending with a :desc suffix (missing
+    /// values sort last)
+    #[arg(long, visible_alias = "sort", value_name = "FIELD[:desc]")]
+    sort_by: Option<String>,
+    /// Sort in descending order
+    #[arg(long, requires = "sort_by")]
+    reverse: bool,
+    /// Return at most N records (the first N after sorting)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    limit: Option<u32>,
+    /// Always sort on the daemon, failing if it can't
+    #[arg(long, requires = "sort_by", conflicts_with = "client_sort")]
+    server_sort: bool,
+    /// Always sort here, after fetching every matching record
+    #[arg(long, requires = "sort_by")]
+    client_sort: bool,
+}
+
+/// Pull one nested value out of each record `get` or `query` returns.
+#[derive(Args)]
+struct ExtractArgs {
+    /// Print only the value at this JSON pointer (e.g. /address/city)
+    #[arg(long, value_name = "POINTER", value_parser = parse_pointer)]
+    extract: Option<String>,
+    /// Where --extract doesn't resolve, print null (get) or skip the record (query)
+    #[arg(long, requires = "extract")]
+    extract_optional: bool,
+}
+
+/// Time bounds for commands that return a list of records.
+#[derive(Args)]
+struct TimeArgs {
+    /// Only records whose time field is at or after TIME (RFC 3339 or epoch seconds)
+    #[arg(long, value_name = "TIME")]
+    since: Option<String>,
+    /// Only records whose time field is at or before TIME (RFC 3339 or epoch seconds)
+    #[arg(long, value_name = "TIME")]
+    until: Option<String>,
+    /// Field --since and --until compare against
+    #[arg(long, value_name = "FIELD", default_value = "timestamp")]
+    time_field: String,
+}
+
+impl TimeArgs {
+    fn is_set(&self) -> bool {
+        self.since.is_some() || self.until.is_some()
+    }
+
+    /// Add the bounds, if any, to `pattern` as a range on the time field.
+    fn apply(&self, pattern: &mut query::Pattern) -> Result<(), Error> {
+        if !self.is_set() {
+            return Ok(());
+        }
+        let parse = |flag: &str, text: &Option<String>| {
+            text.as_deref()
+                .map(|text| {
+                    timestamp::parse(text).ok_or_else(|| {
+                        Error::Input(format!(
+                            "invalid {} '{}': expected RFC 3339 (e.g. 2024-01-15T10:30:00Z) \
+                             or epoch seconds",
+                            flag, text
+                        ))
+                    })
+                })
+                .transpose()
+        };
+        let range = query::Range {
+            since: parse("--since", &self.since)?,
+            until: parse("--until", &self.until)?,
+        };
+        if let (Some(since), Some(until)) = (range.since, range.until) {
+            if since > until {
+                return Err(Error::Input("--since is later than --until".to_string()));
+            }
+        }
+        validate_name("field", &self.time_field)?;
+        pattern.add_range(&self.time_field, range);
+        Ok(())
+    }
+}
+
+/// The aggregate to compute; exactly one is required.
+#[derive(Args)]
+#[group(required = true, multiple = false)]
+struct AggregateArgs {
+    /// Sum of a numeric field
+    #[arg(long, value_name = "FIELD")]
+    sum: Option<String>,
+    /// Mean of a numeric field
+    #[arg(long, value_name = "FIELD")]
+    avg: Option<String>,
+    /// Smallest value of a numeric field
+    #[arg(long, value_name = "FIELD")]
+    min: Option<String>,
+    /// Largest value of a numeric field
+    #[arg(long, value_name = "FIELD")]
+    max: Option<String>,
+    /// Number of records
+    #[arg(long)]
+    count: bool,
+}
+
+impl AggregateArgs {
+    fn op(&self) -> aggregate::Op {
+        use aggregate::Op;
+        match self {
+            AggregateArgs { sum: Some(f), .. } => Op::Sum(f.clone()),
+            AggregateArgs { avg: Some(f), .. } => Op::Avg(f.clone()),
+            AggregateArgs { min: Some(f), .. } => Op::Min(f.clone()),
+            AggregateArgs { max: Some(f), .. } => Op::Max(f.clone()),
+            _ => Op::Count,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum MigrateCommands {
+    /// Add an attribute, setting it on every existing record
+    AddAttribute {
+        /// Table name
+        table: String,
+        /// New attribute name
+        name: String,
+        /// Value existing records get for the attribute [default: null]
+        #[arg(long, value_name = "JSON")]
+        default: Option<String>,
+    },
+}
+
 #[derive(Subcommand)]
 enum AclCommands {
     /// Grant permissions
     Grant {
-        /// Identity (uid:NUMBER or * for world)
+        /// Identity (uid:NUMBER, gid:NUMBER, or * for world)
         identity: String,
         /// Table name
         table: String,
         /// Permissions (comma-separated: read,write,admin)
         perms: String,
+        /// Auto-revoke after a duration (e.g. 30m, 2h, 1d)
+        #[arg(long, value_name = "DURATION")]
+        expires: Option<String>,
     },
 
     /// Revoke permissions
@@ -137,567 +879,5868 @@ enum AclCommands {
     },
 
     /// List ACLs for your tables
-    List,
+    List {
+        /// Only list grants on this table
+        #[arg(long)]
+        table: Option<String>,
+        /// Only list grants to this identity (uid:NUMBER, gid:NUMBER, or *)
+        #[arg(long)]
+        identity: Option<String>,
+    },
+
+    /// Show the effective permissions an identity has on a table
+    Check {
+        /// Identity (uid:NUMBER, gid:NUMBER, or * for world)
+        identity: String,
+        /// Table name
+        table: String,
+    },
+}
+
+impl Commands {
+    /// The existing tables this command operates on, checked with
+    /// [`validate_name`] before anything is sent.
+    fn table_names(&self) -> Vec<&str> {
+        match self {
+            Commands::DropTable { name, .. } => vec![name],
+            Commands::Truncate { table, .. }
+            | Commands::CreateIndex { table, .. }
+            | Commands::DropIndex { table, .. }
+            | Commands::Describe { table }
+            | Commands::Get { table, .. }
+            | Commands::Exists { table, .. }
+            | Commands::Put { table, .. }
+            | Commands::PutMany { table, .. }
+            | Commands::Append { table, .. }
+            | Commands::Incr { table, .. }
+            | Commands::Decr { table, .. }
+            | Commands::Expire { table, .. }
+            | Commands::Delete { table, .. }
+            | Commands::Query { table, .. }
+            | Commands::All { table, .. }
+            | Commands::Aggregate { table, .. }
+            | Commands::Count { table, .. }
+            | Commands::Keys { table, .. }
+            | Commands::Range { table, .. }
+            | Commands::Export { table, .. }
+            | Commands::Import { table, .. }
+            | Commands::Watch { table, .. } => vec![table],
+            Commands::CopyTable { src, dst, .. } => vec![src, dst],
+            Commands::Diff { left, right } => vec![left, right],
+            Commands::Migrate {
+                command: MigrateCommands::AddAttribute { table, .. },
+            } => vec![table],
+            Commands::Acl {
+                command:
+                    AclCommands::Grant { table, .. }
+                    | AclCommands::Revoke { table, .. }
+                    | AclCommands::Check { table, .. }
+                    | AclCommands::List {
+                        table: Some(table), ..
+                    },
+            } => vec![table],
+            _ => vec![],
+        }
+    }
 }
 
 fn main() -> ExitCode {
-    let cli = Cli::parse();
+    completions::complete_if_asked();
+    let mut cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(e) => {
+            // Keep usage errors off exit code 2, which means "connection error"
+            let _ = e.print();
+            return if e.use_stderr() {
+                ExitCode::from(error::EXIT_INPUT)
+            } else {
+                ExitCode::SUCCESS
+            };
+        }
+    };
 
-    let result = match &cli.command {
-        None => {
-            print_help();
-            Ok(None)
+    let started = Instant::now();
+    let result = Config::load(cli.config.as_deref())
+        .and_then(|mut config| {
+            config.apply_env(|name| std::env::var(name).ok())?;
+            cli.apply_config(config);
+            Ok(())
+        })
+        .and_then(|_| run(&cli));
+    let elapsed = started.elapsed();
+    let (result, answer) = exists_answer(cli.command.as_ref(), result);
+
+    let color = cli.stdout_color();
+    let status = finish_with_stats(
+        &cli,
+        result,
+        elapsed,
+        color,
+        &mut io::stdout().lock(),
+        &mut io::stderr(),
+    );
+    ExitCode::from(if status == 0 { answer } else { status })
+}
+
+/// `exists` answers through its exit status, 0 if the record is there and
+/// 1 if not, and prints `true` or `false` only with `--print`. Returns the
+/// part of `result` left to print and the status to exit with if that
+/// succeeds.
+fn exists_answer(
+    command: Option<&Commands>,
+    result: Result<Option<Value>, Error>,
+) -> (Result<Option<Value>, Error>, u8) {
+    match (command, result) {
+        (Some(Commands::Exists { print, .. }), Ok(Some(Value::Boolean(found)))) => {
+            let shown = print.then_some(Value::Boolean(found));
+            (Ok(shown), if found { 0 } else { 1 })
         }
-        Some(Commands::Ping) => call(&cli.socket, "ping", vec![]),
-        Some(Commands::Status) => call(&cli.socket, "status", vec![]),
-        Some(Commands::Tables) => call(&cli.socket, "tables", vec![]),
-        Some(Commands::CreateTable { name, attrs }) => {
-            let attributes: Vec<Value> = attrs
-                .split(',')
-                .map(|s| Value::String(s.trim().into()))
-                .collect();
-            call(
-                &cli.socket,
-                "create_table",
-                vec![Value::String(name.clone().into()), Value::Array(attributes)],
-            )
+        (_, result) => (result, 0),
+    }
+}
+
+/// [`finish`], then under `--stats` a `# N records in Tms` line on `err`
+/// once the result has been written.
+fn finish_with_stats(
+    cli: &Cli,
+    result: Result<Option<Value>, Error>,
+    elapsed: Duration,
+    color: bool,
+    out: &mut impl Write,
+    err: &mut impl Write,
+) -> u8 {
+    let stats = match &result {
+        Ok(Some(value)) if cli.stats && !cli.quiet => Some(render_stats(value, elapsed)),
+        _ => None,
+    };
+    let status = finish(cli, result, color, out, err);
+    if let (0, Some(stats)) = (status, stats) {
+        let _ = writeln!(err, "{}", stats);
+    }
+    status
+}
+
+/// Count an array's elements, or a single result as one record.
+fn render_stats(value: &Value, elapsed: Duration) -> String {
+    let count = match value {
+        Value::Array(items) => items.len(),
+        _ => 1,
+    };
+    format!(
+        "# {} record{} in {}ms",
+        count,
+        if count == 1 { "" } else { "s" },
+        elapsed.as_millis()
+    )
+}
+
+/// Write a command's result to `out`, or its error to `err`, and return the
+/// process exit status. Under `--quiet` only errors are written.
+fn finish(
+    cli: &Cli,
+    result: Result<Option<Value>, Error>,
+    color: bool,
+    out: &mut impl Write,
+    err: &mut impl Write,
+) -> u8 {
+    let newline = if cli.no_newline { "" } else { "\n" };
+    let written = match result {
+        Ok(Some(Value::Array(items))) if cli.null_delimited && !cli.quiet => {
+            write_null_delimited(out, &items, cli.no_newline)
         }
-        Some(Commands::DropTable { name }) => call(
-            &cli.socket,
-            "drop_table",
-            vec![Value::String(name.clone().into())],
-        ),
-        Some(Commands::Get { table, key }) => call(
-            &cli.socket,
-            "get",
-            vec![
-                Value::String(table.clone().into()),
-                Value::String(key.clone().into()),
-            ],
-        ),
-        Some(Commands::Put { table, json }) => {
-            let record: serde_json::Value = match serde_json::from_str(json) {
-                Ok(v) => v,
-                Err(e) => {
-                    eprintln!("error: invalid JSON: {}", e);
-                    return ExitCode::FAILURE;
-                }
-            };
-            let record_msgpack = json_to_msgpack(&record);
-            call(
-                &cli.socket,
-                "put",
-                vec![Value::String(table.clone().into()), record_msgpack],
-            )
+        Ok(Some(Value::Array(items))) if cli.output == Some(OutputFormat::Ndjson) && !cli.quiet => {
+            let last = items.len().saturating_sub(1);
+            items.iter().enumerate().try_for_each(|(i, item)| {
+                write_json_line(out, item, color, if i == last { newline } else { "\n" })
+            })
         }
-        Some(Commands::Delete { table, key }) => call(
-            &cli.socket,
-            "delete",
-            vec![
-                Value::String(table.clone().into()),
-                Value::String(key.clone().into()),
-            ],
-        ),
-        Some(Commands::Query { table, pattern }) => {
-            let pat: serde_json::Value = match serde_json::from_str(pattern) {
-                Ok(v) => v,
-                Err(e) => {
-                    eprintln!("error: invalid JSON pattern: {}", e);
-                    return ExitCode::FAILURE;
-                }
-            };
-            let pat_msgpack = json_to_msgpack(&pat);
-            call(
-                &cli.socket,
-                "match",
-                vec![Value::String(table.clone().into()), pat_msgpack],
-            )
+        Ok(Some(value)) if cli.output == Some(OutputFormat::Ndjson) && !cli.quiet => {
+            write_json_line(out, &value, color, newline)
         }
-        Some(Commands::All { table }) => call(
-            &cli.socket,
-            "all",
-            vec![Value::String(table.clone().into())],
-        ),
-        Some(Commands::Keys { table }) => call(
-            &cli.socket,
-            "keys",
-            vec![Value::String(table.clone().into())],
-        ),
-        Some(Commands::Acl { command }) => match command {
-            AclCommands::Grant {
-                identity,
-                table,
-                perms,
-            } => call(
-                &cli.socket,
-                "acl_grant",
-                vec![
-                    Value::String(identity.clone().into()),
-                    Value::String(table.clone().into()),
-                    Value::String(perms.clone().into()),
-                ],
-            ),
-            AclCommands::Revoke {
-                identity,
-                table,
-                perms,
-            } => call(
-                &cli.socket,
-                "acl_revoke",
-                vec![
-                    Value::String(identity.clone().into()),
-                    Value::String(table.clone().into()),
-                    Value::String(perms.clone().into()),
-                ],
-            ),
-            AclCommands::List => call(&cli.socket, "acl_list", vec![]),
-        },
-        Some(Commands::HelpTopic { topic }) => {
-            print_topic_help(topic.as_deref());
-            Ok(None)
+        Ok(Some(value)) if cli.output == Some(OutputFormat::Raw) && !cli.quiet => {
+            write_raw(out, &msgpack_to_json(&value), newline)
         }
-    };
-
-    match result {
-        Ok(Some(value)) => {
+        Ok(Some(value)) if cli.output == Some(OutputFormat::Msgpack) && !cli.quiet => {
+            rmpv::encode::write_value(out, &value)
+                .map_err(|e| Error::Output(format!("write error: {}", e)))
+        }
+        Ok(Some(value)) if !cli.quiet => {
+            // An explicit --output json is for machines, which want every byte
+            let value = match cli.compact_binary {
+                Some(limit) if cli.output != Some(OutputFormat::Json) => {
+                    summarize_binary(value, limit)
+                }
+                _ => value,
+            };
             let json = msgpack_to_json(&value);
-            if cli.pretty {
-                println!("{}", serde_json::to_string_pretty(&json).unwrap());
-            } else {
-                println!("{}", serde_json::to_string(&json).unwrap());
+            let rendered = match cli.output {
+                Some(OutputFormat::Table) => render::table(&json),
+                Some(OutputFormat::Csv) => render::csv(&json),
+                Some(OutputFormat::Yaml) => Some(render::yaml(&json)),
+                _ => None,
+            };
+            match rendered {
+                Some(text) => write!(out, "{}{}", text.trim_end_matches('\n'), newline),
+                None => {
+                    let json = match cli.compact_under {
+                        Some(limit) => render::json_compact_under(&json, limit, color),
+                        None => render::json(&json, cli.pretty, color),
+                    };
+                    write!(out, "{}{}", json, newline)
+                }
             }
-            ExitCode::SUCCESS
+            .map_err(|e| Error::Output(format!("write error: {}", e)))
         }
-        Ok(None) => ExitCode::SUCCESS,
+        Ok(_) => Ok(()),
+        Err(e) => Err(e),
+    };
+
+    match written {
+        Ok(()) => 0,
         Err(e) => {
-            eprintln!("error: {}", e);
-            ExitCode::FAILURE
+            let json = match cli.errors {
+                Some(format) => format == ErrorFormat::Json,
+                None => cli.output == Some(OutputFormat::Json),
+            };
+            let _ = writeln!(err, "{}", render_error(&e, json));
+            e.exit_code()
         }
     }
 }
 
-fn call(socket_path: &str, method: &str, params: Vec<Value>) -> Result<Option<Value>, String> {
-    let mut stream = UnixStream::connect(socket_path)
-        .map_err(|e| format!("cannot connect to {}: {}", socket_path, e))?;
+/// Write a scalar result for `--output raw`: a string as-is, null as
+/// nothing, and a number or boolean as JSON. Arrays and objects are refused.
+fn write_raw(out: &mut impl Write, value: &serde_json::Value, newline: &str) -> Result<(), Error> {
+    let text = match value {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Null => String::new(),
+        serde_json::Value::Array(_) | serde_json::Value::Object(_) => {
+            return Err(Error::Input(
+                "raw output is a single value, not an array or object \
+                 (pick one out with e.g. get --extract)"
+                    .to_string(),
+            ))
+        }
+        other => other.to_string(),
+    };
+    write!(out, "{}{}", text, newline).map_err(|e| Error::Output(format!("write error: {}", e)))
+}
 
-    let msgid = MSG_ID.fetch_add(1, Ordering::SeqCst);
-    let request = Value::Array(vec![
-        Value::Integer(0.into()),
-        Value::Integer(msgid.into()),
-        Value::String(method.into()),
-        Value::Array(params),
-    ]);
+/// Print a human-readable rendering to stdout unless `--quiet` is set.
+fn print_text(cli: &Cli, text: &str) {
+    match (cli.quiet, cli.no_newline) {
+        (true, _) => {}
+        (false, true) => print!("{}", text.trim_end_matches('\n')),
+        (false, false) => print!("{}", text),
+    }
+}
 
-    let mut buf = Vec::new();
-    rmpv::encode::write_value(&mut buf, &request).map_err(|e| format!("encode error: {}", e))?;
+impl Cli {
+    /// Fill in settings not given on the command line from the config file
+    /// (with any environment overrides already applied).
+    fn apply_config(&mut self, config: Config) {
+        self.socket = self.socket.take().or(config.socket);
+        self.output = self.output.or(config.output);
+        self.timeout = self.timeout.take().or(config.timeout);
+        self.retry = self.retry.or(config.retry);
+        self.retry_on_busy = self.retry_on_busy.or(config.retry_on_busy);
+        self.max_value_size = self.max_value_size.or(config.max_value_size);
+        self.max_response_size = self.max_response_size.or(config.max_response_size);
+        if self.busy_pattern.is_empty() {
+            self.busy_pattern = config.busy_patterns.unwrap_or_default();
+        }
+        self.pretty |= config.pretty.unwrap_or(false);
+    }
 
-    stream
-        .write_all(&buf)
-        .map_err(|e| format!("write error: {}", e))?;
+    /// A progress indicator for a bulk operation, drawn on stderr only when
+    /// it's a terminal and `--quiet` isn't set.
+    fn progress(&self, label: &'static str, total: Option<usize>) -> progress::Progress {
+        if self.show_progress(io::stderr().is_terminal()) {
+            progress::Progress::new(label, total, Box::new(io::stderr()))
+        } else {
+            progress::Progress::hidden()
+        }
+    }
 
-    let mut response_buf = vec![0u8; 65536];
-    let n = stream
-        .read(&mut response_buf)
-        .map_err(|e| format!("read error: {}", e))?;
+    fn show_progress(&self, stderr_is_tty: bool) -> bool {
+        stderr_is_tty && !self.quiet
+    }
 
-    let response = rmpv::decode::read_value(&mut &response_buf[..n])
-        .map_err(|e| format!("decode error: {}", e))?;
+    /// Whether to colorize what goes to this process's stdout.
+    fn stdout_color(&self) -> bool {
+        let no_color_env = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+        self.use_color(io::stdout().is_terminal(), no_color_env)
+    }
 
-    match response {
-        Value::Array(parts) if parts.len() == 4 => {
-            let error = &parts[2];
-            let result = &parts[3];
+    /// Whether to colorize output, given whether stdout is a terminal and
+    /// whether `NO_COLOR` is set. An explicit `--color always` wins over both.
+    fn use_color(&self, stdout_is_tty: bool, no_color_env: bool) -> bool {
+        match (self.no_color, self.color.unwrap_or(ColorChoice::Auto)) {
+            (true, _) | (_, ColorChoice::Never) => false,
+            (_, ColorChoice::Always) => true,
+            (_, ColorChoice::Auto) => stdout_is_tty && !no_color_env,
+        }
+    }
+}
 
-            if *error != Value::Nil {
-                let err_str = match error {
-                    Value::String(s) => s.as_str().unwrap_or("unknown error").to_string(),
-                    _ => format!("{}", error),
-                };
-                Err(err_str)
-            } else {
-                Ok(Some(result.clone()))
-            }
+fn render_error(e: &Error, json: bool) -> String {
+    if json {
+        let mut object = serde_json::json!({ "error": e.to_string(), "code": e.code() });
+        if let Error::DaemonCode { code, .. } = e {
+            object["reason"] = serde_json::Value::from(code.as_str());
         }
-        _ => Err("invalid response format".to_string()),
+        object.to_string()
+    } else {
+        format!("error: {}", e)
     }
 }
 
-fn json_to_msgpack(value: &serde_json::Value) -> Value {
-    match value {
-        serde_json::Value::Null => Value::Nil,
-        serde_json::Value::Bool(b) => Value::Boolean(*b),
-        serde_json::Value::Number(n) => {
-            if let Some(i) = n.as_i64() {
-                Value::Integer(i.into())
-            } else if let Some(f) = n.as_f64() {
-                Value::F64(f)
+fn run(cli: &Cli) -> Result<Option<Value>, Error> {
+    if let Some(command) = &cli.command {
+        for table in command.table_names() {
+            validate_name("table", table)?;
+        }
+    }
+
+    match &cli.command {
+        None => {
+            help::print_help();
+            Ok(None)
+        }
+        Some(command) => commands::run(cli, command),
+    }
+}
+
+/// The contents of a `--params-file` or `--file`, read from `stdin` when the
+/// path is `-`.
+fn read_input_file(path: &Path, stdin: &mut impl Read) -> Result<String, Error> {
+    let mut params = String::new();
+    let read = if path.as_os_str() == "-" {
+        stdin.read_to_string(&mut params)
+    } else {
+        File::open(path).and_then(|mut file| file.read_to_string(&mut params))
+    };
+    read.map_err(|e| Error::Input(format!("cannot read {}: {}", path.display(), e)))?;
+    Ok(params)
+}
+
+/// A JSON argument given inline or, with `--file`, as a file's contents.
+/// `what` names the inline argument in parse errors.
+fn read_json_arg(
+    inline: Option<&str>,
+    file: Option<&Path>,
+    what: &str,
+) -> Result<serde_json::Value, Error> {
+    match file {
+        Some(path) => {
+            let text = read_input_file(path, &mut io::stdin().lock())?;
+            serde_json::from_str(&text)
+                .map_err(|e| Error::Input(format!("invalid JSON in {}: {}", path.display(), e)))
+        }
+        None => serde_json::from_str(inline.unwrap_or_default())
+            .map_err(|e| Error::Input(format!("invalid {}: {}", what, e))),
+    }
+}
+
+/// The JSON values in the file at `path` (stdin for `-`): usually one,
+/// but possibly several one after another, such as one per line.
+fn read_json_values(path: &Path) -> Result<Vec<serde_json::Value>, Error> {
+    let text = read_input_file(path, &mut io::stdin().lock())?;
+    let values = serde_json::Deserializer::from_str(&text)
+        .into_iter()
+        .collect::<Result<Vec<serde_json::Value>, _>>()
+        .map_err(|e| Error::Input(format!("invalid JSON in {}: {}", path.display(), e)))?;
+    if values.is_empty() {
+        return Err(Error::Input(format!("no JSON in {}", path.display())));
+    }
+    Ok(values)
+}
+
+/// Convert a key argument into the MessagePack type the table is keyed by.
+fn parse_key(key: &str, key_type: KeyType) -> Result<Value, Error> {
+    let invalid = |expected: &str| {
+        Error::Input(format!(
+            "invalid key '{}': expected {} for --key-type",
+            key, expected
+        ))
+    };
+
+    match key_type {
+        KeyType::String => Ok(Value::String(key.into())),
+        KeyType::Int => {
+            if let Ok(n) = key.parse::<i64>() {
+                Ok(Value::from(n))
             } else {
-                Value::Nil
+                key.parse::<u64>()
+                    .map(Value::from)
+                    .map_err(|_| invalid("an integer"))
+            }
+        }
+        KeyType::Float => key
+            .parse::<f64>()
+            .ok()
+            .filter(|f| f.is_finite())
+            .map(Value::F64)
+            .ok_or_else(|| invalid("a number")),
+        KeyType::Bool => key
+            .parse::<bool>()
+            .map(Value::Boolean)
+            .map_err(|_| invalid("true or false")),
+    }
+}
+
+/// Reject table and attribute names the daemon can't use: empty names,
+/// whitespace, and `:`, which separates the owner's uid from the table name.
+fn validate_name(kind: &str, name: &str) -> Result<(), Error> {
+    let problem = if name.is_empty() {
+        "must not be empty"
+    } else if name.contains(':') {
+        "must not contain ':'"
+    } else if name.chars().any(char::is_whitespace) {
+        "must not contain whitespace"
+    } else {
+        return Ok(());
+    };
+    Err(Error::Input(format!(
+        "invalid {} name '{}': {}",
+        kind, name, problem
+    )))
+}
+
+/// A bare number of seconds, or a duration like `30m`.
+fn parse_seconds(input: &str) -> Result<u64, Error> {
+    match input.trim().parse::<u64>() {
+        Ok(secs) if secs > 0 => Ok(secs),
+        _ => parse_duration(input),
+    }
+}
+
+/// Parse a duration like `90s`, `30m`, `2h`, or `1d` into seconds.
+fn parse_duration(input: &str) -> Result<u64, Error> {
+    let invalid = || {
+        Error::Input(format!(
+            "invalid duration '{}': expected a number followed by s, m, h, or d (e.g. 30m)",
+            input
+        ))
+    };
+
+    let input = input.trim();
+    let (split, _) = input.char_indices().last().ok_or_else(invalid)?;
+    let (amount, unit) = input.split_at(split);
+    let amount: u64 = amount.parse().map_err(|_| invalid())?;
+
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+
+    match amount.checked_mul(multiplier) {
+        Some(0) | None => Err(invalid()),
+        Some(secs) => Ok(secs),
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Gate a destructive operation behind `--yes` or an interactive y/N prompt.
+///
+/// Without `--yes`, a non-interactive stdin refuses outright so scripts
+/// never hang waiting for input.
+fn confirm(
+    prompt: &str,
+    yes: bool,
+    interactive: bool,
+    input: &mut impl BufRead,
+) -> Result<(), Error> {
+    let prompt = format!("{} [y/N] ", prompt);
+    ask(&prompt, yes, interactive, input, |answer| {
+        matches!(answer.to_lowercase().as_str(), "y" | "yes")
+    })
+}
+
+/// Like `confirm`, but the user must type `expected` (e.g. the table name)
+/// rather than just "y", so a stray keypress can't destroy data.
+fn confirm_typed(
+    expected: &str,
+    yes: bool,
+    interactive: bool,
+    input: &mut impl BufRead,
+) -> Result<(), Error> {
+    let prompt = format!(
+        "This permanently deletes '{}' and all its data.\nType the table name to confirm: ",
+        expected
+    );
+    ask(&prompt, yes, interactive, input, |answer| {
+        answer == expected
+    })
+}
+
+/// Print `prompt` on stderr and read one line of `input`, going ahead only
+/// if `accepts` the trimmed answer. `--yes` skips the prompt, and without it
+/// a non-interactive stdin is refused.
+fn ask(
+    prompt: &str,
+    yes: bool,
+    interactive: bool,
+    input: &mut impl BufRead,
+    accepts: impl Fn(&str) -> bool,
+) -> Result<(), Error> {
+    if yes {
+        return Ok(());
+    }
+    if !interactive {
+        return Err(Error::Input(
+            "refusing destructive operation without --yes".to_string(),
+        ));
+    }
+
+    eprint!("{}", prompt);
+    io::stderr().flush().ok();
+
+    let mut answer = String::new();
+    input
+        .read_line(&mut answer)
+        .map_err(|e| Error::Input(format!("read error: {}", e)))?;
+
+    if accepts(answer.trim()) {
+        Ok(())
+    } else {
+        Err(Error::Input("aborted".to_string()))
+    }
+}
+
+/// A connection from [`connect`]: a new one, or the one shared by the
+/// commands of a `batch`.
+enum Session<'a> {
+    Owned(Connection),
+    Shared(RefMut<'a, Connection>),
+}
+
+impl Deref for Session<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        match self {
+            Session::Owned(conn) => conn,
+            Session::Shared(conn) => conn,
+        }
+    }
+}
+
+impl DerefMut for Session<'_> {
+    fn deref_mut(&mut self) -> &mut Connection {
+        match self {
+            Session::Owned(conn) => conn,
+            Session::Shared(conn) => conn,
+        }
+    }
+}
+
+/// The batch's shared connection if there is one and it's free, or else a
+/// new connection.
+fn connect(cli: &Cli) -> Result<Session<'_>, Error> {
+    if let Some(conn) = cli.shared.as_ref().and_then(|c| c.try_borrow_mut().ok()) {
+        return Ok(Session::Shared(conn));
+    }
+    open(cli).map(Session::Owned)
+}
+
+/// Open a connection using the effective socket, retry, timeout, and trace
+/// settings.
+fn open(cli: &Cli) -> Result<Connection, Error> {
+    let socket = cli.socket.as_deref().unwrap_or(DEFAULT_SOCKET);
+    let timeout = cli
+        .timeout
+        .as_deref()
+        .map(|t| parse_seconds(t).map(Duration::from_secs))
+        .transpose()?;
+
+    let mut attempt = 0;
+    let conn = loop {
+        match Connection::new(socket) {
+            Ok(conn) => break conn,
+            Err(Error::Connection(_)) if attempt < cli.retry.unwrap_or(0) => {
+                attempt += 1;
+                std::thread::sleep(RETRY_DELAY * attempt);
             }
+            Err(e) => return Err(e),
         }
-        serde_json::Value::String(s) => Value::String(s.clone().into()),
-        serde_json::Value::Array(arr) => Value::Array(arr.iter().map(json_to_msgpack).collect()),
-        serde_json::Value::Object(obj) => Value::Map(
-            obj.iter()
-                .map(|(k, v)| (Value::String(k.clone().into()), json_to_msgpack(v)))
+    };
+
+    conn.set_timeout(timeout)?;
+    let conn = match cli.max_response_size.unwrap_or(DEFAULT_MAX_RESPONSE_SIZE) {
+        0 => conn,
+        limit => conn.with_max_response_size(usize::try_from(limit).unwrap_or(usize::MAX)),
+    };
+    let conn = match cli.retry_on_busy {
+        Some(attempts) => conn.with_busy_retry(connection::BusyRetry {
+            attempts,
+            patterns: if cli.busy_pattern.is_empty() {
+                connection::DEFAULT_BUSY_PATTERNS.map(String::from).to_vec()
+            } else {
+                cli.busy_pattern.clone()
+            },
+            delay: RETRY_DELAY,
+        }),
+        None => conn,
+    };
+    let mut conn = if cli.verbose {
+        conn.with_trace(Box::new(io::stderr()))
+    } else {
+        conn
+    };
+    if !cli.no_handshake {
+        handshake(&mut conn, &mut io::stderr())?;
+    }
+    Ok(conn)
+}
+
+/// Exchange protocol versions, warning on `warn` if the daemon speaks a
+/// different major version. Daemons without `hello` are taken on trust.
+fn handshake(conn: &mut Connection, warn: &mut impl Write) -> Result<(), Error> {
+    let Some(daemon) = conn.hello()? else {
+        return Ok(());
+    };
+    let major = |version: &str| version.split('.').next().unwrap_or_default().to_string();
+    if major(&daemon) != major(connection::PROTOCOL_VERSION) {
+        // Best-effort, like the handshake itself
+        let _ = writeln!(
+            warn,
+            "warning: daemon speaks protocol {} but this client speaks {}; requests may fail",
+            daemon,
+            connection::PROTOCOL_VERSION
+        );
+    }
+    Ok(())
+}
+
+/// Methods that change data. Under `--dry-run` these are shown instead of sent.
+const MUTATING_METHODS: [&str; 17] = [
+    "put",
+    "put_many",
+    "transaction",
+    "cas_put",
+    "append",
+    "incr",
+    "expire",
+    "delete",
+    "delete_match",
+    "create_table",
+    "alter_table",
+    "drop_table",
+    "create_index",
+    "drop_index",
+    "truncate",
+    "acl_grant",
+    "acl_revoke",
+];
+
+fn call(cli: &Cli, method: &str, params: Vec<Value>) -> Result<Option<Value>, Error> {
+    if cli.dry_run && MUTATING_METHODS.contains(&method) {
+        return Ok(Some(dry_run_request(method, params)));
+    }
+    connect(cli)?.call(method, params)
+}
+
+/// What `--dry-run` prints in place of sending a request.
+fn dry_run_request(method: &str, params: Vec<Value>) -> Value {
+    Value::Map(vec![
+        (Value::String("dry_run".into()), Value::Boolean(true)),
+        (Value::String("method".into()), Value::String(method.into())),
+        (Value::String("params".into()), Value::Array(params)),
+    ])
+}
+
+fn write_json_line(
+    out: &mut impl Write,
+    value: &Value,
+    color: bool,
+    end: &str,
+) -> Result<(), Error> {
+    write!(
+        out,
+        "{}{}",
+        render::json(&msgpack_to_json(value), false, color),
+        end
+    )
+    .and_then(|_| out.flush())
+    .map_err(|e| Error::Output(format!("write error: {}", e)))
+}
+
+/// Write each item followed by a NUL, leaving the last one unterminated
+/// under `--no-newline`. Strings are written as-is rather than quoted, so
+/// keys can go straight to `xargs -0`; other values as compact JSON.
+fn write_null_delimited(
+    out: &mut impl Write,
+    items: &[Value],
+    unterminated: bool,
+) -> Result<(), Error> {
+    let last = items.len().saturating_sub(1);
+    items
+        .iter()
+        .enumerate()
+        .try_for_each(|(i, item)| {
+            let text = match item.as_str() {
+                Some(s) => s.to_string(),
+                None => render::json(&msgpack_to_json(item), false, false),
+            };
+            let end = if unterminated && i == last { "" } else { "\0" };
+            write!(out, "{}{}", text, end)
+        })
+        .and_then(|_| out.flush())
+        .map_err(|e| Error::Output(format!("write error: {}", e)))
+}
+
+/// Keep only `fields`, in the given order, of an object or of each object
+/// in an array. Fields a record doesn't have are omitted.
+fn project(value: Value, fields: &[String]) -> Value {
+    match value {
+        Value::Map(entries) => Value::Map(
+            fields
+                .iter()
+                .filter_map(|field| {
+                    entries
+                        .iter()
+                        .find(|(k, _)| k.as_str() == Some(field.as_str()))
+                        .cloned()
+                })
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(
+            items
+                .into_iter()
+                .map(|item| project(item, fields))
                 .collect(),
         ),
+        other => other,
     }
 }
 
-fn msgpack_to_json(value: &Value) -> serde_json::Value {
-    match value {
-        Value::Nil => serde_json::Value::Null,
-        Value::Boolean(b) => serde_json::Value::Bool(*b),
-        Value::Integer(i) => {
-            if let Some(n) = i.as_i64() {
-                serde_json::Value::Number(n.into())
-            } else if let Some(n) = i.as_u64() {
-                serde_json::Value::Number(n.into())
-            } else {
-                serde_json::Value::Null
+impl ExtractArgs {
+    /// The value at the `--extract` pointer in `record`, named `which` in
+    /// errors; None if it doesn't resolve and `--extract-optional` allows
+    /// that. Without `--extract` the record comes back whole.
+    fn one(&self, record: Value, which: &str) -> Result<Option<Value>, Error> {
+        let Some(pointer) = &self.extract else {
+            return Ok(Some(record));
+        };
+        match msgpack_to_json(&record).pointer(pointer) {
+            Some(value) => Ok(Some(json_to_msgpack(value))),
+            None if self.extract_optional => Ok(None),
+            None => Err(Error::Input(format!(
+                "--extract '{}' does not resolve in {} (pass --extract-optional to allow this)",
+                pointer, which
+            ))),
+        }
+    }
+}
+
+/// Check that `--extract` is an RFC 6901 JSON pointer: empty for the whole
+/// record, or `/`-separated reference tokens with `~0` for `~` and `~1`
+/// for `/`.
+fn parse_pointer(text: &str) -> Result<String, String> {
+    if !text.is_empty() && !text.starts_with('/') {
+        return Err(format!(
+            "'{}' is not a JSON pointer; it must start with '/' (e.g. /address/city)",
+            text
+        ));
+    }
+    let mut rest = text;
+    while let Some(at) = rest.find('~') {
+        if !matches!(rest.as_bytes().get(at + 1), Some(b'0' | b'1')) {
+            return Err(format!(
+                "'{}' is not a JSON pointer: '~' must be followed by 0 or 1",
+                text
+            ));
+        }
+        rest = &rest[at + 2..];
+    }
+    Ok(text.to_string())
+}
+
+impl SortArgs {
+    /// The field to sort on and whether to sort it descending. An `:asc` or
+    /// `:desc` suffix on `--sort-by` overrides `--reverse`.
+    fn order(&self) -> Option<(&str, bool)> {
+        let key = self.sort_by.as_deref()?;
+        Some(match key.rsplit_once(':') {
+            Some((field, "desc")) => (field, true),
+            Some((field, "asc")) => (field, false),
+            _ => (key, self.reverse),
+        })
+    }
+
+    /// Reject a sort field that `--fields` would project away.
+    fn check_fields(&self, fields: Option<&str>) -> Result<(), Error> {
+        match (self.order(), fields) {
+            (Some((key, _)), Some(fields)) if !fields.split(',').any(|f| f.trim() == key) => {
+                Err(Error::Input(format!(
+                    "--sort-by field '{}' must be included in --fields",
+                    key
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The `order_by`, `order`, and (when `truncate` is set) `limit`
+    /// conditions asking the daemon to sort, or None when there's nothing to
+    /// sort or `--client-sort` is set.
+    fn server_conditions(&self, truncate: bool) -> Option<Vec<(Value, Value)>> {
+        let (key, descending) = self.order().filter(|_| !self.client_sort)?;
+        let order = if descending { "desc" } else { "asc" };
+        let mut conditions = vec![
+            (Value::from("order_by"), Value::from(key)),
+            (Value::from("order"), Value::from(order)),
+        ];
+        if let Some(limit) = self.limit.filter(|_| truncate) {
+            conditions.push((Value::from("limit"), Value::from(limit)));
+        }
+        Some(conditions)
+    }
+
+    /// Stable-sort an array of records by the sort field, if one was given,
+    /// then keep the first `--limit` of them. Records the daemon already
+    /// sorted are left as they are; a daemon that ignored the request to sort
+    /// hands them back unsorted, and they're sorted here instead, unless
+    /// `--server-sort` demanded the daemon's order.
+    fn apply(&self, value: Value) -> Result<Value, Error> {
+        let Value::Array(mut records) = value else {
+            return Ok(value);
+        };
+        if let Some((key, descending)) = self.order() {
+            let order = |a: &Value, b: &Value| match (field(a, key), field(b, key)) {
+                (None, None) => Ordering::Equal,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(_), None) => Ordering::Less,
+                (Some(a), Some(b)) if descending => compare_values(b, a),
+                (Some(a), Some(b)) => compare_values(a, b),
+            };
+            if !records.is_sorted_by(|a, b| order(a, b).is_le()) {
+                if self.server_sort {
+                    return Err(Error::Daemon(format!(
+                        "the daemon didn't sort by '{}'; drop --server-sort",
+                        key
+                    )));
+                }
+                records.sort_by(order);
             }
         }
-        Value::F32(f) => serde_json::Value::Number(
-            serde_json::Number::from_f64(*f as f64).unwrap_or(serde_json::Number::from(0)),
+        if let Some(limit) = self.limit {
+            records.truncate(limit as usize);
+        }
+        Ok(Value::Array(records))
+    }
+}
+
+fn field<'a>(record: &'a Value, name: &str) -> Option<&'a Value> {
+    record
+        .as_map()?
+        .iter()
+        .find(|(k, _)| k.as_str() == Some(name))
+        .map(|(_, v)| v)
+}
+
+/// Order values numbers first (numerically), then strings (lexically), then
+/// booleans, then anything else.
+fn compare_values(a: &Value, b: &Value) -> Ordering {
+    fn rank(v: &Value) -> u8 {
+        match v {
+            Value::Integer(_) | Value::F32(_) | Value::F64(_) => 0,
+            Value::String(_) => 1,
+            Value::Boolean(_) => 2,
+            _ => 3,
+        }
+    }
+
+    match (a, b) {
+        (Value::Integer(x), Value::Integer(y)) => match (x.as_i64(), y.as_i64()) {
+            (Some(x), Some(y)) => x.cmp(&y),
+            _ => x.as_u64().cmp(&y.as_u64()),
+        },
+        (Value::String(x), Value::String(y)) => x.as_bytes().cmp(y.as_bytes()),
+        (Value::Boolean(x), Value::Boolean(y)) => x.cmp(y),
+        _ if rank(a) == 0 && rank(b) == 0 => {
+            let (x, y) = (a.as_f64().unwrap_or(0.0), b.as_f64().unwrap_or(0.0));
+            x.total_cmp(&y)
+        }
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+/// Object key marking a summary of binary data too long to show:
+/// `{"__bin__": "<4096 bytes, sha256=9f86d081884c>"}`.
+const BIN_TAG: &str = "__bin__";
+
+/// Replace binary values longer than `limit` bytes, however deeply nested,
+/// with their length and the start of their SHA-256 hash.
+fn summarize_binary(value: Value, limit: usize) -> Value {
+    match value {
+        Value::Binary(bytes) if bytes.len() > limit => {
+            let digest = Sha256::digest(&bytes);
+            let hash: String = digest[..6].iter().map(|b| format!("{:02x}", b)).collect();
+            let summary = format!("<{} bytes, sha256={}>", bytes.len(), hash);
+            Value::Map(vec![(Value::from(BIN_TAG), Value::from(summary))])
+        }
+        Value::Array(items) => Value::Array(
+            items
+                .into_iter()
+                .map(|item| summarize_binary(item, limit))
+                .collect(),
         ),
-        Value::F64(f) => serde_json::Value::Number(
-            serde_json::Number::from_f64(*f).unwrap_or(serde_json::Number::from(0)),
+        Value::Map(entries) => Value::Map(
+            entries
+                .into_iter()
+                .map(|(k, v)| (k, summarize_binary(v, limit)))
+                .collect(),
         ),
-        Value::String(s) => serde_json::Value::String(s.as_str().unwrap_or_default().to_string()),
-        Value::Binary(b) => serde_json::Value::String(String::from_utf8_lossy(b).to_string()),
-        Value::Array(arr) => serde_json::Value::Array(arr.iter().map(msgpack_to_json).collect()),
-        Value::Map(map) => {
-            let obj: serde_json::Map<String, serde_json::Value> = map
-                .iter()
-                .filter_map(|(k, v)| {
-                    let key = match k {
-                        Value::String(s) => s.as_str().map(|s| s.to_string()),
-                        _ => Some(format!("{}", k)),
-                    };
-                    key.map(|k| (k, msgpack_to_json(v)))
-                })
-                .collect();
-            serde_json::Value::Object(obj)
-        }
-        Value::Ext(_, _) => serde_json::Value::Null,
-    }
-}
-
-fn print_help() {
-    println!(
-        r#"cortex - Local storage daemon CLI
-
-USAGE:
-  cortex <command> [args] [--pretty]
-
-COMMANDS:
-  ping                          Health check
-  status                        Daemon status
-  tables                        List your tables
-
-  create-table NAME ATTRS       Create table (ATTRS: comma-separated, first is key)
-  drop-table NAME               Drop a table
-  get TABLE KEY                 Get record by key
-  put TABLE JSON                Insert/update record
-  delete TABLE KEY              Delete record
-  query TABLE PATTERN           Query by pattern (JSON)
-  all TABLE                     List all records
-  keys TABLE                    List all keys in a table
-
-  acl grant IDENTITY TABLE PERMS    Grant permissions
-  acl revoke IDENTITY TABLE PERMS   Revoke permissions
-  acl list                          List ACLs for your tables
-
-OPTIONS:
-  --pretty                      Pretty-print JSON output
-  --socket PATH                 Socket path (default: /run/cortex/cortex.sock)
-  --version                     Show version
-  --help                        Show this help
-
-EXAMPLES:
-  cortex create-table users id,name,email
-  cortex put users '{{"id":"u1","name":"alice","email":"a@b.com"}}'
-  cortex get users u1
-  cortex query users '{{"name":"alice"}}'
-  cortex acl grant 'uid:1001' users read
-  cortex acl grant '*' users read"#
-    );
+        value => value,
+    }
 }
 
-fn print_topic_help(topic: Option<&str>) {
-    match topic {
-        None | Some("") => print_help(),
-        Some("ping") => println!(
-            r#"cortex ping - Health check
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{acl, batch, queries, records, server, tables, transfer, watch};
+    use std::os::unix::net::UnixListener;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::thread::{self, JoinHandle};
 
-USAGE:
-  cortex ping
+    static SOCKET_SEQ: AtomicUsize = AtomicUsize::new(0);
 
-DESCRIPTION:
-  Tests connectivity to the Cortex daemon. Returns "pong" if the daemon
-  is running and responsive.
+    fn temp_socket_path() -> String {
+        let seq = SOCKET_SEQ.fetch_add(1, Ordering::SeqCst);
+        std::env::temp_dir()
+            .join(format!(
+                "cortex-cli-test-{}-{}.sock",
+                std::process::id(),
+                seq
+            ))
+            .to_string_lossy()
+            .into_owned()
+    }
 
-EXAMPLES:
-  cortex ping
-  # Output: "pong""#
-        ),
-        Some("status") => println!(
-            r#"cortex status - Daemon status
+    /// Serve a single connection, answering one request per reply in order.
+    /// Returns the socket path and a handle yielding the decoded requests.
+    fn mock_server(replies: Vec<Result<Value, &'static str>>) -> (String, JoinHandle<Vec<Value>>) {
+        mock_server_then(replies, vec![])
+    }
 
-USAGE:
-  cortex status [--pretty]
+    /// Like `mock_server`, but writes `trailing` messages after the last
+    /// reply and then closes the connection.
+    fn mock_server_then(
+        replies: Vec<Result<Value, &'static str>>,
+        trailing: Vec<Value>,
+    ) -> (String, JoinHandle<Vec<Value>>) {
+        let path = temp_socket_path();
+        let listener = UnixListener::bind(&path).unwrap();
+        let cleanup = path.clone();
 
-DESCRIPTION:
-  Returns detailed status information about the Cortex daemon including
-  version, uptime, and Mnesia database state.
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut requests = Vec::new();
+            for reply in replies {
+                let request = rmpv::decode::read_value(&mut stream).unwrap();
+                let msgid = request[1].clone();
+                let (error, result) = match reply {
+                    Ok(v) => (Value::Nil, v),
+                    Err(e) => (Value::String(e.into()), Value::Nil),
+                };
+                let response = Value::Array(vec![Value::Integer(1.into()), msgid, error, result]);
+                rmpv::encode::write_value(&mut stream, &response).unwrap();
+                requests.push(request);
+            }
+            for message in trailing {
+                rmpv::encode::write_value(&mut stream, &message).unwrap();
+            }
+            std::fs::remove_file(cleanup).ok();
+            requests
+        });
 
-OPTIONS:
-  --pretty    Pretty-print the JSON output
+        (path, handle)
+    }
 
-EXAMPLES:
-  cortex status
-  cortex status --pretty"#
-        ),
-        Some("tables") => println!(
-            r#"cortex tables - List your tables
+    /// Like `mock_server`, but answers each request on a connection of its
+    /// own, for commands that connect once per request.
+    fn mock_server_per_request(
+        replies: Vec<Result<Value, &'static str>>,
+    ) -> (String, JoinHandle<Vec<Value>>) {
+        let path = temp_socket_path();
+        let listener = UnixListener::bind(&path).unwrap();
+        let cleanup = path.clone();
 
-USAGE:
-  cortex tables [--pretty]
+        let handle = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for reply in replies {
+                let (mut stream, _) = listener.accept().unwrap();
+                let request = rmpv::decode::read_value(&mut stream).unwrap();
+                let (error, result) = match reply {
+                    Ok(v) => (Value::Nil, v),
+                    Err(e) => (Value::String(e.into()), Value::Nil),
+                };
+                let response =
+                    Value::Array(vec![Value::from(1), request[1].clone(), error, result]);
+                rmpv::encode::write_value(&mut stream, &response).unwrap();
+                requests.push(request);
+            }
+            std::fs::remove_file(cleanup).ok();
+            requests
+        });
 
-DESCRIPTION:
-  Lists all tables owned by the current user (based on UID). Tables are
-  automatically namespaced by your UID internally.
+        (path, handle)
+    }
 
-EXAMPLES:
-  cortex tables
-  cortex tables --pretty"#
-        ),
-        Some("create-table") => println!(
-            r#"cortex create-table - Create a new table
+    /// Parse a command line, skipping the handshake so mock servers only
+    /// see the requests a command makes.
+    fn parse(args: &[&str]) -> Cli {
+        Cli::try_parse_from(
+            ["cortex", "--no-handshake"]
+                .into_iter()
+                .chain(args.iter().copied()),
+        )
+        .unwrap()
+    }
 
-USAGE:
-  cortex create-table NAME ATTRS
+    fn params(request: &Value) -> &[Value] {
+        request[3].as_array().unwrap()
+    }
 
-ARGUMENTS:
-  NAME    Table name (will be namespaced to your UID automatically)
-  ATTRS   Comma-separated attribute names; first attribute is the primary key
+    #[test]
+    fn flags_override_config_file() {
+        let mut cli = parse(&["--socket", "/tmp/flag.sock", "--timeout", "5s", "ping"]);
+        cli.apply_config(Config {
+            socket: Some("/tmp/file.sock".to_string()),
+            timeout: Some("30s".to_string()),
+            ..Config::default()
+        });
 
-DESCRIPTION:
-  Creates a new Mnesia table owned by you. The first attribute becomes
-  the primary key for get/delete operations.
+        assert_eq!(cli.socket.as_deref(), Some("/tmp/flag.sock"));
+        assert_eq!(cli.timeout.as_deref(), Some("5s"));
+    }
 
-EXAMPLES:
-  cortex create-table users id,name,email
-  cortex create-table sessions session_id,user_id,expires"#
-        ),
-        Some("drop-table") => println!(
-            r#"cortex drop-table - Drop a table
+    #[test]
+    fn config_file_overrides_defaults() {
+        let mut cli = parse(&["ping"]);
+        cli.apply_config(
+            Config::parse(
+                "socket = \"/tmp/file.sock\"\noutput = \"json\"\npretty = true\nretry = 2",
+            )
+            .unwrap(),
+        );
 
-USAGE:
-  cortex drop-table NAME
+        assert_eq!(cli.socket.as_deref(), Some("/tmp/file.sock"));
+        assert!(cli.output == Some(OutputFormat::Json));
+        assert!(cli.pretty);
+        assert_eq!(cli.retry, Some(2));
+        assert_eq!(cli.timeout, None);
+    }
 
-DESCRIPTION:
-  Permanently deletes a table and all its data.
-  WARNING: This operation cannot be undone.
+    #[test]
+    fn output_environment_variable_sets_the_default_format() {
+        let env = |name: &str| (name == "CORTEX_OUTPUT").then(|| "ndjson".to_string());
+        let keys = Value::Array(vec![Value::from("a"), Value::from("b")]);
+        let render = |args: &[&str]| {
+            let mut cli = parse(args);
+            let mut config = Config::parse("output = \"json\"").unwrap();
+            config.apply_env(env).unwrap();
+            cli.apply_config(config);
+            let mut out = Vec::new();
+            finish(
+                &cli,
+                Ok(Some(keys.clone())),
+                false,
+                &mut out,
+                &mut Vec::new(),
+            );
+            String::from_utf8(out).unwrap()
+        };
 
-EXAMPLES:
-  cortex drop-table old_sessions"#
-        ),
-        Some("get") => println!(
-            r#"cortex get - Get a record by key
+        assert_eq!(render(&["keys", "users"]), "\"a\"\n\"b\"\n");
+        assert_eq!(
+            render(&["--output", "json", "keys", "users"]),
+            "[\"a\",\"b\"]\n"
+        );
+    }
 
-USAGE:
-  cortex get TABLE KEY [--pretty]
+    fn age_schema() -> PathBuf {
+        let path = PathBuf::from(temp_socket_path() + ".schema.json");
+        let schema = r#"{"type": "object", "properties": {"age": {"type": "integer"}}}"#;
+        std::fs::write(&path, schema).unwrap();
+        path
+    }
 
-DESCRIPTION:
-  Retrieves a single record by its primary key.
+    #[test]
+    fn put_schema_refuses_records_that_do_not_match() {
+        let schema = age_schema();
+        let cli = parse(&[
+            "--socket",
+            "/nonexistent/cortex.sock",
+            "put",
+            "users",
+            r#"{"id": "u1", "age": "old"}"#,
+            "--schema",
+            schema.to_str().unwrap(),
+        ]);
 
-EXAMPLES:
-  cortex get users u1
-  cortex get config database_url --pretty"#
-        ),
-        Some("put") => println!(
-            r#"cortex put - Insert or update a record
+        let err = run(&cli).unwrap_err();
+        assert_eq!(err.code(), "input");
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "record does not match {}:\n  /age: \"old\" is not of type \"integer\"",
+                schema.display()
+            )
+        );
 
-USAGE:
-  cortex put TABLE JSON
+        // Among several records, the one that failed is named
+        let records = [
+            serde_json::json!({"age": 3}),
+            serde_json::json!({"age": 2.5}),
+        ];
+        let err = records::check_schema(&schema, &records).unwrap_err();
+        assert!(err.to_string().starts_with("record 2 does not match"));
+        std::fs::remove_file(schema).unwrap();
+    }
 
-DESCRIPTION:
-  Inserts a new record or updates an existing one. The JSON must contain
-  the primary key field defined when the table was created.
+    #[test]
+    fn validate_counts_passing_and_failing_records() {
+        let schema = age_schema();
+        let records = PathBuf::from(temp_socket_path() + ".ndjson");
+        std::fs::write(&records, "{\"age\": 3}\n{\"age\": 2.5}\n{}\n").unwrap();
+        let args = ["validate", "users", "--schema", schema.to_str().unwrap()];
+        let args = [&args[..], &["--file", records.to_str().unwrap()]].concat();
 
-EXAMPLES:
-  cortex put users '{{"id":"u1","name":"alice","email":"a@b.com"}}'
-  cortex put config '{{"key":"theme","value":"dark"}}'"#
-        ),
-        Some("delete") => println!(
-            r#"cortex delete - Delete a record
+        let report = records::validation_report(
+            "users",
+            &schema::Schema::load(&schema).unwrap(),
+            &commands::parse_records(&std::fs::read_to_string(&records).unwrap()).unwrap(),
+        );
+        assert_eq!(
+            report,
+            serde_json::json!({
+                "table": "users",
+                "passed": 2,
+                "failed": 1,
+                "failures": [{"record": 2, "errors": ["/age: 2.5 is not of type \"integer\""]}],
+            })
+        );
+        let err = run(&parse(&args)).unwrap_err();
+        assert_eq!(err.code(), "conflict");
+        assert_eq!(err.to_string(), "1 of 3 records do not match the schema");
 
-USAGE:
-  cortex delete TABLE KEY
+        std::fs::remove_file(schema).unwrap();
+        std::fs::remove_file(records).unwrap();
+    }
 
-DESCRIPTION:
-  Permanently deletes a single record by its primary key.
+    #[test]
+    fn put_writes_every_record_of_a_stream() {
+        let records = PathBuf::from(temp_socket_path() + ".jsonl");
+        std::fs::write(&records, "{\"id\": \"u1\"}\n{\"id\": \"u2\"}\n").unwrap();
+        let path = records.to_str().unwrap();
+        let outcomes = Value::Array(vec![Value::from("ok"), Value::from("ok")]);
+        let (socket, server) = mock_server(vec![Ok(outcomes)]);
 
-EXAMPLES:
-  cortex delete users u1
-  cortex delete sessions expired_session_123"#
-        ),
-        Some("query") => println!(
-            r#"cortex query - Query records by pattern
+        let report = run(&parse(&[
+            "--socket", &socket, "put", "users", "--file", path,
+        ]))
+        .unwrap()
+        .unwrap();
+        assert_eq!(report["written"].as_u64(), Some(2));
+        assert_eq!(methods(&server.join().unwrap()), ["put_many"]);
 
-USAGE:
-  cortex query TABLE PATTERN [--pretty]
+        let cli = parse(&["put", "users", "--file", path, "--ttl", "1h"]);
+        let err = run(&cli).unwrap_err();
+        assert_eq!(err.code(), "input");
+        assert_eq!(
+            err.to_string(),
+            "--if-absent, --if-match, and --ttl take one record, not several"
+        );
 
-DESCRIPTION:
-  Finds all records matching the given pattern. The pattern is a JSON
-  object where each field must match exactly.
+        std::fs::write(&records, "{\"id\": \"u1\"}").unwrap();
+        let (socket, server) = mock_server(vec![Ok(Value::from("ok"))]);
+        let cli = parse(&["--socket", &socket, "put", "users", "--file", path]);
+        assert_eq!(run(&cli).unwrap(), Some(Value::from("ok")));
+        assert_eq!(methods(&server.join().unwrap()), ["put"]);
+        std::fs::remove_file(records).unwrap();
+    }
 
-EXAMPLES:
-  cortex query users '{{"name":"alice"}}' --pretty
-  cortex query sessions '{{"user_id":"u1"}}'"#
-        ),
-        Some("all") => println!(
-            r#"cortex all - List all records in a table
+    #[test]
+    fn put_takes_a_dash_for_stdin() {
+        let cli = parse(&["put", "users", "-"]);
+        let Some(Commands::Put { json, file, .. }) = cli.command else {
+            panic!("expected put");
+        };
+        assert_eq!((json.as_deref(), file), (Some("-"), None));
+    }
 
-USAGE:
-  cortex all TABLE [--pretty]
+    #[test]
+    fn txn_sends_ops_in_one_transaction() {
+        let ops = PathBuf::from(temp_socket_path() + ".json");
+        std::fs::write(
+            &ops,
+            r#"[{"op": "put", "table": "orders", "record": {"id": "o1"}},
+                {"op": "delete", "table": "carts", "key": "c1"}]"#,
+        )
+        .unwrap();
+        let path = ops.to_str().unwrap();
+        let (socket, server) = mock_server(vec![Ok(Value::from(2))]);
 
-DESCRIPTION:
-  Returns all records in a table as a JSON array.
+        let cli = parse(&["--socket", &socket, "txn", "--file", path]);
+        assert_eq!(run(&cli).unwrap(), Some(Value::from(2)));
+        let requests = server.join().unwrap();
+        assert_eq!(methods(&requests), ["transaction"]);
+        let sent = &params(&requests[0])[0];
+        assert_eq!(sent[0]["table"].as_str(), Some("orders"));
+        assert_eq!(sent[1]["op"].as_str(), Some("delete"));
 
-EXAMPLES:
-  cortex all users --pretty
-  cortex all config"#
-        ),
-        Some("keys") => println!(
-            r#"cortex keys - List all keys in a table
+        std::fs::write(
+            &ops,
+            r#"[{"op": "put", "table": "orders", "record": {}}, {}]"#,
+        )
+        .unwrap();
+        let err = run(&parse(&["txn", "--file", path])).unwrap_err();
+        assert_eq!(err.code(), "input");
+        assert_eq!(err.to_string(), "op is missing \"table\": {} (op 2)");
+        std::fs::remove_file(ops).unwrap();
+    }
 
-USAGE:
-  cortex keys TABLE [--pretty]
+    #[test]
+    fn cursor_fetches_one_page_and_passes_on_the_next_cursor() {
+        let page = |name: &str, items: Vec<Value>, next: Value| {
+            Value::Map(vec![
+                (Value::from(name), Value::Array(items)),
+                (Value::from("cursor"), next),
+            ])
+        };
+        let run_page = |args: &[&str], reply: Value| {
+            let (socket, server) = mock_server(vec![Ok(reply)]);
+            let result = run(&parse(&[&["--socket", &socket][..], args].concat()));
+            let requests = server.join().unwrap();
+            (result.unwrap().unwrap(), params(&requests[0]).to_vec())
+        };
+        let paging = |cursor: Value, limit: u32| {
+            vec![
+                (Value::from("cursor"), cursor),
+                (Value::from("limit"), Value::from(limit)),
+            ]
+        };
 
-DESCRIPTION:
-  Returns all primary keys in a table as a JSON array. Useful for
-  debugging or iterating over records without fetching full data.
+        let first = page(
+            "records",
+            vec![record(&[("id", "u1"), ("name", "ann")])],
+            Value::from("u1"),
+        );
+        let (result, sent) = run_page(
+            &[
+                "all", "users", "--limit", "1", "--cursor", "--fields", "name",
+            ],
+            first,
+        );
+        assert_eq!(
+            result,
+            page(
+                "records",
+                vec![record(&[("name", "ann")])],
+                Value::from("u1")
+            )
+        );
+        assert_eq!(sent[1], Value::Map(paging(Value::Nil, 1)));
 
-EXAMPLES:
-  cortex keys users
-  cortex keys sessions --pretty"#
-        ),
-        Some("acl") => println!(
-            r#"cortex acl - Access control commands
-
-USAGE:
-  cortex acl <subcommand> [args]
-
-SUBCOMMANDS:
-  grant IDENTITY TABLE PERMS    Grant permissions
-  revoke IDENTITY TABLE PERMS   Revoke permissions
-  list                          List ACLs for your tables
-
-IDENTITIES:
-  uid:1001    Specific user by UID
-  *           World (any authenticated user)
-
-PERMISSIONS:
-  read        Can get, query, all
-  write       Can put, delete
-  admin       Can grant/revoke ACLs, drop table
-
-EXAMPLES:
-  cortex acl grant 'uid:1001' users read
-  cortex acl grant '*' public_data read
-  cortex acl revoke 'uid:1001' users write
-  cortex acl list --pretty"#
-        ),
-        Some("patterns") => println!(
-            r#"Cortex Usage Patterns
+        let last = page("records", vec![], Value::Nil);
+        let (_, sent) = run_page(
+            &[
+                "query",
+                "users",
+                r#"{"role": "admin"}"#,
+                "--limit",
+                "2",
+                "--cursor",
+                "u1",
+            ],
+            last,
+        );
+        assert_eq!(sent[1], record(&[("role", "admin")]));
+        assert_eq!(sent[2], Value::Map(paging(Value::from("u1"), 2)));
 
-Cortex is a generic storage layer - it has no opinions about how you
-structure your data. Here are common patterns that work well:
+        let keys = page("keys", vec![Value::from("s:1")], Value::Nil);
+        let (_, sent) = run_page(
+            &[
+                "keys", "cache", "--prefix", "s:", "--limit", "5", "--cursor",
+            ],
+            keys,
+        );
+        let mut options = paging(Value::Nil, 5);
+        options.push((Value::from("prefix"), Value::from("s:")));
+        assert_eq!(sent[1], Value::Map(options));
 
-AVAILABLE PATTERNS:
-  cortex help memories       Public/private agent memories
-  cortex help statemachine   Workflow state machines
-  cortex help identities     Agent identity via Unix users
+        let nested = r#"{"address": {"city": "NYC"}}"#;
+        let cli = parse(&["query", "users", nested, "--limit", "2", "--cursor"]);
+        assert_eq!(run(&cli).unwrap_err().code(), "input");
+        assert!(Cli::try_parse_from(["cortex", "all", "users", "--cursor"]).is_err());
+    }
 
-Run 'cortex help <pattern>' for detailed documentation."#
-        ),
-        Some("memories") => println!(
-            r#"Pattern: Public/Private Agent Memories
+    #[test]
+    fn create_index_explains_daemon_refusals() {
+        let create = |reason: &'static str| {
+            let (socket, server) = mock_server(vec![Err(reason)]);
+            let cli = parse(&["--socket", &socket, "create-index", "users", "email"]);
+            let err = run(&cli).unwrap_err();
+            let requests = server.join().unwrap();
+            assert_eq!(methods(&requests), ["create_index"]);
+            assert_eq!(params(&requests[0])[1], Value::from("email"));
+            err
+        };
 
-OVERVIEW:
-  AI agents often need both private working memory and shared knowledge.
-  Use separate tables with different ACLs to implement this pattern.
+        let err = create("no_attribute");
+        assert_eq!(err.code(), "input");
+        assert_eq!(
+            err.to_string(),
+            "table 'users' has no attribute 'email' (add it with migrate add-attribute)"
+        );
+        assert_eq!(create("index_exists").code(), "conflict");
+        assert_eq!(create("access_denied").to_string(), "access_denied");
+    }
 
-SETUP:
-  # Create private memory (only you can access)
-  cortex create-table private_memories id,content,timestamp,tags
+    #[test]
+    fn describe_marks_indexed_attributes() {
+        let schema = serde_json::json!({
+            "table": "users",
+            "key": "id",
+            "attributes": ["id", "name", "email"],
+            "indexes": ["email"],
+        });
+        assert_eq!(
+            tables::render_describe(&schema),
+            "table: users\nkey:   id\nattributes:\n  id (primary key)\n  name\n  email (indexed)\n"
+        );
+    }
 
-  # Create public memory (world-readable)
-  cortex create-table public_memories id,content,timestamp,tags
-  cortex acl grant '*' public_memories read
+    #[test]
+    fn range_sends_only_the_bounds_given() {
+        let run_range = |args: &[&str]| {
+            let (socket, server) = mock_server(vec![Ok(Value::Array(vec![]))]);
+            let args = [&["--socket", &socket, "range", "events"][..], args].concat();
+            assert_eq!(run(&parse(&args)).unwrap(), Some(Value::Array(vec![])));
+            let requests = server.join().unwrap();
+            assert_eq!(methods(&requests), ["range"]);
+            params(&requests[0])[1].clone()
+        };
 
-MULTI-AGENT SETUP:
-  Each agent runs as a separate Unix user with its own UID:
+        assert_eq!(
+            run_range(&["--from", "2024-03", "--to", "2024-04", "--limit", "5"]),
+            Value::Map(vec![
+                (Value::from("from"), Value::from("2024-03")),
+                (Value::from("to"), Value::from("2024-04")),
+                (Value::from("limit"), Value::from(5)),
+            ])
+        );
+        assert_eq!(
+            run_range(&["--to", "m"]),
+            Value::Map(vec![(Value::from("to"), Value::from("m"))])
+        );
+        assert!(Cli::try_parse_from(["cortex", "range", "t", "--limit", "0"]).is_err());
+    }
 
-  sudo useradd -r -s /usr/sbin/nologin agent-researcher
-  sudo useradd -r -s /usr/sbin/nologin agent-coder
+    #[test]
+    fn put_many_reports_records_the_daemon_rejects() {
+        let records = [
+            serde_json::json!({"id": "u1"}),
+            serde_json::json!({"name": "x"}),
+            serde_json::json!({"id": "u3"}),
+        ];
+        let outcomes = Value::Array(vec![
+            Value::from("ok"),
+            Value::Map(vec![(Value::from("error"), Value::from("missing_key"))]),
+            Value::from("ok"),
+        ]);
+        let (socket, server) = mock_server(vec![Ok(outcomes)]);
 
-  Each agent's tables are isolated. They can only read each other's
-  public_memories tables (if world-readable ACL is set)."#
-        ),
-        Some("statemachine") => println!(
-            r#"Pattern: Workflow State Machines
+        let cli = parse(&["--socket", &socket, "put-many", "users", "-"]);
+        let mut out = Vec::new();
+        let err = records::write_records(&cli, "users", &records, false, &mut out).unwrap_err();
+        assert_eq!(err.code(), "daemon");
+        assert_eq!(err.to_string(), "1 of 3 records were not written");
 
-OVERVIEW:
-  Track multi-step workflows with explicit states and transitions.
+        // The report still goes out, to show which record failed
+        let report: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(report["table"], "users");
+        assert_eq!(report["written"], 2);
+        assert_eq!(report["failed"], 1);
+        assert_eq!(report["failures"][0]["record"], 2);
+        assert_eq!(report["failures"][0]["code"], "daemon");
 
-SETUP:
-  cortex create-table sm_definitions id,name,states,transitions
-  cortex create-table sm_instances id,definition,state,data,created,updated
+        let requests = server.join().unwrap();
+        assert_eq!(methods(&requests), ["put_many"]);
+        assert_eq!(params(&requests[0])[1].as_array().map(Vec::len), Some(3));
+    }
 
-DEFINE A WORKFLOW:
-  cortex put sm_definitions '{{
-    "id": "task-workflow",
-    "name": "Task Workflow",
-    "states": ["todo", "in_progress", "review", "done"]
-  }}'
+    #[test]
+    fn put_many_batches_by_size_and_falls_back_to_puts() {
+        let records = PathBuf::from(temp_socket_path() + ".jsonl");
+        let lines: Vec<String> = (0..3)
+            .map(|i| serde_json::json!({"id": i, "bio": "x".repeat(40)}).to_string())
+            .collect();
+        std::fs::write(&records, lines.join("\n")).unwrap();
+        let path = records.to_str().unwrap();
 
-QUERY BY STATE:
-  cortex query sm_instances '{{"state":"review"}}' --pretty"#
-        ),
-        Some("identities") => println!(
-            r#"Pattern: Agent Identities
-
-OVERVIEW:
-  Cortex identifies users by their Unix UID, extracted from the socket
-  connection via SO_PEERCRED. This provides kernel-enforced identity.
-
-HOW IT WORKS:
-  1. Client connects to Unix socket
-  2. Cortex extracts UID via getpeereid/SO_PEERCRED (kernel-enforced)
-  3. All operations are scoped to that UID
-  4. Tables are namespaced: "users" becomes "1000:users" internally
-
-CREATING AGENT USERS:
-  sudo useradd -r -s /usr/sbin/nologin agent-coder
-  sudo -u agent-coder cortex put memories '{{...}}'
-
-FINDING YOUR UID:
-  id -u                    # Your current UID
-  id -u agent-coder        # Another user's UID"#
-        ),
-        Some(other) => {
-            eprintln!("Unknown help topic: {}", other);
-            eprintln!();
-            eprintln!("Available commands:");
-            eprintln!("  ping, status, tables, create-table, drop-table,");
-            eprintln!("  get, put, delete, query, all, keys, acl");
-            eprintln!();
-            eprintln!("Available patterns:");
-            eprintln!("  patterns, memories, statemachine, identities");
-        }
+        // 51 bytes a record, so two fit under the limit and the third goes alone
+        let cli = parse(&[
+            "--max-value-size",
+            "110",
+            "--dry-run",
+            "put-many",
+            "t",
+            path,
+        ]);
+        let requests = run(&cli).unwrap().unwrap();
+        let sizes: Vec<usize> = requests
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["params"][1].as_array().unwrap().len())
+            .collect();
+        assert_eq!(sizes, [2, 1]);
+
+        let (socket, server) = mock_server(vec![
+            Err("unknown method: put_many"),
+            Ok(Value::from("ok")),
+            Ok(Value::from("ok")),
+            Err("access_denied"),
+        ]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "--max-value-size",
+            "110",
+            "put-many",
+            "t",
+            path,
+        ]);
+        let values: Vec<serde_json::Value> = lines
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let mut out = Vec::new();
+        let err = records::write_records(&cli, "t", &values, false, &mut out).unwrap_err();
+        assert_eq!(err.to_string(), "1 of 3 records were not written");
+        let report: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(report["written"], 2);
+        assert_eq!(report["failures"][0]["record"], 3);
+        assert_eq!(report["failures"][0]["error"], "access_denied");
+        assert_eq!(
+            methods(&server.join().unwrap()),
+            ["put_many", "put", "put", "put"]
+        );
+        std::fs::remove_file(records).unwrap();
+    }
+
+    #[test]
+    fn put_checks_the_encoded_record_size() {
+        // fixmap(2) + "id" + "u1" + "data" + str8 header = 14 bytes around the data
+        let put = |socket: &str, data_len: usize| {
+            let record = serde_json::json!({"id": "u1", "data": "x".repeat(data_len)});
+            run(&parse(&[
+                "--socket",
+                socket,
+                "--max-value-size",
+                "100",
+                "put",
+                "users",
+                &record.to_string(),
+            ]))
+        };
+
+        let (socket, server) = mock_server(vec![Ok(Value::from("ok"))]);
+        assert_eq!(put(&socket, 86).unwrap(), Some(Value::from("ok")));
+        assert_eq!(methods(&server.join().unwrap()), ["put"]);
+
+        let err = put("/nonexistent/cortex.sock", 87).unwrap_err();
+        assert_eq!(err.code(), "input");
+        assert_eq!(
+            err.to_string(),
+            "record is 101 bytes encoded, over the 100 byte limit (raise it with --max-value-size)"
+        );
+    }
+
+    #[test]
+    fn responses_over_the_size_limit_are_abandoned() {
+        let records = Value::Array(
+            (0..100)
+                .map(|i| record(&[("id", &format!("u{}", i)), ("bio", &"x".repeat(40))]))
+                .collect(),
+        );
+        let run_with = |limit: &str, args: &[&str], reply: &Value| {
+            let (socket, _server) = mock_server(vec![Ok(reply.clone())]);
+            let limit = format!("--max-response-size={}", limit);
+            let mut full = vec!["--socket", &socket, &limit];
+            full.extend(args);
+            run(&parse(&full))
+        };
+        let message = "response is over the 2000 byte limit \
+                       (raise it with --max-response-size, or 0 for none)";
+
+        let err = run_with("2000", &["all", "users"], &records).unwrap_err();
+        assert_eq!(err.exit_code(), error::EXIT_PROTOCOL);
+        assert_eq!(err.to_string(), message);
+
+        let big = record(&[("id", "u1"), ("bio", &"x".repeat(5000))]);
+        let err = run_with("2000", &["get", "users", "u1"], &big).unwrap_err();
+        assert_eq!(err.to_string(), message);
+
+        assert_eq!(
+            run_with("0", &["get", "users", "u1"], &big).unwrap(),
+            Some(big.clone())
+        );
+        assert_eq!(
+            run_with("0", &["all", "users"], &records).unwrap(),
+            Some(records.clone())
+        );
+    }
+
+    #[test]
+    fn retry_on_busy_resends_transient_errors() {
+        let (socket, server) = mock_server(vec![
+            Err("database busy"),
+            Err("database busy"),
+            Ok(Value::from("ok")),
+        ]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "--retry-on-busy",
+            "3",
+            "put",
+            "users",
+            r#"{"id": "u1"}"#,
+        ]);
+
+        assert_eq!(run(&cli).unwrap(), Some(Value::from("ok")));
+        assert_eq!(methods(&server.join().unwrap()), ["put", "put", "put"]);
+    }
+
+    #[test]
+    fn retry_on_busy_leaves_other_errors_alone() {
+        let (socket, server) = mock_server(vec![Err("access_denied")]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "--retry-on-busy",
+            "3",
+            "--busy-pattern",
+            "locked",
+            "get",
+            "users",
+            "u1",
+        ]);
+
+        assert_eq!(
+            run(&cli).unwrap_err(),
+            Error::Daemon("access_denied".to_string())
+        );
+        assert_eq!(server.join().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn retry_waits_for_late_daemon() {
+        let path = temp_socket_path();
+        let late = path.clone();
+        let server = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            let listener = UnixListener::bind(&late).unwrap();
+            let (mut stream, _) = listener.accept().unwrap();
+            let request = rmpv::decode::read_value(&mut stream).unwrap();
+            let response = Value::Array(vec![
+                Value::Integer(1.into()),
+                request[1].clone(),
+                Value::Nil,
+                Value::String("pong".into()),
+            ]);
+            rmpv::encode::write_value(&mut stream, &response).unwrap();
+            std::fs::remove_file(late).ok();
+        });
+
+        let cli = parse(&["--socket", &path, "--retry", "3", "ping"]);
+        assert_eq!(run(&cli).unwrap(), Some(Value::String("pong".into())));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn timeout_takes_bare_seconds() {
+        // Parsed before connecting, so a bad value fails as input
+        let missing = "/nonexistent/cortex.sock";
+        let err = run(&parse(&["--socket", missing, "--timeout", "10", "ping"])).unwrap_err();
+        assert_eq!(err.code(), "connection");
+        let err = run(&parse(&["--socket", missing, "--timeout", "10x", "ping"])).unwrap_err();
+        assert_eq!(err.code(), "input");
+    }
+
+    #[test]
+    fn timeout_reports_an_unresponsive_daemon() {
+        let path = temp_socket_path();
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = thread::spawn(move || {
+            // Accept and read, but never answer
+            let (mut stream, _) = listener.accept().unwrap();
+            rmpv::decode::read_value(&mut stream).unwrap();
+            thread::sleep(Duration::from_millis(1500));
+        });
+
+        let err = run(&parse(&["--socket", &path, "--timeout", "1s", "ping"])).unwrap_err();
+        assert_eq!(err.exit_code(), error::EXIT_TIMEOUT);
+        server.join().unwrap();
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn color_follows_terminal_and_no_color() {
+        let auto = parse(&["ping"]);
+        assert!(auto.use_color(true, false));
+        assert!(!auto.use_color(false, false));
+        assert!(!auto.use_color(true, true));
+
+        assert!(parse(&["--color", "always", "ping"]).use_color(false, true));
+        assert!(!parse(&["--color", "never", "ping"]).use_color(true, false));
+        assert!(!parse(&["--no-color", "ping"]).use_color(true, false));
+    }
+
+    #[test]
+    fn no_color_output_has_no_escape_codes() {
+        let cli = parse(&["--no-color", "--pretty", "ping"]);
+        let value = serde_json::json!({"id": "u1", "n": 1, "ok": true});
+        let out = render::json(&value, cli.pretty, cli.use_color(true, false));
+
+        assert!(!out.contains('\x1b'));
+        assert_eq!(out, serde_json::to_string_pretty(&value).unwrap());
+    }
+
+    #[test]
+    fn raw_sends_method_with_empty_params() {
+        let (socket, server) = mock_server(vec![Ok(Value::String("pong".into()))]);
+        let result = run(&parse(&["--socket", &socket, "raw", "ping", "[]"])).unwrap();
+
+        assert_eq!(result, Some(Value::String("pong".into())));
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0][2].as_str(), Some("ping"));
+        assert!(params(&requests[0]).is_empty());
+    }
+
+    #[test]
+    fn raw_passes_mixed_params_through() {
+        let (socket, server) = mock_server(vec![Ok(Value::Nil)]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "raw",
+            "frobnicate",
+            r#"["users", 42, 1.5, true, null, {"k": ["v"]}]"#,
+        ]);
+
+        run(&cli).unwrap();
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0][2].as_str(), Some("frobnicate"));
+        assert_eq!(
+            params(&requests[0]),
+            &[
+                Value::String("users".into()),
+                Value::from(42),
+                Value::F64(1.5),
+                Value::Boolean(true),
+                Value::Nil,
+                Value::Map(vec![(
+                    Value::String("k".into()),
+                    Value::Array(vec![Value::String("v".into())])
+                )]),
+            ]
+        );
+    }
+
+    #[test]
+    fn raw_params_file_is_read_and_sent() {
+        let path = PathBuf::from(temp_socket_path() + ".json");
+        std::fs::write(&path, r#"["users", {"id": "u1"}]"#).unwrap();
+        let (socket, server) = mock_server(vec![Ok(Value::from("ok"))]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "raw",
+            "put",
+            "--params-file",
+            path.to_str().unwrap(),
+        ]);
+
+        run(&cli).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        let requests = server.join().unwrap();
+        assert_eq!(
+            params(&requests[0]),
+            &[Value::from("users"), record(&[("id", "u1")])]
+        );
+    }
+
+    #[test]
+    fn put_file_sends_the_files_record() {
+        let path = PathBuf::from(temp_socket_path() + ".json");
+        std::fs::write(&path, r#"{"id": "u1", "name": "alice"}"#).unwrap();
+        let (socket, server) = mock_server(vec![Ok(Value::from("ok"))]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "put",
+            "users",
+            "--file",
+            path.to_str().unwrap(),
+        ]);
+
+        run(&cli).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        let requests = server.join().unwrap();
+        assert_eq!(
+            params(&requests[0]),
+            &[
+                Value::from("users"),
+                record(&[("id", "u1"), ("name", "alice")])
+            ]
+        );
+    }
+
+    #[test]
+    fn put_file_missing_or_invalid_is_an_input_error() {
+        let missing = parse(&["put", "users", "--file", "/nonexistent/record.json"]);
+        let err = run(&missing).unwrap_err();
+        assert_eq!(err.exit_code(), error::EXIT_INPUT);
+        assert!(err
+            .to_string()
+            .starts_with("cannot read /nonexistent/record.json:"));
+
+        let path = PathBuf::from(temp_socket_path() + ".json");
+        std::fs::write(&path, "{\"id\": ").unwrap();
+        let invalid = parse(&["query", "users", "--file", path.to_str().unwrap()]);
+        let err = run(&invalid).unwrap_err();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(err.exit_code(), error::EXIT_INPUT);
+        assert!(err
+            .to_string()
+            .starts_with(&format!("invalid JSON in {}:", path.display())));
+    }
+
+    #[test]
+    fn put_needs_exactly_one_of_json_and_file() {
+        let kind = |args: &[&str]| Cli::try_parse_from(args).err().map(|e| e.kind());
+        assert_eq!(
+            kind(&["cortex", "put", "users"]),
+            Some(clap::error::ErrorKind::MissingRequiredArgument)
+        );
+        assert_eq!(
+            kind(&["cortex", "put", "users", "{}", "--file", "r.json"]),
+            Some(clap::error::ErrorKind::ArgumentConflict)
+        );
+    }
+
+    const BATCH_SCRIPT: &str =
+        "get users u1\n# comment\n\nget users nope\nput users '{\"id\": \"u2\"}'\n";
+
+    fn run_test_batch(
+        replies: Vec<Result<Value, &'static str>>,
+        fail_fast: bool,
+    ) -> (Value, Vec<Value>) {
+        let (socket, server) = mock_server(replies);
+        let cli = parse(&["--socket", &socket, "batch", "-"]);
+        let shared = Rc::new(RefCell::new(Connection::new(&socket).unwrap()));
+        let results = batch::run_batch(&cli, &shared, BATCH_SCRIPT, fail_fast);
+        drop(shared);
+        (results, server.join().unwrap())
+    }
+
+    #[test]
+    fn batch_reports_each_result_in_order() {
+        let (results, requests) = run_test_batch(
+            vec![
+                Ok(record(&[("id", "u1")])),
+                Err("not_found"),
+                Ok(Value::from("ok")),
+            ],
+            false,
+        );
+
+        assert_eq!(methods(&requests), ["get", "get", "put"]);
+        assert_eq!(params(&requests[2])[1], record(&[("id", "u2")]));
+        assert_eq!(
+            msgpack_to_json(&results),
+            serde_json::json!([
+                {"ok": {"id": "u1"}},
+                {"error": "not_found", "code": "daemon"},
+                {"ok": "ok"},
+            ])
+        );
+    }
+
+    #[test]
+    fn batch_fail_fast_stops_at_the_first_error() {
+        let (results, requests) =
+            run_test_batch(vec![Ok(record(&[("id", "u1")])), Err("not_found")], true);
+
+        assert_eq!(methods(&requests), ["get", "get"]);
+        assert_eq!(
+            msgpack_to_json(&results),
+            serde_json::json!([
+                {"ok": {"id": "u1"}},
+                {"error": "not_found", "code": "daemon"},
+            ])
+        );
+    }
+
+    #[test]
+    fn batch_rejects_bad_and_streaming_lines() {
+        let (socket, server) = mock_server(vec![]);
+        let cli = parse(&["--socket", &socket, "batch", "-"]);
+        let shared = Rc::new(RefCell::new(Connection::new(&socket).unwrap()));
+        let script = "frobnicate\nwatch users\nget users 'u1\n";
+        let results = msgpack_to_json(&batch::run_batch(&cli, &shared, script, false));
+        drop(shared);
+        assert!(server.join().unwrap().is_empty());
+
+        assert_eq!(results[0]["code"], "input");
+        assert_eq!(results[1]["error"], "'watch users' can't run in a batch");
+        assert_eq!(results[2]["error"], "unterminated quote in 'get users 'u1'");
+    }
+
+    #[test]
+    fn split_command_line_handles_quotes_and_escapes() {
+        assert_eq!(
+            batch::split_command_line(r#"put users '{"id": "a b"}' --ttl 2h"#).unwrap(),
+            ["put", "users", r#"{"id": "a b"}"#, "--ttl", "2h"]
+        );
+        assert_eq!(
+            batch::split_command_line(r#"get "my \"key\"" a\ b x""y"#).unwrap(),
+            ["get", "my \"key\"", "a b", "xy"]
+        );
+        assert!(batch::split_command_line("   ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn raw_params_file_dash_reads_stdin() {
+        let mut stdin = io::Cursor::new(r#"["users", "u1"]"#);
+        let params = read_input_file(Path::new("-"), &mut stdin).unwrap();
+        assert_eq!(
+            batch::parse_raw_params(&params).unwrap(),
+            [Value::from("users"), Value::from("u1")]
+        );
+
+        let err = read_input_file(Path::new("/nonexistent/params.json"), &mut stdin);
+        assert_eq!(err.unwrap_err().code(), "input");
+    }
+
+    #[test]
+    fn raw_params_and_params_file_conflict() {
+        let err = Cli::try_parse_from(["cortex", "raw", "get", "[]", "--params-file", "p.json"])
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn raw_rejects_non_array_params() {
+        let err = batch::parse_raw_params(r#"{"table": "users"}"#).unwrap_err();
+        assert_eq!(err.to_string(), "params must be a JSON array");
+        assert!(batch::parse_raw_params("[1,").is_err());
+    }
+
+    #[test]
+    fn quiet_get_prints_nothing_and_succeeds() {
+        let (socket, server) = mock_server(vec![Ok(record(&[("id", "u1")]))]);
+        let cli = parse(&["--socket", &socket, "--quiet", "get", "users", "u1"]);
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+
+        let status = finish(&cli, run(&cli), false, &mut out, &mut err);
+        server.join().unwrap();
+
+        assert_eq!(status, 0);
+        assert!(out.is_empty());
+        assert!(err.is_empty());
+    }
+
+    #[test]
+    fn quiet_still_reports_errors() {
+        let (socket, server) = mock_server(vec![Err("not_found")]);
+        let cli = parse(&["--socket", &socket, "-q", "get", "users", "u9"]);
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+
+        let status = finish(&cli, run(&cli), false, &mut out, &mut err);
+        server.join().unwrap();
+
+        assert_eq!(status, error::EXIT_DAEMON);
+        assert!(out.is_empty());
+        assert_eq!(String::from_utf8(err).unwrap(), "error: not_found\n");
+    }
+
+    #[test]
+    fn result_is_printed_without_quiet() {
+        let (socket, server) = mock_server(vec![Ok(record(&[("id", "u1")]))]);
+        let cli = parse(&["--socket", &socket, "get", "users", "u1"]);
+        let mut out = Vec::new();
+
+        let status = finish(&cli, run(&cli), false, &mut out, &mut Vec::new());
+        server.join().unwrap();
+
+        assert_eq!(status, 0);
+        assert_eq!(String::from_utf8(out).unwrap(), "{\"id\":\"u1\"}\n");
+    }
+
+    #[test]
+    fn backup_writes_schemas_and_records_per_table() {
+        let schema = |table: &str, attrs: &[&str]| {
+            Value::Map(vec![
+                (Value::String("table".into()), Value::String(table.into())),
+                (Value::String("key".into()), Value::String(attrs[0].into())),
+                (
+                    Value::String("attributes".into()),
+                    Value::Array(attrs.iter().map(|a| Value::String((*a).into())).collect()),
+                ),
+            ])
+        };
+        let (socket, server) = mock_server(vec![
+            Ok(Value::Array(vec![
+                Value::String("users".into()),
+                Value::String("orders".into()),
+            ])),
+            Ok(Value::Array(vec![])),
+            Ok(schema("users", &["id", "name"])),
+            Ok(Value::Array(vec![
+                record(&[("id", "u1"), ("name", "Ada")]),
+                record(&[("id", "u2"), ("name", "Grace")]),
+            ])),
+            Ok(schema("orders", &["order_id"])),
+            Ok(Value::Array(vec![record(&[("order_id", "o1")])])),
+        ]);
+
+        let mut out = Vec::new();
+        let summary = backup::backup(
+            &mut Connection::new(&socket).unwrap(),
+            &mut out,
+            &progress::Progress::hidden(),
+        )
+        .unwrap();
+
+        let methods: Vec<_> = server
+            .join()
+            .unwrap()
+            .iter()
+            .map(|r| r[2].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            methods,
+            ["tables", "acl_list", "describe", "all", "describe", "all"]
+        );
+        assert_eq!(
+            summary,
+            backup::Summary {
+                tables: 2,
+                records: 3
+            }
+        );
+
+        let doc: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(
+            doc,
+            serde_json::json!({
+                "cortex_backup": 1,
+                "tables": [
+                    {
+                        "table": "users",
+                        "key": "id",
+                        "attributes": ["id", "name"],
+                        "records": [{"id": "u1", "name": "Ada"}, {"id": "u2", "name": "Grace"}]
+                    },
+                    {
+                        "table": "orders",
+                        "key": "order_id",
+                        "attributes": ["order_id"],
+                        "records": [{"order_id": "o1"}]
+                    }
+                ]
+            })
+        );
+    }
+
+    /// Write sink the test can inspect after handing it to another thread.
+    #[derive(Clone, Default)]
+    struct SharedBuf(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn backup_describes_each_table_once_per_connection() {
+        let names = ["users", "orders", "items"];
+        let schema = |table: &str| {
+            Ok(json_to_msgpack(
+                &serde_json::json!({"table": table, "key": "id", "attributes": ["id"]}),
+            ))
+        };
+        let mut replies = vec![schema("users")];
+        replies.push(Ok(Value::Array(
+            names.iter().map(|n| Value::from(*n)).collect(),
+        )));
+        replies.push(Ok(Value::Array(vec![])));
+        for name in names {
+            if name != "users" {
+                replies.push(schema(name));
+            }
+            replies.push(Ok(Value::Array(vec![record(&[("id", "r1")])])));
+        }
+        let (socket, server) = mock_server(replies);
+        let mut conn = Connection::new(&socket).unwrap();
+
+        // Something earlier in the session already needed one schema
+        conn.describe("users").unwrap();
+        backup::backup(&mut conn, &mut Vec::new(), &progress::Progress::hidden()).unwrap();
+        backup::dump_table(&mut conn, "orders", true).unwrap();
+        drop(conn);
+
+        let requests = server.join().unwrap();
+        assert_eq!(
+            methods(&requests),
+            ["describe", "tables", "acl_list", "all", "describe", "all", "describe", "all"]
+        );
+    }
+
+    #[test]
+    fn schema_changes_invalidate_the_describe_cache() {
+        let schema = || {
+            Ok(json_to_msgpack(
+                &serde_json::json!({"table": "users", "key": "id", "attributes": ["id"]}),
+            ))
+        };
+        let (socket, server) = mock_server(vec![schema(), Ok(Value::from("ok")), schema()]);
+        let mut conn = Connection::new(&socket).unwrap();
+
+        conn.describe("users").unwrap();
+        conn.describe("users").unwrap();
+        let change = json_to_msgpack(&serde_json::json!({"add_attribute": "name"}));
+        conn.call("alter_table", vec![Value::from("users"), change])
+            .unwrap();
+        conn.describe("users").unwrap();
+        drop(conn);
+
+        let requests = server.join().unwrap();
+        assert_eq!(methods(&requests), ["describe", "alter_table", "describe"]);
+    }
+
+    #[test]
+    fn backup_progress_stays_off_the_data_stream() {
+        let (socket, server) = mock_server(vec![
+            Ok(Value::Array(vec![Value::String("users".into())])),
+            Ok(Value::Array(vec![])),
+            Ok(json_to_msgpack(
+                &serde_json::json!({"table": "users", "key": "id", "attributes": ["id"]}),
+            )),
+            Ok(Value::Array(vec![
+                record(&[("id", "u1")]),
+                record(&[("id", "u2")]),
+            ])),
+        ]);
+        let stderr = SharedBuf::default();
+        let progress = progress::Progress::new("backup", None, Box::new(stderr.clone()));
+        let mut out = Vec::new();
+
+        backup::backup(&mut Connection::new(&socket).unwrap(), &mut out, &progress).unwrap();
+        progress.finish();
+        server.join().unwrap();
+
+        let stderr = String::from_utf8(stderr.0.lock().unwrap().clone()).unwrap();
+        assert!(stderr.contains("backup: 2 records"));
+        assert!(stderr.ends_with("\r\x1b[K"));
+        // stdout is exactly the backup document
+        let doc: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(doc["tables"][0]["records"].as_array().unwrap().len(), 2);
+        assert!(!out.contains(&b'\r'));
+    }
+
+    #[test]
+    fn progress_only_on_a_terminal_without_quiet() {
+        assert!(parse(&["backup"]).show_progress(true));
+        assert!(!parse(&["backup"]).show_progress(false));
+        assert!(!parse(&["--quiet", "backup"]).show_progress(true));
+    }
+
+    fn backup_fixture() -> &'static str {
+        concat!(
+            r#"{"cortex_backup":1,"tables":["#,
+            r#"{"table":"users","key":"id","attributes":["name","id"],"#,
+            r#""records":[{"id":"u1","name":"Ada"},{"id":"u2","name":"Grace"}]},"#,
+            r#"{"table":"orders","key":"order_id","attributes":["order_id"],"#,
+            r#""records":[{"order_id":"o1"}]}"#,
+            "]}"
+        )
+    }
+
+    fn methods(requests: &[Value]) -> Vec<String> {
+        requests
+            .iter()
+            .map(|r| r[2].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn restore_recreates_tables_and_records() {
+        let ok = || Ok(Value::String("ok".into()));
+        let created = || Ok(Value::String("created".into()));
+        let (socket, server) = mock_server(vec![
+            Ok(Value::Array(vec![])),
+            created(),
+            ok(),
+            ok(),
+            created(),
+            ok(),
+        ]);
+        let doc = backup::Document::read(backup_fixture().as_bytes()).unwrap();
+
+        let summary = backup::restore(
+            &mut Connection::new(&socket).unwrap(),
+            &doc,
+            backup::Existing::Fail("--skip-existing or --drop-first"),
+        )
+        .unwrap();
+
+        let requests = server.join().unwrap();
+        assert_eq!(
+            methods(&requests),
+            [
+                "tables",
+                "create_table",
+                "put",
+                "put",
+                "create_table",
+                "put"
+            ]
+        );
+        // Primary key moved to the front, where create_table expects it
+        assert_eq!(
+            params(&requests[1])[1],
+            Value::Array(vec![
+                Value::String("id".into()),
+                Value::String("name".into())
+            ])
+        );
+        assert_eq!(
+            params(&requests[3])[1],
+            record(&[("id", "u2"), ("name", "Grace")])
+        );
+        assert_eq!(
+            summary,
+            [
+                serde_json::json!({"table": "users", "action": "created", "records": 2}),
+                serde_json::json!({"table": "orders", "action": "created", "records": 1}),
+            ]
+        );
+    }
+
+    /// Accept `connections` connections and serve each until it closes,
+    /// answering `tables` with an empty list, `put`s for the key `fail`
+    /// with an error, `put_many` with "ok" for each record, and everything
+    /// else with "ok". Yields each connection's requests.
+    fn mock_multi_server(connections: usize) -> (String, JoinHandle<Vec<Vec<Value>>>) {
+        let path = temp_socket_path();
+        let listener = UnixListener::bind(&path).unwrap();
+        let cleanup = path.clone();
+
+        let handle = thread::spawn(move || {
+            let servers: Vec<_> = (0..connections)
+                .map(|_| {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    thread::spawn(move || {
+                        let mut requests = Vec::new();
+                        while let Ok(request) = rmpv::decode::read_value(&mut stream) {
+                            let fail = request[3][1]["id"].as_str() == Some("fail");
+                            let (error, result) = match request[2].as_str() {
+                                Some("tables") => (Value::Nil, Value::Array(vec![])),
+                                Some("put_many") => {
+                                    let n = request[3][1].as_array().map_or(0, Vec::len);
+                                    (Value::Nil, Value::Array(vec![Value::from("ok"); n]))
+                                }
+                                _ if fail => (Value::from("access_denied"), Value::Nil),
+                                _ => (Value::Nil, Value::from("ok")),
+                            };
+                            let response = Value::Array(vec![
+                                Value::from(1),
+                                request[1].clone(),
+                                error,
+                                result,
+                            ]);
+                            requests.push(request);
+                            // A client that hit an error may have hung up
+                            if rmpv::encode::write_value(&mut stream, &response).is_err() {
+                                break;
+                            }
+                        }
+                        requests
+                    })
+                })
+                .collect();
+            let requests = servers.into_iter().map(|s| s.join().unwrap()).collect();
+            std::fs::remove_file(cleanup).ok();
+            requests
+        });
+
+        (path, handle)
+    }
+
+    /// A backup of one table with `n` records, written to a temp file.
+    fn numbered_backup(n: usize, fail_at: Option<usize>) -> PathBuf {
+        let records: Vec<_> = (0..n)
+            .map(|i| match fail_at {
+                Some(at) if at == i => serde_json::json!({"id": "fail"}),
+                _ => serde_json::json!({"id": format!("r{}", i)}),
+            })
+            .collect();
+        let doc = serde_json::json!({"cortex_backup": 1, "tables": [
+            {"table": "items", "key": "id", "attributes": ["id"], "records": records}
+        ]});
+        let path = PathBuf::from(temp_socket_path() + ".json");
+        std::fs::write(&path, doc.to_string()).unwrap();
+        path
+    }
+
+    #[test]
+    fn parallel_restore_writes_every_record_once() {
+        let (socket, server) = mock_multi_server(4);
+        let file = numbered_backup(3000, None);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "restore",
+            file.to_str().unwrap(),
+            "--parallel",
+            "4",
+        ]);
+
+        let summary = run(&cli).unwrap().unwrap();
+        let per_connection = server.join().unwrap();
+        std::fs::remove_file(file).ok();
+
+        assert_eq!(
+            msgpack_to_json(&summary),
+            serde_json::json!([{"table": "items", "action": "created", "records": 3000}])
+        );
+        let mut ids: Vec<String> = Vec::new();
+        for requests in &per_connection {
+            let puts: Vec<_> = requests
+                .iter()
+                .filter(|r| r[2].as_str() == Some("put"))
+                .collect();
+            assert_eq!(puts.len(), 750);
+            ids.extend(
+                puts.iter()
+                    .map(|r| r[3][1]["id"].as_str().unwrap().to_string()),
+            );
+        }
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 3000);
+    }
+
+    #[test]
+    fn parallel_restore_reports_worker_errors_with_counts() {
+        let (socket, server) = mock_multi_server(2);
+        // The second connection's share starts at record 50
+        let file = numbered_backup(100, Some(60));
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "restore",
+            file.to_str().unwrap(),
+            "--parallel=2",
+        ]);
+
+        let err = run(&cli).unwrap_err();
+        server.join().unwrap();
+        std::fs::remove_file(file).ok();
+
+        assert_eq!(err.exit_code(), error::EXIT_DAEMON);
+        assert_eq!(
+            err.to_string(),
+            "access_denied (1 of 2 workers failed; 60 of 100 records written to 'items')"
+        );
+    }
+
+    #[test]
+    fn parallel_import_writes_every_record_once() {
+        let lines: String = (0..4000)
+            .map(|i| format!("{{\"id\":\"r{}\"}}\n", i))
+            .collect();
+        let file = TempFile::new("import-parallel.jsonl", &lines);
+        let (socket, server) = mock_multi_server(4);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "import",
+            "items",
+            file.path(),
+            "--on-conflict",
+            "overwrite",
+            "--parallel",
+            "4",
+        ]);
+
+        let report = msgpack_to_json(&run(&cli).unwrap().unwrap());
+        let per_connection = server.join().unwrap();
+
+        assert_eq!(report["written"], 4000);
+        assert_eq!(report["failed"], 0);
+        let mut ids: Vec<String> = Vec::new();
+        for requests in &per_connection {
+            // One put_many batch for each connection
+            assert_eq!(methods(requests), ["put_many"]);
+            let records = params(&requests[0])[1].as_array().unwrap().clone();
+            assert_eq!(records.len(), 1000);
+            ids.extend(
+                records
+                    .iter()
+                    .map(|r| r["id"].as_str().unwrap().to_string()),
+            );
+        }
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 4000);
+    }
+
+    #[test]
+    fn parallel_is_capped() {
+        assert!(Cli::try_parse_from(["cortex", "restore", "f", "--parallel", "17"]).is_err());
+        assert!(Cli::try_parse_from(["cortex", "restore", "f", "--parallel", "0"]).is_err());
+        assert!(Cli::try_parse_from(["cortex", "import", "t", "f", "--parallel", "17"]).is_err());
+    }
+
+    #[test]
+    fn restore_skip_existing_leaves_table_alone() {
+        let (socket, server) = mock_server(vec![
+            Ok(Value::Array(vec![Value::String("users".into())])),
+            Ok(Value::String("created".into())),
+            Ok(Value::String("ok".into())),
+        ]);
+        let doc = backup::Document::read(backup_fixture().as_bytes()).unwrap();
+
+        let summary = backup::restore(
+            &mut Connection::new(&socket).unwrap(),
+            &doc,
+            backup::Existing::Skip,
+        )
+        .unwrap();
+
+        let requests = server.join().unwrap();
+        assert_eq!(methods(&requests), ["tables", "create_table", "put"]);
+        assert_eq!(params(&requests[1])[0], Value::String("orders".into()));
+        assert_eq!(summary[0]["action"], "skipped");
+        assert_eq!(summary[1]["action"], "created");
+    }
+
+    #[test]
+    fn restore_refuses_existing_tables_by_default() {
+        let (socket, server) =
+            mock_server(vec![Ok(Value::Array(vec![Value::String("orders".into())]))]);
+        let doc = backup::Document::read(backup_fixture().as_bytes()).unwrap();
+
+        let err = backup::restore(
+            &mut Connection::new(&socket).unwrap(),
+            &doc,
+            backup::Existing::Fail("--skip-existing or --drop-first"),
+        )
+        .unwrap_err();
+
+        // Nothing beyond the initial table listing was sent
+        assert_eq!(methods(&server.join().unwrap()), ["tables"]);
+        assert!(err.to_string().contains("'orders' already exists"));
+    }
+
+    #[test]
+    fn restore_rejects_malformed_backup() {
+        for bad in [
+            "not json",
+            r#"{"tables": []}"#,
+            r#"{"cortex_backup": 99, "tables": []}"#,
+            r#"{"cortex_backup":1,"tables":[{"table":"t","key":"id","attributes":["x"],"records":[]}]}"#,
+        ] {
+            let err = backup::Document::read(bad.as_bytes()).unwrap_err();
+            assert_eq!(err.code(), "input", "{}", bad);
+        }
+    }
+
+    #[test]
+    fn backup_round_trips_through_restore() {
+        let expired = json_to_msgpack(&serde_json::json!({
+            "identity": "uid:1002", "table": "1000:users", "permissions": ["read"],
+            "expires_at": 1,
+        }));
+        let mut grants = acl_fixture();
+        if let Value::Array(grants) = &mut grants {
+            grants.push(expired);
+        }
+        let (socket, server) = mock_server(vec![
+            Ok(Value::Array(vec![Value::String("users".into())])),
+            Ok(grants),
+            Ok(users_schema()),
+            Ok(Value::Array(vec![record(&[("id", "u1"), ("name", "Ada")])])),
+        ]);
+        let mut dump = Vec::new();
+        backup::backup(
+            &mut Connection::new(&socket).unwrap(),
+            &mut dump,
+            &progress::Progress::hidden(),
+        )
+        .unwrap();
+        server.join().unwrap();
+
+        let ok = || Ok(Value::String("ok".into()));
+        let (socket, server) = mock_server(vec![Ok(Value::Array(vec![])), ok(), ok(), ok(), ok()]);
+        let doc = backup::Document::read(dump.as_slice()).unwrap();
+        // Only the grants on users, and not the one that has lapsed
+        assert_eq!(doc.tables[0].acls.len(), 3);
+        let summary = backup::restore(
+            &mut Connection::new(&socket).unwrap(),
+            &doc,
+            backup::Existing::Fail("--skip-existing or --drop-first"),
+        )
+        .unwrap();
+        assert_eq!(summary[0]["acls"], 2);
+
+        let requests = server.join().unwrap();
+        assert_eq!(
+            methods(&requests),
+            ["tables", "create_table", "put", "acl_grant", "acl_grant"]
+        );
+        assert_eq!(
+            params(&requests[3]),
+            &[
+                Value::from("uid:1000"),
+                Value::from("users"),
+                Value::from("read,write,admin"),
+            ]
+        );
+        assert_eq!(params(&requests[1])[0], Value::String("users".into()));
+        assert_eq!(
+            params(&requests[2])[1],
+            record(&[("id", "u1"), ("name", "Ada")])
+        );
+    }
+
+    #[test]
+    fn failed_backup_keeps_existing_file() {
+        let path =
+            std::env::temp_dir().join(format!("cortex-backup-test-{}.json", std::process::id()));
+        std::fs::write(&path, "previous").unwrap();
+        let (socket, server) = mock_server(vec![Err("access_denied")]);
+
+        let result = transfer::backup_to_file(
+            &mut Connection::new(&socket).unwrap(),
+            &path,
+            &progress::Progress::hidden(),
+        );
+        server.join().unwrap();
+
+        assert!(result.is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "previous");
+        std::fs::remove_file(&path).ok();
+    }
+
+    /// An `all` page of `records` followed by `cursor` (None on the last).
+    fn records_page(records: Vec<Value>, cursor: Option<&str>) -> Value {
+        Value::Map(vec![
+            (Value::from("records"), Value::Array(records)),
+            (
+                Value::from("cursor"),
+                cursor.map_or(Value::Nil, Value::from),
+            ),
+        ])
+    }
+
+    #[test]
+    fn export_pages_through_the_table_by_cursor() {
+        let (socket, server) = mock_server(vec![
+            Ok(records_page(
+                vec![record(&[("id", "u1")]), record(&[("id", "u2")])],
+                Some("u2"),
+            )),
+            Ok(records_page(vec![record(&[("id", "u3")])], None)),
+        ]);
+        let mut out = Vec::new();
+        let count = export::export(
+            &mut Connection::new(&socket).unwrap(),
+            "users",
+            export::Format::Jsonl,
+            &mut out,
+            &progress::Progress::hidden(),
+        )
+        .unwrap();
+
+        assert_eq!(count, 3);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"id\":\"u1\"}\n{\"id\":\"u2\"}\n{\"id\":\"u3\"}\n"
+        );
+        let requests = server.join().unwrap();
+        assert_eq!(methods(&requests), ["all", "all"]);
+        assert_eq!(
+            msgpack_to_json(&params(&requests[0])[1]),
+            serde_json::json!({"cursor": null, "limit": export::PAGE_SIZE})
+        );
+        assert_eq!(params(&requests[1])[1]["cursor"], Value::from("u2"));
+    }
+
+    #[test]
+    fn export_csv_has_a_column_per_attribute() {
+        let (socket, server) = mock_server(vec![
+            Ok(users_schema()),
+            Ok(records_page(
+                vec![
+                    record(&[("id", "u1"), ("name", "Ada, Countess"), ("role", "x")]),
+                    record(&[("id", "u2"), ("email", "g@h.org")]),
+                ],
+                None,
+            )),
+        ]);
+        let mut out = Vec::new();
+        export::export(
+            &mut Connection::new(&socket).unwrap(),
+            "users",
+            export::Format::Csv,
+            &mut out,
+            &progress::Progress::hidden(),
+        )
+        .unwrap();
+        server.join().unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "id,name,email\nu1,\"Ada, Countess\",\nu2,,g@h.org\n"
+        );
+    }
+
+    #[test]
+    fn export_msgpack_writes_records_as_stored() {
+        let stored = Value::Map(vec![
+            (Value::from("id"), Value::from("k1")),
+            (Value::from("hash"), Value::Binary(vec![0xde, 0xad])),
+        ]);
+        let (socket, server) = mock_server(vec![Ok(records_page(vec![stored.clone()], None))]);
+        let path =
+            std::env::temp_dir().join(format!("cortex-export-test-{}.msgpack", std::process::id()));
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "--quiet",
+            "export",
+            "keys",
+            path.to_str().unwrap(),
+            "--format",
+            "msgpack",
+        ]);
+
+        assert_eq!(run(&cli).unwrap(), None);
+        server.join().unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(
+            rmpv::decode::read_value(&mut bytes.as_slice()).unwrap(),
+            stored
+        );
+    }
+
+    /// A temporary file holding `contents`, removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, contents: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("{}-{}", std::process::id(), name));
+            std::fs::write(&path, contents).unwrap();
+            TempFile(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            std::fs::remove_file(&self.0).ok();
+        }
+    }
+
+    #[test]
+    fn import_creates_the_table_from_the_first_record() {
+        let file = TempFile::new("import-create.csv", "id,name\nu1,Ada\nu2,Grace\n");
+        let (socket, server) = mock_server_per_request(vec![
+            Err("already_exists"),
+            Ok(Value::Array(vec![Value::from("ok"), Value::from("ok")])),
+        ]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "import",
+            "users",
+            file.path(),
+            "--format",
+            "csv",
+            "--create",
+            "--on-conflict",
+            "overwrite",
+        ]);
+
+        let report = msgpack_to_json(&run(&cli).unwrap().unwrap());
+        assert_eq!(report["written"], 2);
+
+        let requests = server.join().unwrap();
+        assert_eq!(methods(&requests), ["create_table", "put_many"]);
+        assert_eq!(
+            msgpack_to_json(&params(&requests[0])[1]),
+            serde_json::json!(["id", "name"])
+        );
+    }
+
+    #[test]
+    fn import_skips_or_stops_at_existing_keys() {
+        let file = TempFile::new(
+            "import-conflict.jsonl",
+            "{\"id\":\"a\"}\n{\"id\":\"b\"}\n{\"id\":\"c\"}\n",
+        );
+        let import = |socket: &str, on_conflict: &str| {
+            run(&parse(&[
+                "--socket",
+                socket,
+                "import",
+                "t",
+                file.path(),
+                "--on-conflict",
+                on_conflict,
+            ]))
+        };
+
+        let (socket, server) = mock_server(vec![
+            Ok(Value::from("ok")),
+            Err("condition_failed"),
+            Ok(Value::from("ok")),
+        ]);
+        let report = msgpack_to_json(&import(&socket, "skip").unwrap().unwrap());
+        assert_eq!(report["written"], 2);
+        assert_eq!(report["skipped"], 1);
+        let requests = server.join().unwrap();
+        assert_eq!(methods(&requests), ["cas_put", "cas_put", "cas_put"]);
+        assert_eq!(params(&requests[0])[2], Value::Nil);
+
+        let (socket, server) = mock_server(vec![Ok(Value::from("ok")), Err("condition_failed")]);
+        let err = import(&socket, "fail").unwrap_err();
+        assert_eq!(err.exit_code(), error::EXIT_CONFLICT);
+        assert_eq!(
+            err.to_string(),
+            Error::Conflict(
+                "record 2 has a key already in 't' (1 records were written before the import stopped)".into()
+            )
+            .to_string()
+        );
+        assert_eq!(server.join().unwrap().len(), 2);
+    }
+
+    /// Output sink that signals once the first complete line is written.
+    struct FirstLine {
+        buf: Vec<u8>,
+        signal: Option<std::sync::mpsc::Sender<()>>,
+    }
+
+    impl Write for FirstLine {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.buf.extend_from_slice(buf);
+            if self.buf.contains(&b'\n') {
+                if let Some(signal) = self.signal.take() {
+                    signal.send(()).ok();
+                }
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn ndjson_streams_records_before_the_response_is_complete() {
+        const RECORDS: u32 = 10_000;
+        let path = temp_socket_path();
+        let listener = UnixListener::bind(&path).unwrap();
+        let (signal, first_line) = std::sync::mpsc::channel();
+
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let request = rmpv::decode::read_value(&mut stream).unwrap();
+
+            let mut head = Vec::new();
+            rmp::encode::write_array_len(&mut head, 4).unwrap();
+            rmp::encode::write_uint(&mut head, 1).unwrap();
+            rmp::encode::write_uint(&mut head, request[1].as_u64().unwrap()).unwrap();
+            rmp::encode::write_nil(&mut head).unwrap();
+            rmp::encode::write_array_len(&mut head, RECORDS).unwrap();
+            rmpv::encode::write_value(&mut head, &record(&[("id", "0")])).unwrap();
+            stream.write_all(&head).unwrap();
+
+            // Hold back the rest until the client has printed the first record
+            let streamed = first_line.recv_timeout(Duration::from_secs(5)).is_ok();
+            let mut rest = Vec::new();
+            for i in 1..RECORDS {
+                let id = i.to_string();
+                rmpv::encode::write_value(&mut rest, &record(&[("id", &id)])).unwrap();
+            }
+            stream.write_all(&rest).unwrap();
+            streamed
+        });
+
+        let mut out = FirstLine {
+            buf: Vec::new(),
+            signal: Some(signal),
+        };
+        let mut conn = Connection::new(&path).unwrap();
+        queries::stream_records(&mut conn, "all", vec![], None, None, false, &mut out).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert!(server.join().unwrap(), "first record was not printed early");
+        let text = String::from_utf8(out.buf).unwrap();
+        assert_eq!(text.lines().count(), RECORDS as usize);
+        assert_eq!(text.lines().next(), Some("{\"id\":\"0\"}"));
+        assert_eq!(text.lines().last(), Some("{\"id\":\"9999\"}"));
+    }
+
+    #[test]
+    fn page_records_reads_every_page_once_in_order() {
+        let page = |ids: &[&str], next: Option<&str>| {
+            Ok(Value::Map(vec![
+                (
+                    Value::from("records"),
+                    Value::Array(ids.iter().map(|id| record(&[("id", id)])).collect()),
+                ),
+                (Value::from("cursor"), next.map_or(Value::Nil, Value::from)),
+            ]))
+        };
+        let (socket, server) = mock_server(vec![
+            page(&["a", "b"], Some("b")),
+            page(&["c", "d"], Some("d")),
+            page(&["e"], None),
+        ]);
+        let mut conn = Connection::new(&socket).unwrap();
+
+        let mut out = Vec::new();
+        let total = queries::page_records(&mut conn, "users", 2, None, false, &mut out).unwrap();
+
+        assert_eq!(total, 5);
+        let ids: Vec<String> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["id"].to_string())
+            .collect();
+        assert_eq!(ids, ["\"a\"", "\"b\"", "\"c\"", "\"d\"", "\"e\""]);
+
+        let cursors: Vec<_> = server
+            .join()
+            .unwrap()
+            .iter()
+            .map(|r| msgpack_to_json(&params(r)[1]))
+            .collect();
+        assert_eq!(
+            cursors,
+            [
+                serde_json::json!({"cursor": null, "limit": 2}),
+                serde_json::json!({"cursor": "b", "limit": 2}),
+                serde_json::json!({"cursor": "d", "limit": 2}),
+            ]
+        );
+    }
+
+    #[test]
+    fn page_size_defaults_and_rejects_sorting() {
+        let cli = parse(&["all", "users", "--page-size"]);
+        assert!(matches!(
+            cli.command,
+            Some(Commands::All {
+                page_size: Some(5000),
+                ..
+            })
+        ));
+        assert!(
+            Cli::try_parse_from(["cortex", "all", "t", "--page-size", "10", "--sort-by", "x"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn stream_records_projects_fields_and_reports_daemon_errors() {
+        let (socket, server) = mock_server(vec![
+            Ok(Value::Array(vec![record(&[("name", "Ada"), ("id", "u1")])])),
+            Err("access_denied"),
+        ]);
+        let mut conn = Connection::new(&socket).unwrap();
+
+        let mut out = Vec::new();
+        queries::stream_records(
+            &mut conn,
+            "all",
+            vec![],
+            Some("id,name"),
+            None,
+            false,
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"id\":\"u1\",\"name\":\"Ada\"}\n"
+        );
+
+        let err =
+            queries::stream_records(&mut conn, "all", vec![], None, None, false, &mut Vec::new())
+                .unwrap_err();
+        assert_eq!(err, Error::Daemon("access_denied".to_string()));
+
+        let requests = server.join().unwrap();
+        assert_eq!(
+            params(&requests[0]).last(),
+            Some(&commands::fields_param(&commands::parse_fields("id,name")))
+        );
+    }
+
+    #[test]
+    fn ndjson_prints_list_results_one_per_line() {
+        let cli = parse(&["--output", "ndjson", "keys", "users"]);
+        let keys = Value::Array(vec![Value::String("a".into()), Value::String("b".into())]);
+        let mut out = Vec::new();
+
+        assert_eq!(
+            finish(&cli, Ok(Some(keys)), false, &mut out, &mut Vec::new()),
+            0
+        );
+        assert_eq!(String::from_utf8(out).unwrap(), "\"a\"\n\"b\"\n");
+    }
+
+    #[test]
+    fn compact_binary_summarizes_long_blobs_for_people() {
+        let blob = |len: usize| {
+            Value::Map(vec![
+                (Value::from("id"), Value::from("f1")),
+                (Value::from("data"), Value::Binary(vec![b'x'; len])),
+            ])
+        };
+        let render = |args: &[&str], len: usize| {
+            let mut out = Vec::new();
+            finish(
+                &parse(args),
+                Ok(Some(blob(len))),
+                false,
+                &mut out,
+                &mut Vec::new(),
+            );
+            String::from_utf8(out).unwrap()
+        };
+        let args = ["--compact-binary=8", "get", "files", "f1"];
+
+        // sha256("xxxxxxxxx") starts a73add1aecea
+        assert_eq!(
+            render(&args, 9),
+            r#"{"id":"f1","data":{"__bin__":"<9 bytes, sha256=a73add1aecea>"}}"#.to_string() + "\n"
+        );
+        assert_eq!(render(&args, 8), "{\"id\":\"f1\",\"data\":\"xxxxxxxx\"}\n");
+
+        let machine = [
+            "--output",
+            "json",
+            "--compact-binary=8",
+            "get",
+            "files",
+            "f1",
+        ];
+        assert_eq!(
+            render(&machine, 9),
+            "{\"id\":\"f1\",\"data\":\"xxxxxxxxx\"}\n"
+        );
+        assert_eq!(render(&["get", "files", "f1"], 9), render(&machine, 9));
+    }
+
+    #[test]
+    fn stats_go_to_stderr_after_the_result() {
+        let cli = parse(&["--stats", "all", "users"]);
+        let records = Value::Array(vec![record(&[("id", "u1")]), record(&[("id", "u2")])]);
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+
+        let status = finish_with_stats(
+            &cli,
+            Ok(Some(records)),
+            Duration::from_millis(13),
+            false,
+            &mut out,
+            &mut err,
+        );
+
+        assert_eq!(status, 0);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "[{\"id\":\"u1\"},{\"id\":\"u2\"}]\n"
+        );
+        assert_eq!(String::from_utf8(err).unwrap(), "# 2 records in 13ms\n");
+    }
+
+    #[test]
+    fn stats_count_a_scalar_as_one_and_stay_off_by_default() {
+        assert_eq!(
+            render_stats(&record(&[("id", "u1")]), Duration::from_millis(2)),
+            "# 1 record in 2ms"
+        );
+
+        let mut err = Vec::new();
+        let cli = parse(&["get", "users", "u1"]);
+        let value = Ok(Some(record(&[("id", "u1")])));
+        finish_with_stats(
+            &cli,
+            value,
+            Duration::ZERO,
+            false,
+            &mut Vec::new(),
+            &mut err,
+        );
+        assert!(err.is_empty());
+    }
+
+    #[test]
+    fn compact_under_keeps_small_records_inline() {
+        let records = || {
+            Ok(Some(Value::Array(vec![
+                record(&[("id", "u1")]),
+                record(&[("id", "u2"), ("name", "a much longer name")]),
+            ])))
+        };
+        let mut out = Vec::new();
+        let cli = parse(&["--output", "json", "--compact-under", "20", "all", "users"]);
+        finish(&cli, records(), false, &mut out, &mut Vec::new());
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            concat!(
+                "[\n",
+                "  {\"id\":\"u1\"},\n",
+                "  {\n",
+                "    \"id\": \"u2\",\n",
+                "    \"name\": \"a much longer name\"\n",
+                "  }\n",
+                "]\n"
+            )
+        );
+    }
+
+    #[test]
+    fn no_newline_drops_only_the_final_newline() {
+        let value = Some(record(&[("id", "u1")]));
+        let mut out = Vec::new();
+        finish(
+            &parse(&["get", "users", "u1"]),
+            Ok(value.clone()),
+            false,
+            &mut out,
+            &mut Vec::new(),
+        );
+        assert_eq!(out, b"{\"id\":\"u1\"}\n");
+
+        let mut out = Vec::new();
+        let cli = parse(&["--no-newline", "get", "users", "u1"]);
+        finish(&cli, Ok(value), false, &mut out, &mut Vec::new());
+        assert_eq!(out, b"{\"id\":\"u1\"}");
+
+        let keys = Value::Array(vec![Value::from("a"), Value::from("b")]);
+        let mut out = Vec::new();
+        let cli = parse(&["--no-newline", "--output", "ndjson", "keys", "users"]);
+        finish(&cli, Ok(Some(keys)), false, &mut out, &mut Vec::new());
+        assert_eq!(out, b"\"a\"\n\"b\"");
+    }
+
+    #[test]
+    fn null_delimited_keys() {
+        let keys = || {
+            Some(Value::Array(vec![
+                Value::from("u1"),
+                Value::from("two words"),
+                Value::from(true),
+            ]))
+        };
+
+        let mut out = Vec::new();
+        let cli = parse(&["--null-delimited", "keys", "users"]);
+        finish(&cli, Ok(keys()), false, &mut out, &mut Vec::new());
+        assert_eq!(out, b"u1\0two words\0true\0");
+
+        let mut out = Vec::new();
+        let cli = parse(&["-0", "--no-newline", "keys", "users"]);
+        finish(&cli, Ok(keys()), false, &mut out, &mut Vec::new());
+        assert_eq!(out, b"u1\0two words\0true");
+    }
+
+    #[test]
+    fn table_output_renders_records_and_falls_back_to_json() {
+        let cli = parse(&["--output", "table", "all", "users"]);
+        let records = Value::Array(vec![record(&[("id", "u1"), ("name", "alice")])]);
+        let mut out = Vec::new();
+        finish(&cli, Ok(Some(records)), false, &mut out, &mut Vec::new());
+        assert!(String::from_utf8(out).unwrap().contains("| id | name  |\n"));
+
+        let mut out = Vec::new();
+        finish(
+            &cli,
+            Ok(Some(Value::String("pong".into()))),
+            false,
+            &mut out,
+            &mut Vec::new(),
+        );
+        assert_eq!(String::from_utf8(out).unwrap(), "\"pong\"\n");
+    }
+
+    #[test]
+    fn format_renders_csv_yaml_and_raw() {
+        let render = |args: &[&str], result: Value| {
+            let (mut out, mut err) = (Vec::new(), Vec::new());
+            let status = finish(&parse(args), Ok(Some(result)), false, &mut out, &mut err);
+            (
+                status,
+                String::from_utf8(out).unwrap(),
+                String::from_utf8(err).unwrap(),
+            )
+        };
+        let records = || {
+            Value::Array(vec![
+                record(&[("id", "u1"), ("name", "Ada, Countess")]),
+                record(&[("id", "u2")]),
+            ])
+        };
+
+        let (_, out, _) = render(&["--format", "csv", "all", "users"], records());
+        assert_eq!(out, "id,name\nu1,\"Ada, Countess\"\nu2,\n");
+        let (_, out, _) = render(&["--format", "yaml", "all", "users"], records());
+        assert_eq!(out, "- id: u1\n  name: \"Ada, Countess\"\n- id: u2\n");
+        let (_, out, _) = render(
+            &["--format", "raw", "get", "t", "k"],
+            Value::from("a \"b\""),
+        );
+        assert_eq!(out, "a \"b\"\n");
+        let (_, out, _) = render(
+            &["--output", "raw", "--no-newline", "raw", "ping"],
+            42.into(),
+        );
+        assert_eq!(out, "42");
+
+        let (status, out, err) = render(&["--format", "raw", "all", "users"], records());
+        assert_eq!(status, error::EXIT_INPUT);
+        assert!(out.is_empty());
+        assert!(err.contains("not an array or object"), "{}", err);
+    }
+
+    #[test]
+    fn format_msgpack_writes_the_result_as_sent() {
+        let result = Value::Map(vec![(Value::from("hash"), Value::Binary(vec![0xff]))]);
+        let mut out = Vec::new();
+        let cli = parse(&["--format", "msgpack", "get", "t", "k"]);
+        finish(
+            &cli,
+            Ok(Some(result.clone())),
+            false,
+            &mut out,
+            &mut Vec::new(),
+        );
+        assert_eq!(
+            rmpv::decode::read_value(&mut out.as_slice()).unwrap(),
+            result
+        );
+    }
+
+    #[test]
+    fn hex_tag_becomes_binary() {
+        let json = serde_json::json!({"id": "k1", "hash": {"__bin__hex__": "DEADbeef00"}});
+        assert_eq!(
+            input_to_msgpack(&json).unwrap(),
+            Value::Map(vec![
+                (Value::from("id"), Value::from("k1")),
+                (
+                    Value::from("hash"),
+                    Value::Binary(vec![0xde, 0xad, 0xbe, 0xef, 0x00])
+                ),
+            ])
+        );
+        assert_eq!(
+            input_to_msgpack(&serde_json::json!({"__bin__hex__": ""})).unwrap(),
+            Value::Binary(vec![])
+        );
+    }
+
+    #[test]
+    fn hex_tag_rejects_invalid_digits() {
+        let err = input_to_msgpack(&serde_json::json!([{"__bin__hex__": "abzz"}])).unwrap_err();
+        assert_eq!(err.code(), "input");
+        assert_eq!(
+            err.to_string(),
+            "invalid __bin__hex__ value: 'z' at position 2 is not a hex digit"
+        );
+    }
+
+    #[test]
+    fn hex_tag_rejects_odd_length() {
+        let err = batch::parse_raw_params(r#"["t", {"k": {"__bin__hex__": "abc"}}]"#).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid __bin__hex__ value: odd number of digits (3)"
+        );
+    }
+
+    #[test]
+    fn integer_and_string_keys_stay_distinct() {
+        let map = Value::Map(vec![
+            (Value::from(1), Value::String("int".into())),
+            (Value::String("1".into()), Value::String("string".into())),
+        ]);
+
+        let json = msgpack_to_json(&map);
+        assert_eq!(
+            json,
+            serde_json::json!({"__map__": [[1, "int"], ["1", "string"]]})
+        );
+        assert_eq!(json_to_msgpack(&json), map);
+    }
+
+    #[test]
+    fn string_keyed_maps_stay_plain_objects() {
+        let json = msgpack_to_json(&record(&[("__map__", "x"), ("id", "1")]));
+        assert_eq!(json, serde_json::json!({"__map__": "x", "id": "1"}));
+        assert!(matches!(json_to_msgpack(&json), Value::Map(entries) if entries.len() == 2));
+    }
+
+    #[test]
+    fn dry_run_put_shows_request_without_connecting() {
+        // Nothing listens here, so any connection attempt would fail
+        let missing = temp_socket_path();
+        let cli = parse(&[
+            "--socket",
+            &missing,
+            "--dry-run",
+            "put",
+            "users",
+            r#"{"id":"u1"}"#,
+        ]);
+
+        let result = run(&cli).unwrap().unwrap();
+
+        assert_eq!(
+            msgpack_to_json(&result),
+            serde_json::json!({
+                "dry_run": true,
+                "method": "put",
+                "params": ["users", {"id": "u1"}]
+            })
+        );
+    }
+
+    #[test]
+    fn dry_run_skips_confirmation_for_destructive_commands() {
+        let missing = temp_socket_path();
+        let cli = parse(&["--socket", &missing, "--dry-run", "drop-table", "users"]);
+
+        let result = run(&cli).unwrap().unwrap();
+        assert_eq!(msgpack_to_json(&result)["method"], "drop_table");
+    }
+
+    #[test]
+    fn dry_run_lets_read_commands_through() {
+        let (socket, server) = mock_server(vec![Ok(record(&[("id", "u1")]))]);
+        let cli = parse(&["--socket", &socket, "--dry-run", "get", "users", "u1"]);
+
+        assert_eq!(run(&cli).unwrap(), Some(record(&[("id", "u1")])));
+        assert_eq!(server.join().unwrap()[0][2].as_str(), Some("get"));
+    }
+
+    #[test]
+    fn dry_run_restore_lists_every_request() {
+        let path = std::env::temp_dir().join(format!("cortex-dry-run-{}.json", std::process::id()));
+        std::fs::write(&path, backup_fixture()).unwrap();
+        let missing = temp_socket_path();
+        let cli = parse(&[
+            "--socket",
+            &missing,
+            "--dry-run",
+            "restore",
+            path.to_str().unwrap(),
+        ]);
+
+        let result = msgpack_to_json(&run(&cli).unwrap().unwrap());
+        std::fs::remove_file(&path).ok();
+
+        let methods: Vec<_> = result
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["method"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            methods,
+            ["create_table", "put", "put", "create_table", "put"]
+        );
+    }
+
+    #[test]
+    fn copy_table_recreates_schema_and_records() {
+        let (socket, server) = mock_server(vec![
+            Ok(users_schema()),
+            Ok(Value::Array(vec![
+                record(&[("id", "u1"), ("name", "Ann")]),
+                record(&[("id", "u2"), ("name", "Bo")]),
+            ])),
+            Ok(Value::Array(vec![Value::String("users".into())])),
+            Ok(Value::String("created".into())),
+            Ok(Value::String("ok".into())),
+            Ok(Value::String("ok".into())),
+        ]);
+        let cli = parse(&["--socket", &socket, "copy-table", "users", "people"]);
+
+        let result = msgpack_to_json(&run(&cli).unwrap().unwrap());
+        assert_eq!(
+            result,
+            serde_json::json!({"table": "people", "action": "created", "records": 2})
+        );
+
+        let requests = server.join().unwrap();
+        assert_eq!(
+            methods(&requests),
+            ["describe", "all", "tables", "create_table", "put", "put"]
+        );
+        assert_eq!(params(&requests[3])[0].as_str(), Some("people"));
+        assert_eq!(params(&requests[5])[0].as_str(), Some("people"));
+        assert_eq!(
+            params(&requests[5])[1],
+            record(&[("id", "u2"), ("name", "Bo")])
+        );
+    }
+
+    #[test]
+    fn copy_table_schema_only_skips_records() {
+        let (socket, server) = mock_server(vec![
+            Ok(users_schema()),
+            Ok(Value::Array(vec![Value::String("users".into())])),
+            Ok(Value::String("created".into())),
+        ]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "copy-table",
+            "users",
+            "people",
+            "--schema-only",
+        ]);
+
+        let result = msgpack_to_json(&run(&cli).unwrap().unwrap());
+        assert_eq!(result["records"], 0);
+
+        let requests = server.join().unwrap();
+        assert_eq!(methods(&requests), ["describe", "tables", "create_table"]);
+        assert_eq!(
+            params(&requests[2])[1],
+            Value::Array(vec![
+                Value::String("id".into()),
+                Value::String("name".into()),
+                Value::String("email".into()),
+            ])
+        );
+    }
+
+    #[test]
+    fn describe_to_create_keeps_attribute_order() {
+        let names = |attrs: &[&str]| Value::Array(attrs.iter().map(|&a| Value::from(a)).collect());
+        let round_trip = |schema: serde_json::Value| {
+            let (key, attributes) = backup::schema_order("t", &schema)?;
+            Ok::<_, Error>(backup::create_table_params("t", &key, &attributes))
+        };
+
+        let schema = serde_json::json!({
+            "table": "t", "key": "id", "attributes": ["id", "zeta", "alpha", "mid"]
+        });
+        assert_eq!(
+            round_trip(schema).unwrap(),
+            [Value::from("t"), names(&["id", "zeta", "alpha", "mid"])]
+        );
+
+        // The key goes first; the others stay in their described order
+        let schema = serde_json::json!({
+            "table": "t", "key": "email", "attributes": ["zeta", "email", "alpha"]
+        });
+        assert_eq!(
+            round_trip(schema).unwrap(),
+            [Value::from("t"), names(&["email", "zeta", "alpha"])]
+        );
+
+        // Attributes given as a map have no order to keep
+        let schema = serde_json::json!({
+            "table": "t", "key": "id", "attributes": {"id": 0, "name": 1}
+        });
+        assert_eq!(
+            round_trip(schema).unwrap_err(),
+            Error::Protocol("malformed schema for 't'".to_string())
+        );
+    }
+
+    #[test]
+    fn copy_table_refuses_existing_destination() {
+        let (socket, server) = mock_server(vec![
+            Ok(users_schema()),
+            Ok(Value::Array(vec![])),
+            Ok(Value::Array(vec![
+                Value::String("users".into()),
+                Value::String("people".into()),
+            ])),
+        ]);
+        let cli = parse(&["--socket", &socket, "copy-table", "users", "people"]);
+
+        let err = run(&cli).unwrap_err();
+        assert_eq!(err.code(), "input");
+        assert!(err
+            .to_string()
+            .contains("'people' already exists (use --overwrite)"));
+        assert_eq!(
+            methods(&server.join().unwrap()),
+            ["describe", "all", "tables"]
+        );
+    }
+
+    #[test]
+    fn copy_table_overwrite_drops_destination_first() {
+        let (socket, server) = mock_server(vec![
+            Ok(users_schema()),
+            Ok(Value::Array(vec![])),
+            Ok(Value::Array(vec![Value::String("people".into())])),
+            Ok(Value::String("dropped".into())),
+            Ok(Value::String("created".into())),
+        ]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "copy-table",
+            "users",
+            "people",
+            "--overwrite",
+            "--yes",
+        ]);
+
+        let result = msgpack_to_json(&run(&cli).unwrap().unwrap());
+        assert_eq!(result["action"], "recreated");
+        assert_eq!(
+            methods(&server.join().unwrap()),
+            ["describe", "all", "tables", "drop_table", "create_table"]
+        );
+    }
+
+    #[test]
+    fn get_default_ignored_when_record_exists() {
+        let (socket, _server) = mock_server(vec![Ok(record(&[("id", "u1")]))]);
+        let cli = parse(&["--socket", &socket, "get", "users", "u1", "--default", "{}"]);
+
+        assert_eq!(run(&cli).unwrap(), Some(record(&[("id", "u1")])));
+    }
+
+    #[test]
+    fn get_default_replaces_missing_record() {
+        let (socket, _server) = mock_server(vec![Err("not_found")]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "get",
+            "config",
+            "log_level",
+            "--default",
+            r#"{"value":"info"}"#,
+        ]);
+
+        assert_eq!(run(&cli).unwrap(), Some(record(&[("value", "info")])));
+    }
+
+    #[test]
+    fn get_without_default_reports_missing_record() {
+        let (socket, _server) = mock_server(vec![Err("not_found")]);
+        let cli = parse(&["--socket", &socket, "get", "config", "log_level"]);
+
+        let err = run(&cli).unwrap_err();
+        assert_eq!(err.code(), "daemon");
+        assert_eq!(err.to_string(), "not_found");
+    }
+
+    fn nested_user(id: &str, address: serde_json::Value) -> Value {
+        json_to_msgpack(&serde_json::json!({"id": id, "address": address}))
+    }
+
+    #[test]
+    fn get_extract_prints_the_pointed_to_value() {
+        let user = nested_user(
+            "u1",
+            serde_json::json!({"city": "NYC", "lines": ["1 Main St"]}),
+        );
+        let get = |pointer: &str| {
+            let (socket, _server) = mock_server(vec![Ok(user.clone())]);
+            let extract = format!("--extract={}", pointer);
+            run(&parse(&[
+                "--socket", &socket, "get", "users", "u1", &extract,
+            ]))
+        };
+
+        assert_eq!(get("/address/city").unwrap(), Some(Value::from("NYC")));
+        assert_eq!(
+            get("/address/lines/0").unwrap(),
+            Some(Value::from("1 Main St"))
+        );
+    }
+
+    #[test]
+    fn get_extract_that_does_not_resolve() {
+        let user = nested_user("u1", serde_json::json!({"city": "NYC"}));
+        let get = |optional: &[&str]| {
+            let (socket, _server) = mock_server(vec![Ok(user.clone())]);
+            let mut args = vec![
+                "--socket",
+                &socket,
+                "get",
+                "users",
+                "u1",
+                "--extract",
+                "/address/zip",
+            ];
+            args.extend(optional);
+            run(&parse(&args))
+        };
+
+        let err = get(&[]).unwrap_err();
+        assert_eq!(err.code(), "input");
+        assert_eq!(
+            err.to_string(),
+            "--extract '/address/zip' does not resolve in record 'u1' \
+             (pass --extract-optional to allow this)"
+        );
+
+        assert_eq!(get(&["--extract-optional"]).unwrap(), Some(Value::Nil));
+    }
+
+    #[test]
+    fn raw_string_prints_strings_unquoted() {
+        let cli = parse(&["get", "config", "url", "-r"]);
+        let print = |value: serde_json::Value| {
+            let (mut out, mut notes) = (Vec::new(), Vec::new());
+            let result = records::print_raw_string(
+                &cli,
+                Some(json_to_msgpack(&value)),
+                &mut out,
+                &mut notes,
+            );
+            (
+                result.unwrap().map(|v| msgpack_to_json(&v)),
+                String::from_utf8(out).unwrap(),
+                String::from_utf8(notes).unwrap(),
+            )
+        };
+
+        let (rest, out, notes) = print(serde_json::json!("http://a.example/\"q\""));
+        assert_eq!(rest, None);
+        assert_eq!(out, "http://a.example/\"q\"\n");
+        assert_eq!(notes, "");
+
+        // Anything else goes back to be printed as JSON, with a note
+        let note = "note: --raw-string: result is not a string; printing JSON\n";
+        let (rest, out, notes) = print(serde_json::json!(42));
+        assert_eq!(rest, Some(serde_json::json!(42)));
+        assert_eq!((out.as_str(), notes.as_str()), ("", note));
+
+        let (rest, out, notes) = print(serde_json::json!({"url": "http://a.example/"}));
+        assert_eq!(rest, Some(serde_json::json!({"url": "http://a.example/"})));
+        assert_eq!((out.as_str(), notes.as_str()), ("", note));
+    }
+
+    #[test]
+    fn raw_string_applies_after_extract() {
+        let user = nested_user("u1", serde_json::json!({"zip": 10001}));
+        let (socket, _server) = mock_server(vec![Ok(user)]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "get",
+            "users",
+            "u1",
+            "--extract",
+            "/address/zip",
+            "--raw-string",
+            "--quiet",
+        ]);
+        assert_eq!(run(&cli).unwrap(), Some(Value::from(10001)));
+
+        assert!(Cli::try_parse_from(["cortex", "get", "t", "--keys-file", "-", "-r"]).is_err());
+    }
+
+    #[test]
+    fn query_extract_maps_each_result() {
+        let records = Value::Array(vec![
+            nested_user("u1", serde_json::json!({"city": "NYC"})),
+            nested_user("u2", serde_json::json!("unknown")),
+            nested_user("u3", serde_json::json!({"city": "LA"})),
+        ]);
+        let query = |optional: &[&str]| {
+            let (socket, _server) = mock_server(vec![Ok(records.clone())]);
+            let mut args = vec!["--socket", &socket, "query", "users", "{}"];
+            args.extend(["--extract", "/address/city"]);
+            args.extend(optional);
+            run(&parse(&args))
+        };
+
+        let err = query(&[]).unwrap_err();
+        assert_eq!(err.code(), "input");
+        assert!(
+            err.to_string().contains("does not resolve in result 2"),
+            "{}",
+            err
+        );
+
+        assert_eq!(
+            query(&["--extract-optional"]).unwrap(),
+            Some(Value::Array(vec![Value::from("NYC"), Value::from("LA")]))
+        );
+    }
+
+    #[test]
+    fn extract_must_be_a_json_pointer() {
+        assert!(
+            Cli::try_parse_from(["cortex", "get", "users", "u1", "--extract", "address"]).is_err()
+        );
+        assert!(
+            Cli::try_parse_from(["cortex", "get", "users", "u1", "--extract", "/a~2b"]).is_err()
+        );
+        assert_eq!(parse_pointer("/a~1b/~0c"), Ok("/a~1b/~0c".to_string()));
+        assert_eq!(parse_pointer(""), Ok(String::new()));
+    }
+
+    #[test]
+    fn query_filters_nested_patterns_client_side() {
+        let nyc = Value::Map(vec![
+            (Value::String("id".into()), Value::String("u1".into())),
+            (
+                Value::String("address".into()),
+                record(&[("city", "NYC"), ("zip", "10001")]),
+            ),
+        ]);
+        let la = Value::Map(vec![
+            (Value::String("id".into()), Value::String("u2".into())),
+            (Value::String("address".into()), record(&[("city", "LA")])),
+        ]);
+        let (socket, server) = mock_server(vec![Ok(Value::Array(vec![nyc, la]))]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "query",
+            "users",
+            r#"{"address":{"city":"NYC"}}"#,
+            "--fields",
+            "id",
+        ]);
+
+        let result = run(&cli).unwrap();
+        assert_eq!(result, Some(Value::Array(vec![record(&[("id", "u1")])])));
+
+        // The daemon sees only the flat part of the pattern, and no projection
+        let requests = server.join().unwrap();
+        assert_eq!(params(&requests[0]).len(), 2);
+        assert_eq!(params(&requests[0])[1], Value::Map(vec![]));
+    }
+
+    #[test]
+    fn query_sends_regex_to_the_daemon() {
+        let m1 = record(&[("id", "m1"), ("content", "foo and bar")]);
+        let (socket, server) = mock_server(vec![Ok(Value::Array(vec![m1.clone()]))]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "query",
+            "memories",
+            r#"{"content":{"$regex":"foo.*bar"}}"#,
+        ]);
+
+        assert_eq!(run(&cli).unwrap(), Some(Value::Array(vec![m1])));
+
+        let requests = server.join().unwrap();
+        assert_eq!(
+            msgpack_to_json(&requests[0][3]),
+            serde_json::json!(["memories", {}, {"regex": {"content": "foo.*bar"}}])
+        );
+    }
+
+    #[test]
+    fn query_falls_back_to_local_regex() {
+        let m1 = record(&[("id", "m1"), ("content", "foo and bar")]);
+        let m2 = record(&[("id", "m2"), ("content", "bar only")]);
+        let (socket, server) = mock_server(vec![
+            Err("unknown method: match"),
+            Ok(Value::Array(vec![m1.clone(), m2])),
+        ]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "query",
+            "memories",
+            r#"{"kind":"note","content":{"$regex":"foo.*bar"}}"#,
+        ]);
+
+        assert_eq!(run(&cli).unwrap(), Some(Value::Array(vec![m1])));
+
+        let requests = server.join().unwrap();
+        assert_eq!(
+            msgpack_to_json(&requests[1][3]),
+            serde_json::json!(["memories", {"kind": "note"}])
+        );
+    }
+
+    #[test]
+    fn query_sends_operators_to_the_daemon_and_rechecks_them() {
+        let u1 = serde_json::json!({"id": "u1", "age": 41, "role": "ops"});
+        let u2 = serde_json::json!({"id": "u2", "age": 25, "role": "ops"});
+        // A daemon that predates operators ignores them and returns both
+        let (socket, server) = mock_server(vec![Ok(json_to_msgpack(&serde_json::json!([
+            u1.clone(),
+            u2
+        ])))]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "query",
+            "users",
+            r#"{"age":{"$gt":30},"role":{"$in":["admin","ops"]}}"#,
+        ]);
+
+        assert_eq!(
+            run(&cli).unwrap().map(|r| msgpack_to_json(&r)),
+            Some(serde_json::json!([u1]))
+        );
+
+        let requests = server.join().unwrap();
+        assert_eq!(
+            msgpack_to_json(&requests[0][3]),
+            serde_json::json!([
+                "users",
+                {},
+                {"where": {"age": {"gt": 30}, "role": {"in": ["admin", "ops"]}}}
+            ])
+        );
+    }
+
+    #[test]
+    fn limit_waits_for_the_local_filter() {
+        let records = serde_json::json!([
+            {"id": "u1", "age": 25, "meta": {"team": "red"}},
+            {"id": "u2", "age": 30, "meta": {"team": "blue"}},
+            {"id": "u3", "age": 35, "meta": {"team": "blue"}},
+            {"id": "u4", "age": 40, "meta": {"team": "blue"}}
+        ]);
+        let (socket, server) = mock_server(vec![Ok(json_to_msgpack(&records))]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "query",
+            "users",
+            r#"{"meta":{"team":"blue"}}"#,
+            "--sort-by",
+            "age",
+            "--limit",
+            "2",
+        ]);
+
+        assert_eq!(ids(&run(&cli).unwrap().unwrap()), ["u2", "u3"]);
+        let requests = server.join().unwrap();
+        assert_eq!(
+            msgpack_to_json(&requests[0][3]),
+            serde_json::json!(["users", {}, {"order_by": "age", "order": "asc"}])
+        );
+    }
+
+    #[test]
+    fn query_rejects_invalid_regex_before_connecting() {
+        let cli = parse(&[
+            "--socket",
+            "/nonexistent/cortex.sock",
+            "query",
+            "memories",
+            r#"{"content":{"$regex":"["}}"#,
+        ]);
+        assert_eq!(run(&cli).unwrap_err().code(), "input");
+    }
+
+    fn hello_reply(protocol: &str) -> Value {
+        Value::Map(vec![(
+            Value::String("protocol".into()),
+            Value::String(protocol.into()),
+        )])
+    }
+
+    #[test]
+    fn handshake_precedes_the_first_request() {
+        let (socket, server) = mock_server(vec![
+            Ok(hello_reply(connection::PROTOCOL_VERSION)),
+            Ok(Value::String("pong".into())),
+        ]);
+        let cli = Cli::try_parse_from(["cortex", "--socket", &socket, "ping"]).unwrap();
+
+        assert_eq!(run(&cli).unwrap(), Some(Value::String("pong".into())));
+
+        let requests = server.join().unwrap();
+        assert_eq!(methods(&requests), ["hello", "ping"]);
+        assert_eq!(
+            params(&requests[0]),
+            &[Value::String(connection::PROTOCOL_VERSION.into())]
+        );
+    }
+
+    #[test]
+    fn handshake_with_matching_version_is_silent() {
+        let (socket, _server) = mock_server(vec![Ok(hello_reply("1.7"))]);
+        let mut warn = Vec::new();
+
+        handshake(&mut Connection::new(&socket).unwrap(), &mut warn).unwrap();
+        assert!(warn.is_empty());
+    }
+
+    #[test]
+    fn handshake_warns_on_major_mismatch() {
+        let (socket, _server) = mock_server(vec![Ok(hello_reply("2.0"))]);
+        let mut warn = Vec::new();
+
+        handshake(&mut Connection::new(&socket).unwrap(), &mut warn).unwrap();
+        let warn = String::from_utf8(warn).unwrap();
+        assert!(
+            warn.starts_with("warning: daemon speaks protocol 2.0"),
+            "{}",
+            warn
+        );
+    }
+
+    #[test]
+    fn handshake_ignores_daemons_without_hello() {
+        let (socket, _server) = mock_server(vec![Err("unknown method: hello")]);
+        let mut warn = Vec::new();
+
+        handshake(&mut Connection::new(&socket).unwrap(), &mut warn).unwrap();
+        assert!(warn.is_empty());
+    }
+
+    #[test]
+    fn aggregate_fetches_only_the_fields_it_needs() {
+        let rows = Value::Array(vec![
+            json_to_msgpack(&serde_json::json!({"region": "east", "total": 10})),
+            json_to_msgpack(&serde_json::json!({"region": "west", "total": 4})),
+            json_to_msgpack(&serde_json::json!({"region": "east", "total": 5})),
+        ]);
+        let (socket, server) = mock_server(vec![Ok(rows)]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "aggregate",
+            "orders",
+            "--sum",
+            "total",
+            "--group-by",
+            "region",
+        ]);
+
+        let result = msgpack_to_json(&run(&cli).unwrap().unwrap());
+        assert_eq!(result, serde_json::json!({"east": 15, "west": 4}));
+
+        let requests = server.join().unwrap();
+        assert_eq!(
+            params(&requests[0])[1],
+            commands::fields_param(&commands::parse_fields("total,region"))
+        );
+    }
+
+    #[test]
+    fn aggregate_requires_exactly_one_op() {
+        assert!(Cli::try_parse_from(["cortex", "aggregate", "orders"]).is_err());
+        assert!(
+            Cli::try_parse_from(["cortex", "aggregate", "orders", "--count", "--sum", "x"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn put_ttl_is_sent_only_when_given() {
+        let sent = |args: &[&str]| {
+            let (socket, server) = mock_server(vec![Ok(Value::String("ok".into()))]);
+            let mut argv = vec![
+                "--socket",
+                socket.as_str(),
+                "put",
+                "sessions",
+                r#"{"id":"s1"}"#,
+            ];
+            argv.extend(args);
+            run(&parse(&argv)).unwrap();
+            server.join().unwrap().remove(0)
+        };
+
+        assert_eq!(params(&sent(&[])).len(), 2);
+
+        let request = sent(&["--ttl", "30m"]);
+        assert_eq!(
+            msgpack_to_json(&params(&request)[2]),
+            serde_json::json!({"ttl": 1800})
+        );
+
+        let request = sent(&["--ttl", "3600"]);
+        assert_eq!(
+            msgpack_to_json(&params(&request)[2]),
+            serde_json::json!({"ttl": 3600})
+        );
+
+        let request = sent(&["--if-absent", "--ttl", "2h"]);
+        assert_eq!(request[2].as_str(), Some("cas_put"));
+        assert_eq!(params(&request)[2], Value::Nil);
+        assert_eq!(
+            msgpack_to_json(&params(&request)[3]),
+            serde_json::json!({"ttl": 7200})
+        );
+    }
+
+    #[test]
+    fn put_rejects_invalid_ttl_before_connecting() {
+        let missing = temp_socket_path();
+        for ttl in ["0s", "0", "30x", "10w", "-5m"] {
+            let ttl = format!("--ttl={}", ttl);
+            let cli = parse(&["--socket", &missing, "put", "t", "{}", &ttl]);
+            assert_eq!(run(&cli).unwrap_err().code(), "input", "{}", ttl);
+        }
+    }
+
+    #[test]
+    fn keys_prefix_is_sent_to_the_daemon() {
+        let keys = |ks: &[&str]| Value::Array(ks.iter().map(|k| Value::from(*k)).collect());
+        let (socket, server) = mock_server(vec![Ok(keys(&["session:1", "session:2"]))]);
+        let cli = parse(&["--socket", &socket, "keys", "cache", "--prefix", "session:"]);
+
+        assert_eq!(run(&cli).unwrap(), Some(keys(&["session:1", "session:2"])));
+        let requests = server.join().unwrap();
+        assert_eq!(
+            msgpack_to_json(&params(&requests[0])[1]),
+            serde_json::json!({"prefix": "session:"})
+        );
+    }
+
+    #[test]
+    fn keys_prefix_falls_back_to_filtering_the_full_list() {
+        let keys = |ks: &[&str]| Value::Array(ks.iter().map(|k| Value::from(*k)).collect());
+        let (socket, server) = mock_server(vec![
+            Err("unknown method: keys"),
+            Ok(keys(&["session:1", "user:1", "session:2", "session:3"])),
+        ]);
+        let cli = parse(&[
+            "--socket", &socket, "keys", "cache", "--prefix", "session:", "--limit", "2",
+        ]);
+
+        assert_eq!(run(&cli).unwrap(), Some(keys(&["session:1", "session:2"])));
+        let requests = server.join().unwrap();
+        assert_eq!(params(&requests[1]), &[Value::from("cache")]);
+    }
+
+    fn acl_fixture() -> Value {
+        json_to_msgpack(&serde_json::json!([
+            {"identity": "uid:1000", "table": "1000:users", "permissions": ["read", "write", "admin"], "expires_at": null},
+            {"identity": "uid:1001", "table": "1000:users", "permissions": ["read"], "expires_at": null},
+            {"identity": "uid:1001", "table": "1000:notes", "permissions": ["read"], "expires_at": null},
+        ]))
+    }
+
+    #[test]
+    fn acl_list_sends_filters() {
+        let (socket, server) = mock_server(vec![Ok(Value::Array(vec![]))]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "acl",
+            "list",
+            "--table",
+            "users",
+            "--identity",
+            "uid:1001",
+        ]);
+
+        run(&cli).unwrap();
+        let requests = server.join().unwrap();
+        assert_eq!(
+            msgpack_to_json(&params(&requests[0])[0]),
+            serde_json::json!({"table": "users", "identity": "uid:1001"})
+        );
+    }
+
+    #[test]
+    fn acl_list_filters_full_list_from_older_daemons() {
+        let (socket, _server) = mock_server(vec![Ok(acl_fixture())]);
+        let cli = parse(&["--socket", &socket, "acl", "list", "--table", "users"]);
+        let result = msgpack_to_json(&run(&cli).unwrap().unwrap());
+        let identities: Vec<_> = result
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|a| a["identity"].clone())
+            .collect();
+        assert_eq!(identities, ["uid:1000", "uid:1001"]);
+
+        let filtered = msgpack_to_json(&acl::filter_acls(acl_fixture(), None, Some("uid:1001")));
+        let tables: Vec<_> = filtered
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|a| a["table"].clone())
+            .collect();
+        assert_eq!(tables, ["1000:users", "1000:notes"]);
+    }
+
+    #[test]
+    fn diff_aligns_both_tables_on_the_primary_key() {
+        let (socket, server) = mock_server(vec![
+            Ok(users_schema()),
+            Ok(Value::Array(vec![record(&[("id", "u1"), ("name", "Ann")])])),
+            Ok(users_schema()),
+            Ok(Value::Array(vec![
+                record(&[("id", "u1"), ("name", "Ann")]),
+                record(&[("id", "u2"), ("name", "Bo")]),
+            ])),
+        ]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "--output",
+            "json",
+            "diff",
+            "users",
+            "users_copy",
+        ]);
+
+        let result = msgpack_to_json(&run(&cli).unwrap().unwrap());
+        assert_eq!(result["added"], serde_json::json!(["u2"]));
+        assert_eq!(result["unchanged"], 1);
+
+        let requests = server.join().unwrap();
+        assert_eq!(methods(&requests), ["describe", "all", "describe", "all"]);
+        assert_eq!(params(&requests[3]), &[Value::from("users_copy")]);
+    }
+
+    #[test]
+    fn migrate_add_attribute_sends_alter_table() {
+        let reply = json_to_msgpack(&serde_json::json!({"added": "role", "backfilled": 3}));
+        let (socket, server) = mock_server(vec![Ok(reply)]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "migrate",
+            "add-attribute",
+            "users",
+            "role",
+            "--default",
+            r#""member""#,
+        ]);
+        run(&cli).unwrap();
+        let request = &server.join().unwrap()[0];
+        assert_eq!(request[2].as_str(), Some("alter_table"));
+        assert_eq!(
+            params(request),
+            &[
+                Value::from("users"),
+                json_to_msgpack(&serde_json::json!({"add_attribute": "role", "default": "member"})),
+            ]
+        );
+    }
+
+    #[test]
+    fn migrate_add_attribute_defaults_to_null() {
+        let (socket, server) = mock_server(vec![Err("attribute_exists")]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "migrate",
+            "add-attribute",
+            "users",
+            "role",
+        ]);
+
+        let err = run(&cli).unwrap_err();
+        assert_eq!(err.code(), "input");
+        assert!(err.to_string().contains("already has an attribute 'role'"));
+
+        let requests = server.join().unwrap();
+        assert_eq!(
+            msgpack_to_json(&params(&requests[0])[1]),
+            serde_json::json!({"add_attribute": "role", "default": null})
+        );
+    }
+
+    #[test]
+    fn get_assert_eq_passes_on_an_equal_record() {
+        let user = record(&[("id", "u1"), ("name", "alice")]);
+        let (socket, _server) = mock_server(vec![Ok(user.clone())]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "get",
+            "users",
+            "u1",
+            "--assert-eq",
+            r#"{"name": "alice", "id": "u1"}"#,
+            "--assert-field",
+            "name=alice",
+        ]);
+
+        assert_eq!(run(&cli).unwrap(), Some(user));
+    }
+
+    #[test]
+    fn get_assert_reports_each_mismatch() {
+        let user = record(&[("id", "u1"), ("name", "alicia"), ("role", "admin")]);
+        let (socket, _server) = mock_server(vec![Ok(user)]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "get",
+            "users",
+            "u1",
+            "--assert-eq",
+            r#"{"id": "u1", "name": "alice"}"#,
+            "--assert-field",
+            "active=true",
+        ]);
+
+        let err = run(&cli).unwrap_err();
+        assert_eq!(err.exit_code(), error::EXIT_CONFLICT);
+        assert_eq!(
+            err.to_string(),
+            concat!(
+                "assertion failed for record 'u1':\n",
+                "  name: expected \"alice\", got \"alicia\"\n",
+                "  role: expected null, got \"admin\"\n",
+                "  active: expected true, got null",
+            )
+        );
+    }
+
+    #[test]
+    fn get_assert_fails_on_a_missing_record() {
+        let (socket, _server) = mock_server(vec![Err("not_found")]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "get",
+            "users",
+            "u9",
+            "--assert-field",
+            "name=alice",
+        ]);
+
+        let err = run(&cli).unwrap_err();
+        assert_eq!(err.exit_code(), error::EXIT_CONFLICT);
+        assert_eq!(
+            err.to_string(),
+            "assertion failed: no record 'u9' in 'users'"
+        );
+    }
+
+    #[test]
+    fn query_since_and_until_send_a_range() {
+        let (socket, server) = mock_server(vec![Ok(Value::Array(vec![]))]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "query",
+            "memories",
+            r#"{"tags": "deploy"}"#,
+            "--since",
+            "2023-11-14T22:13:20Z",
+            "--until",
+            "1700003600.5",
+        ]);
+
+        run(&cli).unwrap();
+        let requests = server.join().unwrap();
+        assert_eq!(
+            msgpack_to_json(&requests[0][3]),
+            serde_json::json!(["memories", {"tags": "deploy"}, {"range": {
+                "timestamp": {"gte": 1_700_000_000, "lte": 1_700_003_600.5}
+            }}])
+        );
+    }
+
+    #[test]
+    fn all_since_matches_on_the_time_field() {
+        let (socket, server) = mock_server(vec![Ok(Value::Array(vec![]))]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "all",
+            "sm_instances",
+            "--since",
+            "1700000000",
+            "--time-field",
+            "updated",
+        ]);
+
+        run(&cli).unwrap();
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0][2].as_str(), Some("match"));
+        assert_eq!(
+            msgpack_to_json(&requests[0][3]),
+            serde_json::json!(["sm_instances", {}, {"range": {"updated": {"gte": 1_700_000_000}}}])
+        );
+    }
+
+    #[test]
+    fn unparseable_since_is_an_input_error() {
+        let cli = parse(&["all", "memories", "--since", "last tuesday"]);
+        let err = run(&cli).unwrap_err();
+        assert_eq!(err.code(), "input");
+        assert_eq!(
+            err.to_string(),
+            "invalid --since 'last tuesday': expected RFC 3339 (e.g. 2024-01-15T10:30:00Z) \
+             or epoch seconds"
+        );
+
+        let cli = parse(&["all", "memories", "--since", "20", "--until", "10"]);
+        assert_eq!(
+            run(&cli).unwrap_err().to_string(),
+            "--since is later than --until"
+        );
+    }
+
+    #[test]
+    fn tables_pattern_filters_names() {
+        let names = ["sm_definitions", "users", "sm_instances"];
+        let (socket, _server) = mock_server(vec![Ok(Value::Array(
+            names.iter().map(|&n| Value::from(n)).collect(),
+        ))]);
+        let cli = parse(&["--socket", &socket, "tables", "--pattern", "sm_*"]);
+
+        assert_eq!(
+            run(&cli).unwrap(),
+            Some(Value::Array(vec![
+                Value::from("sm_definitions"),
+                Value::from("sm_instances"),
+            ]))
+        );
+    }
+
+    #[test]
+    fn create_table_if_not_exists_creates_a_new_table() {
+        let (socket, server) = mock_server(vec![Ok(Value::from("created"))]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "create-table",
+            "users",
+            "id,name,email",
+            "--if-not-exists",
+        ]);
+
+        assert_eq!(run(&cli).unwrap(), Some(Value::from("created")));
+        assert_eq!(methods(&server.join().unwrap()), ["create_table"]);
+    }
+
+    #[test]
+    fn create_table_if_not_exists_accepts_a_matching_table() {
+        let (socket, server) = mock_server(vec![Err("already_exists"), Ok(users_schema())]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "create-table",
+            "users",
+            "id,email,name",
+            "--if-not-exists",
+        ]);
+
+        assert_eq!(run(&cli).unwrap(), None);
+        let requests = server.join().unwrap();
+        assert_eq!(methods(&requests), ["create_table", "describe"]);
+        assert_eq!(params(&requests[1]), &[Value::from("users")]);
+    }
+
+    #[test]
+    fn create_table_if_not_exists_rejects_a_different_schema() {
+        let (socket, server) = mock_server(vec![Err("already_exists"), Ok(users_schema())]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "create-table",
+            "users",
+            "email,id,name",
+            "--if-not-exists",
+        ]);
+
+        let err = run(&cli).unwrap_err();
+        server.join().unwrap();
+        assert_eq!(err.code(), "conflict");
+        assert_eq!(
+            err.to_string(),
+            "table 'users' already exists with attributes id,name,email (key id), \
+             not email,id,name"
+        );
+    }
+
+    #[test]
+    fn truncate_sends_table_name() {
+        let (socket, server) = mock_server(vec![Ok(Value::String("truncated".into()))]);
+        let cli = parse(&["--socket", &socket, "truncate", "sessions", "--yes"]);
+
+        let result = run(&cli).unwrap();
+        assert_eq!(result, Some(Value::String("truncated".into())));
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0][2].as_str(), Some("truncate"));
+        assert_eq!(params(&requests[0]), &[Value::String("sessions".into())]);
+    }
+
+    #[test]
+    fn confirm_requires_yes_when_not_interactive() {
+        let mut input = io::empty();
+        assert!(confirm("Really?", false, false, &mut input).is_err());
+        assert!(confirm("Really?", true, false, &mut input).is_ok());
+    }
+
+    #[test]
+    fn confirm_accepts_only_explicit_yes() {
+        assert!(confirm("Really?", false, true, &mut "y\n".as_bytes()).is_ok());
+        assert!(confirm("Really?", false, true, &mut "\n".as_bytes()).is_err());
+        assert!(confirm("Really?", false, true, &mut "nope\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn drop_table_with_yes_skips_prompt() {
+        let (socket, server) = mock_server(vec![Ok(Value::String("dropped".into()))]);
+        let cli = parse(&["--socket", &socket, "drop-table", "old", "-y"]);
+
+        assert_eq!(run(&cli).unwrap(), Some(Value::String("dropped".into())));
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0][2].as_str(), Some("drop_table"));
+        assert_eq!(params(&requests[0]), &[Value::String("old".into())]);
+    }
+
+    #[test]
+    fn confirm_typed_refuses_without_tty() {
+        let err = confirm_typed("old", false, false, &mut io::empty()).unwrap_err();
+        assert!(err.to_string().contains("--yes"));
+    }
+
+    #[test]
+    fn confirm_typed_requires_exact_name() {
+        assert!(confirm_typed("old", false, true, &mut "old\n".as_bytes()).is_ok());
+        assert!(confirm_typed("old", false, true, &mut "y\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn acl_check_reports_resolved_permissions() {
+        // Daemon resolves uid:1001's write grant together with the world read grant
+        let union = Value::Array(vec![
+            Value::String("read".into()),
+            Value::String("write".into()),
+        ]);
+        let (socket, server) = mock_server(vec![Ok(union.clone())]);
+        let cli = parse(&["--socket", &socket, "acl", "check", "uid:1001", "users"]);
+
+        assert_eq!(run(&cli).unwrap(), Some(union));
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0][2].as_str(), Some("acl_check"));
+        assert_eq!(
+            params(&requests[0]),
+            &[
+                Value::String("uid:1001".into()),
+                Value::String("users".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn get_sends_integer_key_for_int_keyed_table() {
+        let (socket, server) = mock_server(vec![Ok(record(&[("name", "Ada")]))]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "get",
+            "users",
+            "42",
+            "--key-type",
+            "int",
+        ]);
+
+        run(&cli).unwrap();
+
+        let requests = server.join().unwrap();
+        assert_eq!(
+            params(&requests[0]),
+            &[Value::String("users".into()), Value::from(42)]
+        );
+    }
+
+    #[test]
+    fn keys_are_strings_by_default() {
+        let (socket, server) = mock_server(vec![Ok(Value::String("ok".into()))]);
+        let cli = parse(&["--socket", &socket, "delete", "users", "42"]);
+
+        run(&cli).unwrap();
+
+        let requests = server.join().unwrap();
+        assert_eq!(params(&requests[0])[1], Value::String("42".into()));
+    }
+
+    fn keys_file(keys: &str) -> PathBuf {
+        let path = PathBuf::from(temp_socket_path() + ".keys");
+        std::fs::write(&path, keys).unwrap();
+        path
+    }
+
+    #[test]
+    fn get_keys_file_reports_each_key_in_order() {
+        let path = keys_file("u2\nu9\n\nu1\n");
+        let (socket, server) = mock_server(vec![
+            Ok(record(&[("id", "u2"), ("name", "bob")])),
+            Err("not_found"),
+            Ok(record(&[("id", "u1"), ("name", "alice")])),
+        ]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "get",
+            "users",
+            "--keys-file",
+            path.to_str().unwrap(),
+            "--fields",
+            "name",
+        ]);
+
+        let result = run(&cli).unwrap().unwrap();
+        assert_eq!(
+            msgpack_to_json(&result),
+            serde_json::json!([
+                {"key": "u2", "ok": {"name": "bob"}},
+                {"key": "u9", "error": "not_found", "code": "daemon"},
+                {"key": "u1", "ok": {"name": "alice"}},
+            ])
+        );
+
+        let requests = server.join().unwrap();
+        assert_eq!(methods(&requests), ["get", "get", "get"]);
+        let keys: Vec<_> = requests.iter().map(|r| params(r)[1].clone()).collect();
+        assert_eq!(
+            keys,
+            [Value::from("u2"), Value::from("u9"), Value::from("u1")]
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn delete_keys_file_needs_confirmation() {
+        let path = keys_file("3\n1\n2\n");
+        let delete = |socket: &str, yes: &[&str]| {
+            let mut args = vec!["--socket", socket, "delete", "counters"];
+            args.extend(["--keys-file", path.to_str().unwrap(), "--key-type", "int"]);
+            args.extend(yes);
+            run(&parse(&args))
+        };
+
+        // Not a terminal, so no prompt: nothing is sent without --yes
+        let err = delete("/nonexistent/cortex.sock", &[]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "refusing destructive operation without --yes"
+        );
+
+        let ok = || Ok(Value::from("ok"));
+        let (socket, server) = mock_server(vec![ok(), ok(), ok()]);
+        let result = delete(&socket, &["--yes"]).unwrap().unwrap();
+        assert_eq!(
+            msgpack_to_json(&result),
+            serde_json::json!([
+                {"key": 3, "ok": "ok"},
+                {"key": 1, "ok": "ok"},
+                {"key": 2, "ok": "ok"},
+            ])
+        );
+        assert_eq!(
+            methods(&server.join().unwrap()),
+            ["delete", "delete", "delete"]
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn keys_file_conflicts_with_a_key() {
+        assert!(Cli::try_parse_from(["cortex", "get", "t", "k", "--keys-file", "-"]).is_err());
+        assert!(Cli::try_parse_from([
+            "cortex",
+            "delete",
+            "t",
+            "--keys-file",
+            "-",
+            "--pattern",
+            "{}"
+        ])
+        .is_err());
+        assert!(Cli::try_parse_from(["cortex", "delete", "t", "k", "--yes"]).is_err());
+    }
+
+    #[test]
+    fn delete_pattern_sends_delete_match() {
+        let (socket, server) = mock_server(vec![Ok(Value::from(3))]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "delete",
+            "sessions",
+            "--pattern",
+            r#"{"status":"expired"}"#,
+            "--yes",
+        ]);
+
+        assert_eq!(run(&cli).unwrap(), Some(Value::from(3)));
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0][2].as_str(), Some("delete_match"));
+        assert_eq!(
+            params(&requests[0]),
+            &[
+                Value::String("sessions".into()),
+                record(&[("status", "expired")])
+            ]
+        );
+    }
+
+    #[test]
+    fn delete_key_and_pattern_build_different_requests() {
+        let (method, _) = records::delete_request(
+            "t",
+            Some("k"),
+            KeyType::String,
+            None,
+            false,
+            false,
+            &mut io::empty(),
+        )
+        .unwrap();
+        assert_eq!(method, "delete");
+
+        let (method, _) = records::delete_request(
+            "t",
+            None,
+            KeyType::String,
+            Some("{}"),
+            true,
+            false,
+            &mut io::empty(),
+        )
+        .unwrap();
+        assert_eq!(method, "delete_match");
+    }
+
+    #[test]
+    fn delete_pattern_requires_confirmation() {
+        let refused = records::delete_request(
+            "t",
+            None,
+            KeyType::String,
+            Some("{}"),
+            false,
+            false,
+            &mut io::empty(),
+        );
+        assert_eq!(refused.unwrap_err().code(), "input");
+
+        let answered = records::delete_request(
+            "t",
+            None,
+            KeyType::String,
+            Some("{}"),
+            false,
+            true,
+            &mut "y\n".as_bytes(),
+        );
+        assert!(answered.is_ok());
+    }
+
+    #[test]
+    fn delete_key_conflicts_with_pattern() {
+        assert!(Cli::try_parse_from(["cortex", "delete", "t", "k", "--pattern", "{}"]).is_err());
+        assert!(Cli::try_parse_from(["cortex", "delete", "t"]).is_err());
+    }
+
+    #[test]
+    fn put_if_absent_sends_nil_condition() {
+        let (socket, server) = mock_server(vec![Ok(Value::String("ok".into()))]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "put",
+            "locks",
+            r#"{"id":"a"}"#,
+            "--if-absent",
+        ]);
+
+        run(&cli).unwrap();
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0][2].as_str(), Some("cas_put"));
+        assert_eq!(
+            params(&requests[0]),
+            &[
+                Value::String("locks".into()),
+                record(&[("id", "a")]),
+                Value::Nil
+            ]
+        );
+    }
+
+    #[test]
+    fn put_if_match_sends_expected_record() {
+        let (socket, server) = mock_server(vec![Ok(Value::String("ok".into()))]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "put",
+            "tasks",
+            r#"{"id":"t1","state":"running"}"#,
+            "--if-match",
+            r#"{"id":"t1","state":"pending"}"#,
+        ]);
+
+        run(&cli).unwrap();
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0][2].as_str(), Some("cas_put"));
+        assert_eq!(
+            params(&requests[0])[2],
+            record(&[("id", "t1"), ("state", "pending")])
+        );
+    }
+
+    #[test]
+    fn failed_condition_exits_with_conflict() {
+        let (socket, server) = mock_server(vec![Err("condition_failed")]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "put",
+            "locks",
+            r#"{"id":"a"}"#,
+            "--if-absent",
+        ]);
+
+        let err = run(&cli).unwrap_err();
+        server.join().unwrap();
+
+        assert_eq!(err.exit_code(), error::EXIT_CONFLICT);
+        assert!(err.to_string().contains("already exists"));
+    }
+
+    #[test]
+    fn append_to_existing_array() {
+        let (socket, server) = mock_server(vec![Ok(Value::from(3))]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "append",
+            "memories",
+            "m1",
+            "tags",
+            r#""urgent""#,
+        ]);
+
+        assert_eq!(run(&cli).unwrap(), Some(Value::from(3)));
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0][2].as_str(), Some("append"));
+        assert_eq!(
+            params(&requests[0]),
+            &[
+                Value::from("memories"),
+                Value::from("m1"),
+                Value::from("tags"),
+                Value::from("urgent"),
+            ]
+        );
+    }
+
+    #[test]
+    fn append_creating_array_sends_json_value() {
+        // The daemon starts a new array when the field is absent
+        let (socket, server) = mock_server(vec![Ok(Value::from(1))]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "append",
+            "counters",
+            "42",
+            "samples",
+            r#"{"at":1}"#,
+            "--key-type",
+            "int",
+        ]);
+
+        assert_eq!(run(&cli).unwrap(), Some(Value::from(1)));
+
+        let requests = server.join().unwrap();
+        assert_eq!(
+            params(&requests[0])[1..],
+            [
+                Value::from(42),
+                Value::from("samples"),
+                json_to_msgpack(&serde_json::json!({"at": 1})),
+            ]
+        );
+    }
+
+    #[test]
+    fn append_to_non_array_is_a_conflict() {
+        let (socket, server) = mock_server(vec![Err("not_an_array")]);
+        let cli = parse(&[
+            "--socket", &socket, "append", "users", "u1", "name", r#""x""#,
+        ]);
+
+        let err = run(&cli).unwrap_err();
+        server.join().unwrap();
+
+        assert_eq!(err.exit_code(), error::EXIT_CONFLICT);
+        assert_eq!(
+            err.to_string(),
+            Error::Conflict("field 'name' of record 'u1' is not an array".into()).to_string()
+        );
+    }
+
+    #[test]
+    fn expire_sets_or_clears_the_ttl() {
+        let (socket, server) = mock_server_per_request(vec![
+            Ok(Value::from("ok")),
+            Ok(Value::from("ok")),
+            Err("not_found"),
+        ]);
+        run(&parse(&[
+            "--socket", &socket, "expire", "sessions", "s1", "1h",
+        ]))
+        .unwrap();
+        run(&parse(&[
+            "--socket",
+            &socket,
+            "expire",
+            "counters",
+            "42",
+            "--persist",
+            "--key-type",
+            "int",
+        ]))
+        .unwrap();
+        let err = run(&parse(&[
+            "--socket", &socket, "expire", "sessions", "gone", "60",
+        ]))
+        .unwrap_err();
+        assert_eq!(err.reason(), Some("not_found"));
+
+        let requests = server.join().unwrap();
+        assert_eq!(methods(&requests), ["expire", "expire", "expire"]);
+        assert_eq!(
+            params(&requests[0]),
+            &[
+                Value::from("sessions"),
+                Value::from("s1"),
+                Value::from(3600)
+            ]
+        );
+        assert_eq!(
+            params(&requests[1]),
+            &[Value::from("counters"), Value::from(42), Value::Nil]
+        );
+
+        assert!(Cli::try_parse_from(["cortex", "expire", "sessions", "s1"]).is_err());
+        assert!(
+            Cli::try_parse_from(["cortex", "expire", "sessions", "s1", "60", "--persist"]).is_err()
+        );
+    }
+
+    #[test]
+    fn get_with_ttl_adds_the_time_left() {
+        let (socket, server) = mock_server_per_request(vec![
+            Ok(record(&[("id", "s1")])),
+            Ok(Value::from(1794)),
+            Ok(record(&[("id", "s2")])),
+            Ok(Value::Nil),
+        ]);
+        let get = |key: &str| {
+            let cli = parse(&["--socket", &socket, "get", "sessions", key, "--with-ttl"]);
+            msgpack_to_json(&run(&cli).unwrap().unwrap())
+        };
+
+        assert_eq!(
+            get("s1"),
+            serde_json::json!({"record": {"id": "s1"}, "ttl": 1794})
+        );
+        assert_eq!(
+            get("s2"),
+            serde_json::json!({"record": {"id": "s2"}, "ttl": null})
+        );
+
+        let requests = server.join().unwrap();
+        assert_eq!(methods(&requests), ["get", "ttl", "get", "ttl"]);
+        assert_eq!(
+            params(&requests[1]),
+            &[Value::from("sessions"), Value::from("s1")]
+        );
+    }
+
+    #[test]
+    fn completion_offers_the_daemons_tables() {
+        let tables = Value::Array(vec![
+            Value::from("users"),
+            Value::from("user_sessions"),
+            Value::from("events"),
+        ]);
+        let (socket, server) = mock_server(vec![Ok(tables)]);
+        assert_eq!(
+            completions::table_names(&socket, "user"),
+            ["users", "user_sessions"]
+        );
+        assert_eq!(methods(&server.join().unwrap()), ["tables"]);
+
+        // No daemon, no suggestions
+        assert!(completions::table_names(&temp_socket_path(), "").is_empty());
+    }
+
+    #[test]
+    fn completion_knows_which_arguments_are_tables() {
+        use clap_complete::engine::ArgValueCompleter;
+
+        let command = completions::command();
+        let completes_tables = |path: &[&str], arg: &str| {
+            let sub = path.iter().fold(&command, |cmd, name| {
+                cmd.find_subcommand(name).expect("subcommand")
+            });
+            let arg = sub.get_arguments().find(|a| a.get_id() == arg).unwrap();
+            arg.get::<ArgValueCompleter>().is_some()
+        };
+
+        assert!(completes_tables(&["get"], "table"));
+        assert!(completes_tables(&["drop-table"], "name"));
+        assert!(completes_tables(&["copy-table"], "src"));
+        assert!(completes_tables(&["diff"], "right"));
+        assert!(completes_tables(&["acl", "grant"], "table"));
+        assert!(!completes_tables(&["create-table"], "name"));
+        assert!(!completes_tables(&["get"], "key"));
+
+        let mut script = Vec::new();
+        completions::write_script(completions::Shell::Zsh, &mut script).unwrap();
+        let script = String::from_utf8(script).unwrap();
+        assert!(script.contains("CORTEX_COMPLETE"), "{}", script);
+    }
+
+    /// Output sink whose reader has gone away.
+    struct ClosedPipe;
+
+    impl Write for ClosedPipe {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn exists_answers_with_the_exit_status() {
+        let (socket, server) = mock_server_per_request(vec![
+            Ok(Value::Map(vec![])),
+            Err("not_found"),
+            Err("access_denied"),
+            Err("not_found"),
+        ]);
+        let exists = |key: &str, print: bool| {
+            let mut argv = vec!["--socket", socket.as_str(), "exists", "users", key];
+            if print {
+                argv.push("--print");
+            }
+            let cli = parse(&argv);
+            let (result, status) = exists_answer(cli.command.as_ref(), run(&cli));
+            let mut out = Vec::new();
+            let status = match finish(&cli, result, false, &mut out, &mut io::sink()) {
+                0 => status,
+                failed => failed,
+            };
+            (status, String::from_utf8(out).unwrap())
+        };
+
+        assert_eq!(exists("u1", false), (0, String::new()));
+        assert_eq!(exists("u2", true), (1, "false\n".to_string()));
+        assert_eq!(exists("u3", false), (error::EXIT_DAEMON, String::new()));
+
+        // An answer --print can't write doesn't pass for "absent"
+        let cli = parse(&["--socket", &socket, "exists", "users", "u4", "--print"]);
+        let (result, status) = exists_answer(cli.command.as_ref(), run(&cli));
+        assert_eq!(status, 1);
+        let failed = finish(&cli, result, false, &mut ClosedPipe, &mut io::sink());
+        assert_eq!(failed, error::EXIT_OUTPUT);
+
+        let requests = server.join().unwrap();
+        assert_eq!(methods(&requests), ["get", "get", "get", "get"]);
+        // The record is projected onto no fields
+        assert_eq!(
+            params(&requests[0]),
+            &[
+                Value::from("users"),
+                Value::from("u1"),
+                Value::Array(vec![])
+            ]
+        );
+    }
+
+    #[test]
+    fn count_asks_the_daemon() {
+        let (socket, server) =
+            mock_server_per_request(vec![Ok(Value::from(1200)), Ok(Value::from(3))]);
+        let count = |args: &[&str]| {
+            let mut argv = vec!["--socket", socket.as_str(), "count", "events"];
+            argv.extend(args);
+            run(&parse(&argv)).unwrap()
+        };
+
+        assert_eq!(count(&[]), Some(Value::from(1200)));
+        assert_eq!(
+            count(&[r#"{"kind":"login","at":{"$gte":100}}"#]),
+            Some(Value::from(3))
+        );
+
+        let requests = server.join().unwrap();
+        assert_eq!(methods(&requests), ["count", "count"]);
+        assert_eq!(
+            msgpack_to_json(&requests[0][3]),
+            serde_json::json!(["events", {}])
+        );
+        assert_eq!(
+            msgpack_to_json(&requests[1][3]),
+            serde_json::json!(["events", {"kind": "login"}, {"where": {"at": {"gte": 100}}}])
+        );
+    }
+
+    #[test]
+    fn count_fetches_and_counts_what_the_daemon_cannot() {
+        let nyc = serde_json::json!({"id": "u1", "address": {"city": "NYC"}});
+        let la = serde_json::json!({"id": "u2", "address": {"city": "LA"}});
+        let (socket, server) = mock_server_per_request(vec![
+            Ok(json_to_msgpack(&serde_json::json!([nyc, la]))),
+            Err("unknown method: count"),
+            Ok(json_to_msgpack(&serde_json::json!([nyc, la]))),
+        ]);
+        let count = |pattern: &str| {
+            let cli = parse(&["--socket", &socket, "count", "users", pattern]);
+            run(&cli).unwrap()
+        };
+
+        // Nested fields never go to count
+        assert_eq!(count(r#"{"address":{"city":"NYC"}}"#), Some(Value::from(1)));
+        // Nor does anything, to a daemon without it
+        assert_eq!(count("{}"), Some(Value::from(2)));
+
+        let requests = server.join().unwrap();
+        assert_eq!(methods(&requests), ["match", "count", "match"]);
+    }
+
+    #[test]
+    fn incr_and_decr_send_signed_amounts() {
+        let (socket, server) = mock_server_per_request(vec![
+            Ok(Value::from(1)),
+            Ok(Value::from(-4)),
+            Ok(Value::from(2.5)),
+            Err("not_a_number"),
+        ]);
+        let run_args = |args: &[&str]| {
+            let mut argv = vec!["--socket", socket.as_str()];
+            argv.extend(args);
+            run(&parse(&argv))
+        };
+
+        assert_eq!(
+            run_args(&["incr", "jobs", "j1", "attempts"]).unwrap(),
+            Some(Value::from(1))
+        );
+        run_args(&["decr", "quotas", "7", "left", "5", "--key-type", "int"]).unwrap();
+        run_args(&["incr", "stats", "s1", "load", "-0.5"]).unwrap();
+        let err = run_args(&["incr", "users", "u1", "name"]).unwrap_err();
+        assert_eq!(err.exit_code(), error::EXIT_CONFLICT);
+        assert_eq!(
+            err.to_string(),
+            Error::Conflict("field 'name' of record 'u1' is not a number".into()).to_string()
+        );
+
+        let requests = server.join().unwrap();
+        assert_eq!(methods(&requests), ["incr", "incr", "incr", "incr"]);
+        assert_eq!(
+            params(&requests[0]),
+            &[
+                Value::from("jobs"),
+                Value::from("j1"),
+                Value::from("attempts"),
+                Value::from(1),
+            ]
+        );
+        assert_eq!(params(&requests[1])[1], Value::from(7));
+        assert_eq!(params(&requests[1])[3], Value::from(-5));
+        assert_eq!(params(&requests[2])[3], Value::from(-0.5));
+    }
+
+    #[test]
+    fn incr_rejects_non_numeric_amounts_before_connecting() {
+        let missing = temp_socket_path();
+        for amount in ["two", "1e999", "\"1\""] {
+            let cli = parse(&["--socket", &missing, "incr", "t", "k", "n", amount]);
+            assert_eq!(run(&cli).unwrap_err().code(), "input", "{}", amount);
+        }
+    }
+
+    #[test]
+    fn plain_put_is_unconditional() {
+        let (socket, server) = mock_server(vec![Ok(Value::String("ok".into()))]);
+        run(&parse(&[
+            "--socket",
+            &socket,
+            "put",
+            "locks",
+            r#"{"id":"a"}"#,
+        ]))
+        .unwrap();
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0][2].as_str(), Some("put"));
+        assert_eq!(params(&requests[0]).len(), 2);
+    }
+
+    #[test]
+    fn parse_key_converts_each_type() {
+        assert_eq!(parse_key("-7", KeyType::Int).unwrap(), Value::from(-7));
+        assert_eq!(
+            parse_key("18446744073709551615", KeyType::Int).unwrap(),
+            Value::from(u64::MAX)
+        );
+        assert_eq!(parse_key("1.5", KeyType::Float).unwrap(), Value::F64(1.5));
+        assert_eq!(
+            parse_key("true", KeyType::Bool).unwrap(),
+            Value::Boolean(true)
+        );
+        assert!(parse_key("abc", KeyType::Int).is_err());
+        assert!(parse_key("NaN", KeyType::Float).is_err());
+        assert!(parse_key("yes", KeyType::Bool).is_err());
+    }
+
+    #[test]
+    fn validate_identity_accepts_known_forms() {
+        assert!(acl::validate_identity("uid:1000").is_ok());
+        assert!(acl::validate_identity("gid:1001").is_ok());
+        assert!(acl::validate_identity("*").is_ok());
+    }
+
+    #[test]
+    fn validate_identity_rejects_malformed() {
+        assert!(acl::validate_identity("group:foo").is_err());
+        assert!(acl::validate_identity("gid:").is_err());
+        assert!(acl::validate_identity("uid:12a").is_err());
+        assert!(acl::validate_identity("1000").is_err());
+    }
+
+    #[test]
+    fn validate_name_accepts_plain_names() {
+        assert!(validate_name("table", "users").is_ok());
+        assert!(validate_name("attribute", "user_id").is_ok());
+    }
+
+    #[test]
+    fn validate_name_rejects_malformed() {
+        for name in ["", "1000:users", "my table", "tab\tname"] {
+            let err = validate_name("table", name).unwrap_err();
+            assert_eq!(err.code(), "input", "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn invalid_names_are_rejected_before_connecting() {
+        let missing = temp_socket_path();
+        for args in [
+            vec!["create-table", "1000:users", "id,name"],
+            vec!["create-table", "users", "id,,name"],
+            vec!["create-table", "users", "id,full name"],
+            vec!["get", "", "u1"],
+            vec!["copy-table", "users", "1000:users"],
+        ] {
+            let mut argv = vec!["--socket", missing.as_str()];
+            argv.extend(args);
+            let err = run(&parse(&argv)).unwrap_err();
+            assert_eq!(err.code(), "input", "{:?}", argv);
+        }
+    }
+
+    #[test]
+    fn wait_ready_succeeds_once_the_daemon_starts() {
+        let path = temp_socket_path();
+        let server_path = path.clone();
+        let server = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(300));
+            let listener = UnixListener::bind(&server_path).unwrap();
+            let (mut stream, _) = listener.accept().unwrap();
+            let request = rmpv::decode::read_value(&mut stream).unwrap();
+            let response = Value::Array(vec![
+                Value::from(1),
+                request[1].clone(),
+                Value::Nil,
+                Value::from("pong"),
+            ]);
+            rmpv::encode::write_value(&mut stream, &response).unwrap();
+            std::fs::remove_file(server_path).ok();
+            request
+        });
+
+        let started = Instant::now();
+        let cli = parse(&["--socket", &path, "--timeout", "5s", "wait-ready"]);
+        assert_eq!(run(&cli), Ok(None));
+        assert!(started.elapsed() >= Duration::from_millis(300));
+        assert_eq!(server.join().unwrap()[2].as_str(), Some("ping"));
+    }
+
+    #[test]
+    fn wait_ready_times_out() {
+        let started = Instant::now();
+        let err = server::wait_ready(&temp_socket_path(), Duration::from_millis(350)).unwrap_err();
+
+        assert_eq!(err.exit_code(), error::EXIT_TIMEOUT);
+        assert!(err.to_string().contains("not ready after"));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn parse_duration_accepts_units() {
+        assert_eq!(parse_duration("90s"), Ok(90));
+        assert_eq!(parse_duration("30m"), Ok(30 * 60));
+        assert_eq!(parse_duration("2h"), Ok(2 * 60 * 60));
+        assert_eq!(parse_duration("1d"), Ok(24 * 60 * 60));
+    }
+
+    #[test]
+    fn parse_seconds_takes_bare_seconds_or_a_duration() {
+        assert_eq!(parse_seconds("30"), Ok(30));
+        assert_eq!(parse_seconds(" 30 "), Ok(30));
+        assert_eq!(parse_seconds("5m"), Ok(5 * 60));
+        for input in ["", "0", "-30", "30x"] {
+            assert!(parse_seconds(input).is_err(), "accepted {:?}", input);
+        }
+    }
+
+    #[test]
+    fn parse_duration_rejects_invalid() {
+        for input in [
+            "",
+            "h",
+            "30",
+            "30x",
+            "-1h",
+            "0m",
+            "1.5h",
+            "5µ",
+            "99999999999999999999d",
+        ] {
+            assert!(parse_duration(input).is_err(), "accepted {:?}", input);
+        }
+    }
+
+    #[test]
+    fn acl_grant_sends_expiry_only_when_requested() {
+        let ok = || Ok(Value::String("granted".into()));
+        let (socket, server) = mock_server(vec![ok()]);
+        run(&parse(&[
+            "--socket", &socket, "acl", "grant", "*", "t", "read",
+        ]))
+        .unwrap();
+        assert_eq!(params(&server.join().unwrap()[0]).len(), 3);
+
+        let (socket, server) = mock_server(vec![ok()]);
+        let before = unix_now();
+        run(&parse(&[
+            "--socket",
+            &socket,
+            "acl",
+            "grant",
+            "*",
+            "t",
+            "read",
+            "--expires",
+            "2h",
+        ]))
+        .unwrap();
+        let expires_at = params(&server.join().unwrap()[0])[3].as_u64().unwrap();
+        assert!(expires_at >= before + 7200 && expires_at <= unix_now() + 7200);
+    }
+
+    fn users_schema() -> Value {
+        Value::Map(vec![
+            (Value::String("table".into()), Value::String("users".into())),
+            (Value::String("key".into()), Value::String("id".into())),
+            (
+                Value::String("attributes".into()),
+                Value::Array(vec![
+                    Value::String("id".into()),
+                    Value::String("name".into()),
+                    Value::String("email".into()),
+                ]),
+            ),
+        ])
+    }
+
+    #[test]
+    fn describe_returns_schema_as_json() {
+        let (socket, server) = mock_server(vec![Ok(users_schema())]);
+        let cli = parse(&["--socket", &socket, "--output", "json", "describe", "users"]);
+
+        assert_eq!(run(&cli).unwrap(), Some(users_schema()));
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0][2].as_str(), Some("describe"));
+        assert_eq!(params(&requests[0]), &[Value::String("users".into())]);
+    }
+
+    #[test]
+    fn version_reports_daemon_version() {
+        let status = Value::Map(vec![(
+            Value::String("version".into()),
+            Value::String(VERSION.into()),
+        )]);
+        let (socket, server) = mock_server(vec![Ok(status)]);
+        let cli = parse(&["--socket", &socket, "--output", "json", "version"]);
+
+        let report = msgpack_to_json(&run(&cli).unwrap().unwrap());
+        assert_eq!(
+            report,
+            serde_json::json!({"cli": VERSION, "daemon": VERSION, "compatible": true})
+        );
+        assert_eq!(methods(&server.join().unwrap()), ["status"]);
+    }
+
+    #[test]
+    fn version_without_daemon_still_succeeds() {
+        let missing = temp_socket_path();
+        let cli = parse(&["--socket", &missing, "--output", "json", "version"]);
+
+        let report = msgpack_to_json(&run(&cli).unwrap().unwrap());
+        assert_eq!(
+            report,
+            serde_json::json!({"cli": VERSION, "daemon": null, "compatible": null})
+        );
+        assert_eq!(
+            server::render_version(&report),
+            format!(
+                "cli:        {}\ndaemon:     unreachable\ncompatible: unknown\n",
+                VERSION
+            )
+        );
+    }
+
+    #[test]
+    fn versions_compatible_follows_semver() {
+        assert!(server::versions_compatible("1.2.0", "1.4.3"));
+        assert!(!server::versions_compatible("1.2.0", "2.0.0"));
+        assert!(server::versions_compatible("0.1.0-beta", "0.1.3"));
+        assert!(!server::versions_compatible("0.1.0", "0.2.0"));
+        assert!(!server::versions_compatible("0.1.0", "dev"));
+    }
+
+    #[test]
+    fn whoami_prints_the_uid_the_daemon_reports() {
+        let whoami = json_to_msgpack(&serde_json::json!({"uid": 1001, "identity": "uid:1001"}));
+        let (socket, server) = mock_server(vec![Ok(whoami.clone())]);
+        let cli = parse(&["--socket", &socket, "--output", "json", "whoami"]);
+
+        assert_eq!(run(&cli).unwrap(), Some(whoami.clone()));
+        assert_eq!(methods(&server.join().unwrap()), ["whoami"]);
+
+        let (socket, _server) = mock_server(vec![Ok(whoami)]);
+        let cli = parse(&["--socket", &socket, "whoami"]);
+        let mut out = Vec::new();
+        finish(&cli, run(&cli), false, &mut out, &mut Vec::new());
+        assert_eq!(String::from_utf8(out).unwrap(), "1001\n");
+    }
+
+    #[test]
+    fn render_status_aligns_fields_and_formats_uptime() {
+        let status = serde_json::json!({
+            "version": "0.3.1",
+            "status": "running",
+            "node": "cortex@localhost",
+            "tables": 7,
+            "uptime_seconds": 273_900,
+            "mnesia": "yes",
+        });
+        assert_eq!(
+            server::render_status(&status),
+            concat!(
+                "status:  running\n",
+                "version: 0.3.1\n",
+                "uptime:  3d 4h 5m\n",
+                "mnesia:  yes\n",
+                "node:    cortex@localhost\n",
+                "tables:  7\n",
+            )
+        );
+    }
+
+    fn status_reply(mnesia: &str, writable: Option<bool>) -> Value {
+        let mut status = serde_json::json!({"status": "running", "mnesia": mnesia});
+        if let Some(writable) = writable {
+            status["data_dir_writable"] = writable.into();
+        }
+        json_to_msgpack(&status)
+    }
+
+    #[test]
+    fn health_reports_healthy() {
+        let (socket, server) = mock_server(vec![
+            Ok(Value::from("pong")),
+            Ok(status_reply("yes", Some(true))),
+        ]);
+        let report = server::health_report(Ok(&mut Connection::new(&socket).unwrap()));
+
+        assert_eq!(methods(&server.join().unwrap()), ["ping", "status"]);
+        assert_eq!(
+            report,
+            serde_json::json!({"status": "healthy", "checks": [
+                {"name": "connectivity", "status": "ok"},
+                {"name": "mnesia", "status": "ok"},
+                {"name": "disk", "status": "ok"},
+            ]})
+        );
+        assert_eq!(server::health_failure(&report), None);
+    }
+
+    #[test]
+    fn health_reports_degraded_when_mnesia_is_down() {
+        let (socket, _server) =
+            mock_server(vec![Ok(Value::from("pong")), Ok(status_reply("no", None))]);
+        let report = server::health_report(Ok(&mut Connection::new(&socket).unwrap()));
+
+        assert_eq!(report["status"], "degraded");
+        assert_eq!(
+            report["checks"][1],
+            serde_json::json!({"name": "mnesia", "status": "fail",
+                               "detail": "mnesia is not running (no)"})
+        );
+        assert_eq!(report["checks"][2]["status"], "unknown");
+        let failure = server::health_failure(&report).unwrap();
+        assert_eq!(failure.exit_code(), error::EXIT_DAEMON);
+        assert_eq!(
+            failure.to_string(),
+            "daemon is degraded (mnesia: mnesia is not running (no))"
+        );
+    }
+
+    #[test]
+    fn health_reports_down_without_a_daemon() {
+        let cli = parse(&["--socket", "/nonexistent/cortex.sock", "--quiet", "health"]);
+        let err = run(&cli).unwrap_err();
+        assert_eq!(err.exit_code(), error::EXIT_CONNECTION);
+        assert!(err
+            .to_string()
+            .starts_with("daemon is down (connectivity: cannot connect"));
+
+        let report = server::health_report(Err(Error::Connection("refused".to_string())));
+        assert_eq!(
+            report,
+            serde_json::json!({"status": "down", "checks": [
+                {"name": "connectivity", "status": "fail", "detail": "refused"},
+            ]})
+        );
+    }
+
+    #[test]
+    fn render_status_flags_stopped_database() {
+        let status = serde_json::json!({ "status": "running", "mnesia": "stopping" });
+        assert!(server::render_status(&status).contains("mnesia: stopping (UNHEALTHY)\n"));
+    }
+
+    #[test]
+    fn format_uptime_drops_leading_zero_units() {
+        assert_eq!(server::format_uptime(42), "42s");
+        assert_eq!(server::format_uptime(600), "10m");
+        assert_eq!(server::format_uptime(3_660), "1h 1m");
+        assert_eq!(server::format_uptime(86_400), "1d 0h 0m");
+    }
+
+    #[test]
+    fn render_describe_marks_primary_key() {
+        assert_eq!(
+            tables::render_describe(&msgpack_to_json(&users_schema())),
+            "table: users\nkey:   id\nattributes:\n  id (primary key)\n  name\n  email\n"
+        );
+    }
+
+    #[test]
+    fn ping_latency_times_each_request() {
+        let pong = || Ok(Value::String("pong".into()));
+        let (socket, server) = mock_server(vec![pong(), pong(), pong()]);
+
+        let times = server::ping_latency(&mut Connection::new(&socket).unwrap(), 3).unwrap();
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert!(requests.iter().all(|r| r[2].as_str() == Some("ping")));
+        assert_eq!(times.len(), 3);
+        assert!(times.iter().all(|t| *t < Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn render_latency_reports_min_avg_max() {
+        let times = [
+            Duration::from_micros(1_000),
+            Duration::from_micros(2_500),
+            Duration::from_micros(3_000),
+        ];
+        assert_eq!(
+            server::render_latency(&times),
+            "3 pings: min/avg/max = 1.000/2.167/3.000 ms"
+        );
+    }
+
+    #[test]
+    fn ping_count_requires_latency() {
+        assert!(Cli::try_parse_from(["cortex", "ping", "--count", "3"]).is_err());
+        assert!(Cli::try_parse_from(["cortex", "ping", "--latency", "--count", "0"]).is_err());
+    }
+
+    #[test]
+    fn connect_only_ping_sends_nothing() {
+        let path = temp_socket_path();
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut sent = Vec::new();
+            stream.read_to_end(&mut sent).unwrap();
+            sent
+        });
+
+        let result = run(&parse(&["--socket", &path, "ping", "--connect-only"])).unwrap();
+        assert_eq!(result, Some(Value::from("connected")));
+        assert!(server.join().unwrap().is_empty());
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn connect_only_ping_reports_missing_and_refusing_sockets() {
+        let missing = temp_socket_path();
+        let err = run(&parse(&["--socket", &missing, "ping", "--connect-only"])).unwrap_err();
+        assert_eq!(err.exit_code(), error::EXIT_CONNECTION);
+        assert_eq!(err.to_string(), format!("no socket at {}", missing));
+
+        // A socket file with nobody listening refuses connections
+        let stale = temp_socket_path();
+        drop(UnixListener::bind(&stale).unwrap());
+        let err = run(&parse(&["--socket", &stale, "ping", "--connect-only"])).unwrap_err();
+        std::fs::remove_file(&stale).ok();
+        assert_eq!(err.exit_code(), error::EXIT_CONNECTION);
+        assert_eq!(
+            err.to_string(),
+            format!("{} exists but is not accepting connections", stale)
+        );
+
+        assert!(Cli::try_parse_from(["cortex", "ping", "--connect-only", "--latency"]).is_err());
+    }
+
+    #[test]
+    fn watch_prints_each_notification_until_close() {
+        let change = |op: &str, key: &str| {
+            Value::Array(vec![
+                Value::Integer(2.into()),
+                Value::String("change".into()),
+                Value::Array(vec![Value::Map(vec![
+                    (Value::String("op".into()), Value::String(op.into())),
+                    (Value::String("key".into()), Value::String(key.into())),
+                ])]),
+            ])
+        };
+        let (socket, server) = mock_server_then(
+            vec![Ok(Value::String("subscribed".into()))],
+            vec![
+                change("write", "a"),
+                change("write", "b"),
+                change("delete", "a"),
+            ],
+        );
+
+        let mut out = Vec::new();
+        let stop = AtomicBool::new(false);
+        watch::watch(
+            &mut Connection::new(&socket).unwrap(),
+            "users",
+            &stop,
+            None,
+            &mut out,
+        )
+        .unwrap();
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0][2].as_str(), Some("subscribe"));
+        assert_eq!(params(&requests[0]), &[Value::String("users".into())]);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            concat!(
+                "{\"op\":\"write\",\"key\":\"a\"}\n",
+                "{\"op\":\"write\",\"key\":\"b\"}\n",
+                "{\"op\":\"delete\",\"key\":\"a\"}\n",
+            )
+        );
+    }
+
+    #[test]
+    fn watch_unsubscribes_when_stopped() {
+        let (socket, server) = mock_server(vec![
+            Ok(Value::from("subscribed")),
+            Ok(Value::from("unsubscribed")),
+        ]);
+
+        let mut out = Vec::new();
+        let stop = AtomicBool::new(true);
+        watch::watch(
+            &mut Connection::new(&socket).unwrap(),
+            "users",
+            &stop,
+            None,
+            &mut out,
+        )
+        .unwrap();
+
+        let requests = server.join().unwrap();
+        assert_eq!(methods(&requests), ["subscribe", "unsubscribe"]);
+        assert_eq!(params(&requests[1]), &[Value::from("users")]);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn watch_unsubscribes_at_the_deadline() {
+        let (socket, server) = mock_server(vec![
+            Ok(Value::from("subscribed")),
+            Err("unknown method: unsubscribe"),
+        ]);
+
+        let stop = AtomicBool::new(false);
+        let deadline = Instant::now() + Duration::from_millis(300);
+        watch::watch(
+            &mut Connection::new(&socket).unwrap(),
+            "users",
+            &stop,
+            Some(deadline),
+            &mut Vec::new(),
+        )
+        .unwrap();
+
+        assert!(Instant::now() >= deadline);
+        assert_eq!(
+            methods(&server.join().unwrap()),
+            ["subscribe", "unsubscribe"]
+        );
+    }
+
+    #[test]
+    fn watch_reconnect_resubscribes_after_the_daemon_goes_away() {
+        let change = |key: &str| {
+            Value::Array(vec![
+                Value::Integer(2.into()),
+                Value::from("change"),
+                Value::Array(vec![record(&[("op", "write"), ("key", key)])]),
+            ])
+        };
+        let subscribed = || vec![Ok(Value::from("subscribed"))];
+        // Each mock closes the connection once it has sent its change
+        let servers = vec![
+            mock_server_then(subscribed(), vec![change("a")]),
+            mock_server_then(subscribed(), vec![change("b")]),
+        ];
+        let sockets: Vec<String> = servers.iter().map(|(socket, _)| socket.clone()).collect();
+
+        let stop = AtomicBool::new(false);
+        let mut opened = 0;
+        let open = || {
+            opened += 1;
+            match sockets.get(opened - 1) {
+                Some(socket) => Connection::new(socket),
+                None => {
+                    stop.store(true, std::sync::atomic::Ordering::SeqCst);
+                    Err(Error::Connection("daemon is gone for good".to_string()))
+                }
+            }
+        };
+        let (mut out, mut notices) = (Vec::new(), Vec::new());
+        watch::watch_reconnecting(open, "users", &stop, None, &mut out, &mut notices).unwrap();
+
+        for (_, server) in servers {
+            assert_eq!(methods(&server.join().unwrap()), ["subscribe"]);
+        }
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            concat!(
+                "{\"op\":\"write\",\"key\":\"a\"}\n",
+                "{\"__reconnected__\":true}\n",
+                "{\"op\":\"write\",\"key\":\"b\"}\n",
+            )
+        );
+        let notices = String::from_utf8(notices).unwrap();
+        assert_eq!(notices.matches("reconnecting").count(), 2);
+        assert_eq!(notices.matches("reconnected to 'users'").count(), 1);
+    }
+
+    fn record(pairs: &[(&str, &str)]) -> Value {
+        Value::Map(
+            pairs
+                .iter()
+                .map(|(k, v)| (Value::String((*k).into()), Value::String((*v).into())))
+                .collect(),
+        )
+    }
+
+    fn sort_by(field: &str, reverse: bool) -> SortArgs {
+        SortArgs {
+            sort_by: Some(field.to_string()),
+            reverse,
+            limit: None,
+            server_sort: false,
+            client_sort: false,
+        }
+    }
+
+    fn ids(records: &Value) -> Vec<&str> {
+        records
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| field(r, "id").unwrap().as_str().unwrap())
+            .collect()
+    }
+
+    fn scored(id: &str, score: Option<Value>) -> Value {
+        let mut entries = vec![(Value::String("id".into()), Value::String(id.into()))];
+        if let Some(score) = score {
+            entries.push((Value::String("score".into()), score));
+        }
+        Value::Map(entries)
+    }
+
+    #[test]
+    fn sort_compares_numbers_numerically() {
+        let records = Value::Array(vec![
+            scored("a", Some(Value::from(10))),
+            scored("b", Some(Value::F64(2.5))),
+            scored("c", Some(Value::from(-3))),
+        ]);
+
+        assert_eq!(
+            ids(&sort_by("score", false).apply(records.clone()).unwrap()),
+            ["c", "b", "a"]
+        );
+        assert_eq!(
+            ids(&sort_by("score", true).apply(records).unwrap()),
+            ["a", "b", "c"]
+        );
+    }
+
+    #[test]
+    fn sort_compares_strings_lexically() {
+        let records = Value::Array(vec![record(&[("id", "10")]), record(&[("id", "9")])]);
+        assert_eq!(
+            ids(&sort_by("id", false).apply(records).unwrap()),
+            ["10", "9"]
+        );
+    }
+
+    #[test]
+    fn sort_puts_missing_fields_last_in_both_directions() {
+        let records = Value::Array(vec![
+            scored("none", None),
+            scored("low", Some(Value::from(1))),
+            scored("high", Some(Value::from(2))),
+        ]);
+
+        assert_eq!(
+            ids(&sort_by("score", false).apply(records.clone()).unwrap()),
+            ["low", "high", "none"]
+        );
+        assert_eq!(
+            ids(&sort_by("score", true).apply(records).unwrap()),
+            ["high", "low", "none"]
+        );
+    }
+
+    #[test]
+    fn sort_asks_the_daemon_to_order_and_limit() {
+        let sorted = vec![
+            scored("a", Some(Value::from(9))),
+            scored("b", Some(Value::from(7))),
+        ];
+        let (socket, server) = mock_server(vec![Ok(Value::Array(sorted))]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "query",
+            "scores",
+            r#"{"team":"red"}"#,
+            "--sort-by",
+            "score",
+            "--reverse",
+            "--limit",
+            "2",
+        ]);
+
+        assert_eq!(ids(&run(&cli).unwrap().unwrap()), ["a", "b"]);
+        let requests = server.join().unwrap();
+        assert_eq!(
+            msgpack_to_json(&requests[0][3]),
+            serde_json::json!([
+                "scores",
+                {"team": "red"},
+                {"order_by": "score", "order": "desc", "limit": 2}
+            ])
+        );
+
+        // all sorts the same way, as a match on everything
+        let (socket, server) = mock_server(vec![Ok(Value::Array(vec![]))]);
+        run(&parse(&[
+            "--socket",
+            &socket,
+            "all",
+            "scores",
+            "--sort-by",
+            "score",
+        ]))
+        .unwrap();
+        assert_eq!(
+            msgpack_to_json(&server.join().unwrap()[0][3]),
+            serde_json::json!(["scores", {}, {"order_by": "score", "order": "asc"}])
+        );
+    }
+
+    #[test]
+    fn sort_suffix_picks_the_direction() {
+        let (socket, server) = mock_server(vec![Ok(Value::Array(vec![]))]);
+        run(&parse(&[
+            "--socket",
+            &socket,
+            "all",
+            "memories",
+            "--sort",
+            "timestamp:desc",
+            "--limit",
+            "10",
+            "--fields",
+            "id,timestamp",
+        ]))
+        .unwrap();
+        assert_eq!(
+            msgpack_to_json(&server.join().unwrap()[0][3]),
+            serde_json::json!([
+                "memories",
+                {},
+                {"order_by": "timestamp", "order": "desc", "limit": 10}
+            ])
+        );
+
+        let records = Value::Array(vec![
+            scored("a", Some(Value::from(1))),
+            scored("b", Some(Value::from(2))),
+        ]);
+        let asc = sort_by("score:asc", true);
+        assert_eq!(asc.order(), Some(("score", false)));
+        assert_eq!(ids(&asc.apply(records.clone()).unwrap()), ["a", "b"]);
+        assert_eq!(
+            ids(&sort_by("score:desc", false).apply(records).unwrap()),
+            ["b", "a"]
+        );
+        // Anything else after a colon is part of the field name
+        assert_eq!(sort_by("a:b", false).order(), Some(("a:b", false)));
+    }
+
+    #[test]
+    fn sort_falls_back_when_the_daemon_ignores_it() {
+        // A daemon that doesn't know order_by returns every match, unsorted
+        let unsorted = Value::Array(vec![
+            scored("mid", Some(Value::from(5))),
+            scored("low", Some(Value::from(1))),
+            scored("high", Some(Value::from(8))),
+        ]);
+        let (socket, _server) = mock_server(vec![Ok(unsorted.clone())]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "all",
+            "scores",
+            "--sort-by",
+            "score",
+            "--reverse",
+            "--limit",
+            "2",
+        ]);
+        assert_eq!(ids(&run(&cli).unwrap().unwrap()), ["high", "mid"]);
+
+        // One that predates match conditions altogether gets a plain match
+        let (socket, server) =
+            mock_server(vec![Err("unknown method: match"), Ok(unsorted.clone())]);
+        let cli = parse(&["--socket", &socket, "all", "scores", "--sort-by", "score"]);
+        assert_eq!(ids(&run(&cli).unwrap().unwrap()), ["low", "mid", "high"]);
+        assert_eq!(params(&server.join().unwrap()[1]).len(), 2);
+
+        // --server-sort fails rather than sort them itself
+        let mut forced = sort_by("score", false);
+        forced.server_sort = true;
+        assert_eq!(forced.apply(unsorted).unwrap_err().code(), "daemon");
+    }
+
+    #[test]
+    fn client_sort_sends_no_order() {
+        let (socket, server) = mock_server(vec![Ok(Value::Array(vec![
+            scored("b", Some(Value::from(2))),
+            scored("a", Some(Value::from(1))),
+        ]))]);
+        let cli = parse(&[
+            "--socket",
+            &socket,
+            "all",
+            "scores",
+            "--sort-by",
+            "score",
+            "--client-sort",
+        ]);
+
+        assert_eq!(ids(&run(&cli).unwrap().unwrap()), ["a", "b"]);
+        let requests = server.join().unwrap();
+        assert_eq!(methods(&requests), ["all"]);
+        assert_eq!(params(&requests[0]).len(), 1);
+    }
+
+    #[test]
+    fn sort_field_must_survive_projection() {
+        assert!(sort_by("age", false).check_fields(Some("name")).is_err());
+        assert!(sort_by("age", false).check_fields(Some("name,age")).is_ok());
+        assert!(sort_by("age", false).check_fields(None).is_ok());
+    }
+
+    #[test]
+    fn project_keeps_requested_fields_in_order() {
+        let user = record(&[("id", "u1"), ("name", "alice"), ("email", "a@b.com")]);
+        let fields = vec!["email".to_string(), "id".to_string()];
+
+        assert_eq!(
+            project(user, &fields),
+            record(&[("email", "a@b.com"), ("id", "u1")])
+        );
+    }
+
+    #[test]
+    fn project_applies_to_each_record_and_skips_unknown_fields() {
+        let users = Value::Array(vec![
+            record(&[("id", "u1"), ("name", "alice")]),
+            record(&[("id", "u2")]),
+        ]);
+        let fields = vec!["name".to_string(), "nope".to_string()];
+
+        assert_eq!(
+            project(users, &fields),
+            Value::Array(vec![record(&[("name", "alice")]), record(&[])])
+        );
+    }
+
+    #[test]
+    fn fields_are_sent_to_daemon() {
+        let (socket, server) = mock_server(vec![Ok(Value::Array(vec![]))]);
+        run(&parse(&[
+            "--socket", &socket, "all", "users", "--fields", "id, name",
+        ]))
+        .unwrap();
+
+        let requests = server.join().unwrap();
+        assert_eq!(
+            params(&requests[0])[1],
+            Value::Array(vec![
+                Value::String("id".into()),
+                Value::String("name".into())
+            ])
+        );
+    }
+
+    #[test]
+    fn connection_failure_renders_as_json() {
+        let missing = temp_socket_path();
+        let err = run(&parse(&["--socket", &missing, "ping"])).unwrap_err();
+
+        assert_eq!(err.code(), "connection");
+        let rendered: serde_json::Value = serde_json::from_str(&render_error(&err, true)).unwrap();
+        assert_eq!(rendered["code"], "connection");
+        assert!(rendered["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("cannot connect to"));
+    }
+
+    #[test]
+    fn daemon_error_renders_as_json() {
+        let (socket, server) = mock_server(vec![Err("access_denied")]);
+        let err = run(&parse(&["--socket", &socket, "get", "t", "k"])).unwrap_err();
+        server.join().unwrap();
+
+        assert_eq!(err, Error::Daemon("access_denied".to_string()));
+        assert_eq!(
+            render_error(&err, true),
+            r#"{"error":"access_denied","code":"daemon"}"#
+        );
+        assert_eq!(render_error(&err, false), "error: access_denied");
+    }
+
+    #[test]
+    fn coded_daemon_error_renders_message_and_reason() {
+        let err = Error::DaemonCode {
+            code: "condition_failed".to_string(),
+            message: "stored record changed".to_string(),
+        };
+
+        assert_eq!(
+            render_error(&err, true),
+            r#"{"error":"stored record changed","code":"conflict","reason":"condition_failed"}"#
+        );
+        assert_eq!(render_error(&err, false), "error: stored record changed");
+        assert_eq!(err.exit_code(), error::EXIT_CONFLICT);
+    }
+
+    #[test]
+    fn refused_socket_and_bad_json_exit_differently() {
+        // A socket file with nobody listening refuses connections
+        let path = temp_socket_path();
+        drop(UnixListener::bind(&path).unwrap());
+        let refused = run(&parse(&["--socket", &path, "ping"])).unwrap_err();
+        std::fs::remove_file(&path).ok();
+
+        let bad_json = run(&parse(&["--socket", &path, "put", "t", "{nope"])).unwrap_err();
+
+        assert_eq!(refused.exit_code(), error::EXIT_CONNECTION);
+        assert_eq!(bad_json.exit_code(), error::EXIT_INPUT);
     }
 }
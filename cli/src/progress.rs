@@ -0,0 +1,126 @@
+//! A one-line progress indicator for bulk operations, drawn on stderr.
+
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Least time between redraws.
+const INTERVAL: Duration = Duration::from_millis(100);
+
+/// Counts processed records and redraws `label: done/total records (pct%),
+/// rate/s` in place. Safe to advance from several threads at once.
+pub struct Progress {
+    label: &'static str,
+    total: Option<usize>,
+    done: AtomicUsize,
+    sink: Option<Mutex<Sink>>,
+}
+
+struct Sink {
+    out: Box<dyn Write + Send>,
+    started: Instant,
+    drawn: Option<Instant>,
+}
+
+impl Progress {
+    /// Progress drawn to `out`, with `total` records expected if known.
+    pub fn new(label: &'static str, total: Option<usize>, out: Box<dyn Write + Send>) -> Self {
+        Progress {
+            label,
+            total,
+            done: AtomicUsize::new(0),
+            sink: Some(Mutex::new(Sink {
+                out,
+                started: Instant::now(),
+                drawn: None,
+            })),
+        }
+    }
+
+    /// Progress that counts but never draws.
+    pub fn hidden() -> Self {
+        Progress {
+            label: "",
+            total: None,
+            done: AtomicUsize::new(0),
+            sink: None,
+        }
+    }
+
+    /// Count `n` more records processed, redrawing if enough time has passed.
+    pub fn add(&self, n: usize) {
+        let done = self.done.fetch_add(n, Ordering::Relaxed) + n;
+        let Some(sink) = &self.sink else {
+            return;
+        };
+        // Another thread is drawing; its line will be current enough
+        let Ok(mut sink) = sink.try_lock() else {
+            return;
+        };
+        let now = Instant::now();
+        if sink.drawn.is_some_and(|at| now - at < INTERVAL) {
+            return;
+        }
+        sink.drawn = Some(now);
+        let line = self.line(done, now - sink.started);
+        let _ = write!(sink.out, "\r\x1b[K{}", line);
+        let _ = sink.out.flush();
+    }
+
+    /// Erase the indicator, if it was ever drawn, so later messages start
+    /// on a clean line.
+    pub fn finish(&self) {
+        if let Some(sink) = &self.sink {
+            let mut sink = sink.lock().unwrap_or_else(|e| e.into_inner());
+            if sink.drawn.take().is_some() {
+                let _ = write!(sink.out, "\r\x1b[K");
+                let _ = sink.out.flush();
+            }
+        }
+    }
+
+    fn line(&self, done: usize, elapsed: Duration) -> String {
+        let rate = match elapsed.as_secs_f64() {
+            secs if secs > 0.0 => format!(", {:.0}/s", done as f64 / secs),
+            _ => String::new(),
+        };
+        match self.total {
+            Some(total) if total > 0 => format!(
+                "{}: {}/{} records ({}%){}",
+                self.label,
+                done,
+                total,
+                done * 100 / total,
+                rate
+            ),
+            _ => format!("{}: {} records{}", self.label, done, rate),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_shows_done_total_and_rate() {
+        let progress = Progress::new("restore", Some(3000), Box::new(std::io::sink()));
+        assert_eq!(
+            progress.line(1200, Duration::from_secs(2)),
+            "restore: 1200/3000 records (40%), 600/s"
+        );
+
+        let progress = Progress::new("backup", None, Box::new(std::io::sink()));
+        assert_eq!(progress.line(50, Duration::ZERO), "backup: 50 records");
+    }
+
+    #[test]
+    fn hidden_progress_still_counts() {
+        let progress = Progress::hidden();
+        progress.add(2);
+        progress.add(3);
+        progress.finish();
+        assert_eq!(progress.done.load(Ordering::Relaxed), 5);
+    }
+}
@@ -0,0 +1,685 @@
+//! Nested query patterns.
+//!
+//! The daemon's `match` compares each top-level pattern field with the stored
+//! value by equality (or membership, when the stored value is an array), so a
+//! pattern like `{"address": {"city": "NYC"}}` would only find records whose
+//! entire `address` is `{"city": "NYC"}`. Fields whose pattern is an object are
+//! therefore held back from the daemon and checked here against the superset
+//! of records it returns for the remaining, top-level fields.
+//!
+//! At every depth:
+//! - an object matches an object that has each of its fields, recursively
+//! - an object matches an array if any element matches it
+//! - a scalar matches an equal scalar, or an array containing one
+//! - an array matches only an equal array
+//!
+//! A top-level field whose pattern is `{"$regex": "..."}` matches a string
+//! the expression finds a match in, or an array containing one. Regexes are
+//! checked here before anything is sent, then passed to the daemon in a
+//! separate `{"regex": {field: source}}` conditions param to `match`.
+//!
+//! The comparison operators `$gt`, `$gte`, `$lt`, `$lte`, `$in`,
+//! `$contains`, and `$prefix` travel the same way, as
+//! `{"where": {field: {"gt": 30, ...}}}`; see [`Operator`] for what each
+//! matches. Several operators on one field must all hold. Since a daemon
+//! that predates them ignores the `where` condition, they are also checked
+//! here against whatever it returns.
+//!
+//! A time range added with [`Pattern::add_range`] travels the same way, as
+//! `{"range": {field: {"gte": since, "lte": until}}}` in epoch seconds, and
+//! matches records whose field is epoch seconds or an RFC 3339 string within
+//! the bounds. A daemon without conditions support leaves both to
+//! [`Pattern::evaluate_conditions_locally`].
+
+use crate::error::Error;
+use crate::timestamp;
+use regex::Regex;
+use rmpv::Value;
+use std::cmp::Ordering;
+
+/// Inclusive bounds, in epoch seconds, on a time field.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Range {
+    pub since: Option<f64>,
+    pub until: Option<f64>,
+}
+
+impl Range {
+    fn contains(&self, value: &Value) -> bool {
+        let secs = match value {
+            Value::String(s) => s.as_str().and_then(timestamp::parse_rfc3339),
+            other => other.as_f64(),
+        };
+        secs.is_some_and(|secs| {
+            self.since.is_none_or(|since| secs >= since)
+                && self.until.is_none_or(|until| secs <= until)
+        })
+    }
+}
+
+/// A comparison operator in a `{"$gt": 30}`-style field pattern.
+///
+/// `Contains` finds a substring in a string or an element in an array. The
+/// others match an array when any element does: `In` an element equal to
+/// one of the operands, `Prefix` a string starting with it, and the
+/// orderings a number compared with a number or a string with a string (so
+/// RFC 3339 times compare in time order).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    In,
+    Contains,
+    Prefix,
+}
+
+impl Operator {
+    /// The operator spelled `$name` in a pattern.
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "$gt" => Operator::Gt,
+            "$gte" => Operator::Gte,
+            "$lt" => Operator::Lt,
+            "$lte" => Operator::Lte,
+            "$in" => Operator::In,
+            "$contains" => Operator::Contains,
+            "$prefix" => Operator::Prefix,
+            _ => return None,
+        })
+    }
+
+    /// The name the daemon takes, without the `$`.
+    fn name(self) -> &'static str {
+        match self {
+            Operator::Gt => "gt",
+            Operator::Gte => "gte",
+            Operator::Lt => "lt",
+            Operator::Lte => "lte",
+            Operator::In => "in",
+            Operator::Contains => "contains",
+            Operator::Prefix => "prefix",
+        }
+    }
+
+    /// What `operand` has to be, if it isn't.
+    fn check(self, operand: &Value) -> Result<(), &'static str> {
+        match (self, operand) {
+            (Operator::In, Value::Array(_)) => Ok(()),
+            (Operator::In, _) => Err("an array"),
+            (Operator::Prefix, Value::String(_)) => Ok(()),
+            (Operator::Prefix, _) => Err("a string"),
+            (Operator::Contains, _) => Ok(()),
+            (_, Value::String(_)) => Ok(()),
+            (_, value) if value.as_f64().is_some() => Ok(()),
+            _ => Err("a number or a string"),
+        }
+    }
+
+    fn matches(self, value: &Value, operand: &Value) -> bool {
+        match (self, value) {
+            (Operator::Contains, Value::String(s)) => s
+                .as_str()
+                .zip(operand.as_str())
+                .is_some_and(|(s, part)| s.contains(part)),
+            (Operator::Contains, Value::Array(items)) => {
+                items.iter().any(|item| scalar_eq(item, operand))
+            }
+            (Operator::Contains, _) => false,
+            (_, Value::Array(items)) => items.iter().any(|item| self.matches(item, operand)),
+            (Operator::In, _) => operand
+                .as_array()
+                .is_some_and(|operands| operands.iter().any(|o| scalar_eq(value, o))),
+            (Operator::Prefix, _) => value
+                .as_str()
+                .zip(operand.as_str())
+                .is_some_and(|(s, prefix)| s.starts_with(prefix)),
+            (Operator::Gt, _) => compare(value, operand).is_some_and(Ordering::is_gt),
+            (Operator::Gte, _) => compare(value, operand).is_some_and(Ordering::is_ge),
+            (Operator::Lt, _) => compare(value, operand).is_some_and(Ordering::is_lt),
+            (Operator::Lte, _) => compare(value, operand).is_some_and(Ordering::is_le),
+        }
+    }
+}
+
+/// A query pattern split into the part the daemon evaluates and the nested
+/// fields filtered client-side.
+#[derive(Debug)]
+pub struct Pattern {
+    /// Sent as the `match` pattern
+    pub server: Value,
+    nested: Vec<(Value, Value)>,
+    regexes: Vec<(Value, Regex)>,
+    ranges: Vec<(Value, Range)>,
+    operators: Vec<(Value, Vec<(Operator, Value)>)>,
+    local_conditions: bool,
+}
+
+impl Pattern {
+    pub fn new(pattern: Value) -> Result<Self, Error> {
+        let Value::Map(entries) = pattern else {
+            // Let the daemon reject it
+            return Ok(Pattern {
+                server: pattern,
+                nested: Vec::new(),
+                regexes: Vec::new(),
+                ranges: Vec::new(),
+                operators: Vec::new(),
+                local_conditions: false,
+            });
+        };
+
+        let mut flat = Vec::new();
+        let mut nested = Vec::new();
+        let mut regexes = Vec::new();
+        let mut operators = Vec::new();
+        for (key, value) in entries {
+            match value {
+                Value::Map(ops) if ops.iter().any(|(op, _)| is_operator(op)) => {
+                    let mut comparisons = Vec::new();
+                    for (op, operand) in ops {
+                        match op.as_str() {
+                            Some("$regex") => regexes.push((key.clone(), regex(&key, &operand)?)),
+                            name => {
+                                let op = name.and_then(Operator::from_name).ok_or_else(|| {
+                                    Error::Input(format!(
+                                        "unknown operator {} for '{}' (expected $gt, $gte, \
+                                         $lt, $lte, $in, $contains, $prefix, or $regex)",
+                                        op,
+                                        field_name(&key)
+                                    ))
+                                })?;
+                                op.check(&operand).map_err(|expected| {
+                                    Error::Input(format!(
+                                        "${} for '{}' must be {}",
+                                        op.name(),
+                                        field_name(&key),
+                                        expected
+                                    ))
+                                })?;
+                                comparisons.push((op, operand));
+                            }
+                        }
+                    }
+                    if !comparisons.is_empty() {
+                        operators.push((key, comparisons));
+                    }
+                }
+                Value::Map(_) => nested.push((key, value)),
+                _ => flat.push((key, value)),
+            }
+        }
+        Ok(Pattern {
+            server: Value::Map(flat),
+            nested,
+            regexes,
+            ranges: Vec::new(),
+            operators,
+            local_conditions: false,
+        })
+    }
+
+    /// Also require `field` to hold a time within `range`.
+    pub fn add_range(&mut self, field: &str, range: Range) {
+        self.ranges.push((Value::from(field), range));
+    }
+
+    /// Whether any records the daemon returns still need filtering.
+    pub fn is_nested(&self) -> bool {
+        !self.nested.is_empty()
+            || !self.operators.is_empty()
+            || (self.local_conditions && self.has_conditions())
+    }
+
+    /// Whether any fields hold nested patterns, which only
+    /// [`Pattern::matches`] can check.
+    pub fn has_nested_fields(&self) -> bool {
+        !self.nested.is_empty()
+    }
+
+    fn has_conditions(&self) -> bool {
+        !self.regexes.is_empty() || !self.ranges.is_empty() || !self.operators.is_empty()
+    }
+
+    /// The `{"regex": {field: source}, "range": {field: bounds}, "where":
+    /// {field: {op: operand}}}` param for the daemon's `match`, with
+    /// whichever parts the pattern has, if any.
+    pub fn conditions_param(&self) -> Option<Value> {
+        if !self.has_conditions() {
+            return None;
+        }
+        let mut conditions = Vec::new();
+        if !self.regexes.is_empty() {
+            let sources = self
+                .regexes
+                .iter()
+                .map(|(key, regex)| (key.clone(), Value::from(regex.as_str())))
+                .collect();
+            conditions.push((Value::from("regex"), Value::Map(sources)));
+        }
+        if !self.ranges.is_empty() {
+            let bounds = self
+                .ranges
+                .iter()
+                .map(|(key, range)| {
+                    let bound = |name: &str, secs: Option<f64>| {
+                        secs.map(|secs| (Value::from(name), seconds(secs)))
+                    };
+                    let bounds = bound("gte", range.since)
+                        .into_iter()
+                        .chain(bound("lte", range.until))
+                        .collect();
+                    (key.clone(), Value::Map(bounds))
+                })
+                .collect();
+            conditions.push((Value::from("range"), Value::Map(bounds)));
+        }
+        if !self.operators.is_empty() {
+            let fields = self
+                .operators
+                .iter()
+                .map(|(key, comparisons)| {
+                    let ops = comparisons
+                        .iter()
+                        .map(|(op, operand)| (Value::from(op.name()), operand.clone()))
+                        .collect();
+                    (key.clone(), Value::Map(ops))
+                })
+                .collect();
+            conditions.push((Value::from("where"), Value::Map(fields)));
+        }
+        Some(Value::Map(conditions))
+    }
+
+    /// Check the regexes and ranges here, for a daemon that can't.
+    /// Operators are checked here either way.
+    pub fn evaluate_conditions_locally(&mut self) {
+        self.local_conditions = true;
+    }
+
+    pub fn matches(&self, record: &Value) -> bool {
+        fields_match(record, &self.nested)
+            && self.operators.iter().all(|(k, comparisons)| {
+                field(record, k).is_some_and(|value| {
+                    comparisons
+                        .iter()
+                        .all(|(op, operand)| op.matches(value, operand))
+                })
+            })
+            && (!self.local_conditions
+                || (self
+                    .regexes
+                    .iter()
+                    .all(|(k, r)| regex_matches(record, k, r))
+                    && self
+                        .ranges
+                        .iter()
+                        .all(|(k, range)| field(record, k).is_some_and(|v| range.contains(v)))))
+    }
+
+    /// Keep only the records in a `match` result that match the nested fields.
+    pub fn filter(&self, records: Value) -> Value {
+        match records {
+            Value::Array(records) => {
+                Value::Array(records.into_iter().filter(|r| self.matches(r)).collect())
+            }
+            other => other,
+        }
+    }
+}
+
+/// Whether a key in a field's pattern names an operator rather than a
+/// nested field.
+fn is_operator(key: &Value) -> bool {
+    key.as_str().is_some_and(|k| k.starts_with('$'))
+}
+
+/// The `$regex` operand for `key`, compiled.
+fn regex(key: &Value, source: &Value) -> Result<Regex, Error> {
+    let source = source.as_str().ok_or_else(|| {
+        Error::Input(format!("$regex for '{}' must be a string", field_name(key)))
+    })?;
+    Regex::new(source)
+        .map_err(|e| Error::Input(format!("invalid $regex for '{}': {}", field_name(key), e)))
+}
+
+fn field_name(key: &Value) -> String {
+    key.as_str().map_or_else(|| key.to_string(), str::to_string)
+}
+
+/// Whole seconds as an integer, so the daemon sees `1700000000` and not
+/// `1700000000.0`.
+fn seconds(secs: f64) -> Value {
+    if secs.fract() == 0.0 && secs.abs() < i64::MAX as f64 {
+        Value::from(secs as i64)
+    } else {
+        Value::F64(secs)
+    }
+}
+
+fn field<'a>(record: &'a Value, key: &Value) -> Option<&'a Value> {
+    let Value::Map(entries) = record else {
+        return None;
+    };
+    entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+}
+
+fn regex_matches(record: &Value, key: &Value, regex: &Regex) -> bool {
+    let is_match = |value: &Value| value.as_str().is_some_and(|s| regex.is_match(s));
+    match field(record, key) {
+        Some(Value::Array(items)) => items.iter().any(is_match),
+        Some(value) => is_match(value),
+        None => false,
+    }
+}
+
+fn fields_match(data: &Value, pattern: &[(Value, Value)]) -> bool {
+    let Value::Map(entries) = data else {
+        return false;
+    };
+    pattern.iter().all(|(key, expected)| {
+        entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .is_some_and(|(_, value)| value_matches(value, expected))
+    })
+}
+
+fn value_matches(data: &Value, pattern: &Value) -> bool {
+    match (data, pattern) {
+        (Value::Array(items), Value::Map(_)) => items.iter().any(|i| value_matches(i, pattern)),
+        (_, Value::Map(fields)) => fields_match(data, fields),
+        (_, Value::Array(_)) => scalar_eq(data, pattern),
+        (Value::Array(items), _) => items.iter().any(|i| scalar_eq(i, pattern)),
+        _ => scalar_eq(data, pattern),
+    }
+}
+
+/// How `a` orders against `b`, if both are numbers or both strings.
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => Some(a.as_bytes().cmp(b.as_bytes())),
+        _ => a.as_f64()?.partial_cmp(&b.as_f64()?),
+    }
+}
+
+/// Equality that, like the daemon's, treats `1` and `1.0` as equal.
+fn scalar_eq(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (
+            Value::Integer(_) | Value::F32(_) | Value::F64(_),
+            Value::Integer(_) | Value::F32(_) | Value::F64(_),
+        ) => a.as_f64() == b.as_f64(),
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json_to_msgpack;
+    use serde_json::json;
+
+    fn pattern(value: serde_json::Value) -> Pattern {
+        Pattern::new(json_to_msgpack(&value)).unwrap()
+    }
+
+    fn matches(pattern_json: serde_json::Value, record: serde_json::Value) -> bool {
+        pattern(pattern_json).matches(&json_to_msgpack(&record))
+    }
+
+    #[test]
+    fn flat_patterns_go_to_the_daemon_unchanged() {
+        let p = pattern(json!({"name": "alice", "tags": ["a"]}));
+        assert!(!p.is_nested());
+        assert_eq!(
+            p.server,
+            json_to_msgpack(&json!({"name": "alice", "tags": ["a"]}))
+        );
+    }
+
+    #[test]
+    fn nested_fields_are_held_back() {
+        let p = pattern(json!({"name": "alice", "address": {"city": "NYC"}}));
+        assert!(p.is_nested());
+        assert_eq!(p.server, json_to_msgpack(&json!({"name": "alice"})));
+    }
+
+    #[test]
+    fn matches_two_levels() {
+        let p = json!({"address": {"city": "NYC"}});
+        assert!(matches(
+            p.clone(),
+            json!({"id": "u1", "address": {"city": "NYC", "zip": "10001"}})
+        ));
+        assert!(!matches(
+            p.clone(),
+            json!({"id": "u2", "address": {"city": "LA"}})
+        ));
+        assert!(!matches(p.clone(), json!({"id": "u3", "address": "NYC"})));
+        assert!(!matches(p, json!({"id": "u4"})));
+    }
+
+    #[test]
+    fn matches_three_levels() {
+        let p = json!({"profile": {"address": {"city": "NYC"}, "active": true}});
+        assert!(matches(
+            p.clone(),
+            json!({"profile": {"active": true, "address": {"city": "NYC", "street": "Main"}}})
+        ));
+        assert!(!matches(
+            p.clone(),
+            json!({"profile": {"active": false, "address": {"city": "NYC"}}})
+        ));
+        assert!(!matches(
+            p,
+            json!({"profile": {"active": true, "address": {"city": "SF"}}})
+        ));
+    }
+
+    #[test]
+    fn arrays_within_nested_patterns() {
+        // A scalar matches an array containing it
+        assert!(matches(
+            json!({"meta": {"tags": "red"}}),
+            json!({"meta": {"tags": ["red", "blue"]}})
+        ));
+        // An object matches any element of an array
+        assert!(matches(
+            json!({"meta": {"owners": {"name": "bo"}}}),
+            json!({"meta": {"owners": [{"name": "al"}, {"name": "bo", "role": "x"}]}})
+        ));
+        // An array must match exactly
+        assert!(matches(
+            json!({"meta": {"tags": ["red", "blue"]}}),
+            json!({"meta": {"tags": ["red", "blue"]}})
+        ));
+        assert!(!matches(
+            json!({"meta": {"tags": ["red"]}}),
+            json!({"meta": {"tags": ["red", "blue"]}})
+        ));
+    }
+
+    #[test]
+    fn regex_goes_to_the_daemon_as_its_own_param() {
+        let p = pattern(json!({"kind": "note", "content": {"$regex": "foo.*bar"}}));
+        assert_eq!(p.server, json_to_msgpack(&json!({"kind": "note"})));
+        assert_eq!(
+            p.conditions_param(),
+            Some(json_to_msgpack(&json!({"regex": {"content": "foo.*bar"}})))
+        );
+        // The daemon evaluates it, so nothing is left to filter
+        assert!(!p.is_nested());
+        assert!(pattern(json!({"kind": "note"}))
+            .conditions_param()
+            .is_none());
+    }
+
+    #[test]
+    fn invalid_regex_is_an_input_error() {
+        let err =
+            Pattern::new(json_to_msgpack(&json!({"content": {"$regex": "foo("}}))).unwrap_err();
+        assert_eq!(err.code(), "input");
+        assert!(err.to_string().starts_with("invalid $regex for 'content'"));
+
+        let err = Pattern::new(json_to_msgpack(&json!({"content": {"$regex": 1}}))).unwrap_err();
+        assert_eq!(err.to_string(), "$regex for 'content' must be a string");
+    }
+
+    #[test]
+    fn regex_evaluated_locally_for_old_daemons() {
+        let mut p = pattern(json!({"content": {"$regex": "^foo.*bar$"}}));
+        p.evaluate_conditions_locally();
+        assert!(p.is_nested());
+
+        let records = json_to_msgpack(&json!([
+            {"id": "m1", "content": "foo and bar"},
+            {"id": "m2", "content": "bar and foo"},
+            {"id": "m3", "content": ["x", "foobar"]},
+            {"id": "m4", "content": 7},
+        ]));
+        assert_eq!(
+            p.filter(records),
+            json_to_msgpack(&json!([
+                {"id": "m1", "content": "foo and bar"},
+                {"id": "m3", "content": ["x", "foobar"]},
+            ]))
+        );
+    }
+
+    #[test]
+    fn operators_go_to_the_daemon_under_where() {
+        let p = pattern(json!({
+            "kind": "note",
+            "age": {"$gte": 18, "$lt": 65},
+            "name": {"$prefix": "a", "$regex": "e$"},
+        }));
+        assert_eq!(p.server, json_to_msgpack(&json!({"kind": "note"})));
+        assert_eq!(
+            p.conditions_param(),
+            Some(json_to_msgpack(&json!({
+                "regex": {"name": "e$"},
+                "where": {"age": {"gte": 18, "lt": 65}, "name": {"prefix": "a"}},
+            })))
+        );
+        // Checked again here in case the daemon ignored them
+        assert!(p.is_nested());
+    }
+
+    #[test]
+    fn operators_match_as_the_daemon_does() {
+        let record = json!({
+            "age": 30,
+            "score": 2.5,
+            "name": "alice",
+            "tags": ["red", "blue"],
+            "seen": "2024-01-15T10:30:00Z",
+        });
+        let yes = |p: serde_json::Value| assert!(matches(p.clone(), record.clone()), "{}", p);
+        let no = |p: serde_json::Value| assert!(!matches(p.clone(), record.clone()), "{}", p);
+
+        yes(json!({"age": {"$gt": 29, "$lte": 30.0}}));
+        no(json!({"age": {"$gt": 30}}));
+        yes(json!({"score": {"$lt": 3}}));
+        no(json!({"name": {"$gt": 1}}));
+        yes(json!({"name": {"$gte": "alice", "$lt": "bob"}}));
+        yes(json!({"seen": {"$gte": "2024-01-01", "$lt": "2024-02-01"}}));
+        yes(json!({"age": {"$in": [1, 30.0]}}));
+        no(json!({"name": {"$in": ["bob"]}}));
+        yes(json!({"tags": {"$in": ["blue", "green"]}}));
+        yes(json!({"name": {"$contains": "lic"}}));
+        yes(json!({"tags": {"$contains": "red"}}));
+        no(json!({"tags": {"$contains": "re"}}));
+        yes(json!({"name": {"$prefix": "al"}}));
+        yes(json!({"tags": {"$prefix": "bl"}}));
+        no(json!({"missing": {"$lt": 100}}));
+    }
+
+    #[test]
+    fn bad_operators_are_input_errors() {
+        let error = |p| Pattern::new(json_to_msgpack(&p)).unwrap_err().to_string();
+        assert!(
+            error(json!({"age": {"$between": [1, 2]}}))
+                .starts_with("unknown operator \"$between\" for 'age'"),
+            "{}",
+            error(json!({"age": {"$between": [1, 2]}}))
+        );
+        assert_eq!(
+            error(json!({"role": {"$in": "admin"}})),
+            "$in for 'role' must be an array"
+        );
+        assert_eq!(
+            error(json!({"name": {"$prefix": 1}})),
+            "$prefix for 'name' must be a string"
+        );
+        assert_eq!(
+            error(json!({"age": {"$gt": [1]}})),
+            "$gt for 'age' must be a number or a string"
+        );
+    }
+
+    #[test]
+    fn ranges_go_to_the_daemon_in_epoch_seconds() {
+        let mut p = pattern(json!({"content": {"$regex": "x"}}));
+        p.add_range(
+            "timestamp",
+            Range {
+                since: Some(1_700_000_000.0),
+                until: Some(1_700_000_000.5),
+            },
+        );
+        p.add_range(
+            "updated",
+            Range {
+                since: None,
+                until: Some(5.0),
+            },
+        );
+        assert_eq!(
+            p.conditions_param(),
+            Some(json_to_msgpack(&json!({
+                "regex": {"content": "x"},
+                "range": {
+                    "timestamp": {"gte": 1_700_000_000, "lte": 1_700_000_000.5},
+                    "updated": {"lte": 5},
+                },
+            })))
+        );
+        assert!(!p.is_nested());
+    }
+
+    #[test]
+    fn ranges_evaluated_locally_for_old_daemons() {
+        let mut p = pattern(json!({}));
+        p.add_range(
+            "timestamp",
+            Range {
+                since: Some(1_700_000_000.0),
+                until: None,
+            },
+        );
+        p.evaluate_conditions_locally();
+        assert!(p.is_nested());
+
+        let records = json_to_msgpack(&json!([
+            {"id": "m1", "timestamp": 1_699_999_999},
+            {"id": "m2", "timestamp": 1_700_000_000},
+            {"id": "m3", "timestamp": "2023-11-15T00:00:00Z"},
+            {"id": "m4", "timestamp": "soon"},
+            {"id": "m5"},
+        ]));
+        assert_eq!(
+            p.filter(records),
+            json_to_msgpack(&json!([
+                {"id": "m2", "timestamp": 1_700_000_000},
+                {"id": "m3", "timestamp": "2023-11-15T00:00:00Z"},
+            ]))
+        );
+    }
+
+    #[test]
+    fn numbers_compare_by_value() {
+        assert!(matches(json!({"a": {"n": 1}}), json!({"a": {"n": 1.0}})));
+    }
+}
@@ -0,0 +1,519 @@
+use serde_json::Value;
+
+const KEY: &str = "\x1b[1;34m";
+const STRING: &str = "\x1b[32m";
+const NUMBER: &str = "\x1b[36m";
+const BOOL: &str = "\x1b[33m";
+const NULL: &str = "\x1b[90m";
+const RESET: &str = "\x1b[0m";
+
+/// Serialize `value` as JSON, laid out exactly like `serde_json`'s compact
+/// or pretty output, optionally with ANSI colors for keys and scalars.
+pub fn json(value: &Value, pretty: bool, color: bool) -> String {
+    let mut out = String::new();
+    Writer {
+        pretty,
+        color,
+        compact_under: None,
+    }
+    .value(&mut out, value, 0);
+    out
+}
+
+/// Pretty-print `value`, but write any nested object or array whose compact
+/// form is shorter than `limit` bytes on one line.
+pub fn json_compact_under(value: &Value, limit: usize, color: bool) -> String {
+    let mut out = String::new();
+    Writer {
+        pretty: true,
+        color,
+        compact_under: Some(limit),
+    }
+    .value(&mut out, value, 0);
+    out
+}
+
+struct Writer {
+    pretty: bool,
+    color: bool,
+    compact_under: Option<usize>,
+}
+
+impl Writer {
+    fn value(&self, out: &mut String, value: &Value, depth: usize) {
+        if let (Some(limit), true, Value::Array(_) | Value::Object(_)) =
+            (self.compact_under, depth > 0, value)
+        {
+            if json(value, false, false).len() < limit {
+                out.push_str(&json(value, false, self.color));
+                return;
+            }
+        }
+        match value {
+            Value::Null => self.paint(out, NULL, "null"),
+            Value::Bool(b) => self.paint(out, BOOL, &b.to_string()),
+            Value::Number(n) => self.paint(out, NUMBER, &n.to_string()),
+            Value::String(s) => self.paint(out, STRING, &quote(s)),
+            Value::Array(items) => self.container(out, '[', ']', items.len(), depth, |out, i| {
+                self.value(out, &items[i], depth + 1)
+            }),
+            Value::Object(map) => {
+                let entries: Vec<_> = map.iter().collect();
+                self.container(out, '{', '}', entries.len(), depth, |out, i| {
+                    let (key, value) = entries[i];
+                    self.paint(out, KEY, &quote(key));
+                    out.push_str(if self.pretty { ": " } else { ":" });
+                    self.value(out, value, depth + 1);
+                })
+            }
+        }
+    }
+
+    fn container(
+        &self,
+        out: &mut String,
+        open: char,
+        close: char,
+        len: usize,
+        depth: usize,
+        mut item: impl FnMut(&mut String, usize),
+    ) {
+        out.push(open);
+        for i in 0..len {
+            if i > 0 {
+                out.push(',');
+            }
+            self.newline(out, depth + 1);
+            item(out, i);
+        }
+        if len > 0 {
+            self.newline(out, depth);
+        }
+        out.push(close);
+    }
+
+    fn newline(&self, out: &mut String, depth: usize) {
+        if self.pretty {
+            out.push('\n');
+            out.push_str(&"  ".repeat(depth));
+        }
+    }
+
+    fn paint(&self, out: &mut String, color: &str, text: &str) {
+        if self.color {
+            out.push_str(color);
+            out.push_str(text);
+            out.push_str(RESET);
+        } else {
+            out.push_str(text);
+        }
+    }
+}
+
+/// Widest a table cell may be, in characters, before it is truncated.
+pub const MAX_CELL: usize = 40;
+
+/// Lay out an object, or an array of objects, as a bordered ASCII table
+/// with one column per key (in order of first appearance).
+///
+/// Strings are shown unquoted, numbers right-aligned, and nested values as
+/// compact JSON. Returns None for anything else, which has no columns.
+pub fn table(value: &Value) -> Option<String> {
+    let rows: Vec<&serde_json::Map<String, Value>> = match value {
+        Value::Object(row) => vec![row],
+        Value::Array(items) => items.iter().map(Value::as_object).collect::<Option<_>>()?,
+        _ => return None,
+    };
+    if rows.is_empty() {
+        return Some("(no rows)\n".to_string());
+    }
+
+    let mut columns: Vec<&str> = Vec::new();
+    for row in &rows {
+        for key in row.keys() {
+            if !columns.contains(&key.as_str()) {
+                columns.push(key);
+            }
+        }
+    }
+
+    let mut truncated = false;
+    let mut cell = |text: String| match text.char_indices().nth(MAX_CELL - 1) {
+        Some((end, _)) if text.chars().count() > MAX_CELL => {
+            truncated = true;
+            format!("{}…", &text[..end])
+        }
+        _ => text,
+    };
+    let header: Vec<String> = columns.iter().map(|c| cell(c.to_string())).collect();
+    let body: Vec<Vec<(String, bool)>> = rows
+        .iter()
+        .map(|row| {
+            columns
+                .iter()
+                .map(|c| match row.get(*c) {
+                    None => (String::new(), false),
+                    Some(Value::String(s)) => (cell(s.clone()), false),
+                    Some(Value::Number(n)) => (n.to_string(), true),
+                    Some(other) => (cell(other.to_string()), false),
+                })
+                .collect()
+        })
+        .collect();
+
+    let widths: Vec<usize> = (0..columns.len())
+        .map(|i| {
+            body.iter()
+                .map(|row| row[i].0.chars().count())
+                .chain(std::iter::once(header[i].chars().count()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    let border: String = widths
+        .iter()
+        .map(|w| format!("+{}", "-".repeat(w + 2)))
+        .collect::<String>()
+        + "+\n";
+    let line = |cells: &mut dyn Iterator<Item = (&str, bool)>| {
+        cells
+            .zip(&widths)
+            .map(|((text, right), width)| {
+                let pad = " ".repeat(width - text.chars().count());
+                if right {
+                    format!("| {}{} ", pad, text)
+                } else {
+                    format!("| {}{} ", text, pad)
+                }
+            })
+            .collect::<String>()
+            + "|\n"
+    };
+
+    let mut out = border.clone();
+    out += &line(&mut header.iter().map(|h| (h.as_str(), false)));
+    out += &border;
+    for row in &body {
+        out += &line(&mut row.iter().map(|(text, right)| (text.as_str(), *right)));
+    }
+    out += &border;
+    if truncated {
+        out += &format!("(values longer than {} characters truncated)\n", MAX_CELL);
+    }
+    Some(out)
+}
+
+/// Lay out an object, or an array of objects, as CSV: a header row with one
+/// column per key (in order of first appearance), then one row per object.
+/// Returns None for anything else, which has no columns.
+pub fn csv(value: &Value) -> Option<String> {
+    let rows: Vec<&serde_json::Map<String, Value>> = match value {
+        Value::Object(row) => vec![row],
+        Value::Array(items) => items.iter().map(Value::as_object).collect::<Option<_>>()?,
+        _ => return None,
+    };
+    if rows.is_empty() {
+        return Some(String::new());
+    }
+
+    let mut columns: Vec<&str> = Vec::new();
+    for row in &rows {
+        for key in row.keys() {
+            if !columns.contains(&key.as_str()) {
+                columns.push(key);
+            }
+        }
+    }
+
+    let mut out = csv_row(columns.iter().copied());
+    for row in rows {
+        let cells: Vec<String> = columns
+            .iter()
+            .map(|c| csv_cell(row.get(*c).unwrap_or(&Value::Null)))
+            .collect();
+        out += &csv_row(cells.iter().map(String::as_str));
+    }
+    Some(out)
+}
+
+/// A value's CSV text: strings as they are, nothing for null, and anything
+/// else as compact JSON.
+pub fn csv_cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// One CSV line, quoting any cell with a comma, quote, or line break in it
+/// (RFC 4180).
+pub fn csv_row<'a>(cells: impl Iterator<Item = &'a str>) -> String {
+    let cells: Vec<String> = cells
+        .map(|cell| {
+            if cell.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", cell.replace('"', "\"\""))
+            } else {
+                cell.to_string()
+            }
+        })
+        .collect();
+    cells.join(",") + "\n"
+}
+
+/// Serialize `value` as block-style YAML. Strings are left unquoted where
+/// YAML would read them back as the same string, and double-quoted (with
+/// JSON escapes, which YAML shares) otherwise.
+pub fn yaml(value: &Value) -> String {
+    if is_yaml_block(value) {
+        yaml_block(value, 0)
+    } else {
+        yaml_scalar(value) + "\n"
+    }
+}
+
+/// Whether `value` is written as indented lines rather than on one line:
+/// arrays and objects, unless empty.
+fn is_yaml_block(value: &Value) -> bool {
+    match value {
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(fields) => !fields.is_empty(),
+        _ => false,
+    }
+}
+
+/// A non-empty array or object as YAML lines indented by `indent`.
+fn yaml_block(value: &Value, indent: usize) -> String {
+    let pad = " ".repeat(indent);
+    let mut out = String::new();
+    match value {
+        Value::Array(items) => {
+            for item in items {
+                if is_yaml_block(item) {
+                    // The item's first line goes after the dash
+                    let block = yaml_block(item, indent + 2);
+                    out += &format!("{}- {}", pad, &block[indent + 2..]);
+                } else {
+                    out += &format!("{}- {}\n", pad, yaml_scalar(item));
+                }
+            }
+        }
+        Value::Object(fields) => {
+            for (key, field) in fields {
+                if is_yaml_block(field) {
+                    out += &format!(
+                        "{}{}:\n{}",
+                        pad,
+                        yaml_string(key),
+                        yaml_block(field, indent + 2)
+                    );
+                } else {
+                    out += &format!("{}{}: {}\n", pad, yaml_string(key), yaml_scalar(field));
+                }
+            }
+        }
+        scalar => out += &format!("{}{}\n", pad, yaml_scalar(scalar)),
+    }
+    out
+}
+
+fn yaml_scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => yaml_string(s),
+        Value::Array(_) => "[]".to_string(),
+        Value::Object(_) => "{}".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// `s` as a YAML string: plain if it can't be mistaken for anything else.
+fn yaml_string(s: &str) -> String {
+    let reserved = matches!(
+        s.to_ascii_lowercase().as_str(),
+        "" | "~" | "null" | "true" | "false" | "yes" | "no" | "on" | "off" | "y" | "n"
+    );
+    let plain = !reserved
+        && s.starts_with(|c: char| c.is_alphabetic() || c == '_' || c == '/')
+        && !s.ends_with(' ')
+        && s.chars()
+            .all(|c| c.is_alphanumeric() || " _-./@+".contains(c))
+        && s.parse::<f64>().is_err();
+    if plain {
+        s.to_string()
+    } else {
+        quote(s)
+    }
+}
+
+fn quote(s: &str) -> String {
+    serde_json::to_string(s).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compact_under_inlines_small_nested_values() {
+        // {"id":"u1","tags":["a","b"]} is 28 bytes
+        let records = serde_json::json!([{"id": "u1", "tags": ["a", "b"]}]);
+
+        assert_eq!(
+            json_compact_under(&records, 29, false),
+            "[\n  {\"id\":\"u1\",\"tags\":[\"a\",\"b\"]}\n]"
+        );
+        // At the limit the record is expanded, but its small array isn't
+        assert_eq!(
+            json_compact_under(&records, 28, false),
+            concat!(
+                "[\n",
+                "  {\n",
+                "    \"id\": \"u1\",\n",
+                "    \"tags\": [\"a\",\"b\"]\n",
+                "  }\n",
+                "]"
+            )
+        );
+        // Top level is always expanded; scalars are unaffected
+        assert_eq!(
+            json_compact_under(&serde_json::json!({"n": 1}), 100, false),
+            "{\n  \"n\": 1\n}"
+        );
+    }
+    use serde_json::json;
+
+    fn sample() -> Value {
+        json!({
+            "id": "u1",
+            "age": 42,
+            "score": 1.5,
+            "admin": false,
+            "manager": null,
+            "tags": ["a", "b"],
+            "empty": {},
+            "none": [],
+            "quote": "say \"hi\"\n"
+        })
+    }
+
+    #[test]
+    fn plain_output_matches_serde_json() {
+        let value = sample();
+        assert_eq!(
+            json(&value, false, false),
+            serde_json::to_string(&value).unwrap()
+        );
+        assert_eq!(
+            json(&value, true, false),
+            serde_json::to_string_pretty(&value).unwrap()
+        );
+    }
+
+    #[test]
+    fn table_aligns_columns() {
+        let rows = json!([
+            {"id": "u1", "name": "alice", "age": 7},
+            {"id": "u22", "age": 130, "tags": ["a"]}
+        ]);
+        assert_eq!(
+            table(&rows).unwrap(),
+            concat!(
+                "+-----+-------+-----+-------+\n",
+                "| id  | name  | age | tags  |\n",
+                "+-----+-------+-----+-------+\n",
+                "| u1  | alice |   7 |       |\n",
+                "| u22 |       | 130 | [\"a\"] |\n",
+                "+-----+-------+-----+-------+\n",
+            )
+        );
+    }
+
+    #[test]
+    fn table_of_nothing() {
+        assert_eq!(table(&json!([])).unwrap(), "(no rows)\n");
+        assert_eq!(table(&json!(["a", "b"])), None);
+        assert_eq!(table(&json!("pong")), None);
+    }
+
+    #[test]
+    fn table_truncates_wide_cells() {
+        let long = "x".repeat(MAX_CELL + 5);
+        let out = table(&json!({"note": long})).unwrap();
+        let row = out.lines().nth(3).unwrap();
+        assert_eq!(row, format!("| {}… |", "x".repeat(MAX_CELL - 1)));
+        assert!(out.ends_with("(values longer than 40 characters truncated)\n"));
+    }
+
+    #[test]
+    fn colors_keys_and_scalars() {
+        let out = json(
+            &json!({"n": 1, "s": "x", "b": true, "z": null}),
+            false,
+            true,
+        );
+        assert_eq!(
+            out,
+            concat!(
+                "{\x1b[1;34m\"n\"\x1b[0m:\x1b[36m1\x1b[0m,",
+                "\x1b[1;34m\"s\"\x1b[0m:\x1b[32m\"x\"\x1b[0m,",
+                "\x1b[1;34m\"b\"\x1b[0m:\x1b[33mtrue\x1b[0m,",
+                "\x1b[1;34m\"z\"\x1b[0m:\x1b[90mnull\x1b[0m}"
+            )
+        );
+    }
+
+    #[test]
+    fn csv_cells_quote_only_when_needed() {
+        let row = csv_row(["plain", "a,b", "say \"hi\"", "two\nlines", ""].into_iter());
+        assert_eq!(row, "plain,\"a,b\",\"say \"\"hi\"\"\",\"two\nlines\",\n");
+    }
+
+    #[test]
+    fn csv_cells_show_non_strings_as_json() {
+        assert_eq!(csv_cell(&json!("Ada")), "Ada");
+        assert_eq!(csv_cell(&json!(null)), "");
+        assert_eq!(csv_cell(&json!(42)), "42");
+        assert_eq!(csv_cell(&json!(["a", 1])), r#"["a",1]"#);
+    }
+
+    #[test]
+    fn csv_has_a_column_per_key() {
+        let records = json!([{"id": "u1", "age": 36}, {"id": "u2", "email": "g@h.org"}]);
+        assert_eq!(
+            csv(&records).unwrap(),
+            "id,age,email\nu1,36,\nu2,,g@h.org\n"
+        );
+        assert_eq!(csv(&json!([])).unwrap(), "");
+        assert_eq!(csv(&json!(["u1"])), None);
+    }
+
+    #[test]
+    fn yaml_nests_blocks_and_quotes_ambiguous_strings() {
+        let value = json!([
+            {"id": "u1", "tags": ["a", "b"], "address": {"city": "Paris"}},
+            {"id": "yes", "age": 36, "joined": "2024-01-01", "note": "a: b", "none": []},
+            [1, [2]]
+        ]);
+        assert_eq!(
+            yaml(&value),
+            concat!(
+                "- id: u1\n",
+                "  tags:\n",
+                "    - a\n",
+                "    - b\n",
+                "  address:\n",
+                "    city: Paris\n",
+                "- id: \"yes\"\n",
+                "  age: 36\n",
+                "  joined: \"2024-01-01\"\n",
+                "  note: \"a: b\"\n",
+                "  none: []\n",
+                "- - 1\n",
+                "  - - 2\n",
+            )
+        );
+        assert_eq!(yaml(&json!("multi\nline")), "\"multi\\nline\"\n");
+        assert_eq!(yaml(&json!(null)), "null\n");
+    }
+}
@@ -0,0 +1,118 @@
+//! Checking records against a JSON Schema (draft 7) before they are written.
+
+use crate::error::Error;
+use std::fmt;
+use std::path::Path;
+
+/// A compiled draft 7 schema.
+pub struct Schema {
+    validator: jsonschema::Validator,
+}
+
+/// One way a record breaks the schema.
+#[derive(Debug, PartialEq)]
+pub struct Violation {
+    /// JSON pointer to the offending value, `/` for the record itself
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+impl Schema {
+    /// Load the schema in the file at `path`.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| Error::Input(format!("cannot read schema {}: {}", path.display(), e)))?;
+        Self::parse(&text).map_err(|e| match e {
+            Error::Input(reason) => Error::Input(format!("{}: {}", path.display(), reason)),
+            e => e,
+        })
+    }
+
+    /// Compile a schema from JSON text, rejecting anything that isn't a
+    /// valid draft 7 schema.
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let schema: serde_json::Value = serde_json::from_str(text)
+            .map_err(|e| Error::Input(format!("invalid schema JSON: {}", e)))?;
+        let validator = jsonschema::draft7::new(&schema)
+            .map_err(|e| Error::Input(format!("invalid schema: {}", e)))?;
+        Ok(Schema { validator })
+    }
+
+    /// Every way `record` breaks the schema; empty if it conforms.
+    pub fn violations(&self, record: &serde_json::Value) -> Vec<Violation> {
+        self.validator
+            .iter_errors(record)
+            .map(|e| {
+                let path = e.instance_path.to_string();
+                Violation {
+                    path: if path.is_empty() {
+                        "/".to_string()
+                    } else {
+                        path
+                    },
+                    message: e.to_string(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const USER_SCHEMA: &str = r#"{
+        "type": "object",
+        "required": ["id", "name"],
+        "properties": {
+            "id": {"type": "string"},
+            "name": {"type": "string"},
+            "age": {"type": "integer", "minimum": 0}
+        }
+    }"#;
+
+    #[test]
+    fn conforming_record_has_no_violations() {
+        let schema = Schema::parse(USER_SCHEMA).unwrap();
+        let record = json!({"id": "u1", "name": "alice", "age": 30});
+        assert_eq!(schema.violations(&record), []);
+    }
+
+    #[test]
+    fn violations_name_the_failing_field() {
+        let schema = Schema::parse(USER_SCHEMA).unwrap();
+
+        let violations = schema.violations(&json!({"id": "u1", "name": "bob", "age": "old"}));
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].path, "/age");
+        assert_eq!(
+            violations[0].to_string(),
+            r#"/age: "old" is not of type "integer""#
+        );
+
+        let violations = schema.violations(&json!({"id": "u2"}));
+        assert_eq!(violations[0].path, "/");
+        assert!(violations[0].message.contains("\"name\""));
+    }
+
+    #[test]
+    fn rejects_invalid_schemas() {
+        let err = Schema::parse(r#"{"type": 5}"#).err().unwrap();
+        assert_eq!(err.code(), "input");
+        assert!(err.to_string().starts_with("invalid schema: "), "{}", err);
+
+        let err = Schema::parse("{not json").err().unwrap();
+        assert!(
+            err.to_string().starts_with("invalid schema JSON: "),
+            "{}",
+            err
+        );
+    }
+}
@@ -0,0 +1,137 @@
+//! Points in time given on the command line or stored in records, as
+//! seconds since the Unix epoch.
+
+/// Seconds since the epoch for `text`: a plain number of epoch seconds, or
+/// an RFC 3339 timestamp. None if it's neither.
+pub fn parse(text: &str) -> Option<f64> {
+    let text = text.trim();
+    match text.parse::<f64>() {
+        Ok(secs) if secs.is_finite() => Some(secs),
+        Ok(_) => None,
+        Err(_) => parse_rfc3339(text),
+    }
+}
+
+/// Seconds since the epoch for an RFC 3339 timestamp such as
+/// `2024-01-15T10:30:00Z` or `2024-01-15T10:30:00.250+02:00`. A bare date
+/// means midnight UTC.
+pub fn parse_rfc3339(text: &str) -> Option<f64> {
+    let (date, time) = match text.find(['T', 't', ' ']) {
+        Some(at) => (&text[..at], Some(&text[at + 1..])),
+        None => (text, None),
+    };
+
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = digits(parts.next()?, 4)?;
+    let month: u32 = digits(parts.next()?, 2)?;
+    let day: u32 = digits(parts.next()?, 2)?;
+    if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+        return None;
+    }
+    let days = days_from_civil(year, month, day);
+
+    let Some(time) = time else {
+        return Some((days * 86_400) as f64);
+    };
+    let (clock, offset) = match time.find(['Z', 'z', '+', '-']) {
+        Some(at) => time.split_at(at),
+        None => return None,
+    };
+    let offset_secs = match offset {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = if offset.starts_with('-') { -1 } else { 1 };
+            let (hours, minutes) = offset[1..].split_once(':')?;
+            let (hours, minutes): (i64, i64) = (digits(hours, 2)?, digits(minutes, 2)?);
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            sign * (hours * 3600 + minutes * 60)
+        }
+    };
+
+    let (whole, fraction) = match clock.split_once('.') {
+        Some((whole, fraction)) if !fraction.is_empty() => {
+            let fraction: f64 = format!("0.{}", fraction).parse().ok()?;
+            (whole, fraction)
+        }
+        Some(_) => return None,
+        None => (clock, 0.0),
+    };
+    let mut hms = whole.splitn(3, ':');
+    let hour: i64 = digits(hms.next()?, 2)?;
+    let minute: i64 = digits(hms.next()?, 2)?;
+    let second: i64 = digits(hms.next()?, 2)?;
+    // 60 allows for a leap second
+    if hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let secs = days * 86_400 + hour * 3600 + minute * 60 + second - offset_secs;
+    Some(secs as f64 + fraction)
+}
+
+/// `text` as a number if it is exactly `len` ASCII digits.
+fn digits<T: std::str::FromStr>(text: &str, len: usize) -> Option<T> {
+    if text.len() != len || !text.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    text.parse().ok()
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days from 1970-01-01 to the given proleptic Gregorian date.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    // Count from March so the leap day falls at the end of the year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((i64::from(month) + 9) % 12) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn epoch_seconds() {
+        assert_eq!(parse("1700000000"), Some(1_700_000_000.0));
+        assert_eq!(parse("1700000000.5"), Some(1_700_000_000.5));
+        assert_eq!(parse("inf"), None);
+    }
+
+    #[test]
+    fn rfc3339_with_zone() {
+        assert_eq!(parse("1970-01-01T00:00:00Z"), Some(0.0));
+        assert_eq!(parse("2023-11-14T22:13:20Z"), Some(1_700_000_000.0));
+        assert_eq!(parse("2023-11-15T00:13:20+02:00"), Some(1_700_000_000.0));
+        assert_eq!(parse("2023-11-14T22:13:20.25z"), Some(1_700_000_000.25));
+        assert_eq!(parse("2024-02-29"), Some(1_709_164_800.0));
+        assert_eq!(parse("1969-12-31T23:59:59Z"), Some(-1.0));
+    }
+
+    #[test]
+    fn rejects_malformed_times() {
+        for text in [
+            "yesterday",
+            "2024-13-01",
+            "2023-02-29",
+            "2024-01-15T10:30:00",
+            "2024-01-15T25:00:00Z",
+            "2024-1-15",
+            "2024-01-15T10:30Z",
+        ] {
+            assert_eq!(parse(text), None, "{}", text);
+        }
+    }
+}
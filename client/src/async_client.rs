@@ -0,0 +1,483 @@
+//! A non-blocking client for tokio applications.
+
+use crate::client::{
+    applied_count, cas_put_params, create_table_params, json_list, json_params, json_result,
+    key_params, put_many_params, record_or_none, table_names, written_or_not,
+};
+use crate::connection::{decode_put_many, decode_response};
+use crate::error::Error;
+use crate::json::input_to_msgpack;
+use crate::txn::{transaction_params, Op};
+use rmpv::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::unix::OwnedWriteHalf;
+use tokio::net::UnixStream;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+/// Requests waiting for their response, by msgid. None once the connection
+/// is gone and no more responses will arrive.
+type Pending = Arc<Mutex<Option<HashMap<u32, oneshot::Sender<Result<Value, Error>>>>>>;
+
+/// An async counterpart to [`Client`](crate::Client) with the same
+/// methods, taking `&self` so it can be shared (e.g. in an `Arc`) by many
+/// tasks.
+///
+/// Requests from every task go out over one connection as soon as they are
+/// made; a background task reads the responses and hands each to the
+/// request with its msgid, in whatever order the daemon answers.
+pub struct AsyncClient {
+    writer: tokio::sync::Mutex<OwnedWriteHalf>,
+    pending: Pending,
+    next_msgid: AtomicU32,
+    reader: JoinHandle<()>,
+}
+
+impl AsyncClient {
+    /// Connect to the daemon listening on `socket`. Must be called within
+    /// a tokio runtime.
+    pub async fn connect(socket: &str) -> Result<Self, Error> {
+        let stream = UnixStream::connect(socket)
+            .await
+            .map_err(|e| Error::io(&format!("cannot connect to {}", socket), e))?;
+        Ok(Self::from_stream(stream))
+    }
+
+    /// Talk to the daemon over an already connected stream.
+    pub fn from_stream(stream: UnixStream) -> Self {
+        let (reader, writer) = stream.into_split();
+        let pending: Pending = Arc::new(Mutex::new(Some(HashMap::new())));
+        AsyncClient {
+            writer: tokio::sync::Mutex::new(writer),
+            pending: pending.clone(),
+            next_msgid: AtomicU32::new(1),
+            reader: tokio::spawn(read_responses(reader, pending)),
+        }
+    }
+
+    /// Send a request and wait for its result.
+    pub async fn call_raw(&self, method: &str, params: Vec<Value>) -> Result<Option<Value>, Error> {
+        // Wrap to 1, never 0, which some peers treat as "no id"
+        let msgid = match self.next_msgid.fetch_add(1, Ordering::Relaxed) {
+            0 => self.next_msgid.fetch_add(1, Ordering::Relaxed),
+            id => id,
+        };
+        let request = Value::Array(vec![
+            Value::Integer(0.into()),
+            Value::Integer(msgid.into()),
+            Value::String(method.into()),
+            Value::Array(params),
+        ]);
+        let mut buf = Vec::new();
+        rmpv::encode::write_value(&mut buf, &request)
+            .map_err(|e| Error::Protocol(format!("encode error: {}", e)))?;
+
+        let (tx, rx) = oneshot::channel();
+        match self.pending.lock().unwrap().as_mut() {
+            Some(waiting) => waiting.insert(msgid, tx),
+            None => return Err(closed()),
+        };
+        let sent = self.writer.lock().await.write_all(&buf).await;
+        if let Err(e) = sent {
+            if let Some(waiting) = self.pending.lock().unwrap().as_mut() {
+                waiting.remove(&msgid);
+            }
+            return Err(Error::io("write error", e));
+        }
+
+        let response = rx.await.map_err(|_| closed())??;
+        decode_response(response)
+    }
+
+    /// Call any method with JSON params, returning its JSON result.
+    pub async fn call(
+        &self,
+        method: &str,
+        params: &[serde_json::Value],
+    ) -> Result<serde_json::Value, Error> {
+        let result = self.call_raw(method, json_params(params)?).await?;
+        Ok(json_result(result))
+    }
+
+    /// Check the daemon is answering.
+    pub async fn ping(&self) -> Result<(), Error> {
+        self.call_raw("ping", vec![]).await.map(|_| ())
+    }
+
+    /// Names of the tables the caller can see.
+    pub async fn tables(&self) -> Result<Vec<String>, Error> {
+        table_names(self.call_raw("tables", vec![]).await?)
+    }
+
+    /// Create `table` with these attributes; the first is the primary key.
+    pub async fn create_table(&self, table: &str, attributes: &[&str]) -> Result<(), Error> {
+        self.call_raw("create_table", create_table_params(table, attributes))
+            .await
+            .map(|_| ())
+    }
+
+    /// Drop `table` and every record in it.
+    pub async fn drop_table(&self, table: &str) -> Result<(), Error> {
+        self.call_raw("drop_table", vec![Value::from(table)])
+            .await
+            .map(|_| ())
+    }
+
+    /// The record with this key, or None if there isn't one.
+    pub async fn get(
+        &self,
+        table: &str,
+        key: impl Into<serde_json::Value>,
+    ) -> Result<Option<serde_json::Value>, Error> {
+        record_or_none(self.call_raw("get", key_params(table, key)).await)
+    }
+
+    /// Insert `record`, or replace the one with the same key.
+    pub async fn put(&self, table: &str, record: &serde_json::Value) -> Result<(), Error> {
+        let params = vec![Value::from(table), input_to_msgpack(record)?];
+        self.call_raw("put", params).await.map(|_| ())
+    }
+
+    /// Write `record` only if the stored record with its key equals
+    /// `expected`, or, with `expected` None, only if there is no such record
+    /// yet. Gives false, having written nothing, if the condition didn't
+    /// hold.
+    pub async fn cas_put(
+        &self,
+        table: &str,
+        record: &serde_json::Value,
+        expected: Option<&serde_json::Value>,
+    ) -> Result<bool, Error> {
+        let params = cas_put_params(table, record, expected)?;
+        written_or_not(self.call_raw("cas_put", params).await)
+    }
+
+    /// Insert or replace each of `records` in one request. Each record
+    /// succeeds or fails on its own; the outcomes are in the same order.
+    pub async fn put_many(
+        &self,
+        table: &str,
+        records: &[serde_json::Value],
+    ) -> Result<Vec<Result<(), Error>>, Error> {
+        let params = put_many_params(table, records)?;
+        decode_put_many(self.call_raw("put_many", params).await?, records.len())
+    }
+
+    /// Apply `ops` in one transaction: all of them, or none if any fails.
+    /// Gives how many were applied.
+    pub async fn transaction(&self, ops: &[Op]) -> Result<u64, Error> {
+        let result = self
+            .call_raw("transaction", transaction_params(ops)?)
+            .await?;
+        applied_count(result)
+    }
+
+    /// Delete the record with this key.
+    pub async fn delete(
+        &self,
+        table: &str,
+        key: impl Into<serde_json::Value>,
+    ) -> Result<(), Error> {
+        self.call_raw("delete", key_params(table, key))
+            .await
+            .map(|_| ())
+    }
+
+    /// Records whose fields equal every field of `pattern`.
+    pub async fn query(
+        &self,
+        table: &str,
+        pattern: &serde_json::Value,
+    ) -> Result<Vec<serde_json::Value>, Error> {
+        let params = vec![Value::from(table), input_to_msgpack(pattern)?];
+        json_list("match", self.call_raw("match", params).await?)
+    }
+
+    /// Every record in `table`.
+    pub async fn all(&self, table: &str) -> Result<Vec<serde_json::Value>, Error> {
+        json_list("all", self.call_raw("all", vec![Value::from(table)]).await?)
+    }
+
+    /// The primary key of every record in `table`.
+    pub async fn keys(&self, table: &str) -> Result<Vec<serde_json::Value>, Error> {
+        json_list(
+            "keys",
+            self.call_raw("keys", vec![Value::from(table)]).await?,
+        )
+    }
+}
+
+impl Drop for AsyncClient {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+fn closed() -> Error {
+    Error::Connection("connection closed".to_string())
+}
+
+/// Hand each response to the request waiting for it until the connection
+/// fails, then fail every request still waiting.
+async fn read_responses(mut reader: impl AsyncRead + Unpin, pending: Pending) {
+    let mut buf = Vec::new();
+    let mut framer = Framer::default();
+    let error = loop {
+        let message = match read_message(&mut reader, &mut buf, &mut framer).await {
+            Ok(message) => message,
+            Err(e) => break e,
+        };
+        // Notifications ([2, method, params]) have no one waiting for them
+        let msgid = match message.as_array().map(Vec::as_slice) {
+            Some([kind, msgid, _, _]) if kind.as_u64() == Some(1) => msgid.as_u64(),
+            _ => continue,
+        };
+        let waiting = msgid.and_then(|id| {
+            let id = u32::try_from(id).ok()?;
+            pending.lock().unwrap().as_mut()?.remove(&id)
+        });
+        if let Some(tx) = waiting {
+            let _ = tx.send(Ok(message));
+        }
+    };
+
+    let waiting = pending.lock().unwrap().take();
+    for (_, tx) in waiting.into_iter().flatten() {
+        let _ = tx.send(Err(error.clone()));
+    }
+}
+
+/// Read until `buf` holds a whole message, then decode it and drop its
+/// bytes from `buf`.
+async fn read_message(
+    reader: &mut (impl AsyncRead + Unpin),
+    buf: &mut Vec<u8>,
+    framer: &mut Framer,
+) -> Result<Value, Error> {
+    loop {
+        if let Some(len) = framer.scan(buf) {
+            let message = rmpv::decode::read_value(&mut &buf[..len])
+                .map_err(|e| Error::Protocol(format!("decode error: {}", e)));
+            buf.drain(..len);
+            return message;
+        }
+        let received = buf.len();
+        let read = reader
+            .read_buf(buf)
+            .await
+            .map_err(|e| Error::io("read error", e))?;
+        if read == 0 {
+            return Err(match received {
+                0 => closed(),
+                _ => Error::Connection(format!(
+                    "connection closed before full response (got {} bytes)",
+                    received
+                )),
+            });
+        }
+    }
+}
+
+/// Finds where a MessagePack value ends as its bytes arrive, resuming each
+/// scan where the last one ran out of input so a large message is walked
+/// only once.
+#[derive(Default)]
+struct Framer {
+    /// Bytes of the current message accounted for so far
+    pos: usize,
+    /// Values still to come at each level of nesting, innermost last
+    remaining: Vec<u64>,
+}
+
+impl Framer {
+    /// The length of the message at the start of `buf` once it is all
+    /// there, resetting for the next message.
+    fn scan(&mut self, buf: &[u8]) -> Option<usize> {
+        if self.pos == 0 && self.remaining.is_empty() {
+            self.remaining.push(1);
+        }
+        loop {
+            while self.remaining.last() == Some(&0) {
+                self.remaining.pop();
+            }
+            if self.remaining.is_empty() {
+                return Some(std::mem::take(&mut self.pos));
+            }
+            let (len, children) = item_header(&buf[self.pos..])?;
+            if buf.len() - self.pos < len {
+                return None;
+            }
+            self.pos += len;
+            if let Some(top) = self.remaining.last_mut() {
+                *top -= 1;
+            }
+            if children > 0 {
+                self.remaining.push(children);
+            }
+        }
+    }
+}
+
+/// For the item starting `bytes`: its own length (marker, length fields,
+/// and any string, binary, or extension payload) and how many values
+/// follow it as its elements. None if `bytes` cuts off its length field.
+fn item_header(bytes: &[u8]) -> Option<(usize, u64)> {
+    let size = |width: usize| -> Option<usize> {
+        let field = bytes.get(1..1 + width)?;
+        Some(field.iter().fold(0, |n, &b| n << 8 | usize::from(b)))
+    };
+    let item = match *bytes.first()? {
+        0x00..=0x7f | 0xc0..=0xc3 | 0xe0..=0xff => (1, 0),
+        m @ 0x80..=0x8f => (1, 2 * u64::from(m & 0x0f)),
+        m @ 0x90..=0x9f => (1, u64::from(m & 0x0f)),
+        m @ 0xa0..=0xbf => (1 + usize::from(m & 0x1f), 0),
+        0xc4 | 0xd9 => (2 + size(1)?, 0),
+        0xc5 | 0xda => (3 + size(2)?, 0),
+        0xc6 | 0xdb => (5 + size(4)?, 0),
+        0xc7 => (3 + size(1)?, 0),
+        0xc8 => (4 + size(2)?, 0),
+        0xc9 => (6 + size(4)?, 0),
+        0xca | 0xce | 0xd2 => (5, 0),
+        0xcb | 0xcf | 0xd3 => (9, 0),
+        0xcc | 0xd0 => (2, 0),
+        0xcd | 0xd1 => (3, 0),
+        0xd4 => (3, 0),
+        0xd5 => (4, 0),
+        0xd6 => (6, 0),
+        0xd7 => (10, 0),
+        0xd8 => (18, 0),
+        0xdc => (3, size(2)? as u64),
+        0xdd => (5, size(4)? as u64),
+        0xde => (3, 2 * size(2)? as u64),
+        0xdf => (5, 2 * size(4)? as u64),
+    };
+    Some(item)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn encode(value: &Value) -> Vec<u8> {
+        let mut buf = Vec::new();
+        rmpv::encode::write_value(&mut buf, value).unwrap();
+        buf
+    }
+
+    fn response(msgid: &Value, result: Value) -> Vec<u8> {
+        encode(&Value::Array(vec![
+            1.into(),
+            msgid.clone(),
+            Value::Nil,
+            result,
+        ]))
+    }
+
+    #[test]
+    fn framer_finds_message_ends_across_partial_input() {
+        let first = encode(&Value::Array(vec![
+            Value::from("x".repeat(300)),
+            Value::Map(vec![(Value::from(1), Value::Binary(vec![0; 70_000]))]),
+            Value::F64(1.5),
+            Value::Ext(3, vec![1, 2]),
+        ]));
+        let second = encode(&Value::from(-7));
+        let mut bytes = first.clone();
+        bytes.extend(&second);
+
+        let mut framer = Framer::default();
+        for end in 0..first.len() {
+            assert_eq!(framer.scan(&bytes[..end]), None, "at {}", end);
+        }
+        assert_eq!(framer.scan(&bytes), Some(first.len()));
+        assert_eq!(framer.scan(&second), Some(second.len()));
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_get_their_own_responses() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let client = AsyncClient::from_stream(client);
+
+        let daemon = tokio::spawn(async move {
+            let (mut reader, mut writer) = server.into_split();
+            let (mut buf, mut framer) = (Vec::new(), Framer::default());
+            let mut requests = Vec::new();
+            for _ in 0..2 {
+                requests.push(
+                    read_message(&mut reader, &mut buf, &mut framer)
+                        .await
+                        .unwrap(),
+                );
+            }
+            // Answer in reverse order, the second one split across writes
+            let get = requests
+                .iter()
+                .find(|r| r[2].as_str() == Some("get"))
+                .unwrap();
+            let ping = requests
+                .iter()
+                .find(|r| r[2].as_str() == Some("ping"))
+                .unwrap();
+            let record =
+                crate::json::json_to_msgpack(&json!({"id": "u1", "bio": "x".repeat(5000)}));
+            writer.write_all(&response(&get[1], record)).await.unwrap();
+            let pong = response(&ping[1], Value::from("pong"));
+            for chunk in pong.chunks(3) {
+                writer.write_all(chunk).await.unwrap();
+                tokio::task::yield_now().await;
+            }
+        });
+
+        let (pong, record) = tokio::join!(client.ping(), client.get("users", "u1"));
+        pong.unwrap();
+        assert_eq!(record.unwrap().unwrap()["bio"], json!("x".repeat(5000)));
+        daemon.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn pending_requests_fail_when_the_daemon_hangs_up() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let client = AsyncClient::from_stream(client);
+
+        let daemon = tokio::spawn(async move {
+            let (mut reader, _writer) = server.into_split();
+            let (mut buf, mut framer) = (Vec::new(), Framer::default());
+            read_message(&mut reader, &mut buf, &mut framer)
+                .await
+                .unwrap();
+        });
+
+        let err = client.all("users").await.unwrap_err();
+        daemon.await.unwrap();
+        assert_eq!(err, Error::Connection("connection closed".to_string()));
+        assert_eq!(client.ping().await.unwrap_err(), err);
+    }
+
+    #[tokio::test]
+    async fn daemon_errors_reach_the_caller() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let client = AsyncClient::from_stream(client);
+
+        let daemon = tokio::spawn(async move {
+            let (mut reader, mut writer) = server.into_split();
+            let (mut buf, mut framer) = (Vec::new(), Framer::default());
+            let request = read_message(&mut reader, &mut buf, &mut framer)
+                .await
+                .unwrap();
+            let reply = Value::Array(vec![
+                1.into(),
+                request[1].clone(),
+                "not_found".into(),
+                Value::Nil,
+            ]);
+            writer.write_all(&encode(&reply)).await.unwrap();
+        });
+
+        assert_eq!(client.get("users", "u9").await.unwrap(), None);
+        daemon.await.unwrap();
+    }
+}
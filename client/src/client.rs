@@ -0,0 +1,450 @@
+use crate::connection::{decode_notification, decode_put_many, Connection};
+use crate::error::Error;
+use crate::json::{input_to_msgpack, json_to_msgpack, msgpack_to_json};
+use crate::txn::{transaction_params, Op};
+use rmpv::Value;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// A typed handle on the daemon, speaking JSON.
+///
+/// Each method is one request over a persistent connection. Records, keys,
+/// and patterns are `serde_json` values, converted as described in
+/// [`crate::json`]. For methods without a wrapper, use [`Client::call`].
+pub struct Client {
+    conn: Connection,
+}
+
+impl Client {
+    /// Connect to the daemon listening on `socket`.
+    pub fn connect(socket: &str) -> Result<Self, Error> {
+        Connection::new(socket).map(Self::from_connection)
+    }
+
+    /// Wrap a connection that is already set up, e.g. with a timeout.
+    pub fn from_connection(conn: Connection) -> Self {
+        Client { conn }
+    }
+
+    /// Fail requests the daemon takes longer than `timeout` to answer.
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> Result<(), Error> {
+        self.conn.set_timeout(timeout)
+    }
+
+    /// The underlying connection, for pipelining or streaming results.
+    pub fn connection(&mut self) -> &mut Connection {
+        &mut self.conn
+    }
+
+    /// Call any method with JSON params, returning its JSON result.
+    pub fn call(
+        &mut self,
+        method: &str,
+        params: &[serde_json::Value],
+    ) -> Result<serde_json::Value, Error> {
+        let result = self.conn.call(method, json_params(params)?)?;
+        Ok(json_result(result))
+    }
+
+    /// Check the daemon is answering.
+    pub fn ping(&mut self) -> Result<(), Error> {
+        self.conn.call("ping", vec![]).map(|_| ())
+    }
+
+    /// Names of the tables the caller can see.
+    pub fn tables(&mut self) -> Result<Vec<String>, Error> {
+        table_names(self.conn.call("tables", vec![])?)
+    }
+
+    /// Create `table` with these attributes; the first is the primary key.
+    pub fn create_table(&mut self, table: &str, attributes: &[&str]) -> Result<(), Error> {
+        self.conn
+            .call("create_table", create_table_params(table, attributes))
+            .map(|_| ())
+    }
+
+    /// Drop `table` and every record in it.
+    pub fn drop_table(&mut self, table: &str) -> Result<(), Error> {
+        self.conn
+            .call("drop_table", vec![Value::from(table)])
+            .map(|_| ())
+    }
+
+    /// The record with this key, or None if there isn't one.
+    pub fn get(
+        &mut self,
+        table: &str,
+        key: impl Into<serde_json::Value>,
+    ) -> Result<Option<serde_json::Value>, Error> {
+        record_or_none(self.conn.call("get", key_params(table, key)))
+    }
+
+    /// Insert `record`, or replace the one with the same key.
+    pub fn put(&mut self, table: &str, record: &serde_json::Value) -> Result<(), Error> {
+        let params = vec![Value::from(table), input_to_msgpack(record)?];
+        self.conn.call("put", params).map(|_| ())
+    }
+
+    /// Write `record` only if the stored record with its key equals
+    /// `expected`, or, with `expected` None, only if there is no such record
+    /// yet. Gives false, having written nothing, if the condition didn't
+    /// hold.
+    pub fn cas_put(
+        &mut self,
+        table: &str,
+        record: &serde_json::Value,
+        expected: Option<&serde_json::Value>,
+    ) -> Result<bool, Error> {
+        let params = cas_put_params(table, record, expected)?;
+        written_or_not(self.conn.call("cas_put", params))
+    }
+
+    /// Insert or replace each of `records` in one request. Each record
+    /// succeeds or fails on its own; the outcomes are in the same order.
+    pub fn put_many(
+        &mut self,
+        table: &str,
+        records: &[serde_json::Value],
+    ) -> Result<Vec<Result<(), Error>>, Error> {
+        let params = put_many_params(table, records)?;
+        decode_put_many(self.conn.call("put_many", params)?, records.len())
+    }
+
+    /// Delete the record with this key.
+    pub fn delete(&mut self, table: &str, key: impl Into<serde_json::Value>) -> Result<(), Error> {
+        self.conn.call("delete", key_params(table, key)).map(|_| ())
+    }
+
+    /// Apply `ops` in one transaction: all of them, or none if any fails.
+    /// Gives how many were applied.
+    pub fn transaction(&mut self, ops: &[Op]) -> Result<u64, Error> {
+        let result = self.conn.call("transaction", transaction_params(ops)?)?;
+        applied_count(result)
+    }
+
+    /// Records whose fields equal every field of `pattern`.
+    pub fn query(
+        &mut self,
+        table: &str,
+        pattern: &serde_json::Value,
+    ) -> Result<Vec<serde_json::Value>, Error> {
+        let params = vec![Value::from(table), input_to_msgpack(pattern)?];
+        json_list("match", self.conn.call("match", params)?)
+    }
+
+    /// Every record in `table`.
+    pub fn all(&mut self, table: &str) -> Result<Vec<serde_json::Value>, Error> {
+        json_list("all", self.conn.call("all", vec![Value::from(table)])?)
+    }
+
+    /// The primary key of every record in `table`.
+    pub fn keys(&mut self, table: &str) -> Result<Vec<serde_json::Value>, Error> {
+        json_list("keys", self.conn.call("keys", vec![Value::from(table)])?)
+    }
+
+    /// Subscribe to changes to `table`, turning the connection into a
+    /// stream of them. See [`Changes`].
+    pub fn watch(mut self, table: &str) -> Result<Changes, Error> {
+        self.conn.call("subscribe", vec![Value::from(table)])?;
+        Ok(Changes {
+            conn: self.conn,
+            pending: VecDeque::new(),
+        })
+    }
+}
+
+/// The changes to a table, in the order the daemon made them, from
+/// [`Client::watch`].
+///
+/// Each is `{"op": "write", "table", "key", "record"}` or
+/// `{"op": "delete", "table", "key"}`. Iteration blocks until the next
+/// change and ends when the daemon closes the connection. With a timeout
+/// set, a quiet spell gives [`Error::Timeout`], after which iterating can
+/// carry on.
+pub struct Changes {
+    conn: Connection,
+    pending: VecDeque<serde_json::Value>,
+}
+
+impl Iterator for Changes {
+    type Item = Result<serde_json::Value, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(change) = self.pending.pop_front() {
+                return Some(Ok(change));
+            }
+            match self.conn.at_eof() {
+                Ok(true) => return None,
+                Ok(false) => {}
+                Err(e) => return Some(Err(e)),
+            }
+            match self.conn.recv() {
+                Ok(message) => {
+                    if let Some(("change", changes)) = decode_notification(&message) {
+                        self.pending.extend(changes.iter().map(msgpack_to_json));
+                    }
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+// Request params and result handling shared with `AsyncClient`
+
+pub(crate) fn json_params(params: &[serde_json::Value]) -> Result<Vec<Value>, Error> {
+    params.iter().map(input_to_msgpack).collect()
+}
+
+pub(crate) fn json_result(result: Option<Value>) -> serde_json::Value {
+    result
+        .as_ref()
+        .map_or(serde_json::Value::Null, msgpack_to_json)
+}
+
+pub(crate) fn key_params(table: &str, key: impl Into<serde_json::Value>) -> Vec<Value> {
+    vec![Value::from(table), json_to_msgpack(&key.into())]
+}
+
+pub(crate) fn put_many_params(
+    table: &str,
+    records: &[serde_json::Value],
+) -> Result<Vec<Value>, Error> {
+    Ok(vec![
+        Value::from(table),
+        Value::Array(json_params(records)?),
+    ])
+}
+
+pub(crate) fn cas_put_params(
+    table: &str,
+    record: &serde_json::Value,
+    expected: Option<&serde_json::Value>,
+) -> Result<Vec<Value>, Error> {
+    let expected = expected.map(input_to_msgpack).transpose()?;
+    Ok(vec![
+        Value::from(table),
+        input_to_msgpack(record)?,
+        expected.unwrap_or(Value::Nil),
+    ])
+}
+
+/// A `cas_put` result, with a failed condition as false.
+pub(crate) fn written_or_not(result: Result<Option<Value>, Error>) -> Result<bool, Error> {
+    match result {
+        Ok(_) => Ok(true),
+        Err(e) if e.reason() == Some("condition_failed") => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+pub(crate) fn create_table_params(table: &str, attributes: &[&str]) -> Vec<Value> {
+    let attributes = attributes.iter().map(|&a| Value::from(a)).collect();
+    vec![Value::from(table), Value::Array(attributes)]
+}
+
+pub(crate) fn table_names(result: Option<Value>) -> Result<Vec<String>, Error> {
+    match result {
+        Some(Value::Array(names)) => Ok(names
+            .iter()
+            .filter_map(|n| n.as_str().map(str::to_string))
+            .collect()),
+        _ => Err(Error::Protocol("expected a list of tables".to_string())),
+    }
+}
+
+pub(crate) fn applied_count(result: Option<Value>) -> Result<u64, Error> {
+    result
+        .as_ref()
+        .and_then(Value::as_u64)
+        .ok_or_else(|| Error::Protocol("expected a count from transaction".to_string()))
+}
+
+/// A `get` result as JSON, with a missing record as None.
+pub(crate) fn record_or_none(
+    result: Result<Option<Value>, Error>,
+) -> Result<Option<serde_json::Value>, Error> {
+    match result {
+        Ok(None | Some(Value::Nil)) => Ok(None),
+        Ok(Some(record)) => Ok(Some(msgpack_to_json(&record))),
+        Err(e) if e.reason() == Some("not_found") => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub(crate) fn json_list(
+    method: &str,
+    result: Option<Value>,
+) -> Result<Vec<serde_json::Value>, Error> {
+    match result {
+        Some(Value::Array(items)) => Ok(items.iter().map(msgpack_to_json).collect()),
+        _ => Err(Error::Protocol(format!("expected a list from {}", method))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::os::unix::net::UnixStream;
+
+    /// A client whose daemon answers each request with the next of
+    /// `replies` (an error string or a result), returning the requests.
+    fn mock(
+        replies: Vec<Result<Value, &'static str>>,
+    ) -> (Client, std::thread::JoinHandle<Vec<Value>>) {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let handle = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for reply in replies {
+                let request = rmpv::decode::read_value(&mut server).unwrap();
+                let (error, result) = match reply {
+                    Ok(result) => (Value::Nil, result),
+                    Err(e) => (Value::from(e), Value::Nil),
+                };
+                let response = Value::Array(vec![1.into(), request[1].clone(), error, result]);
+                rmpv::encode::write_value(&mut server, &response).unwrap();
+                requests.push(request);
+            }
+            requests
+        });
+        (
+            Client::from_connection(Connection::from_stream(client)),
+            handle,
+        )
+    }
+
+    #[test]
+    fn get_converts_records_and_treats_not_found_as_none() {
+        let record = json_to_msgpack(&json!({"id": "u1", "tags": ["a"]}));
+        let (mut client, server) = mock(vec![Ok(record), Err("not_found")]);
+
+        assert_eq!(
+            client.get("users", "u1").unwrap(),
+            Some(json!({"id": "u1", "tags": ["a"]}))
+        );
+        assert_eq!(client.get("users", 2).unwrap(), None);
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0][2].as_str(), Some("get"));
+        assert_eq!(requests[1][3], Value::Array(vec!["users".into(), 2.into()]));
+    }
+
+    #[test]
+    fn put_and_query_send_json_as_msgpack() {
+        let found = Value::Array(vec![json_to_msgpack(&json!({"id": "u1", "role": "admin"}))]);
+        let (mut client, server) = mock(vec![Ok(Value::from("ok")), Ok(found)]);
+
+        client
+            .put(
+                "users",
+                &json!({"id": "u1", "key": {"__bin__hex__": "ff00"}}),
+            )
+            .unwrap();
+        let matches = client.query("users", &json!({"role": "admin"})).unwrap();
+        assert_eq!(matches, [json!({"id": "u1", "role": "admin"})]);
+
+        let requests = server.join().unwrap();
+        let record = &requests[0][3][1];
+        assert_eq!(record["key"], Value::Binary(vec![0xff, 0x00]));
+        assert_eq!(requests[1][2].as_str(), Some("match"));
+    }
+
+    #[test]
+    fn cas_put_reports_whether_the_condition_held() {
+        let (mut client, server) = mock(vec![Ok(Value::from("ok")), Err("condition_failed")]);
+
+        let record = json!({"id": "t1", "state": "running"});
+        assert!(client.cas_put("tasks", &record, None).unwrap());
+        let expected = json!({"id": "t1", "state": "pending"});
+        assert!(!client.cas_put("tasks", &record, Some(&expected)).unwrap());
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0][2].as_str(), Some("cas_put"));
+        assert_eq!(requests[0][3][2], Value::Nil);
+        assert_eq!(requests[1][3][2], json_to_msgpack(&expected));
+    }
+
+    #[test]
+    fn put_many_gives_an_outcome_per_record() {
+        let outcomes = Value::Array(vec![
+            Value::from("ok"),
+            Value::Map(vec![(Value::from("error"), Value::from("missing_key"))]),
+        ]);
+        let (mut client, server) = mock(vec![Ok(outcomes)]);
+
+        let records = [json!({"id": "u1"}), json!({"name": "no key"})];
+        assert_eq!(
+            client.put_many("users", &records).unwrap(),
+            [Ok(()), Err(Error::Daemon("missing_key".to_string()))]
+        );
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0][2].as_str(), Some("put_many"));
+        assert_eq!(requests[0][3][1].as_array().map(Vec::len), Some(2));
+    }
+
+    #[test]
+    fn watch_yields_each_change_until_the_daemon_closes() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let handle = std::thread::spawn(move || {
+            let request = rmpv::decode::read_value(&mut server).unwrap();
+            let reply = Value::Array(vec![1.into(), request[1].clone(), Value::Nil, "ok".into()]);
+            rmpv::encode::write_value(&mut server, &reply).unwrap();
+            let change = |op: &str, key: &str| json_to_msgpack(&json!({"op": op, "key": key}));
+            let notifications = [
+                vec![change("write", "a"), change("write", "b")],
+                vec![change("delete", "a")],
+            ];
+            for changes in notifications {
+                let message = Value::Array(vec![2.into(), "change".into(), Value::Array(changes)]);
+                rmpv::encode::write_value(&mut server, &message).unwrap();
+            }
+            request
+        });
+
+        let client = Client::from_connection(Connection::from_stream(client));
+        let changes: Vec<serde_json::Value> = client
+            .watch("users")
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            changes,
+            [
+                json!({"op": "write", "key": "a"}),
+                json!({"op": "write", "key": "b"}),
+                json!({"op": "delete", "key": "a"}),
+            ]
+        );
+        let request = handle.join().unwrap();
+        assert_eq!(request[2].as_str(), Some("subscribe"));
+    }
+
+    #[test]
+    fn transaction_sends_every_op_in_one_request() {
+        let (mut client, server) = mock(vec![Ok(Value::from(2))]);
+
+        let ops = [
+            Op::put("orders", json!({"id": "o1", "state": "paid"})),
+            Op::delete("carts", "c1"),
+        ];
+        assert_eq!(client.transaction(&ops).unwrap(), 2);
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0][2].as_str(), Some("transaction"));
+        let sent = &requests[0][3][0];
+        assert_eq!(sent[0]["op"].as_str(), Some("put"));
+        assert_eq!(sent[1]["key"].as_str(), Some("c1"));
+    }
+
+    #[test]
+    fn daemon_errors_pass_through() {
+        let (mut client, server) = mock(vec![Err("access_denied")]);
+
+        let err = client.delete("users", "u1").unwrap_err();
+        assert_eq!(err, Error::Daemon("access_denied".to_string()));
+        server.join().unwrap();
+    }
+}
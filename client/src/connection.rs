@@ -0,0 +1,807 @@
+use crate::error::Error;
+use rmpv::Value;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+/// Wire protocol version sent in the `hello` handshake. Daemons with a
+/// different major version may not understand our requests.
+pub const PROTOCOL_VERSION: &str = "1.0";
+
+/// Methods after which a cached schema for their table (the first param)
+/// is stale.
+const SCHEMA_CHANGING_METHODS: [&str; 3] = ["create_table", "drop_table", "alter_table"];
+
+/// Daemon errors containing any of these are retried by default under
+/// `--retry-on-busy`: Mnesia reports lock contention and transactions that
+/// ran out of time this way, and both usually succeed a moment later.
+pub const DEFAULT_BUSY_PATTERNS: [&str; 2] = ["busy", "timeout"];
+
+/// How `call` retries requests the daemon rejected as transiently busy.
+pub struct BusyRetry {
+    /// Retries after the first attempt
+    pub attempts: u32,
+    /// A daemon error containing any of these is transient
+    pub patterns: Vec<String>,
+    /// Wait before the first retry, growing linearly after that
+    pub delay: Duration,
+}
+
+/// A persistent MessagePack-RPC connection to the daemon.
+///
+/// Responses are decoded straight off a buffered reader, so bytes belonging
+/// to a later response stay buffered for the next `recv` and a response
+/// larger than one socket read is assembled across as many reads as needed.
+pub struct Connection {
+    reader: BufReader<UnixStream>,
+    trace: Option<Box<dyn Write + Send>>,
+    next_msgid: u32,
+    schemas: HashMap<String, Option<Value>>,
+    /// Bytes of the response being read so far, for EOF errors
+    received: usize,
+    /// Abort a response once it grows past this many bytes
+    max_response: Option<usize>,
+    busy_retry: Option<BusyRetry>,
+}
+
+/// Marks the read error raised when a response outgrows `max_response`.
+#[derive(Debug)]
+struct ResponseTooLarge(usize);
+
+impl std::fmt::Display for ResponseTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "response over {} bytes", self.0)
+    }
+}
+
+impl std::error::Error for ResponseTooLarge {}
+
+impl Connection {
+    pub fn new(socket_path: &str) -> Result<Self, Error> {
+        let stream = UnixStream::connect(socket_path)
+            .map_err(|e| Error::io(&format!("cannot connect to {}", socket_path), e))?;
+        Ok(Self::from_stream(stream))
+    }
+
+    pub fn from_stream(stream: UnixStream) -> Self {
+        Connection {
+            reader: BufReader::new(stream),
+            trace: None,
+            next_msgid: 1,
+            schemas: HashMap::new(),
+            received: 0,
+            max_response: None,
+            busy_retry: None,
+        }
+    }
+
+    /// Fail reads and writes that block longer than `timeout` (None waits forever).
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> Result<(), Error> {
+        let stream = self.reader.get_ref();
+        stream
+            .set_read_timeout(timeout)
+            .and_then(|_| stream.set_write_timeout(timeout))
+            .map_err(|e| Error::io("cannot set timeout", e))
+    }
+
+    /// Log every request (decoded and as hex) and response to `sink`.
+    pub fn with_trace(mut self, sink: Box<dyn Write + Send>) -> Self {
+        self.trace = Some(sink);
+        self
+    }
+
+    /// Give up on any response larger than `limit` bytes instead of reading
+    /// however much the daemon sends.
+    pub fn with_max_response_size(mut self, limit: usize) -> Self {
+        self.max_response = Some(limit);
+        self
+    }
+
+    /// Have `call` resend requests that fail with a transient daemon error.
+    pub fn with_busy_retry(mut self, retry: BusyRetry) -> Self {
+        self.busy_retry = Some(retry);
+        self
+    }
+
+    fn trace(&mut self, line: std::fmt::Arguments) {
+        if let Some(sink) = self.trace.as_mut() {
+            // Tracing is best-effort; never fail a request over it
+            let _ = writeln!(sink, "{}", line);
+        }
+    }
+
+    /// Tell the daemon our protocol version and return its own, or None if
+    /// it doesn't implement `hello` or doesn't report one.
+    pub fn hello(&mut self) -> Result<Option<String>, Error> {
+        match self.call("hello", vec![Value::String(PROTOCOL_VERSION.into())]) {
+            Ok(Some(Value::Map(fields))) => Ok(fields
+                .into_iter()
+                .find(|(k, _)| k.as_str() == Some("protocol"))
+                .and_then(|(_, v)| v.as_str().map(str::to_string))),
+            Ok(_) => Ok(None),
+            Err(e) if e.reason().is_some() => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// A table's `describe` result, fetched at most once per connection
+    /// unless a schema-changing request for the table is sent over it.
+    pub fn describe(&mut self, table: &str) -> Result<Option<Value>, Error> {
+        if let Some(schema) = self.schemas.get(table) {
+            return Ok(schema.clone());
+        }
+        let schema = self.call("describe", vec![Value::String(table.into())])?;
+        self.schemas.insert(table.to_string(), schema.clone());
+        Ok(schema)
+    }
+
+    /// Send a request without waiting for its response. Returns the msgid.
+    pub fn send(&mut self, method: &str, params: Vec<Value>) -> Result<u32, Error> {
+        if SCHEMA_CHANGING_METHODS.contains(&method) {
+            if let Some(table) = params.first().and_then(Value::as_str) {
+                self.schemas.remove(table);
+            }
+        }
+
+        let msgid = self.next_msgid;
+        // Wrap to 1, never 0, which some peers treat as "no id"
+        self.next_msgid = msgid.checked_add(1).unwrap_or(1);
+        let request = Value::Array(vec![
+            Value::Integer(0.into()),
+            Value::Integer(msgid.into()),
+            Value::String(method.into()),
+            Value::Array(params),
+        ]);
+
+        let mut buf = Vec::new();
+        rmpv::encode::write_value(&mut buf, &request)
+            .map_err(|e| Error::Protocol(format!("encode error: {}", e)))?;
+
+        if self.trace.is_some() {
+            let hex: Vec<String> = buf.iter().map(|b| format!("{:02x}", b)).collect();
+            self.trace(format_args!("> {}", request));
+            self.trace(format_args!("> {}", hex.join(" ")));
+        }
+
+        self.reader
+            .get_mut()
+            .write_all(&buf)
+            .map_err(|e| Error::io("write error", e))?;
+
+        Ok(msgid)
+    }
+
+    /// Read the next complete message from the daemon.
+    pub fn recv(&mut self) -> Result<Value, Error> {
+        self.received = 0;
+        let message = self.read_value()?;
+        self.trace(format_args!("< {}", message));
+        Ok(message)
+    }
+
+    /// Send a request and hand each element of an array result to `each`
+    /// as soon as it is decoded, so a large result is never held in memory
+    /// all at once. A non-array result is handed over as a single value.
+    /// If `each` fails, the rest of the array is read and dropped before
+    /// its error is returned, leaving the connection ready for the next
+    /// request.
+    pub fn call_each(
+        &mut self,
+        method: &str,
+        params: Vec<Value>,
+        mut each: impl FnMut(Value) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let msgid = self.send(method, params)?;
+        self.received = 0;
+
+        let len = rmp::decode::read_array_len(&mut self.counted())
+            .map_err(|e| header_error(e.into(), self.received))?;
+        let kind: u8 = rmp::decode::read_int(&mut self.counted())
+            .map_err(|e| header_error(e, self.received))?;
+        let id: u64 = rmp::decode::read_int(&mut self.counted())
+            .map_err(|e| header_error(e, self.received))?;
+        if len != 4 || kind != 1 {
+            return Err(Error::Protocol("invalid response format".to_string()));
+        }
+        if id != u64::from(msgid) {
+            return Err(Error::Protocol(format!(
+                "response msgid {} does not match request msgid {}",
+                id, msgid
+            )));
+        }
+
+        let error = self.read_value()?;
+        let is_array = matches!(
+            self.reader.fill_buf().map(|buf| buf.first().copied()),
+            Ok(Some(0x90..=0x9f | 0xdc | 0xdd))
+        );
+        if error != Value::Nil || !is_array {
+            let result = self.read_value()?;
+            self.trace(format_args!("< [1, {}, {}, {}]", id, error, result));
+            return match decode_response(Value::Array(vec![1.into(), id.into(), error, result]))? {
+                Some(result) => each(result),
+                None => Ok(()),
+            };
+        }
+
+        let count = rmp::decode::read_array_len(&mut self.counted())
+            .map_err(|e| header_error(e.into(), self.received))?;
+        self.trace(format_args!(
+            "< [1, {}, nil, <{} streamed items>]",
+            id, count
+        ));
+        for i in 0..count {
+            let item = self.read_value()?;
+            self.trace(format_args!("<   {}", item));
+            if let Err(e) = each(item) {
+                for _ in i + 1..count {
+                    self.read_value()?;
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn read_value(&mut self) -> Result<Value, Error> {
+        use rmpv::decode::Error as DecodeError;
+
+        rmpv::decode::read_value(&mut self.counted()).map_err(|e| match e {
+            DecodeError::InvalidMarkerRead(io) | DecodeError::InvalidDataRead(io)
+                if io.kind() != io::ErrorKind::InvalidData =>
+            {
+                read_error(io, self.received)
+            }
+            other => Error::Protocol(format!("decode error: {}", other)),
+        })
+    }
+
+    /// The reader, counting bytes taken from it into `received`.
+    fn counted(&mut self) -> Counted<'_> {
+        Counted {
+            reader: &mut self.reader,
+            count: &mut self.received,
+            limit: self.max_response,
+        }
+    }
+
+    /// True once the daemon has closed the connection and every buffered
+    /// message has been consumed. Blocks until data arrives or the stream ends.
+    pub fn at_eof(&mut self) -> Result<bool, Error> {
+        self.reader
+            .fill_buf()
+            .map(|buf| buf.is_empty())
+            .map_err(|e| Error::io("read error", e))
+    }
+
+    /// Send a request and wait for its result, retrying transient daemon
+    /// errors if `with_busy_retry` asked for it.
+    pub fn call(&mut self, method: &str, params: Vec<Value>) -> Result<Option<Value>, Error> {
+        let Some(attempts) = self.busy_retry.as_ref().map(|retry| retry.attempts) else {
+            return self.call_once(method, params);
+        };
+
+        let mut attempt = 0;
+        loop {
+            match self.call_once(method, params.clone()) {
+                Err(e) if attempt < attempts && self.is_busy(&e) => {
+                    attempt += 1;
+                    let delay = self.busy_retry.as_ref().map_or(Duration::ZERO, |r| r.delay);
+                    std::thread::sleep(delay * attempt);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn is_busy(&self, e: &Error) -> bool {
+        let Some(reason) = e.reason() else {
+            return false;
+        };
+        let message = e.to_string();
+        self.busy_retry.as_ref().is_some_and(|retry| {
+            retry.patterns.iter().any(|pattern| {
+                reason.contains(pattern.as_str()) || message.contains(pattern.as_str())
+            })
+        })
+    }
+
+    fn call_once(&mut self, method: &str, params: Vec<Value>) -> Result<Option<Value>, Error> {
+        let msgid = self.send(method, params)?;
+        let response = self.recv()?;
+
+        match response.as_array().and_then(|parts| parts.get(1)?.as_u64()) {
+            Some(id) if id == u64::from(msgid) => decode_response(response),
+            Some(id) => Err(Error::Protocol(format!(
+                "response msgid {} does not match request msgid {}",
+                id, msgid
+            ))),
+            None => Err(Error::Protocol("invalid response format".to_string())),
+        }
+    }
+}
+
+struct Counted<'a> {
+    reader: &'a mut BufReader<UnixStream>,
+    count: &'a mut usize,
+    limit: Option<usize>,
+}
+
+impl Read for Counted<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        *self.count += n;
+        match self.limit {
+            Some(limit) if *self.count > limit => Err(io::Error::other(ResponseTooLarge(limit))),
+            _ => Ok(n),
+        }
+    }
+}
+
+/// Classify a failed read, `received` bytes into a response. Running out
+/// of input means the daemon hung up (e.g. crashed) mid-response, which is
+/// worth telling apart from a malformed one.
+fn read_error(e: io::Error, received: usize) -> Error {
+    if let Some(ResponseTooLarge(limit)) = e.get_ref().and_then(|e| e.downcast_ref()) {
+        return Error::Protocol(format!(
+            "response is over the {} byte limit (raise it with --max-response-size, or 0 for none)",
+            limit
+        ));
+    }
+    if e.kind() == io::ErrorKind::UnexpectedEof {
+        return Error::Connection(format!(
+            "connection closed before full response (got {} bytes)",
+            received
+        ));
+    }
+    Error::io("read error", e)
+}
+
+/// Classify a failure while reading a response's `[1, msgid, ...]` header.
+fn header_error(e: rmp::decode::NumValueReadError, received: usize) -> Error {
+    use rmp::decode::NumValueReadError as E;
+
+    match e {
+        E::InvalidMarkerRead(io) | E::InvalidDataRead(io)
+            if io.kind() != io::ErrorKind::InvalidData =>
+        {
+            read_error(io, received)
+        }
+        _ => Error::Protocol("invalid response format".to_string()),
+    }
+}
+
+/// Unpack a `[1, msgid, error, result]` response into its result or error.
+pub fn decode_response(response: Value) -> Result<Option<Value>, Error> {
+    match response {
+        Value::Array(parts) if parts.len() == 4 => {
+            let error = &parts[2];
+            let result = &parts[3];
+
+            if *error != Value::Nil {
+                Err(daemon_error(error))
+            } else {
+                Ok(Some(result.clone()))
+            }
+        }
+        _ => Err(Error::Protocol("invalid response format".to_string())),
+    }
+}
+
+/// The error for a response's error field: a plain string, or a map with a
+/// `code` for scripts and a `message` for people.
+fn daemon_error(error: &Value) -> Error {
+    let field = |name: &str| match error {
+        Value::Map(fields) => fields
+            .iter()
+            .find(|(k, _)| k.as_str() == Some(name))
+            .and_then(|(_, v)| v.as_str()),
+        _ => None,
+    };
+    match (error, field("code"), field("message")) {
+        (Value::String(s), _, _) => {
+            Error::Daemon(s.as_str().unwrap_or("unknown error").to_string())
+        }
+        (_, Some(code), message) => Error::DaemonCode {
+            code: code.to_string(),
+            message: message.unwrap_or(code).to_string(),
+        },
+        (_, None, Some(message)) => Error::Daemon(message.to_string()),
+        _ => Error::Daemon(format!("{}", error)),
+    }
+}
+
+/// Unpack a `put_many` result, which has `"ok"` or `{"error": reason}` for
+/// each of the `count` records sent, in order.
+pub fn decode_put_many(
+    result: Option<Value>,
+    count: usize,
+) -> Result<Vec<Result<(), Error>>, Error> {
+    let outcomes = match result {
+        Some(Value::Array(outcomes)) if outcomes.len() == count => outcomes,
+        _ => {
+            return Err(Error::Protocol(format!(
+                "expected {} results from put_many",
+                count
+            )))
+        }
+    };
+    Ok(outcomes
+        .iter()
+        .map(|outcome| match outcome {
+            Value::Map(fields) => {
+                let error = fields
+                    .iter()
+                    .find(|(k, _)| k.as_str() == Some("error"))
+                    .map_or(outcome, |(_, v)| v);
+                Err(daemon_error(error))
+            }
+            _ => Ok(()),
+        })
+        .collect())
+}
+
+/// Split a `[2, method, params]` notification into its method and params.
+pub fn decode_notification(message: &Value) -> Option<(&str, &[Value])> {
+    match message.as_array()?.as_slice() {
+        [kind, method, Value::Array(params)] if kind.as_u64() == Some(2) => {
+            Some((method.as_str()?, params.as_slice()))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(msgid: u32, result: &str) -> Vec<u8> {
+        let value = Value::Array(vec![
+            Value::Integer(1.into()),
+            Value::Integer(msgid.into()),
+            Value::Nil,
+            Value::String(result.into()),
+        ]);
+        let mut buf = Vec::new();
+        rmpv::encode::write_value(&mut buf, &value).unwrap();
+        buf
+    }
+
+    fn read_request(stream: &mut UnixStream) -> Value {
+        rmpv::decode::read_value(stream).unwrap()
+    }
+
+    #[test]
+    fn reads_back_to_back_responses_from_one_write() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let mut conn = Connection::from_stream(client);
+
+        let first = conn.send("ping", vec![]).unwrap();
+        let second = conn.send("status", vec![]).unwrap();
+        assert_eq!(read_request(&mut server)[2].as_str(), Some("ping"));
+        assert_eq!(read_request(&mut server)[2].as_str(), Some("status"));
+
+        // Both responses land in the socket before the client reads either
+        let mut both = response(first, "pong");
+        both.extend(response(second, "running"));
+        server.write_all(&both).unwrap();
+
+        let r1 = conn.recv().unwrap();
+        let r2 = conn.recv().unwrap();
+        assert_eq!(r1[1].as_u64(), Some(first as u64));
+        assert_eq!(r1[3].as_str(), Some("pong"));
+        assert_eq!(r2[1].as_u64(), Some(second as u64));
+        assert_eq!(r2[3].as_str(), Some("running"));
+    }
+
+    #[test]
+    fn msgid_wraps_to_one_not_zero() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let mut conn = Connection::from_stream(client);
+        conn.next_msgid = u32::MAX;
+
+        // Responses are queued up front; call() only accepts them if the ids match
+        server.write_all(&response(u32::MAX, "first")).unwrap();
+        server.write_all(&response(1, "second")).unwrap();
+
+        assert_eq!(
+            conn.call("ping", vec![]).unwrap(),
+            Some(Value::String("first".into()))
+        );
+        assert_eq!(
+            conn.call("ping", vec![]).unwrap(),
+            Some(Value::String("second".into()))
+        );
+        assert_eq!(read_request(&mut server)[1].as_u64(), Some(u32::MAX as u64));
+        assert_eq!(read_request(&mut server)[1].as_u64(), Some(1));
+    }
+
+    #[test]
+    fn call_rejects_mismatched_msgid() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let mut conn = Connection::from_stream(client);
+
+        let responder = std::thread::spawn(move || {
+            let request = read_request(&mut server);
+            let id = request[1].as_u64().unwrap() as u32;
+            server.write_all(&response(id + 1, "pong")).unwrap();
+        });
+
+        let err = conn.call("ping", vec![]).unwrap_err();
+        responder.join().unwrap();
+        assert_eq!(err.code(), "protocol");
+        assert!(err.to_string().contains("does not match"));
+    }
+
+    #[test]
+    fn eof_mid_response_says_how_much_arrived() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let mut conn = Connection::from_stream(client);
+
+        let msgid = conn.send("get", vec![]).unwrap();
+        read_request(&mut server);
+        let full = response(msgid, &"x".repeat(100));
+        server.write_all(&full[..full.len() / 2]).unwrap();
+        drop(server);
+
+        let err = conn.recv().unwrap_err();
+        assert_eq!(err.code(), "connection");
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "connection closed before full response (got {} bytes)",
+                full.len() / 2
+            )
+        );
+    }
+
+    #[test]
+    fn eof_mid_streamed_response_counts_header_bytes() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let mut conn = Connection::from_stream(client);
+
+        let responder = std::thread::spawn(move || {
+            let request = read_request(&mut server);
+            let id = request[1].as_u64().unwrap() as u32;
+            // [1, id, nil, [<3 items>]] with only the first item sent
+            let mut partial = Vec::new();
+            rmp::encode::write_array_len(&mut partial, 4).unwrap();
+            rmp::encode::write_uint(&mut partial, 1).unwrap();
+            rmp::encode::write_uint(&mut partial, u64::from(id)).unwrap();
+            rmp::encode::write_nil(&mut partial).unwrap();
+            rmp::encode::write_array_len(&mut partial, 3).unwrap();
+            rmp::encode::write_str(&mut partial, "a").unwrap();
+            server.write_all(&partial).unwrap();
+            partial.len()
+        });
+
+        let mut items = Vec::new();
+        let err = conn
+            .call_each("all", vec![], |item| {
+                items.push(item);
+                Ok(())
+            })
+            .unwrap_err();
+        let sent = responder.join().unwrap();
+
+        assert_eq!(items, [Value::from("a")]);
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "connection closed before full response (got {} bytes)",
+                sent
+            )
+        );
+    }
+
+    #[test]
+    fn failed_streamed_call_leaves_the_connection_usable() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let mut conn = Connection::from_stream(client);
+
+        let responder = std::thread::spawn(move || {
+            let request = read_request(&mut server);
+            let id = request[1].as_u64().unwrap();
+            let items = ["a", "b", "c"].map(Value::from).to_vec();
+            let streamed = Value::Array(vec![1.into(), id.into(), Value::Nil, Value::Array(items)]);
+            rmpv::encode::write_value(&mut server, &streamed).unwrap();
+
+            let request = read_request(&mut server);
+            let id = request[1].as_u64().unwrap() as u32;
+            server.write_all(&response(id, "pong")).unwrap();
+        });
+
+        let mut items = Vec::new();
+        let err = conn
+            .call_each("all", vec![], |item| {
+                items.push(item);
+                Err(Error::Output("write error: broken pipe".to_string()))
+            })
+            .unwrap_err();
+        assert_eq!(err.code(), "output");
+        assert_eq!(items, [Value::from("a")]);
+
+        // The rest of the array isn't mistaken for the next response
+        assert_eq!(
+            conn.call("ping", vec![]).unwrap(),
+            Some(Value::from("pong"))
+        );
+        responder.join().unwrap();
+    }
+
+    #[test]
+    fn assembles_a_response_split_across_writes() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let mut conn = Connection::from_stream(client);
+
+        let payload = "x".repeat(200_000);
+        let msgid = conn.send("all", vec![]).unwrap();
+        read_request(&mut server);
+
+        let writer = std::thread::spawn(move || {
+            let bytes = response(msgid, &payload);
+            for chunk in bytes.chunks(7_000) {
+                server.write_all(chunk).unwrap();
+            }
+        });
+
+        let result = decode_response(conn.recv().unwrap()).unwrap();
+        assert_eq!(result.map(|v| v.as_str().unwrap().len()), Some(200_000));
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn streamed_response_stops_at_the_size_limit() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let mut conn = Connection::from_stream(client).with_max_response_size(1000);
+
+        let responder = std::thread::spawn(move || {
+            let request = read_request(&mut server);
+            let id = request[1].as_u64().unwrap() as u32;
+            let items = (0..100).map(|_| Value::from("x".repeat(40))).collect();
+            let response = Value::Array(vec![1.into(), id.into(), Value::Nil, Value::Array(items)]);
+            let mut buf = Vec::new();
+            rmpv::encode::write_value(&mut buf, &response).unwrap();
+            // The client hangs up partway, so the rest may not be accepted
+            let _ = server.write_all(&buf);
+        });
+
+        let mut items = 0;
+        let err = conn
+            .call_each("all", vec![], |_| {
+                items += 1;
+                Ok(())
+            })
+            .unwrap_err();
+        drop(conn);
+        responder.join().unwrap();
+
+        assert_eq!(err.code(), "protocol");
+        assert!(
+            err.to_string().contains("over the 1000 byte limit"),
+            "{}",
+            err
+        );
+        assert!(items < 100);
+    }
+
+    fn error_response(error: Value) -> Value {
+        Value::Array(vec![1.into(), 7.into(), error, Value::Nil])
+    }
+
+    #[test]
+    fn decodes_string_errors() {
+        let err = decode_response(error_response(Value::from("not_found"))).unwrap_err();
+        assert_eq!(err, Error::Daemon("not_found".to_string()));
+        assert_eq!(err.to_string(), "not_found");
+        assert_eq!(err.reason(), Some("not_found"));
+        assert_eq!(err.exit_code(), crate::error::EXIT_DAEMON);
+    }
+
+    #[test]
+    fn decodes_error_maps() {
+        let error = |code: &str, message: &str| {
+            Value::Map(vec![
+                (Value::from("code"), Value::from(code)),
+                (Value::from("message"), Value::from(message)),
+            ])
+        };
+
+        let err = decode_response(error_response(error(
+            "not_found",
+            "no record 'u1' in 'users'",
+        )))
+        .unwrap_err();
+        assert_eq!(err.to_string(), "no record 'u1' in 'users'");
+        assert_eq!(err.reason(), Some("not_found"));
+        assert_eq!(err.code(), "daemon");
+        assert_eq!(err.exit_code(), crate::error::EXIT_DAEMON);
+
+        let err = decode_response(error_response(error("condition_failed", "record changed")))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "record changed");
+        assert_eq!(err.code(), "conflict");
+        assert_eq!(err.exit_code(), crate::error::EXIT_CONFLICT);
+
+        // A map without a code keeps its message; one without either is shown whole
+        let err = decode_response(error_response(Value::Map(vec![(
+            Value::from("message"),
+            Value::from("disk full"),
+        )])))
+        .unwrap_err();
+        assert_eq!(err, Error::Daemon("disk full".to_string()));
+        let err = decode_response(error_response(Value::Map(vec![]))).unwrap_err();
+        assert_eq!(err, Error::Daemon("{}".to_string()));
+    }
+
+    #[test]
+    fn call_assembles_a_response_sent_a_byte_at_a_time() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let mut conn = Connection::from_stream(client);
+
+        let responder = std::thread::spawn(move || {
+            let request = read_request(&mut server);
+            let id = request[1].as_u64().unwrap() as u32;
+            for byte in response(id, &"y".repeat(70_000)) {
+                server.write_all(&[byte]).unwrap();
+            }
+        });
+
+        let result = conn.call("get", vec![]).unwrap();
+        responder.join().unwrap();
+        assert_eq!(result.map(|v| v.as_str().unwrap().len()), Some(70_000));
+    }
+
+    /// Write sink the test can inspect after handing it to the connection.
+    #[derive(Clone, Default)]
+    struct SharedBuf(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuf {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    fn ping_over(conn: &mut Connection, server: &mut UnixStream) -> u32 {
+        let msgid = conn.send("ping", vec![]).unwrap();
+        read_request(server);
+        server.write_all(&response(msgid, "pong")).unwrap();
+        conn.recv().unwrap();
+        msgid
+    }
+
+    #[test]
+    fn trace_logs_request_and_response_when_enabled() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let sink = SharedBuf::default();
+        let mut conn = Connection::from_stream(client).with_trace(Box::new(sink.clone()));
+
+        let msgid = ping_over(&mut conn, &mut server);
+
+        let trace = sink.contents();
+        assert!(trace.contains(&format!("> [0, {}, \"ping\", []]", msgid)));
+        assert!(trace.contains("> 94 00"));
+        assert!(trace.contains(&format!("< [1, {}, nil, \"pong\"]", msgid)));
+    }
+
+    #[test]
+    fn trace_is_off_by_default() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let mut conn = Connection::from_stream(client);
+
+        ping_over(&mut conn, &mut server);
+
+        assert!(conn.trace.is_none());
+    }
+}
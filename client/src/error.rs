@@ -0,0 +1,143 @@
+use std::fmt;
+use std::io;
+
+pub const EXIT_CONNECTION: u8 = 2;
+pub const EXIT_TIMEOUT: u8 = 3;
+pub const EXIT_PROTOCOL: u8 = 4;
+pub const EXIT_DAEMON: u8 = 5;
+pub const EXIT_INPUT: u8 = 6;
+pub const EXIT_CONFLICT: u8 = 7;
+pub const EXIT_OUTPUT: u8 = 8;
+
+/// A client error, categorized by where it happened so scripts can tell
+/// "daemon down" apart from "permission denied" apart from "bad input".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Could not reach the daemon, or the connection dropped
+    Connection(String),
+    /// The daemon did not answer in time
+    Timeout(String),
+    /// The daemon sent something that isn't valid MessagePack-RPC
+    Protocol(String),
+    /// The daemon handled the request and reported an error
+    Daemon(String),
+    /// The daemon reported an error as a `{code, message}` map
+    DaemonCode { code: String, message: String },
+    /// Bad arguments or input, rejected before anything was sent
+    Input(String),
+    /// A conditional write's precondition didn't hold
+    Conflict(String),
+    /// Writing results locally failed (e.g. a closed stdout pipe)
+    Output(String),
+}
+
+impl Error {
+    /// Stable category name for machine-readable error output.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Connection(_) => "connection",
+            Error::Timeout(_) => "timeout",
+            Error::Protocol(_) => "protocol",
+            Error::Daemon(_) => "daemon",
+            Error::DaemonCode { code, .. } => daemon_category(code),
+            Error::Input(_) => "input",
+            Error::Conflict(_) => "conflict",
+            Error::Output(_) => "output",
+        }
+    }
+
+    /// Process exit status for this kind of failure, so scripts can branch
+    /// on the cause without parsing stderr.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::DaemonCode { code, .. } => match daemon_category(code) {
+                "conflict" => EXIT_CONFLICT,
+                "input" => EXIT_INPUT,
+                "timeout" => EXIT_TIMEOUT,
+                _ => EXIT_DAEMON,
+            },
+            Error::Connection(_) => EXIT_CONNECTION,
+            Error::Timeout(_) => EXIT_TIMEOUT,
+            Error::Protocol(_) => EXIT_PROTOCOL,
+            Error::Daemon(_) => EXIT_DAEMON,
+            Error::Input(_) => EXIT_INPUT,
+            Error::Conflict(_) => EXIT_CONFLICT,
+            Error::Output(_) => EXIT_OUTPUT,
+        }
+    }
+
+    /// The same kind of error, with `note` appended to its message.
+    pub fn annotate(self, note: &str) -> Self {
+        let message = format!("{} ({})", self.message(), note);
+        match self {
+            Error::Connection(_) => Error::Connection(message),
+            Error::Timeout(_) => Error::Timeout(message),
+            Error::Protocol(_) => Error::Protocol(message),
+            Error::Daemon(_) => Error::Daemon(message),
+            Error::DaemonCode { code, .. } => Error::DaemonCode { code, message },
+            Error::Input(_) => Error::Input(message),
+            Error::Conflict(_) => Error::Conflict(message),
+            Error::Output(_) => Error::Output(message),
+        }
+    }
+
+    /// Classify an I/O failure on the daemon socket.
+    pub fn io(context: &str, e: io::Error) -> Self {
+        let message = format!("{}: {}", context, e);
+        match e.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => Error::Timeout(message),
+            _ => Error::Connection(message),
+        }
+    }
+
+    /// What the daemon gave as the reason for refusing a request: its code
+    /// for a structured error, or the whole string for a plain one.
+    pub fn reason(&self) -> Option<&str> {
+        match self {
+            Error::Daemon(reason) => Some(reason),
+            Error::DaemonCode { code, .. } => Some(code),
+            _ => None,
+        }
+    }
+
+    /// Whether the daemon doesn't implement the method that was called.
+    pub fn is_unknown_method(&self) -> bool {
+        match self {
+            Error::Daemon(reason) => reason.starts_with("unknown method"),
+            Error::DaemonCode { code, .. } => code == "unknown_method",
+            _ => false,
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            Error::Connection(m)
+            | Error::Timeout(m)
+            | Error::Protocol(m)
+            | Error::Daemon(m)
+            | Error::DaemonCode { message: m, .. }
+            | Error::Input(m)
+            | Error::Conflict(m)
+            | Error::Output(m) => m,
+        }
+    }
+}
+
+/// The error category a daemon error code falls under. Codes the CLI
+/// doesn't know are plain daemon errors.
+fn daemon_category(code: &str) -> &'static str {
+    match code {
+        "conflict" | "condition_failed" => "conflict",
+        "invalid_params" | "invalid_input" => "input",
+        "timeout" => "timeout",
+        _ => "daemon",
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,153 @@
+//! Converting between the daemon's MessagePack values and JSON.
+//!
+//! JSON can't express everything MessagePack can, so two tagged objects
+//! stand in for what it lacks: `{"__map__": [[key, value], ...]}` for maps
+//! with non-string keys and `{"__bin__hex__": "deadbeef"}` for binary.
+
+use crate::error::Error;
+use rmpv::Value;
+
+/// Object key marking a map with non-string keys; see `msgpack_to_json`.
+pub const MAP_TAG: &str = "__map__";
+
+/// Object key marking hex-encoded binary: `{"__bin__hex__": "deadbeef"}`
+/// is sent as MessagePack binary.
+pub const HEX_TAG: &str = "__bin__hex__";
+
+/// Convert JSON given by a user, rejecting malformed `__bin__hex__` values
+/// that `json_to_msgpack` would pass through as plain objects.
+pub fn input_to_msgpack(value: &serde_json::Value) -> Result<Value, Error> {
+    check_hex_tags(value)?;
+    Ok(json_to_msgpack(value))
+}
+
+fn check_hex_tags(value: &serde_json::Value) -> Result<(), Error> {
+    match value {
+        serde_json::Value::Object(obj) => match (obj.len(), obj.get(HEX_TAG)) {
+            (1, Some(serde_json::Value::String(hex))) => decode_hex(hex)
+                .map(|_| ())
+                .map_err(|reason| Error::Input(format!("invalid {} value: {}", HEX_TAG, reason))),
+            (1, Some(_)) => Err(Error::Input(format!("{} value must be a string", HEX_TAG))),
+            _ => obj.values().try_for_each(check_hex_tags),
+        },
+        serde_json::Value::Array(items) => items.iter().try_for_each(check_hex_tags),
+        _ => Ok(()),
+    }
+}
+
+/// The bytes a string of hex digits spells.
+pub fn decode_hex(hex: &str) -> Result<Vec<u8>, String> {
+    let digit = |(i, c): (usize, char)| {
+        c.to_digit(16)
+            .map(|d| d as u8)
+            .ok_or_else(|| format!("'{}' at position {} is not a hex digit", c, i))
+    };
+    let digits = hex
+        .chars()
+        .enumerate()
+        .map(digit)
+        .collect::<Result<Vec<_>, _>>()?;
+    if !digits.len().is_multiple_of(2) {
+        return Err(format!("odd number of digits ({})", digits.len()));
+    }
+    Ok(digits
+        .chunks(2)
+        .map(|pair| pair[0] << 4 | pair[1])
+        .collect())
+}
+
+/// Decode `{"__map__": [[key, value], ...]}` back into map entries.
+fn tagged_map(obj: &serde_json::Map<String, serde_json::Value>) -> Option<Vec<(Value, Value)>> {
+    if obj.len() != 1 {
+        return None;
+    }
+    obj.get(MAP_TAG)?
+        .as_array()?
+        .iter()
+        .map(|pair| match pair.as_array()?.as_slice() {
+            [k, v] => Some((json_to_msgpack(k), json_to_msgpack(v))),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Convert JSON to MessagePack, the inverse of [`msgpack_to_json`].
+///
+/// `{"__bin__hex__": "..."}` becomes binary and `{"__map__": [...]}` a
+/// map with the given keys; anything malformed is kept as a plain object.
+pub fn json_to_msgpack(value: &serde_json::Value) -> Value {
+    match value {
+        serde_json::Value::Null => Value::Nil,
+        serde_json::Value::Bool(b) => Value::Boolean(*b),
+        serde_json::Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                Value::Integer(i.into())
+            } else if let Some(f) = n.as_f64() {
+                Value::F64(f)
+            } else {
+                Value::Nil
+            }
+        }
+        serde_json::Value::String(s) => Value::String(s.clone().into()),
+        serde_json::Value::Array(arr) => Value::Array(arr.iter().map(json_to_msgpack).collect()),
+        serde_json::Value::Object(obj) => match obj.get(HEX_TAG).and_then(|v| v.as_str()) {
+            Some(hex) if obj.len() == 1 => match decode_hex(hex) {
+                Ok(bytes) => Value::Binary(bytes),
+                Err(_) => Value::Map(vec![(Value::from(HEX_TAG), Value::from(hex))]),
+            },
+            _ => Value::Map(tagged_map(obj).unwrap_or_else(|| {
+                obj.iter()
+                    .map(|(k, v)| (Value::String(k.clone().into()), json_to_msgpack(v)))
+                    .collect()
+            })),
+        },
+    }
+}
+
+/// Convert a MessagePack value to JSON.
+///
+/// JSON object keys must be strings, so a map with any non-string key (e.g.
+/// `{1: "a", "1": "b"}`) is written as `{"__map__": [[key, value], ...]}`
+/// rather than stringifying keys that could then collide. `json_to_msgpack`
+/// turns that form back into a map.
+pub fn msgpack_to_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Nil => serde_json::Value::Null,
+        Value::Boolean(b) => serde_json::Value::Bool(*b),
+        Value::Integer(i) => {
+            if let Some(n) = i.as_i64() {
+                serde_json::Value::Number(n.into())
+            } else if let Some(n) = i.as_u64() {
+                serde_json::Value::Number(n.into())
+            } else {
+                serde_json::Value::Null
+            }
+        }
+        Value::F32(f) => serde_json::Value::Number(
+            serde_json::Number::from_f64(*f as f64).unwrap_or(serde_json::Number::from(0)),
+        ),
+        Value::F64(f) => serde_json::Value::Number(
+            serde_json::Number::from_f64(*f).unwrap_or(serde_json::Number::from(0)),
+        ),
+        Value::String(s) => serde_json::Value::String(s.as_str().unwrap_or_default().to_string()),
+        Value::Binary(b) => serde_json::Value::String(String::from_utf8_lossy(b).to_string()),
+        Value::Array(arr) => serde_json::Value::Array(arr.iter().map(msgpack_to_json).collect()),
+        Value::Map(map) if map.iter().all(|(k, _)| k.as_str().is_some()) => {
+            let obj: serde_json::Map<String, serde_json::Value> = map
+                .iter()
+                .filter_map(|(k, v)| Some((k.as_str()?.to_string(), msgpack_to_json(v))))
+                .collect();
+            serde_json::Value::Object(obj)
+        }
+        Value::Map(map) => {
+            let pairs = map
+                .iter()
+                .map(|(k, v)| serde_json::json!([msgpack_to_json(k), msgpack_to_json(v)]))
+                .collect();
+            let mut obj = serde_json::Map::new();
+            obj.insert(MAP_TAG.to_string(), serde_json::Value::Array(pairs));
+            serde_json::Value::Object(obj)
+        }
+        Value::Ext(_, _) => serde_json::Value::Null,
+    }
+}
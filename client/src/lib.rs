@@ -0,0 +1,38 @@
+//! Client library for the Cortex storage daemon.
+//!
+//! The daemon speaks MessagePack-RPC over a Unix socket and identifies
+//! callers by their UID, so there is nothing to configure beyond the socket
+//! path:
+//!
+//! ```no_run
+//! use cortex_client::Client;
+//! use serde_json::json;
+//!
+//! let mut client = Client::connect("/run/cortex/cortex.sock")?;
+//! client.create_table("users", &["id", "name"])?;
+//! client.put("users", &json!({"id": "u1", "name": "Ann"}))?;
+//! assert_eq!(client.get("users", "u1")?, Some(json!({"id": "u1", "name": "Ann"})));
+//! # Ok::<(), cortex_client::Error>(())
+//! ```
+//!
+//! [`Client`] covers the common requests with JSON in and out, including
+//! following changes with [`Client::watch`] and writing several records
+//! all or nothing with [`Client::transaction`]. [`Connection`] is the raw
+//! request/response layer underneath it. With the `tokio` feature,
+//! `AsyncClient` offers the same requests to async code, and many tasks
+//! can have requests in flight over one connection.
+
+#[cfg(feature = "tokio")]
+mod async_client;
+mod client;
+pub mod connection;
+pub mod error;
+pub mod json;
+pub mod txn;
+
+#[cfg(feature = "tokio")]
+pub use async_client::AsyncClient;
+pub use client::{Changes, Client};
+pub use connection::Connection;
+pub use error::Error;
+pub use txn::Op;
@@ -0,0 +1,121 @@
+//! Operations for the `transaction` method, which applies several writes
+//! all or nothing.
+
+use crate::error::Error;
+use crate::json::{input_to_msgpack, json_to_msgpack};
+use rmpv::Value;
+
+/// One write in a transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    /// Insert `record`, or replace the one with the same key.
+    Put {
+        table: String,
+        record: serde_json::Value,
+    },
+    /// Delete the record with this key, if there is one.
+    Delete {
+        table: String,
+        key: serde_json::Value,
+    },
+}
+
+impl Op {
+    pub fn put(table: &str, record: serde_json::Value) -> Self {
+        Op::Put {
+            table: table.to_string(),
+            record,
+        }
+    }
+
+    pub fn delete(table: &str, key: impl Into<serde_json::Value>) -> Self {
+        Op::Delete {
+            table: table.to_string(),
+            key: key.into(),
+        }
+    }
+
+    /// Read an op written as `{"op": "put", "table": T, "record": {...}}`
+    /// or `{"op": "delete", "table": T, "key": K}`, the form the daemon
+    /// takes.
+    pub fn from_json(op: &serde_json::Value) -> Result<Self, Error> {
+        let field = |name: &str| {
+            op.get(name)
+                .ok_or_else(|| Error::Input(format!("op is missing \"{}\": {}", name, op)))
+        };
+        let table = field("table")?
+            .as_str()
+            .ok_or_else(|| Error::Input(format!("op \"table\" must be a string: {}", op)))?;
+        match field("op")?.as_str() {
+            Some("put") => {
+                let record = field("record")?;
+                if !record.is_object() {
+                    return Err(Error::Input(format!(
+                        "put \"record\" must be a JSON object: {}",
+                        op
+                    )));
+                }
+                Ok(Op::put(table, record.clone()))
+            }
+            Some("delete") => Ok(Op::delete(table, field("key")?.clone())),
+            _ => Err(Error::Input(format!(
+                "\"op\" must be \"put\" or \"delete\": {}",
+                op
+            ))),
+        }
+    }
+
+    pub fn table(&self) -> &str {
+        match self {
+            Op::Put { table, .. } | Op::Delete { table, .. } => table,
+        }
+    }
+
+    /// The op as the daemon takes it.
+    pub fn to_msgpack(&self) -> Result<Value, Error> {
+        let (op, table, field, value) = match self {
+            Op::Put { table, record } => ("put", table, "record", input_to_msgpack(record)?),
+            Op::Delete { table, key } => ("delete", table, "key", json_to_msgpack(key)),
+        };
+        Ok(Value::Map(vec![
+            (Value::from("op"), Value::from(op)),
+            (Value::from("table"), Value::from(table.as_str())),
+            (Value::from(field), value),
+        ]))
+    }
+}
+
+/// The params for a `transaction` request applying `ops`.
+pub fn transaction_params(ops: &[Op]) -> Result<Vec<Value>, Error> {
+    let ops = ops.iter().map(Op::to_msgpack).collect::<Result<_, _>>()?;
+    Ok(vec![Value::Array(ops)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn ops_read_from_json() {
+        let put = json!({"op": "put", "table": "users", "record": {"id": "u1"}});
+        assert_eq!(
+            Op::from_json(&put).unwrap(),
+            Op::put("users", json!({"id": "u1"}))
+        );
+        let delete = json!({"op": "delete", "table": "users", "key": 7});
+        assert_eq!(Op::from_json(&delete).unwrap(), Op::delete("users", 7));
+
+        let err = Op::from_json(&json!({"op": "get", "table": "users"})).unwrap_err();
+        assert_eq!(err.code(), "input");
+        assert!(err.to_string().starts_with("\"op\" must be"), "{}", err);
+        let err = Op::from_json(&json!({"op": "put", "table": "users"})).unwrap_err();
+        assert!(
+            err.to_string().starts_with("op is missing \"record\""),
+            "{}",
+            err
+        );
+        let err = Op::from_json(&json!({"op": "put", "table": "t", "record": 1})).unwrap_err();
+        assert!(err.to_string().contains("must be a JSON object"), "{}", err);
+    }
+}
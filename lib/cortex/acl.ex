@@ -12,9 +12,10 @@ defmodule Cortex.ACL do
   Check if the given UID can perform an operation on a table.
 
   Operations:
-  - :read - get, match, all
-  - :write - put, delete
-  - :admin - acl_grant, acl_revoke, drop_table
+  - :read - get, ttl, match, count, all, range, describe, subscribe, unsubscribe
+  - :write - put, put_many, cas_put, append, incr, expire, delete, delete_match, truncate
+  - :admin - acl_grant, acl_revoke, acl_check, drop_table, alter_table, create_index,
+    drop_index
   """
   def authorize(_uid, :table_does_not_exist, _operation) do
     # Table atom doesn't exist - return same error as unauthorized (no info leak)
@@ -120,9 +121,43 @@ defmodule Cortex.ACL do
     end
   end
 
-  defp operation_to_permission(op) when op in [:get, :match, :all], do: :read
-  defp operation_to_permission(op) when op in [:put, :delete], do: :write
-  defp operation_to_permission(op) when op in [:acl_grant, :acl_revoke, :drop_table], do: :admin
+  defp operation_to_permission(op)
+       when op in [
+              :get,
+              :ttl,
+              :match,
+              :count,
+              :all,
+              :range,
+              :describe,
+              :subscribe,
+              :unsubscribe
+            ],
+       do: :read
+  defp operation_to_permission(op)
+       when op in [
+              :put,
+              :put_many,
+              :cas_put,
+              :append,
+              :incr,
+              :expire,
+              :delete,
+              :delete_match,
+              :truncate
+            ],
+       do: :write
+  defp operation_to_permission(op)
+       when op in [
+              :acl_grant,
+              :acl_revoke,
+              :acl_check,
+              :drop_table,
+              :alter_table,
+              :create_index,
+              :drop_index
+            ],
+       do: :admin
   # Return error for unknown operations rather than crashing the handler
   defp operation_to_permission(_op), do: {:error, :unknown_operation}
 end
@@ -10,12 +10,17 @@ defmodule Cortex.Handler do
 
   alias Cortex.{Protocol, Store, ACL, Identity}
 
+  # Wire protocol version reported by `hello`; the major changes on breaking changes
+  @protocol_version "1.0"
+
   # Max buffer size to prevent memory exhaustion (1MB)
   @max_buffer_size 1_048_576
 
   # Valid pattern for table/attribute names (alphanumeric + underscore, starts with letter/underscore)
   @valid_name_pattern ~r/^[a-zA-Z_][a-zA-Z0-9_]*$/
 
+  @range_params_error "invalid params: expected [table, %{from: key, to: key, limit: n}]"
+
   defstruct [:socket, :uid, :buffer]
 
   def start_link(socket) do
@@ -68,6 +73,17 @@ defmodule Cortex.Handler do
     end
   end
 
+  # Change events for tables this connection subscribed to
+  @impl true
+  def handle_info({:mnesia_table_event, event}, state) do
+    case change_event(event, state.uid) do
+      nil -> :ok
+      change -> :gen_tcp.send(state.socket, Protocol.encode_notification("change", [change]))
+    end
+
+    {:noreply, state}
+  end
+
   @impl true
   def handle_info({:tcp_closed, _socket}, state) do
     {:stop, :normal, state}
@@ -113,13 +129,25 @@ defmodule Cortex.Handler do
     {:ok, "pong"}
   end
 
-  defp dispatch("status", _params, _uid) do
+  defp dispatch("hello", _params, _uid) do
+    {:ok, %{protocol: @protocol_version, version: Cortex.Version.version()}}
+  end
+
+  defp dispatch("whoami", _params, uid) do
+    {:ok, %{uid: uid, identity: Identity.uid_to_identity(uid)}}
+  end
+
+  defp dispatch("status", _params, uid) do
     {:ok,
      %{
        version: Cortex.Version.version(),
        status: "running",
+       uid: uid,
        node: node(),
-       tables: :mnesia.system_info(:tables) |> length()
+       tables: :mnesia.system_info(:tables) |> length(),
+       uptime_seconds: div(elem(:erlang.statistics(:wall_clock), 0), 1000),
+       mnesia: :mnesia.system_info(:is_running) |> Atom.to_string(),
+       data_dir_writable: data_dir_writable?()
      }}
   end
 
@@ -148,6 +176,21 @@ defmodule Cortex.Handler do
     end
   end
 
+  defp dispatch("alter_table", [table_name, %{"add_attribute" => name} = change], uid)
+       when is_binary(table_name) and is_binary(name) do
+    table = Store.resolve_table(uid, table_name)
+
+    with :ok <- ACL.authorize(uid, table, :alter_table),
+         {:ok, [attribute]} <- validate_and_convert_attrs([name]),
+         {:ok, backfilled} <- Store.add_attribute(table, attribute, Map.get(change, "default")) do
+      {:ok, %{added: name, backfilled: backfilled}}
+    end
+  end
+
+  defp dispatch("alter_table", _params, _uid) do
+    {:error, "invalid params: expected [table, %{add_attribute: name, default: value}]"}
+  end
+
   defp dispatch("create_table", _params, _uid) do
     {:error, "invalid params: expected [name, [attributes]]"}
   end
@@ -161,6 +204,78 @@ defmodule Cortex.Handler do
     end
   end
 
+  defp dispatch("truncate", [table_name], uid) when is_binary(table_name) do
+    table = Store.resolve_table(uid, table_name)
+
+    with :ok <- ACL.authorize(uid, table, :truncate),
+         {:ok, :ok} <- Store.truncate(table) do
+      {:ok, "truncated"}
+    end
+  end
+
+  defp dispatch("describe", [table_name], uid) when is_binary(table_name) do
+    table = Store.resolve_table(uid, table_name)
+
+    with :ok <- ACL.authorize(uid, table, :describe),
+         {:ok, meta} <- Store.get_table_meta(table),
+         {:ok, indexes} <- Store.indexes(table) do
+      {:ok,
+       %{
+         table: table_name,
+         key: meta.key_field,
+         attributes: meta.attributes,
+         indexes: indexes
+       }}
+    end
+  end
+
+  # Indexing an attribute lets match look records up by its value instead
+  # of scanning the table; create_index returns how many records it indexed
+
+  defp dispatch("create_index", [table_name, field], uid)
+       when is_binary(table_name) and is_binary(field) do
+    table = Store.resolve_table(uid, table_name)
+
+    with :ok <- ACL.authorize(uid, table, :create_index),
+         {:ok, indexed} <- Store.create_index(table, field) do
+      {:ok, %{index: field, indexed: indexed}}
+    end
+  end
+
+  defp dispatch("drop_index", [table_name, field], uid)
+       when is_binary(table_name) and is_binary(field) do
+    table = Store.resolve_table(uid, table_name)
+
+    with :ok <- ACL.authorize(uid, table, :drop_index),
+         {:ok, _} <- Store.drop_index(table, field) do
+      {:ok, "ok"}
+    end
+  end
+
+  # Subscriptions belong to this handler process, so Mnesia drops them
+  # automatically when the connection closes.
+  defp dispatch("subscribe", [table_name], uid) when is_binary(table_name) do
+    table = Store.resolve_table(uid, table_name)
+
+    with :ok <- ACL.authorize(uid, table, :subscribe) do
+      case :mnesia.subscribe({:table, table, :simple}) do
+        {:ok, _node} -> {:ok, "subscribed"}
+        {:error, reason} -> {:error, reason}
+      end
+    end
+  end
+
+  defp dispatch("unsubscribe", [table_name], uid) when is_binary(table_name) do
+    table = Store.resolve_table(uid, table_name)
+
+    with :ok <- ACL.authorize(uid, table, :unsubscribe) do
+      case :mnesia.unsubscribe({:table, table, :simple}) do
+        {:ok, _node} -> {:ok, "unsubscribed"}
+        {:error, reason} -> {:error, reason}
+      end
+    end
+  end
+
   defp dispatch("put", [table_name, record], uid) when is_binary(table_name) and is_map(record) do
     table = Store.resolve_table(uid, table_name)
 
@@ -170,8 +285,106 @@ defmodule Cortex.Handler do
     end
   end
 
+  defp dispatch("cas_put", [table_name, record, expected], uid)
+       when is_binary(table_name) and is_map(record) and (is_nil(expected) or is_map(expected)) do
+    table = Store.resolve_table(uid, table_name)
+
+    with :ok <- ACL.authorize(uid, table, :cas_put),
+         {:ok, :ok} <- Store.cas_put(table, record, expected) do
+      {:ok, "ok"}
+    end
+  end
+
+  # Writes take a trailing %{"ttl" => seconds} to make the record expire
+
+  defp dispatch("put", [table_name, record, %{"ttl" => ttl}], uid)
+       when is_binary(table_name) and is_map(record) and is_integer(ttl) and ttl > 0 do
+    table = Store.resolve_table(uid, table_name)
+
+    with :ok <- ACL.authorize(uid, table, :put),
+         {:ok, :ok} <- Store.put(table, record, ttl) do
+      {:ok, "ok"}
+    end
+  end
+
+  defp dispatch("cas_put", [table_name, record, expected, %{"ttl" => ttl}], uid)
+       when is_binary(table_name) and is_map(record) and
+              (is_nil(expected) or is_map(expected)) and is_integer(ttl) and ttl > 0 do
+    table = Store.resolve_table(uid, table_name)
+
+    with :ok <- ACL.authorize(uid, table, :cas_put),
+         {:ok, :ok} <- Store.cas_put(table, record, expected, ttl) do
+      {:ok, "ok"}
+    end
+  end
+
+  # Each record is written on its own, so one bad record doesn't stop the
+  # rest; the result has "ok" or %{"error" => reason} per record, in order
+  defp dispatch("put_many", [table_name, records], uid)
+       when is_binary(table_name) and is_list(records) do
+    table = Store.resolve_table(uid, table_name)
+
+    with :ok <- ACL.authorize(uid, table, :put_many) do
+      {:ok, Enum.map(records, &put_one(table, &1))}
+    end
+  end
+
+  defp dispatch("put_many", _params, _uid) do
+    {:error, "invalid params: expected [table, [record, ...]]"}
+  end
+
   defp dispatch("put", _params, _uid) do
-    {:error, "invalid params: expected [table, record]"}
+    {:error, "invalid params: expected [table, record] or [table, record, %{ttl: seconds}]"}
+  end
+
+  defp dispatch("append", [table_name, key, field, value], uid)
+       when is_binary(table_name) and is_binary(field) do
+    table = Store.resolve_table(uid, table_name)
+
+    with :ok <- ACL.authorize(uid, table, :append) do
+      Store.append(table, key, field, value)
+    end
+  end
+
+  defp dispatch("incr", [table_name, key, field, amount], uid)
+       when is_binary(table_name) and is_binary(field) and is_number(amount) do
+    table = Store.resolve_table(uid, table_name)
+
+    with :ok <- ACL.authorize(uid, table, :incr) do
+      Store.incr(table, key, field, amount)
+    end
+  end
+
+  # Ops are %{"op" => "put", "table" => t, "record" => r} or
+  # %{"op" => "delete", "table" => t, "key" => k}; every one is authorized
+  # before any is applied, and they are applied all or nothing
+  defp dispatch("transaction", [ops], uid) when is_list(ops) do
+    with {:ok, ops} <- authorize_ops(ops, uid) do
+      Store.transaction(ops)
+    end
+  end
+
+  defp dispatch("transaction", _params, _uid) do
+    {:error, "invalid params: expected [[op, ...]]"}
+  end
+
+  # A nil ttl removes the record's expiry
+  defp dispatch("expire", [table_name, key, ttl], uid)
+       when is_binary(table_name) and (is_nil(ttl) or (is_integer(ttl) and ttl > 0)) do
+    table = Store.resolve_table(uid, table_name)
+
+    with :ok <- ACL.authorize(uid, table, :expire),
+         {:ok, _} <- Store.expire(table, key, ttl) do
+      {:ok, "ok"}
+    end
+  end
+
+  defp dispatch("ttl", [table_name, key], uid) when is_binary(table_name) do
+    table = Store.resolve_table(uid, table_name)
+
+    with :ok <- ACL.authorize(uid, table, :ttl) do
+      Store.ttl(table, key)
+    end
   end
 
   defp dispatch("get", [table_name, key], uid) when is_binary(table_name) do
@@ -191,6 +404,15 @@ defmodule Cortex.Handler do
     end
   end
 
+  defp dispatch("delete_match", [table_name, pattern], uid)
+       when is_binary(table_name) and is_map(pattern) do
+    table = Store.resolve_table(uid, table_name)
+
+    with :ok <- ACL.authorize(uid, table, :delete_match) do
+      Store.delete_match(table, pattern)
+    end
+  end
+
   defp dispatch("match", [table_name, pattern], uid)
        when is_binary(table_name) and is_map(pattern) do
     table = Store.resolve_table(uid, table_name)
@@ -200,6 +422,63 @@ defmodule Cortex.Handler do
     end
   end
 
+  # A trailing conditions map narrows the match: %{"regex" => %{field => source}}
+  # requires each field to match its regular expression, and
+  # %{"range" => %{field => %{"gte" => min, "lte" => max}}} each field to hold
+  # a time (epoch seconds or ISO 8601) within the bounds, and
+  # %{"where" => %{field => %{"gt" => 30, "in" => [...], ...}}} each field to
+  # satisfy the comparison operators given for it. "order_by", "order", and
+  # "limit" sort the matches and keep the first n
+
+  # With %{"cursor" => key | nil, "limit" => n} in place of conditions (and
+  # likewise for `all` and `keys`), results come a page at a time in key
+  # order, as %{"records" => [...], "cursor" => next}; pass `next` back for
+  # the following page until it is nil
+
+  defp dispatch("match", [table_name, pattern, %{"cursor" => cursor, "limit" => limit}], uid)
+       when is_binary(table_name) and is_map(pattern) and
+              (is_nil(cursor) or is_binary(cursor)) and is_integer(limit) and limit > 0 do
+    table = Store.resolve_table(uid, table_name)
+
+    with :ok <- ACL.authorize(uid, table, :match) do
+      Store.page_after(table, cursor, limit, pattern) |> cursor_page("records")
+    end
+  end
+
+  defp dispatch("match", [table_name, pattern, conditions], uid)
+       when is_binary(table_name) and is_map(pattern) and is_map(conditions) do
+    table = Store.resolve_table(uid, table_name)
+
+    with :ok <- ACL.authorize(uid, table, :match) do
+      Store.match(table, pattern, conditions)
+    end
+  end
+
+  # The number of records `match` would return for the pattern (narrowed by
+  # the same conditions), or in the whole table without one
+
+  defp dispatch("count", [table_name], uid) when is_binary(table_name) do
+    dispatch("count", [table_name, %{}], uid)
+  end
+
+  defp dispatch("count", [table_name, pattern], uid)
+       when is_binary(table_name) and is_map(pattern) do
+    table = Store.resolve_table(uid, table_name)
+
+    with :ok <- ACL.authorize(uid, table, :count) do
+      Store.count(table, pattern)
+    end
+  end
+
+  defp dispatch("count", [table_name, pattern, conditions], uid)
+       when is_binary(table_name) and is_map(pattern) and is_map(conditions) do
+    table = Store.resolve_table(uid, table_name)
+
+    with :ok <- ACL.authorize(uid, table, :count) do
+      Store.count(table, pattern, conditions)
+    end
+  end
+
   defp dispatch("all", [table_name], uid) when is_binary(table_name) do
     table = Store.resolve_table(uid, table_name)
 
@@ -208,6 +487,43 @@ defmodule Cortex.Handler do
     end
   end
 
+  # `all` with %{"cursor" => key, "limit" => n} returns one page after key,
+  # in key order
+
+  defp dispatch("all", [table_name, %{"cursor" => cursor, "limit" => limit}], uid)
+       when is_binary(table_name) and (is_nil(cursor) or is_binary(cursor)) and
+              is_integer(limit) and limit > 0 do
+    table = Store.resolve_table(uid, table_name)
+
+    with :ok <- ACL.authorize(uid, table, :all) do
+      Store.page_after(table, cursor, limit) |> cursor_page("records")
+    end
+  end
+
+  # Read methods accept a trailing list of fields to project records onto
+
+  defp dispatch("get", [table_name, key, fields], uid) when is_list(fields) do
+    dispatch("get", [table_name, key], uid) |> project(fields)
+  end
+
+  defp dispatch("match", [table_name, pattern, fields], uid) when is_list(fields) do
+    dispatch("match", [table_name, pattern], uid) |> project(fields)
+  end
+
+  defp dispatch("all", [table_name, fields], uid) when is_list(fields) do
+    dispatch("all", [table_name], uid) |> project(fields)
+  end
+
+  defp dispatch("all", [table_name, %{"cursor" => _} = page, fields], uid) when is_list(fields) do
+    with {:ok, %{"records" => records} = result} <- dispatch("all", [table_name, page], uid) do
+      {:ok, %{result | "records" => Enum.map(records, &Map.take(&1, fields))}}
+    end
+  end
+
+  defp dispatch("all", [table_name, page, fields], uid) when is_map(page) and is_list(fields) do
+    dispatch("all", [table_name, page], uid) |> project(fields)
+  end
+
   defp dispatch("keys", [table_name], uid) when is_binary(table_name) do
     table = Store.resolve_table(uid, table_name)
 
@@ -216,16 +532,49 @@ defmodule Cortex.Handler do
     end
   end
 
-  defp dispatch("acl_grant", [identity, table_name, perms], uid) when is_binary(table_name) do
+  defp dispatch("keys", [table_name, %{"cursor" => cursor, "limit" => limit} = page], uid)
+       when is_binary(table_name) and (is_nil(cursor) or is_binary(cursor)) and
+              is_integer(limit) and limit > 0 do
     table = Store.resolve_table(uid, table_name)
+    prefix = Map.get(page, "prefix", "")
 
-    with :ok <- ACL.authorize(uid, table, :acl_grant),
-         {:ok, perm_list} <- ACL.parse_permissions(perms),
-         {:ok, :ok} <- Store.acl_grant(identity, table, perm_list) do
-      {:ok, "granted"}
+    with :ok <- ACL.authorize(uid, table, :all),
+         true <- is_binary(prefix) || {:error, "invalid params: prefix must be a string"} do
+      Store.key_page(table, prefix, cursor, limit) |> cursor_page("keys")
+    end
+  end
+
+  defp dispatch("keys", [table_name, %{"prefix" => prefix}], uid)
+       when is_binary(table_name) and is_binary(prefix) do
+    table = Store.resolve_table(uid, table_name)
+
+    with :ok <- ACL.authorize(uid, table, :all) do
+      Store.keys(table, prefix)
+    end
+  end
+
+  # Bounds are %{"from" => key, "to" => key, "limit" => n}, each optional
+  defp dispatch("range", [table_name, bounds], uid)
+       when is_binary(table_name) and is_map(bounds) do
+    table = Store.resolve_table(uid, table_name)
+
+    with {:ok, from, to, limit} <- range_bounds(bounds),
+         :ok <- ACL.authorize(uid, table, :range) do
+      Store.range(table, from, to, limit)
     end
   end
 
+  defp dispatch("range", _params, _uid), do: {:error, @range_params_error}
+
+  defp dispatch("acl_grant", [identity, table_name, perms], uid) when is_binary(table_name) do
+    grant(identity, table_name, perms, nil, uid)
+  end
+
+  defp dispatch("acl_grant", [identity, table_name, perms, expires_at], uid)
+       when is_binary(table_name) and (is_integer(expires_at) or is_nil(expires_at)) do
+    grant(identity, table_name, perms, expires_at, uid)
+  end
+
   defp dispatch("acl_revoke", [identity, table_name, perms], uid) when is_binary(table_name) do
     table = Store.resolve_table(uid, table_name)
 
@@ -236,12 +585,29 @@ defmodule Cortex.Handler do
     end
   end
 
-  defp dispatch("acl_list", _params, uid) do
+  defp dispatch("acl_check", [identity, table_name], uid)
+       when is_binary(identity) and is_binary(table_name) do
+    table = Store.resolve_table(uid, table_name)
+
+    with :ok <- ACL.authorize(uid, table, :acl_check) do
+      Store.acl_effective(identity, table)
+    end
+  end
+
+  defp dispatch("acl_list", params, uid) do
+    filters =
+      case params do
+        [filters] when is_map(filters) -> filters
+        _ -> %{}
+      end
+
     case Store.acl_list(uid) do
       {:ok, acls} ->
         formatted =
-          Enum.map(acls, fn {identity, table, perms} ->
-            %{identity: identity, table: table, permissions: perms}
+          acls
+          |> Enum.filter(&acl_listed?(&1, filters, uid))
+          |> Enum.map(fn {identity, table, perms, expires_at} ->
+            %{identity: identity, table: table, permissions: perms, expires_at: expires_at}
           end)
 
         {:ok, formatted}
@@ -255,6 +621,131 @@ defmodule Cortex.Handler do
     {:error, "unknown method: #{method}"}
   end
 
+  # Whether Mnesia can still persist to its data directory
+  defp data_dir_writable? do
+    case File.stat(Cortex.data_dir()) do
+      {:ok, %File.Stat{type: :directory, access: :read_write}} -> true
+      _ -> false
+    end
+  end
+
+  defp grant(identity, table_name, perms, expires_at, uid) do
+    table = Store.resolve_table(uid, table_name)
+
+    with :ok <- ACL.authorize(uid, table, :acl_grant),
+         {:ok, _} <- Identity.parse_identity(identity),
+         {:ok, perm_list} <- ACL.parse_permissions(perms),
+         {:ok, :ok} <- Store.acl_grant(identity, table, perm_list, expires_at) do
+      {:ok, "granted"}
+    end
+  end
+
+  # `acl_list` takes optional %{"table" => name, "identity" => id} filters
+  defp acl_listed?({identity, table, _perms, _expires_at}, filters, uid) do
+    table_listed =
+      case filters do
+        %{"table" => name} when is_binary(name) ->
+          table == Atom.to_string(Store.resolve_table(uid, name))
+
+        _ ->
+          true
+      end
+
+    identity_listed =
+      case filters do
+        %{"identity" => id} when is_binary(id) -> identity == id
+        _ -> true
+      end
+
+    table_listed and identity_listed
+  end
+
+  defp range_bounds(bounds) do
+    from = Map.get(bounds, "from")
+    to = Map.get(bounds, "to")
+    limit = Map.get(bounds, "limit")
+
+    if (is_nil(from) or is_binary(from)) and (is_nil(to) or is_binary(to)) and
+         (is_nil(limit) or (is_integer(limit) and limit > 0)) do
+      {:ok, from, to, limit}
+    else
+      {:error, @range_params_error}
+    end
+  end
+
+  defp authorize_ops(ops, uid) do
+    ops
+    |> Enum.reduce_while([], fn op, acc ->
+      case authorize_op(op, uid) do
+        {:ok, op} -> {:cont, [op | acc]}
+        error -> {:halt, error}
+      end
+    end)
+    |> case do
+      {:error, _} = error -> error
+      ops -> {:ok, Enum.reverse(ops)}
+    end
+  end
+
+  defp authorize_op(%{"op" => "put", "table" => name, "record" => record}, uid)
+       when is_binary(name) and is_map(record) do
+    table = Store.resolve_table(uid, name)
+
+    with :ok <- ACL.authorize(uid, table, :put) do
+      {:ok, {:put, table, record}}
+    end
+  end
+
+  defp authorize_op(%{"op" => "delete", "table" => name, "key" => key}, uid)
+       when is_binary(name) do
+    table = Store.resolve_table(uid, name)
+
+    with :ok <- ACL.authorize(uid, table, :delete) do
+      {:ok, {:delete, table, key}}
+    end
+  end
+
+  defp authorize_op(_op, _uid), do: {:error, :invalid_operation}
+
+  defp cursor_page({:ok, {items, next}}, name), do: {:ok, %{name => items, "cursor" => next}}
+  defp cursor_page(error, _name), do: error
+
+  defp put_one(table, record) when is_map(record) do
+    case Store.put(table, record) do
+      {:ok, :ok} -> "ok"
+      {:error, reason} when is_atom(reason) -> %{"error" => Atom.to_string(reason)}
+      {:error, reason} -> %{"error" => inspect(reason)}
+    end
+  end
+
+  defp put_one(_table, _record), do: %{"error" => "invalid_record"}
+
+  defp project({:ok, records}, fields) when is_list(records) do
+    {:ok, Enum.map(records, &Map.take(&1, fields))}
+  end
+
+  defp project({:ok, record}, fields) when is_map(record), do: {:ok, Map.take(record, fields)}
+  defp project(result, _fields), do: result
+
+  defp change_event({:write, {table, key, data}, _activity}, uid) do
+    %{op: "write", table: table_label(table, uid), key: key, record: data}
+  end
+
+  defp change_event({:delete, {table, key}, _activity}, uid) do
+    %{op: "delete", table: table_label(table, uid), key: key}
+  end
+
+  defp change_event({:delete_object, {table, key, _data}, _activity}, uid) do
+    %{op: "delete", table: table_label(table, uid), key: key}
+  end
+
+  defp change_event(_event, _uid), do: nil
+
+  # Report the caller's own tables by short name, others fully qualified
+  defp table_label(table, uid) do
+    String.replace_prefix(Atom.to_string(table), "#{uid}:", "")
+  end
+
   # Validate name format (alphanumeric + underscore, starts with letter/underscore)
   defp valid_name?(name) when is_binary(name) do
     Regex.match?(@valid_name_pattern, name)
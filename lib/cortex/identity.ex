@@ -6,8 +6,14 @@ defmodule Cortex.Identity do
   This is kernel-enforced and cannot be forged.
   """
 
+  require Logger
+
   alias Cortex.Peercred
 
+  # Called by absolute path: the daemon's PATH must not decide which program
+  # reports group membership.
+  @id_command "/usr/bin/id"
+
   @doc """
   Extract the UID from a connected gen_tcp socket.
 
@@ -29,7 +35,12 @@ defmodule Cortex.Identity do
   def uid_to_identity(uid), do: "uid:#{uid}"
 
   @doc """
-  Parse an identity string to extract the UID.
+  Format a GID as a group identity string.
+  """
+  def gid_to_identity(gid), do: "gid:#{gid}"
+
+  @doc """
+  Parse an identity string to extract the UID (or `{:gid, gid}` for groups).
   """
   def parse_identity("uid:" <> uid_str) do
     case Integer.parse(uid_str) do
@@ -38,6 +49,66 @@ defmodule Cortex.Identity do
     end
   end
 
+  def parse_identity("gid:" <> gid_str) do
+    case Integer.parse(gid_str) do
+      {gid, ""} -> {:ok, {:gid, gid}}
+      _ -> {:error, :invalid_identity}
+    end
+  end
+
   def parse_identity("*"), do: {:ok, :world}
   def parse_identity(_), do: {:error, :invalid_identity}
+
+  @doc """
+  List the groups (primary and supplementary) a UID belongs to.
+
+  Returns {:ok, gids} or {:error, reason}.
+
+  Peer credentials only carry the connecting process's effective GID, so
+  membership is resolved through the system group database instead. Each
+  connection has its own handler process, so the answer is kept in that
+  process's dictionary: groups are looked up once per connection, not on
+  every ACL check. If the lookup fails the UID is treated as having no
+  groups, so a broken lookup never grants group access.
+  """
+  def group_ids(uid) when is_integer(uid) do
+    key = {__MODULE__, :group_ids, uid}
+
+    case Process.get(key) do
+      nil ->
+        result = lookup_group_ids(uid)
+        Process.put(key, result)
+        result
+
+      result ->
+        result
+    end
+  end
+
+  defp lookup_group_ids(uid) do
+    with true <- File.regular?(@id_command),
+         {output, 0} <-
+           System.cmd(@id_command, ["-G", Integer.to_string(uid)], stderr_to_stdout: true) do
+      {:ok, parse_gids(output)}
+    else
+      false ->
+        Logger.error("Cannot resolve groups for uid #{uid}: #{@id_command} not found")
+        {:error, :no_id_command}
+
+      {output, status} ->
+        Logger.warning("Cannot resolve groups for uid #{uid}: id exited #{status}: #{output}")
+        {:error, {:id_failed, status}}
+    end
+  end
+
+  defp parse_gids(output) do
+    output
+    |> String.split()
+    |> Enum.flat_map(fn gid_str ->
+      case Integer.parse(gid_str) do
+        {gid, ""} -> [gid]
+        _ -> []
+      end
+    end)
+  end
 end
@@ -2,12 +2,14 @@ defmodule Cortex.Protocol do
   @moduledoc """
   MessagePack-RPC protocol handler.
 
-  Request:  [0, msgid, method, params]
-  Response: [1, msgid, error, result]
+  Request:      [0, msgid, method, params]
+  Response:     [1, msgid, error, result]
+  Notification: [2, method, params]
   """
 
   @request_type 0
   @response_type 1
+  @notification_type 2
 
   @doc """
   Decode a MessagePack-RPC request.
@@ -43,6 +45,13 @@ defmodule Cortex.Protocol do
     Msgpax.pack!([@response_type, msgid, error_str, nil])
   end
 
+  @doc """
+  Encode a server-pushed notification (e.g. table change events).
+  """
+  def encode_notification(method, params) when is_list(params) do
+    Msgpax.pack!([@notification_type, method, encode_value(params)])
+  end
+
   defp format_error(error) when is_binary(error), do: error
   defp format_error(error) when is_atom(error), do: Atom.to_string(error)
   defp format_error({:error, reason}), do: format_error(reason)
@@ -8,8 +8,20 @@ defmodule Cortex.Store do
   use GenServer
   require Logger
 
+  alias Cortex.Identity
+
   @acl_table :cortex_acls
+  @acl_expiry_table :cortex_acl_expiry
+  @record_expiry_table :cortex_record_expiry
   @meta_table :cortex_meta
+  @index_meta_table :cortex_index_meta
+  @index_table :cortex_indexes
+
+  # How often expired ACL grants are swept (ms)
+  @acl_expiry_interval 60_000
+
+  # How often records past their TTL are swept (ms)
+  @record_expiry_interval 10_000
 
   def start_link(opts) do
     GenServer.start_link(__MODULE__, opts, name: __MODULE__)
@@ -18,9 +30,32 @@ defmodule Cortex.Store do
   @impl true
   def init(_opts) do
     setup_mnesia()
+    schedule_acl_expiry()
+    schedule_record_expiry()
     {:ok, %{}}
   end
 
+  @impl true
+  def handle_info(:expire_acls, state) do
+    expire_acls(System.os_time(:second))
+    schedule_acl_expiry()
+    {:noreply, state}
+  end
+
+  def handle_info(:expire_records, state) do
+    expire_records(System.os_time(:second))
+    schedule_record_expiry()
+    {:noreply, state}
+  end
+
+  defp schedule_acl_expiry do
+    Process.send_after(self(), :expire_acls, @acl_expiry_interval)
+  end
+
+  defp schedule_record_expiry do
+    Process.send_after(self(), :expire_records, @record_expiry_interval)
+  end
+
   defp setup_mnesia do
     # Ensure Mnesia application is loaded (but don't start it yet)
     case Application.load(:mnesia) do
@@ -42,13 +77,18 @@ defmodule Cortex.Store do
 
     # System tables
     create_system_table(@acl_table, [:identity_table, :permissions])
+    create_system_table(@acl_expiry_table, [:identity_table, :expires_at])
+    create_system_table(@record_expiry_table, [:table_key, :expires_at])
     create_system_table(@meta_table, [:table_name, :owner, :key_field, :attributes])
+    create_system_table(@index_meta_table, [:table_name, :fields])
+    # One entry per indexed value of each record: {table, field, value} => key
+    create_system_table(@index_table, [:entry, :key], type: :bag)
 
     Logger.info("Mnesia started, data dir: #{data_dir}")
   end
 
-  defp create_system_table(name, attributes) do
-    opts = [{:attributes, attributes}, {storage_type(), [node()]}]
+  defp create_system_table(name, attributes, opts \\ []) do
+    opts = [{:attributes, attributes}, {storage_type(), [node()]} | opts]
 
     case :mnesia.create_table(name, opts) do
       {:atomic, :ok} -> :ok
@@ -75,7 +115,8 @@ defmodule Cortex.Store do
     table_name = namespaced_table(owner_uid, name)
     key_field = hd(attributes)
 
-    opts = [{:attributes, [:key, :data]}, {storage_type(), [node()]}]
+    # Ordered by key, so pages can walk forward from a cursor
+    opts = [{:attributes, [:key, :data]}, {:type, :ordered_set}, {storage_type(), [node()]}]
 
     case :mnesia.create_table(table_name, opts) do
       {:atomic, :ok} ->
@@ -107,7 +148,14 @@ defmodule Cortex.Store do
       :mnesia.delete({@meta_table, table_name})
 
       :mnesia.match_object({@acl_table, {:_, table_name}, :_})
-      |> Enum.each(fn {_, key, _} -> :mnesia.delete({@acl_table, key}) end)
+      |> Enum.each(fn {_, key, _} ->
+        :mnesia.delete({@acl_table, key})
+        :mnesia.delete({@acl_expiry_table, key})
+      end)
+
+      clear_record_expiry(table_name)
+      clear_indexes(table_name)
+      :mnesia.delete({@index_meta_table, table_name})
     end)
 
     case :mnesia.delete_table(table_name) do
@@ -122,26 +170,329 @@ defmodule Cortex.Store do
     end
   end
 
-  def put(table_name, record) when is_map(record) do
-    case get_table_meta(table_name) do
-      {:ok, meta} ->
-        key_field = Atom.to_string(meta.key_field)
+  # Records, index entries, and expiries go in one transaction, so a
+  # concurrent write can't leave an entry without its record or a record
+  # without its expiry. The table definition stays, and with it the metadata
+  # and ACLs.
+  def truncate(table_name) do
+    :mnesia.transaction(fn ->
+      :mnesia.write_lock_table(table_name)
+
+      :mnesia.all_keys(table_name)
+      |> Enum.each(&:mnesia.delete({table_name, &1}))
+
+      clear_record_expiry(table_name)
+      clear_indexes(table_name)
+    end)
+    |> transaction_result()
+  end
 
-        case Map.get(record, key_field) || Map.get(record, String.to_atom(key_field)) do
-          nil ->
-            {:error, :missing_key}
+  # A `ttl` in seconds makes the record expire; without one it never does,
+  # even if an earlier write gave it a TTL
+  def put(table_name, record, ttl \\ nil) when is_map(record) do
+    with {:ok, key_str} <- record_key(table_name, record) do
+      :mnesia.transaction(fn ->
+        write_record(table_name, key_str, record, ttl)
+      end)
+      |> transaction_result()
+    end
+  end
 
-          key ->
-            key_str = stringify(key)
+  # Write only if the stored record equals `expected`, or, when `expected` is
+  # nil, only if no record exists under the key yet
+  def cas_put(table_name, record, expected, ttl \\ nil) when is_map(record) do
+    with {:ok, key_str} <- record_key(table_name, record) do
+      :mnesia.transaction(fn ->
+        current =
+          case :mnesia.read({table_name, key_str}) do
+            [{^table_name, ^key_str, data}] -> data
+            [] -> nil
+          end
 
-            :mnesia.transaction(fn ->
-              :mnesia.write({table_name, key_str, record})
-            end)
-            |> transaction_result()
+        if current == expected do
+          write_record(table_name, key_str, record, ttl)
+        else
+          :mnesia.abort(:condition_failed)
         end
+      end)
+      |> transaction_result()
+    end
+  end
+
+  # Push `value` onto the array in `field` of the record under `key`, starting
+  # a new array if the field is absent. Returns the array's new length.
+  def append(table_name, key, field, value) do
+    key_str = stringify(key)
+
+    :mnesia.transaction(fn ->
+      case :mnesia.read({table_name, key_str}) do
+        [{^table_name, ^key_str, data}] ->
+          items =
+            case Map.get(data, field, []) do
+              items when is_list(items) -> items ++ [value]
+              _ -> :mnesia.abort(:not_an_array)
+            end
+
+          # A plain write leaves any expiry set by put in place
+          store_record(table_name, key_str, Map.put(data, field, items))
+          length(items)
+
+        [] ->
+          :mnesia.abort(:not_found)
+      end
+    end)
+    |> transaction_result()
+  end
+
+  # Add `amount` to the number in `field` of the record under `key`, in one
+  # transaction. A missing field counts as 0, and a missing record is created
+  # holding just the key and the field. Returns the field's new value.
+  def incr(table_name, key, field, amount) when is_number(amount) do
+    key_str = stringify(key)
+
+    with {:ok, meta} <- get_table_meta(table_name) do
+      key_field = Atom.to_string(meta.key_field)
+
+      :mnesia.transaction(fn ->
+        if field == key_field, do: :mnesia.abort(:key_field)
+
+        data =
+          case :mnesia.read({table_name, key_str}) do
+            [{^table_name, ^key_str, data}] -> data
+            [] -> %{key_field => key}
+          end
+
+        value =
+          case Map.get(data, field, 0) do
+            current when is_number(current) -> current + amount
+            _ -> :mnesia.abort(:not_a_number)
+          end
+
+        # Like append, this leaves any expiry in place
+        store_record(table_name, key_str, Map.put(data, field, value))
+        value
+      end)
+      |> transaction_result()
+    end
+  end
+
+  # Must be called inside a transaction
+  defp write_record(table_name, key_str, record, ttl) do
+    store_record(table_name, key_str, record)
+    set_expiry(table_name, key_str, ttl)
+  end
+
+  defp set_expiry(table_name, key_str, ttl) do
+    case ttl do
+      nil ->
+        :mnesia.delete({@record_expiry_table, {table_name, key_str}})
+
+      seconds ->
+        expires_at = System.os_time(:second) + seconds
+        :mnesia.write({@record_expiry_table, {table_name, key_str}, expires_at})
+    end
+  end
+
+  # Index `field` of `table_name`'s records, so matches on it look up the
+  # records holding the value rather than scanning the table. The field must
+  # be one of the table's attributes other than the key.
+  def create_index(table_name, field) when is_binary(field) do
+    :mnesia.transaction(fn ->
+      case :mnesia.read({@meta_table, table_name}) do
+        [{@meta_table, ^table_name, _owner, key_field, attributes}] ->
+          cond do
+            Atom.to_string(key_field) == field -> :mnesia.abort(:key_field)
+            field not in Enum.map(attributes, &Atom.to_string/1) -> :mnesia.abort(:no_attribute)
+            field in indexed_fields(table_name) -> :mnesia.abort(:index_exists)
+            true -> :ok
+          end
+
+          fields = indexed_fields(table_name) ++ [field]
+          :mnesia.write({@index_meta_table, table_name, fields})
+
+          :mnesia.foldl(
+            fn {_, key, data}, count ->
+              index_record(table_name, key, data, [field])
+              count + 1
+            end,
+            0,
+            table_name
+          )
+
+        [] ->
+          :mnesia.abort(:not_found)
+      end
+    end)
+    |> transaction_result()
+  end
+
+  def drop_index(table_name, field) when is_binary(field) do
+    :mnesia.transaction(fn ->
+      fields = indexed_fields(table_name)
+      unless field in fields, do: :mnesia.abort(:no_index)
+
+      case List.delete(fields, field) do
+        [] -> :mnesia.delete({@index_meta_table, table_name})
+        rest -> :mnesia.write({@index_meta_table, table_name, rest})
+      end
+
+      :mnesia.match_object({@index_table, {table_name, field, :_}, :_})
+      |> Enum.each(&:mnesia.delete_object/1)
+    end)
+    |> transaction_result()
+  end
+
+  def indexes(table_name) do
+    :mnesia.transaction(fn -> indexed_fields(table_name) end)
+    |> transaction_result()
+  end
+
+  # The records that could match `pattern`: with an indexed field in it,
+  # just those the index lists for that field's value (the pattern is still
+  # checked in full afterwards), and otherwise the whole table. Must be
+  # called inside a transaction.
+  defp candidates(table_name, pattern) do
+    indexed = indexed_fields(table_name)
+
+    case Enum.find(pattern, fn {field, value} -> field in indexed and not is_nil(value) end) do
+      {field, value} ->
+        equal_values(value)
+        |> Enum.flat_map(&:mnesia.read({@index_table, {table_name, field, &1}}))
+        |> Enum.map(fn {_, _, key} -> key end)
+        |> Enum.uniq()
+        |> Enum.flat_map(&:mnesia.read({table_name, &1}))
+
+      nil ->
+        :mnesia.match_object({table_name, :_, :_})
+    end
+  end
+
+  # match compares with ==, but index lookups are exact, so 1 must also find
+  # a stored 1.0 and the other way round
+  defp equal_values(value) when is_integer(value), do: [value, value * 1.0]
+
+  defp equal_values(value) when is_float(value) and value == trunc(value),
+    do: [value, trunc(value)]
+
+  defp equal_values(value), do: [value]
+
+  # Every write and delete of a record goes through these two so the
+  # table's indexes stay in step. Must be called inside a transaction.
+
+  defp store_record(table_name, key_str, data) do
+    fields = indexed_fields(table_name)
+    unindex_record(table_name, key_str, fields)
+    :mnesia.write({table_name, key_str, data})
+    index_record(table_name, key_str, data, fields)
+  end
+
+  # Drops the record's expiry too, so a record written later under the same
+  # key isn't reaped when the old one would have expired
+  defp remove_record(table_name, key_str) do
+    unindex_record(table_name, key_str, indexed_fields(table_name))
+    :mnesia.delete({table_name, key_str})
+    :mnesia.delete({@record_expiry_table, {table_name, key_str}})
+  end
+
+  defp indexed_fields(table_name) do
+    case :mnesia.read({@index_meta_table, table_name}) do
+      [{@index_meta_table, ^table_name, fields}] -> fields
+      [] -> []
+    end
+  end
+
+  defp index_record(table_name, key_str, data, fields) do
+    index_entries(table_name, data, fields)
+    |> Enum.each(&:mnesia.write({@index_table, &1, key_str}))
+  end
+
+  defp unindex_record(_table_name, _key_str, []), do: :ok
+
+  defp unindex_record(table_name, key_str, fields) do
+    case :mnesia.read({table_name, key_str}) do
+      [{^table_name, ^key_str, data}] ->
+        index_entries(table_name, data, fields)
+        |> Enum.each(&:mnesia.delete_object({@index_table, &1, key_str}))
+
+      [] ->
+        :ok
+    end
+  end
+
+  # An array is indexed both whole and by each element, as match compares a
+  # scalar pattern value with an array by membership
+  defp index_entries(table_name, data, fields) do
+    for field <- fields,
+        value = Map.get(data, field),
+        not is_nil(value),
+        indexed <- if(is_list(value), do: [value | value], else: [value]),
+        uniq: true,
+        do: {table_name, field, indexed}
+  end
+
+  # Must be called inside a transaction
+  defp clear_indexes(table_name) do
+    :mnesia.match_object({@index_table, {table_name, :_, :_}, :_})
+    |> Enum.each(&:mnesia.delete_object/1)
+  end
+
+  # Make the record under `key` expire `ttl` seconds from now, or never when
+  # `ttl` is nil, leaving the record itself as it is
+  def expire(table_name, key, ttl) do
+    key_str = stringify(key)
+
+    :mnesia.transaction(fn ->
+      case :mnesia.read({table_name, key_str}) do
+        [] -> :mnesia.abort(:not_found)
+        _ -> set_expiry(table_name, key_str, ttl)
+      end
+    end)
+    |> transaction_result()
+  end
+
+  # Seconds until the record under `key` expires, or nil if it never will
+  def ttl(table_name, key) do
+    key_str = stringify(key)
+
+    :mnesia.transaction(fn ->
+      if :mnesia.read({table_name, key_str}) == [], do: :mnesia.abort(:not_found)
+
+      case :mnesia.read({@record_expiry_table, {table_name, key_str}}) do
+        [{_, _, expires_at}] -> max(expires_at - System.os_time(:second), 0)
+        [] -> nil
+      end
+    end)
+    |> transaction_result()
+  end
+
+  # Must be called inside a transaction
+  defp clear_record_expiry(table_name) do
+    :mnesia.match_object({@record_expiry_table, {table_name, :_}, :_})
+    |> Enum.each(fn {_, key, _} -> :mnesia.delete({@record_expiry_table, key}) end)
+  end
 
-      error ->
-        error
+  def expire_records(now) do
+    :mnesia.transaction(fn ->
+      :mnesia.foldl(
+        fn {_, key, expires_at}, acc ->
+          if expired?(expires_at, now), do: [key | acc], else: acc
+        end,
+        [],
+        @record_expiry_table
+      )
+      |> Enum.each(fn {table_name, key_str} -> remove_record(table_name, key_str) end)
+    end)
+    |> transaction_result()
+  end
+
+  defp record_key(table_name, record) do
+    with {:ok, meta} <- get_table_meta(table_name) do
+      key_field = Atom.to_string(meta.key_field)
+
+      case Map.get(record, key_field) || Map.get(record, String.to_atom(key_field)) do
+        nil -> {:error, :missing_key}
+        key -> {:ok, stringify(key)}
+      end
     end
   end
 
@@ -158,21 +509,298 @@ defmodule Cortex.Store do
   def delete(table_name, key) do
     key_str = stringify(key)
 
-    :mnesia.transaction(fn ->
-      :mnesia.delete({table_name, key_str})
-    end)
+    :mnesia.transaction(fn -> remove_record(table_name, key_str) end)
     |> transaction_result()
   end
 
+  # Apply `ops`, each {:put, table, record} or {:delete, table, key}, in one
+  # transaction: all of them, or none if any fails. Returns how many ran.
+  def transaction(ops) when is_list(ops) do
+    with {:ok, writes} <- keyed_ops(ops) do
+      :mnesia.transaction(fn ->
+        Enum.each(writes, fn
+          {:put, table_name, key_str, record} ->
+            write_record(table_name, key_str, record, nil)
+
+          {:delete, table_name, key_str} ->
+            remove_record(table_name, key_str)
+        end)
+
+        length(writes)
+      end)
+      |> transaction_result()
+    end
+  end
+
+  # Find every op's key up front, so a record missing one fails the whole
+  # transaction before anything is written
+  defp keyed_ops(ops) do
+    ops
+    |> Enum.reduce_while([], fn
+      {:put, table_name, record}, acc ->
+        case record_key(table_name, record) do
+          {:ok, key_str} -> {:cont, [{:put, table_name, key_str, record} | acc]}
+          error -> {:halt, error}
+        end
+
+      {:delete, table_name, key}, acc ->
+        {:cont, [{:delete, table_name, stringify(key)} | acc]}
+    end)
+    |> case do
+      {:error, _} = error -> error
+      writes -> {:ok, Enum.reverse(writes)}
+    end
+  end
+
   def match(table_name, pattern) when is_map(pattern) do
     :mnesia.transaction(fn ->
-      :mnesia.match_object({table_name, :_, :_})
+      candidates(table_name, pattern)
       |> Enum.filter(fn {_, _, data} -> map_matches?(data, pattern) end)
       |> Enum.map(fn {_, _, data} -> data end)
     end)
     |> transaction_result()
   end
 
+  # Like match/2, but narrowed by `conditions`: each field in its "regex" map
+  # must also be a string (or a list holding one) that its regular expression
+  # matches, and each field in its "range" map a time within the inclusive
+  # "gte"/"lte" bounds, in epoch seconds. Times may be stored as epoch seconds
+  # or as ISO 8601 strings. Each field in its "where" map must satisfy every
+  # operator given for it (see op_matches?/3). "order_by" sorts the matches
+  # on a field, "order" ("asc" or "desc") says which way, and "limit" keeps
+  # only the first n.
+  def match(table_name, pattern, conditions) when is_map(pattern) and is_map(conditions) do
+    with {:ok, matches?} <- conditions_filter(pattern, conditions),
+         {:ok, ordering} <- validate_ordering(conditions) do
+      :mnesia.transaction(fn ->
+        candidates(table_name, pattern)
+        |> Enum.filter(fn {_, _, data} -> matches?.(data) end)
+        |> Enum.map(fn {_, _, data} -> data end)
+        |> order_records(ordering)
+      end)
+      |> transaction_result()
+    end
+  end
+
+  # How many records match/2 would return, without building the list. An
+  # empty pattern counts the whole table from its size.
+  def count(table_name, pattern) when pattern == %{} do
+    :mnesia.transaction(fn -> :mnesia.table_info(table_name, :size) end)
+    |> transaction_result()
+  end
+
+  def count(table_name, pattern) when is_map(pattern) do
+    :mnesia.transaction(fn ->
+      candidates(table_name, pattern)
+      |> Enum.count(fn {_, _, data} -> map_matches?(data, pattern) end)
+    end)
+    |> transaction_result()
+  end
+
+  # Like count/2, but narrowed by the "regex", "range", and "where"
+  # conditions match/3 takes
+  def count(table_name, pattern, conditions) when is_map(pattern) and is_map(conditions) do
+    with {:ok, matches?} <- conditions_filter(pattern, conditions) do
+      :mnesia.transaction(fn ->
+        candidates(table_name, pattern)
+        |> Enum.count(fn {_, _, data} -> matches?.(data) end)
+      end)
+      |> transaction_result()
+    end
+  end
+
+  # A function telling whether a record's data matches `pattern` and the
+  # "regex", "range", and "where" parts of `conditions`
+  defp conditions_filter(pattern, conditions) do
+    with {:ok, compiled} <- compile_regexes(Map.get(conditions, "regex", %{})),
+         {:ok, ranges} <- validate_ranges(Map.get(conditions, "range", %{})),
+         {:ok, where} <- validate_where(Map.get(conditions, "where", %{})) do
+      {:ok,
+       fn data ->
+         map_matches?(data, pattern) and regexes_match?(data, compiled) and
+           ranges_match?(data, ranges) and where_matches?(data, where)
+       end}
+    end
+  end
+
+  defp validate_ordering(conditions) do
+    order_by = Map.get(conditions, "order_by")
+    order = Map.get(conditions, "order", "asc")
+    limit = Map.get(conditions, "limit")
+
+    valid? =
+      (is_nil(order_by) or is_binary(order_by)) and order in ["asc", "desc"] and
+        (is_nil(limit) or (is_integer(limit) and limit > 0))
+
+    if valid?, do: {:ok, {order_by, order, limit}}, else: {:error, :invalid_order}
+  end
+
+  defp order_records(records, {order_by, order, limit}) do
+    records = if order_by, do: sort_records(records, order_by, order), else: records
+    if limit, do: Enum.take(records, limit), else: records
+  end
+
+  # Records without the field go last either way, as the CLI sorts them
+  defp sort_records(records, field, order) do
+    {present, missing} = Enum.split_with(records, &Map.has_key?(&1, field))
+    direction = if order == "desc", do: :desc, else: :asc
+    Enum.sort_by(present, &sort_key(Map.get(&1, field)), direction) ++ missing
+  end
+
+  # Numbers, then strings, then booleans, then anything else
+  defp sort_key(value) when is_number(value), do: {0, value}
+  defp sort_key(value) when is_binary(value), do: {1, value}
+  defp sort_key(value) when is_boolean(value), do: {2, value}
+  defp sort_key(_value), do: {3, nil}
+
+  defp validate_ranges(ranges) when is_map(ranges) do
+    valid? =
+      Enum.all?(ranges, fn
+        {_field, bounds} when is_map(bounds) ->
+          Enum.all?(bounds, fn {bound, value} ->
+            bound in ["gte", "lte"] and is_number(value)
+          end)
+
+        _ ->
+          false
+      end)
+
+    if valid?, do: {:ok, ranges}, else: {:error, :invalid_range}
+  end
+
+  defp validate_ranges(_ranges), do: {:error, :invalid_range}
+
+  defp ranges_match?(data, ranges) do
+    Enum.all?(ranges, fn {field, bounds} ->
+      case to_epoch_seconds(Map.get(data, field)) do
+        nil ->
+          false
+
+        time ->
+          time >= Map.get(bounds, "gte", time) and time <= Map.get(bounds, "lte", time)
+      end
+    end)
+  end
+
+  defp to_epoch_seconds(value) when is_number(value), do: value
+
+  defp to_epoch_seconds(value) when is_binary(value) do
+    case DateTime.from_iso8601(value) do
+      {:ok, datetime, _offset} -> DateTime.to_unix(datetime, :microsecond) / 1_000_000
+      {:error, _} -> nil
+    end
+  end
+
+  defp to_epoch_seconds(_value), do: nil
+
+  @where_operators ["gt", "gte", "lt", "lte", "in", "contains", "prefix"]
+
+  defp validate_where(where) when is_map(where) do
+    valid? =
+      Enum.all?(where, fn
+        {_field, ops} when is_map(ops) and map_size(ops) > 0 ->
+          Enum.all?(ops, fn
+            {"in", values} -> is_list(values)
+            {"prefix", prefix} -> is_binary(prefix)
+            {op, _operand} -> op in @where_operators
+          end)
+
+        _ ->
+          false
+      end)
+
+    if valid?, do: {:ok, where}, else: {:error, :invalid_where}
+  end
+
+  defp validate_where(_where), do: {:error, :invalid_where}
+
+  defp where_matches?(data, where) do
+    Enum.all?(where, fn {field, ops} ->
+      Map.has_key?(data, field) and
+        Enum.all?(ops, fn {op, operand} -> op_matches?(op, Map.get(data, field), operand) end)
+    end)
+  end
+
+  # "contains" finds a substring in a string or an element in a list. The
+  # other operators match a list when any of its elements does: "in" an
+  # element equal to one of the operands, "prefix" a string starting with
+  # it, and "gt", "gte", "lt", and "lte" a number compared with a number or
+  # a string with a string (so ISO 8601 times compare in time order).
+  defp op_matches?("contains", value, operand) when is_binary(value) and is_binary(operand),
+    do: String.contains?(value, operand)
+
+  defp op_matches?("contains", values, operand) when is_list(values),
+    do: Enum.any?(values, &(&1 == operand))
+
+  defp op_matches?("contains", _value, _operand), do: false
+
+  defp op_matches?(op, values, operand) when is_list(values),
+    do: Enum.any?(values, &op_matches?(op, &1, operand))
+
+  defp op_matches?("in", value, operands), do: Enum.any?(operands, &(&1 == value))
+
+  defp op_matches?("prefix", value, prefix) when is_binary(value),
+    do: String.starts_with?(value, prefix)
+
+  defp op_matches?("prefix", _value, _prefix), do: false
+
+  defp op_matches?(op, value, operand) do
+    case {op, compare(value, operand)} do
+      {_, nil} -> false
+      {"gt", order} -> order == :gt
+      {"gte", order} -> order != :lt
+      {"lt", order} -> order == :lt
+      {"lte", order} -> order != :gt
+    end
+  end
+
+  defp compare(a, b) when (is_number(a) and is_number(b)) or (is_binary(a) and is_binary(b)) do
+    cond do
+      a < b -> :lt
+      a > b -> :gt
+      true -> :eq
+    end
+  end
+
+  defp compare(_a, _b), do: nil
+
+  defp compile_regexes(regexes) do
+    Enum.reduce_while(regexes, {:ok, []}, fn
+      {field, source}, {:ok, acc} when is_binary(source) ->
+        case Regex.compile(source) do
+          {:ok, regex} -> {:cont, {:ok, [{field, regex} | acc]}}
+          {:error, _} -> {:halt, {:error, :invalid_regex}}
+        end
+
+      _, _ ->
+        {:halt, {:error, :invalid_regex}}
+    end)
+  end
+
+  defp regexes_match?(data, compiled) do
+    Enum.all?(compiled, fn {field, regex} ->
+      case Map.get(data, field) do
+        value when is_binary(value) -> string_matches?(regex, value)
+        values when is_list(values) -> Enum.any?(values, &string_matches?(regex, &1))
+        _ -> false
+      end
+    end)
+  end
+
+  defp string_matches?(regex, value), do: is_binary(value) and Regex.match?(regex, value)
+
+  def delete_match(table_name, pattern) when is_map(pattern) do
+    :mnesia.transaction(fn ->
+      matching =
+        :mnesia.match_object({table_name, :_, :_})
+        |> Enum.filter(fn {_, _, data} -> map_matches?(data, pattern) end)
+
+      Enum.each(matching, fn {_, key, _} -> remove_record(table_name, key) end)
+      length(matching)
+    end)
+    |> transaction_result()
+  end
+
   def all(table_name) do
     :mnesia.transaction(fn ->
       :mnesia.match_object({table_name, :_, :_})
@@ -181,9 +809,164 @@ defmodule Cortex.Store do
     |> transaction_result()
   end
 
-  def keys(table_name) do
+  # Append `attribute` to the schema and set it to `default` on every record
+  # that doesn't have it yet. Returns how many records were backfilled.
+  def add_attribute(table_name, attribute, default) when is_atom(attribute) do
+    field = Atom.to_string(attribute)
+
+    :mnesia.transaction(fn ->
+      case :mnesia.read({@meta_table, table_name}) do
+        [{@meta_table, ^table_name, owner, key_field, attributes}] ->
+          if attribute in attributes, do: :mnesia.abort(:attribute_exists)
+
+          :mnesia.write({@meta_table, table_name, owner, key_field, attributes ++ [attribute]})
+
+          backfill =
+            :mnesia.match_object({table_name, :_, :_})
+            |> Enum.reject(fn {_, _, data} -> Map.has_key?(data, field) end)
+
+          Enum.each(backfill, fn {_, key, data} ->
+            store_record(table_name, key, Map.put(data, field, default))
+          end)
+
+          length(backfill)
+
+        [] ->
+          :mnesia.abort(:not_found)
+      end
+    end)
+    |> transaction_result()
+  end
+
+  # Records whose keys fall between `from` and `to` inclusive, in key order,
+  # at most `limit` of them; a nil bound or limit leaves that side open.
+  # Keys are stored as strings, so they compare as strings.
+  def range(table_name, from, to, limit) do
+    guards =
+      [{:>=, :"$1", from}, {:"=<", :"$1", to}]
+      |> Enum.reject(fn {_, _, bound} -> is_nil(bound) end)
+
+    :mnesia.transaction(fn ->
+      :mnesia.select(table_name, [{{table_name, :"$1", :"$2"}, guards, [{{:"$1", :"$2"}}]}])
+      |> Enum.sort_by(fn {key, _} -> key end)
+      |> then(fn rows -> if limit, do: Enum.take(rows, limit), else: rows end)
+      |> Enum.map(fn {_, data} -> data end)
+    end)
+    |> transaction_result()
+  end
+
+  # Up to `limit` records with keys after `cursor` (from the start when nil)
+  # and matching `pattern`, in key order, along with the cursor for the next
+  # page: the last key returned, or nil once nothing is left. Records written
+  # or deleted between calls don't shift later pages.
+  def page_after(table_name, cursor, limit, pattern \\ %{}) do
+    :mnesia.transaction(fn ->
+      if ordered?(table_name) do
+        :mnesia.read_lock_table(table_name)
+        start = if cursor, do: :mnesia.next(table_name, cursor), else: :mnesia.first(table_name)
+        walk_page(table_name, start, limit, pattern, [], 0)
+      else
+        scan_page(table_name, cursor, limit, pattern)
+      end
+    end)
+    |> transaction_result()
+    |> case do
+      {:ok, {rows, next}} -> {:ok, {Enum.map(rows, fn {_, data} -> data end), next}}
+      error -> error
+    end
+  end
+
+  # User tables are ordered sets; ones created before that are hashed, and
+  # paging them means selecting and sorting everything after the cursor
+  defp ordered?(table_name), do: :mnesia.table_info(table_name, :type) == :ordered_set
+
+  # Walk an ordered table from `key`, collecting the first `limit` records
+  # matching `pattern`; the cursor is only set if another match follows them
+  defp walk_page(_table_name, :"$end_of_table", _limit, _pattern, page, _count) do
+    {Enum.reverse(page), nil}
+  end
+
+  defp walk_page(table_name, key, limit, pattern, page, count) do
+    [{^table_name, ^key, data}] = :mnesia.read({table_name, key})
+    next = :mnesia.next(table_name, key)
+
+    cond do
+      not map_matches?(data, pattern) -> walk_page(table_name, next, limit, pattern, page, count)
+      count == limit -> {Enum.reverse(page), page_key(hd(page))}
+      true -> walk_page(table_name, next, limit, pattern, [{key, data} | page], count + 1)
+    end
+  end
+
+  defp scan_page(table_name, cursor, limit, pattern) do
+    guards = if cursor, do: [{:>, :"$1", cursor}], else: []
+
+    :mnesia.select(table_name, [{{table_name, :"$1", :"$2"}, guards, [{{:"$1", :"$2"}}]}])
+    |> Enum.filter(fn {_, data} -> map_matches?(data, pattern) end)
+    |> Enum.sort_by(fn {key, _} -> key end)
+    |> split_page(limit)
+  end
+
+  # Like page_after/4, for keys starting with `prefix`
+  def key_page(table_name, prefix, cursor, limit) do
+    :mnesia.transaction(fn ->
+      if ordered?(table_name) do
+        :mnesia.read_lock_table(table_name)
+        walk_keys(table_name, first_key(table_name, prefix, cursor), prefix, limit, [], 0)
+      else
+        :mnesia.all_keys(table_name)
+        |> Enum.filter(&(String.starts_with?(&1, prefix) and (is_nil(cursor) or &1 > cursor)))
+        |> Enum.sort()
+        |> split_page(limit)
+      end
+    end)
+    |> transaction_result()
+  end
+
+  # The first key after `cursor` that could start with `prefix`: keys
+  # sharing a prefix sort together, starting at the prefix itself
+  defp first_key(table_name, prefix, cursor) when is_binary(cursor) and cursor >= prefix do
+    :mnesia.next(table_name, cursor)
+  end
+
+  defp first_key(table_name, prefix, _cursor) do
+    case :mnesia.read({table_name, prefix}) do
+      [] -> :mnesia.next(table_name, prefix)
+      _ -> prefix
+    end
+  end
+
+  # Collect up to `limit` keys from `key` on, stopping at the first that
+  # doesn't start with `prefix`
+  defp walk_keys(table_name, key, prefix, limit, page, count) do
+    cond do
+      key == :"$end_of_table" or not String.starts_with?(key, prefix) ->
+        {Enum.reverse(page), nil}
+
+      count == limit ->
+        {Enum.reverse(page), hd(page)}
+
+      true ->
+        next = :mnesia.next(table_name, key)
+        walk_keys(table_name, next, prefix, limit, [key | page], count + 1)
+    end
+  end
+
+  # The first `limit` of the sorted `items` and the key of the last one, or
+  # nil for the key if those were all there was
+  defp split_page(items, limit) do
+    case Enum.split(items, limit) do
+      {page, []} -> {page, nil}
+      {page, _rest} -> {page, page_key(List.last(page))}
+    end
+  end
+
+  defp page_key({key, _data}), do: key
+  defp page_key(key), do: key
+
+  def keys(table_name, prefix \\ "") do
     :mnesia.transaction(fn ->
       :mnesia.all_keys(table_name)
+      |> Enum.filter(&String.starts_with?(&1, prefix))
     end)
     |> transaction_result()
   end
@@ -257,7 +1040,10 @@ defmodule Cortex.Store do
 
   # ACL operations
 
-  def acl_grant(identity, table_name, permissions) when is_list(permissions) do
+  # A grant with `expires_at` (unix seconds) is auto-revoked once it passes;
+  # granting without one makes the entry permanent again.
+  def acl_grant(identity, table_name, permissions, expires_at \\ nil)
+      when is_list(permissions) do
     :mnesia.transaction(fn ->
       key = {identity, table_name}
 
@@ -269,6 +1055,12 @@ defmodule Cortex.Store do
 
       merged = Enum.uniq(existing ++ permissions)
       :mnesia.write({@acl_table, key, merged})
+
+      if expires_at do
+        :mnesia.write({@acl_expiry_table, key, expires_at})
+      else
+        :mnesia.delete({@acl_expiry_table, key})
+      end
     end)
     |> transaction_result()
   end
@@ -283,6 +1075,7 @@ defmodule Cortex.Store do
 
           if remaining == [] do
             :mnesia.delete({@acl_table, key})
+            :mnesia.delete({@acl_expiry_table, key})
           else
             :mnesia.write({@acl_table, key, remaining})
           end
@@ -295,34 +1088,90 @@ defmodule Cortex.Store do
   end
 
   def acl_check(identity, table_name, permission) do
+    case acl_effective(identity, table_name) do
+      {:ok, perms} -> {:ok, permission in perms}
+      error -> error
+    end
+  end
+
+  # Effective permissions combine the identity's own grant, grants to any of
+  # its groups, and the world grant
+  def acl_effective(identity, table_name) do
+    with {:ok, grants} <- acl_grants(table_name) do
+      identities = [identity, "*" | group_identities(identity, grants)]
+
+      perms =
+        grants
+        |> Enum.filter(fn {id, _perms} -> id in identities end)
+        |> Enum.flat_map(fn {_id, perms} -> perms end)
+        |> Enum.uniq()
+
+      {:ok, perms}
+    end
+  end
+
+  defp acl_grants(table_name) do
+    now = System.os_time(:second)
+
     :mnesia.transaction(fn ->
-      # Check specific identity
-      case :mnesia.read({@acl_table, {identity, table_name}}) do
-        [{@acl_table, _, perms}] when is_list(perms) ->
-          permission in perms
+      :mnesia.match_object({@acl_table, {:_, table_name}, :_})
+      |> Enum.reject(fn {_, key, _} -> expired?(acl_expires_at(key), now) end)
+      |> Enum.map(fn {_, {id, _}, perms} -> {id, perms} end)
+    end)
+    |> transaction_result()
+  end
 
-        [] ->
-          # Check world access
-          case :mnesia.read({@acl_table, {"*", table_name}}) do
-            [{@acl_table, _, perms}] when is_list(perms) ->
-              permission in perms
+  # Must be called inside a transaction
+  defp acl_expires_at(key) do
+    case :mnesia.read({@acl_expiry_table, key}) do
+      [{@acl_expiry_table, ^key, expires_at}] -> expires_at
+      [] -> nil
+    end
+  end
 
-            [] ->
-              false
-          end
-      end
+  defp expired?(nil, _now), do: false
+  defp expired?(expires_at, now), do: expires_at <= now
+
+  def expire_acls(now) do
+    :mnesia.transaction(fn ->
+      :mnesia.foldl(
+        fn {_, key, expires_at}, acc ->
+          if expired?(expires_at, now), do: [key | acc], else: acc
+        end,
+        [],
+        @acl_expiry_table
+      )
+      |> Enum.each(fn key ->
+        :mnesia.delete({@acl_table, key})
+        :mnesia.delete({@acl_expiry_table, key})
+      end)
     end)
     |> transaction_result()
   end
 
+  # Only pay for a group database lookup when the table has group grants.
+  # If groups can't be resolved (already logged), group grants don't apply.
+  defp group_identities(identity, grants) do
+    has_group_grants =
+      Enum.any?(grants, fn {id, _} -> is_binary(id) and String.starts_with?(id, "gid:") end)
+
+    with true <- has_group_grants,
+         {:ok, uid} when is_integer(uid) <- Identity.parse_identity(identity),
+         {:ok, gids} <- Identity.group_ids(uid) do
+      Enum.map(gids, &Identity.gid_to_identity/1)
+    else
+      _ -> []
+    end
+  end
+
   def acl_list(owner_uid) do
     :mnesia.transaction(fn ->
       # Get ACLs for tables owned by this user
       :mnesia.foldl(
-        fn {_, {id, table}, perms}, acc ->
+        fn {_, {id, table} = key, perms}, acc ->
           case get_table_meta(table) do
             {:ok, %{owner: ^owner_uid}} ->
-              [{id, Atom.to_string(table), perms} | acc]
+              [{id, Atom.to_string(table), perms, acl_expires_at(key)} | acc]
 
             _ ->
               acc
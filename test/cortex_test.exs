@@ -51,6 +51,87 @@ defmodule CortexTest do
       assert Cortex.Identity.parse_identity("*") == {:ok, :world}
       assert Cortex.Identity.parse_identity("invalid") == {:error, :invalid_identity}
     end
+
+    test "parses group identity string" do
+      assert Cortex.Identity.parse_identity("gid:1001") == {:ok, {:gid, 1001}}
+      assert Cortex.Identity.parse_identity("gid:abc") == {:error, :invalid_identity}
+      assert Cortex.Identity.parse_identity("group:foo") == {:error, :invalid_identity}
+    end
+
+    test "looks up a UID's groups once per process" do
+      {:ok, gids} = Cortex.Identity.group_ids(0)
+      assert 0 in gids
+      assert Process.get({Cortex.Identity, :group_ids, 0}) == {:ok, gids}
+      assert Cortex.Identity.group_ids(0) == {:ok, gids}
+    end
+  end
+
+  describe "Store" do
+    test "delete_match clears the expiry of the records it deletes" do
+      name = "expiry_#{:erlang.unique_integer([:positive])}"
+      {:ok, table} = Cortex.Store.create_table(1000, name, [:id, :n])
+      on_exit(fn -> Cortex.Store.drop_table(1000, name) end)
+
+      {:ok, :ok} = Cortex.Store.put(table, %{"id" => "a", "n" => 1}, 60)
+      {:ok, :ok} = Cortex.Store.put(table, %{"id" => "b", "n" => 1}, 60)
+      {:ok, 2} = Cortex.Store.delete_match(table, %{"n" => 1})
+
+      # Neither write sets a TTL of its own
+      {:ok, :ok} = Cortex.Store.put(table, %{"id" => "a", "n" => 2})
+      {:ok, 5} = Cortex.Store.incr(table, "b", "n", 5)
+
+      {:ok, _} = Cortex.Store.expire_records(System.os_time(:second) + 120)
+      assert Cortex.Store.get(table, "a") == {:ok, %{"id" => "a", "n" => 2}}
+      assert Cortex.Store.get(table, "b") == {:ok, %{"id" => "b", "n" => 5}}
+    end
+
+    test "page_after walks from the cursor in key order" do
+      name = "pages_#{:erlang.unique_integer([:positive])}"
+      {:ok, table} = Cortex.Store.create_table(1000, name, [:id, :n])
+      on_exit(fn -> Cortex.Store.drop_table(1000, name) end)
+
+      for {id, n} <- [{"d", 1}, {"a", 1}, {"c", 2}, {"b", 1}, {"e", 1}] do
+        {:ok, :ok} = Cortex.Store.put(table, %{"id" => id, "n" => n})
+      end
+
+      ids = fn {:ok, {records, next}} -> {Enum.map(records, & &1["id"]), next} end
+      assert ids.(Cortex.Store.page_after(table, nil, 2)) == {["a", "b"], "b"}
+      assert ids.(Cortex.Store.page_after(table, "b", 2)) == {["c", "d"], "d"}
+      assert ids.(Cortex.Store.page_after(table, "d", 2)) == {["e"], nil}
+      # A cursor needn't be a stored key, and the last full page has no next
+      assert ids.(Cortex.Store.page_after(table, "bb", 2, %{"n" => 1})) == {["d", "e"], nil}
+    end
+
+    test "key_page walks only the keys with the prefix" do
+      name = "key_pages_#{:erlang.unique_integer([:positive])}"
+      {:ok, table} = Cortex.Store.create_table(1000, name, [:id])
+      on_exit(fn -> Cortex.Store.drop_table(1000, name) end)
+
+      for id <- ["user:3", "order:1", "user:1", "user:2", "zone"] do
+        {:ok, :ok} = Cortex.Store.put(table, %{"id" => id})
+      end
+
+      assert Cortex.Store.key_page(table, "user:", nil, 2) ==
+               {:ok, {["user:1", "user:2"], "user:2"}}
+      assert Cortex.Store.key_page(table, "user:", "user:2", 2) == {:ok, {["user:3"], nil}}
+      assert Cortex.Store.key_page(table, "", "user:3", 5) == {:ok, {["zone"], nil}}
+    end
+
+    test "truncate clears records, indexes, and expiries together" do
+      name = "truncate_#{:erlang.unique_integer([:positive])}"
+      {:ok, table} = Cortex.Store.create_table(1000, name, [:id, :n])
+      on_exit(fn -> Cortex.Store.drop_table(1000, name) end)
+
+      {:ok, 0} = Cortex.Store.create_index(table, "n")
+      {:ok, :ok} = Cortex.Store.put(table, %{"id" => "a", "n" => 1}, 60)
+      {:ok, :ok} = Cortex.Store.put(table, %{"id" => "b", "n" => 1})
+      assert Cortex.Store.truncate(table) == {:ok, :ok}
+      assert Cortex.Store.match(table, %{"n" => 1}) == {:ok, []}
+
+      {:ok, 5} = Cortex.Store.incr(table, "a", "n", 5)
+      {:ok, _} = Cortex.Store.expire_records(System.os_time(:second) + 120)
+      assert Cortex.Store.get(table, "a") == {:ok, %{"id" => "a", "n" => 5}}
+    end
   end
 
   describe "ACL" do